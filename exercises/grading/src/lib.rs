@@ -0,0 +1,211 @@
+//! The harness the weekly exercises are graded with. Each exercise registers named checks with a
+//! `Registry` from a `checks` module of its own, next to the code they check, and the `exercises`
+//! crate's `grade` binary runs every one of them and prints a pass/fail table with a score.
+//!
+//! This is a crate of its own, rather than part of `exercises`, so that the exercises can depend
+//! on it while `exercises` depends on them.
+
+use std::any::Any;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Something about an exercise that can be checked.
+pub trait Check {
+    /// Name of the check in the report, as `exercise::what_is_checked`.
+    fn name(&self) -> &str;
+
+    /// Returns Err with what went wrong if the check fails.
+    fn run(&self) -> Result<(), String>;
+}
+
+/// A check that calls a function.
+pub struct FnCheck<F> {
+    name: String,
+    f: F,
+}
+
+impl<F: Fn() -> Result<(), String>> Check for FnCheck<F> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self) -> Result<(), String> {
+        (self.f)()
+    }
+}
+
+/// Returns Ok if `actual` equals `expected`, and an error saying what each was if not.
+pub fn expect_eq<T: PartialEq + fmt::Debug>(actual: T, expected: T) -> Result<(), String> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!("expected {:?}, got {:?}", expected, actual))
+    }
+}
+
+/// The checks to run, in the order they were registered.
+#[derive(Default)]
+pub struct Registry {
+    checks: Vec<Box<dyn Check>>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    pub fn register<C: Check + 'static>(&mut self, check: C) {
+        self.checks.push(Box::new(check));
+    }
+
+    /// Registers a check that calls `f`.
+    pub fn register_fn<F>(&mut self, name: &str, f: F)
+    where
+        F: Fn() -> Result<(), String> + 'static,
+    {
+        self.register(FnCheck {
+            name: String::from(name),
+            f,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.checks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checks.is_empty()
+    }
+
+    /// Runs every check, in order. A check that panics fails, with the panic message as the reason,
+    /// and the rest still run.
+    pub fn run(&self) -> Report {
+        let outcomes = self
+            .checks
+            .iter()
+            .map(|check| Outcome {
+                name: String::from(check.name()),
+                result: panic::catch_unwind(AssertUnwindSafe(|| check.run()))
+                    .unwrap_or_else(|payload| Err(panic_message(payload))),
+            })
+            .collect();
+        Report { outcomes }
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    let message = match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => String::from(*message),
+            Err(_) => String::from("<non-string payload>"),
+        },
+    };
+    format!("panicked: {}", message)
+}
+
+/// How one check went.
+#[derive(Debug)]
+pub struct Outcome {
+    pub name: String,
+    pub result: Result<(), String>,
+}
+
+/// How every check went, in the order they ran. Displays as a pass/fail table with a score.
+#[derive(Debug)]
+pub struct Report {
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Report {
+    /// Number of checks that passed.
+    pub fn passed(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|outcome| outcome.result.is_ok())
+            .count()
+    }
+
+    pub fn all_passed(&self) -> bool {
+        self.passed() == self.outcomes.len()
+    }
+
+    /// The checks that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &Outcome> {
+        self.outcomes
+            .iter()
+            .filter(|outcome| outcome.result.is_err())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .outcomes
+            .iter()
+            .map(|outcome| outcome.name.len())
+            .max()
+            .unwrap_or(0);
+        for outcome in self.outcomes.iter() {
+            match &outcome.result {
+                Ok(()) => writeln!(f, "PASS  {}", outcome.name)?,
+                Err(reason) => writeln!(
+                    f,
+                    "FAIL  {:width$}  {}",
+                    outcome.name,
+                    reason,
+                    width = width
+                )?,
+            }
+        }
+        writeln!(f, "{}/{} checks passed", self.passed(), self.outcomes.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut registry = Registry::new();
+        registry.register_fn("ok", || Ok(()));
+        registry.register_fn("wrong_answer", || expect_eq(1 + 1, 3));
+        registry.register_fn("panics", || panic!("out of bounds"));
+        registry.register_fn("also_ok", || expect_eq("a", "a"));
+        assert_eq!(registry.len(), 4);
+
+        let report = registry.run();
+        assert_eq!(report.passed(), 2);
+        assert!(!report.all_passed());
+        let failures: Vec<(&str, &str)> = report
+            .failures()
+            .map(|outcome| {
+                let reason = outcome.result.as_ref().unwrap_err();
+                (outcome.name.as_str(), reason.as_str())
+            })
+            .collect();
+        assert_eq!(
+            failures,
+            vec![
+                ("wrong_answer", "expected 3, got 2"),
+                ("panics", "panicked: out of bounds"),
+            ]
+        );
+        assert_eq!(
+            report.to_string(),
+            "PASS  ok\n\
+             FAIL  wrong_answer  expected 3, got 2\n\
+             FAIL  panics        panicked: out of bounds\n\
+             PASS  also_ok\n\
+             2/4 checks passed\n"
+        );
+    }
+
+    #[test]
+    fn test_empty_registry_passes() {
+        let report = Registry::new().run();
+        assert!(report.all_passed());
+        assert_eq!(report.to_string(), "0/0 checks passed\n");
+    }
+}
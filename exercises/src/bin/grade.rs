@@ -0,0 +1,15 @@
+//! Runs the checks for every graded exercise and prints how each went. Exits with status 1 if any
+//! of them failed.
+
+use exercises::{register_all, Registry};
+use std::process;
+
+fn main() {
+    let mut registry = Registry::new();
+    register_all(&mut registry);
+    let report = registry.run();
+    print!("{}", report);
+    if !report.all_passed() {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,272 @@
+//! Decoding of bodies sent with `Transfer-Encoding: chunked`, where the body comes in chunks that
+//! are each preceded by their size, and ends with an empty chunk followed by optional trailers.
+
+use crate::{MAX_BODY_SIZE, MAX_HEADERS_SIZE};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Why a chunked body couldn't be read. The request and response modules turn these into their
+/// own errors.
+#[derive(Debug)]
+pub(crate) enum ChunkError {
+    /// A chunk size line, chunk or trailer isn't what it should be
+    Malformed,
+    /// The decoded body would be bigger than MAX_BODY_SIZE
+    TooLarge,
+    /// The other side hung up before the last chunk. Contains the number of bytes read from the
+    /// stream before it did
+    Incomplete(usize),
+    /// Encountered an I/O error when reading the stream
+    ConnectionError(std::io::Error),
+}
+
+/// Returns the transfer codings of the body, in the order they were applied. They may be split
+/// across any number of Transfer-Encoding headers, each holding a comma-separated list.
+fn transfer_codings(headers: &http::HeaderMap) -> Vec<String> {
+    headers
+        .get_all(http::header::TRANSFER_ENCODING)
+        .iter()
+        .flat_map(|value| {
+            String::from_utf8_lossy(value.as_bytes())
+                .split(',')
+                .map(|coding| coding.trim().to_ascii_lowercase())
+                .filter(|coding| !coding.is_empty())
+                .collect::<Vec<String>>()
+        })
+        .collect()
+}
+
+/// Returns whether the message has a Transfer-Encoding, in which case its Content-Length, if any,
+/// doesn't say where the body ends.
+pub(crate) fn has_transfer_encoding(headers: &http::HeaderMap) -> bool {
+    headers.contains_key(http::header::TRANSFER_ENCODING)
+}
+
+/// Returns whether the body comes chunked, which is when `chunked` is the last coding applied to
+/// it, e.g. `Transfer-Encoding: gzip, chunked`.
+pub(crate) fn is_chunked(headers: &http::HeaderMap) -> bool {
+    transfer_codings(headers)
+        .last()
+        .is_some_and(|coding| coding == "chunked")
+}
+
+/// Takes the `chunked` coding off the Transfer-Encoding, keeping any others, and sets the
+/// Content-Length of the decoded body, so that the message can be passed on as it is. Trailers
+/// aren't kept, so neither is the Trailer header.
+pub(crate) fn unchunk_headers(headers: &mut http::HeaderMap, body_len: usize) {
+    let mut codings = transfer_codings(headers);
+    codings.pop();
+    headers.remove(http::header::TRANSFER_ENCODING);
+    if let Ok(value) = http::HeaderValue::from_str(&codings.join(", ")) {
+        if !codings.is_empty() {
+            headers.insert(http::header::TRANSFER_ENCODING, value);
+        }
+    }
+    headers.remove(http::header::TRAILER);
+    headers.insert(http::header::CONTENT_LENGTH, body_len.into());
+}
+
+/// Reads more of the stream onto the end of `pending`, adding the number of bytes read to
+/// `bytes_read`.
+async fn read_more<S>(
+    stream: &mut S,
+    pending: &mut Vec<u8>,
+    bytes_read: &mut usize,
+) -> Result<(), ChunkError>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buffer = [0_u8; 512];
+    let new_bytes = stream
+        .read(&mut buffer)
+        .await
+        .map_err(ChunkError::ConnectionError)?;
+    if new_bytes == 0 {
+        return Err(ChunkError::Incomplete(*bytes_read));
+    }
+    *bytes_read += new_bytes;
+    pending.extend_from_slice(&buffer[..new_bytes]);
+    Ok(())
+}
+
+/// Reads a chunked body from `stream` and returns it decoded. `pending` holds the start of the
+/// body, if some of it was read along with the headers. Chunk extensions and trailers are read and
+/// thrown away.
+pub(crate) async fn read_chunked_body<S>(
+    stream: &mut S,
+    mut pending: Vec<u8>,
+) -> Result<Vec<u8>, ChunkError>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut body = Vec::new();
+    let mut bytes_read = 0;
+    loop {
+        // Read the chunk size line, which may have extensions after the size
+        let (size_line_len, chunk_size) = loop {
+            match httparse::parse_chunk_size(&pending).or(Err(ChunkError::Malformed))? {
+                httparse::Status::Complete(parsed) => break parsed,
+                httparse::Status::Partial if pending.len() > MAX_HEADERS_SIZE => {
+                    return Err(ChunkError::Malformed)
+                }
+                httparse::Status::Partial => {
+                    read_more(stream, &mut pending, &mut bytes_read).await?
+                }
+            }
+        };
+        pending.drain(..size_line_len);
+        if chunk_size == 0 {
+            break;
+        }
+        // Check the size before reading the chunk, so that it never gets buffered
+        if body.len() as u64 + chunk_size > MAX_BODY_SIZE as u64 {
+            return Err(ChunkError::TooLarge);
+        }
+
+        // Read the chunk, which ends with a CRLF of its own
+        let chunk_size = chunk_size as usize;
+        while pending.len() < chunk_size + 2 {
+            read_more(stream, &mut pending, &mut bytes_read).await?;
+        }
+        if &pending[chunk_size..chunk_size + 2] != b"\r\n" {
+            return Err(ChunkError::Malformed);
+        }
+        body.extend_from_slice(&pending[..chunk_size]);
+        pending.drain(..chunk_size + 2);
+    }
+
+    // Skip the trailers, up to the empty line that ends the message
+    loop {
+        match pending.windows(2).position(|window| window == b"\r\n") {
+            Some(0) => break,
+            Some(line_len) => {
+                pending.drain(..line_len + 2);
+            }
+            None if pending.len() > MAX_HEADERS_SIZE => return Err(ChunkError::Malformed),
+            None => read_more(stream, &mut pending, &mut bytes_read).await?,
+        }
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::Trickle;
+
+    async fn decode(chunks: &[&[u8]]) -> Result<Vec<u8>, ChunkError> {
+        read_chunked_body(&mut Trickle::new(chunks), Vec::new()).await
+    }
+
+    #[tokio::test]
+    async fn test_decode() {
+        let body = decode(&[b"5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n"]).await;
+        assert_eq!(body.unwrap(), b"hello, world");
+        let body = decode(&[b"0\r\n\r\n"]).await;
+        assert_eq!(body.unwrap(), b"");
+        // Sizes are in hex.
+        let body = decode(&[b"A\r\n0123456789\r\n0\r\n\r\n"]).await;
+        assert_eq!(body.unwrap(), b"0123456789");
+    }
+
+    #[tokio::test]
+    async fn test_split_across_reads() {
+        let body = decode(&[
+            b"1",
+            b"2\r",
+            b"\nfirst half",
+            b", second\r\n3",
+            b"\r\nend\r",
+            b"\n0\r\n",
+            b"\r\n",
+        ])
+        .await;
+        assert_eq!(body.unwrap(), b"first half, secondend");
+
+        // The start of the body may have been read along with the headers.
+        let mut stream = Trickle::new(&[b"lo\r\n0\r\n\r\n"]);
+        let body = read_chunked_body(&mut stream, b"5\r\nhel".to_vec()).await;
+        assert_eq!(body.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_extensions_and_trailers() {
+        let body = decode(&[
+            b"5;name=value\r\nhello\r\n0;last\r\nExpires: never\r\nX-Checksum: 1234\r\n\r\n",
+        ])
+        .await;
+        assert_eq!(body.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_malformed() {
+        for chunks in &[
+            &b"zz\r\nhello\r\n0\r\n\r\n"[..],
+            b"5\r\nhello world\r\n0\r\n\r\n",
+            b"5hello\r\n0\r\n\r\n",
+        ] {
+            let error = decode(&[chunks]).await.unwrap_err();
+            assert!(matches!(error, ChunkError::Malformed), "{:?}", error);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_incomplete() {
+        let error = decode(&[b"5\r\nhel"]).await.unwrap_err();
+        assert!(matches!(error, ChunkError::Incomplete(6)), "{:?}", error);
+        let error = decode(&[b"5\r\nhello\r\n"]).await.unwrap_err();
+        assert!(matches!(error, ChunkError::Incomplete(10)), "{:?}", error);
+        let error = decode(&[b"0\r\nTrailer: x\r\n"]).await.unwrap_err();
+        assert!(matches!(error, ChunkError::Incomplete(_)), "{:?}", error);
+    }
+
+    #[tokio::test]
+    async fn test_too_large() {
+        // Rejected as soon as the size line of the chunk that goes over is read.
+        let chunk = vec![b'x'; 1 << 20];
+        let size_line = format!("{:x}\r\n", chunk.len());
+        let mut chunks: Vec<&[u8]> = Vec::new();
+        for _ in 0..9 {
+            chunks.extend(&[size_line.as_bytes(), chunk.as_slice(), b"\r\n"]);
+        }
+        chunks.push(b"F00000\r\nxxxx");
+        let error = decode(&chunks).await.unwrap_err();
+        assert!(matches!(error, ChunkError::TooLarge), "{:?}", error);
+    }
+
+    #[test]
+    fn test_headers() {
+        let mut headers = http::HeaderMap::new();
+        assert!(!is_chunked(&headers));
+        assert!(!has_transfer_encoding(&headers));
+        headers.insert("transfer-encoding", "gzip".parse().unwrap());
+        assert!(!is_chunked(&headers));
+        assert!(has_transfer_encoding(&headers));
+        headers.insert("transfer-encoding", "Chunked".parse().unwrap());
+        assert!(is_chunked(&headers));
+
+        headers.insert("trailer", "Expires".parse().unwrap());
+        unchunk_headers(&mut headers, 12);
+        assert!(!has_transfer_encoding(&headers));
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers["content-length"], "12");
+    }
+
+    #[test]
+    fn test_coding_lists() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("transfer-encoding", "gzip, chunked".parse().unwrap());
+        assert!(is_chunked(&headers));
+        headers.insert("transfer-encoding", "chunked, gzip".parse().unwrap());
+        assert!(!is_chunked(&headers));
+
+        // Split across header lines, which is the same as one comma-separated list.
+        headers.insert("transfer-encoding", "deflate".parse().unwrap());
+        headers.append("transfer-encoding", " gzip ,".parse().unwrap());
+        headers.append("transfer-encoding", "chunked".parse().unwrap());
+        assert!(is_chunked(&headers));
+        unchunk_headers(&mut headers, 3);
+        assert_eq!(headers["transfer-encoding"], "deflate, gzip");
+        assert_eq!(headers.get_all("transfer-encoding").iter().count(), 1);
+        assert_eq!(headers["content-length"], "3");
+    }
+}
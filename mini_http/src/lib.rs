@@ -0,0 +1,104 @@
+//! A small HTTP/1.1 reader and writer for async streams, built on `httparse` and the `http` types.
+//! It reads a request or response with its body into memory, and writes one back out; that's all.
+//! Anything that is `AsyncRead` or `AsyncWrite` (and `Unpin`) will do as the stream, whether a
+//! `TcpStream` or a byte slice in a test.
+
+mod chunked;
+pub mod request;
+pub mod response;
+
+pub use request::{read_request, write_request, Error as RequestError};
+pub use response::{make_http_error, read_response, write_response, Error as ResponseError};
+
+/// Largest set of headers (including the request or status line) that will be read
+const MAX_HEADERS_SIZE: usize = 8000;
+/// Largest body that will be read
+const MAX_BODY_SIZE: usize = 10000000;
+/// Most headers a request or response may have
+const MAX_NUM_HEADERS: usize = 32;
+
+/// Extracts the value of the Content-Length header from `headers`. Returns Ok(Some(usize)) if it's
+/// present and valid, Ok(None) if it isn't present, or Err(()) if it's present but invalid.
+fn get_content_length(headers: &http::HeaderMap) -> Result<Option<usize>, ()> {
+    match headers.get("content-length") {
+        Some(header_value) => Ok(Some(
+            header_value
+                .to_str()
+                .or(Err(()))?
+                .parse::<usize>()
+                .or(Err(()))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Writes the start line, headers and body of a request or response to `stream`.
+async fn write_message<S>(
+    stream: &mut S,
+    start_line: String,
+    headers: &http::HeaderMap,
+    body: &[u8],
+) -> Result<(), std::io::Error>
+where
+    S: tokio::io::AsyncWrite + Unpin,
+{
+    use tokio::io::AsyncWriteExt;
+
+    stream.write_all(start_line.as_bytes()).await?;
+    stream.write_all(b"\r\n").await?;
+    for (header_name, header_value) in headers {
+        stream
+            .write_all(format!("{}: ", header_name).as_bytes())
+            .await?;
+        stream.write_all(header_value.as_bytes()).await?;
+        stream.write_all(b"\r\n").await?;
+    }
+    stream.write_all(b"\r\n").await?;
+    if !body.is_empty() {
+        stream.write_all(body).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test_util {
+    use std::collections::VecDeque;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::AsyncRead;
+
+    /// A stream that hands out its bytes a chunk at a time, the way a socket might, and then
+    /// reports the end of the stream.
+    pub(crate) struct Trickle {
+        chunks: VecDeque<Vec<u8>>,
+    }
+
+    impl Trickle {
+        pub(crate) fn new(chunks: &[&[u8]]) -> Trickle {
+            Trickle {
+                chunks: chunks.iter().map(|chunk| chunk.to_vec()).collect(),
+            }
+        }
+    }
+
+    impl AsyncRead for Trickle {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let chunk = match self.chunks.front_mut() {
+                Some(chunk) => chunk,
+                None => return Poll::Ready(Ok(0)),
+            };
+            let len = chunk.len().min(buf.len());
+            buf[..len].copy_from_slice(&chunk[..len]);
+            chunk.drain(..len);
+            if chunk.is_empty() {
+                self.chunks.pop_front();
+            }
+            Poll::Ready(Ok(len))
+        }
+    }
+}
@@ -0,0 +1,446 @@
+use crate::chunked::{self, ChunkError};
+use crate::{get_content_length, write_message, MAX_BODY_SIZE, MAX_HEADERS_SIZE, MAX_NUM_HEADERS};
+use std::cmp::min;
+use std::fmt;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+
+#[derive(Debug)]
+pub enum Error {
+    /// Client hung up before sending a complete request. IncompleteRequest contains the number of
+    /// bytes that were successfully read before the client hung up
+    IncompleteRequest(usize),
+    /// Client sent an invalid HTTP request. httparse::Error contains more details
+    MalformedRequest(httparse::Error),
+    /// The Content-Length header is present, but does not contain a valid numeric value
+    InvalidContentLength,
+    /// The Content-Length header does not match the size of the request body that was sent
+    ContentLengthMismatch,
+    /// The request body is bigger than MAX_BODY_SIZE
+    RequestBodyTooLarge,
+    /// Encountered an I/O error when reading/writing the stream
+    ConnectionError(std::io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::IncompleteRequest(0) => write!(f, "Client hung up without sending a request"),
+            Error::IncompleteRequest(bytes_read) => write!(
+                f,
+                "Client hung up after sending {} bytes of an incomplete request",
+                bytes_read
+            ),
+            Error::MalformedRequest(err) => write!(f, "Malformed request: {}", err),
+            Error::InvalidContentLength => write!(f, "Invalid Content-Length header"),
+            Error::ContentLengthMismatch => {
+                write!(f, "Request body doesn't match its Content-Length")
+            }
+            Error::RequestBodyTooLarge => write!(f, "Request body is too large"),
+            Error::ConnectionError(err) => write!(f, "Connection error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::MalformedRequest(err) => Some(err),
+            Error::ConnectionError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ChunkError> for Error {
+    fn from(err: ChunkError) -> Self {
+        match err {
+            // httparse has no error for bad chunks, and Token is the closest
+            ChunkError::Malformed => Error::MalformedRequest(httparse::Error::Token),
+            ChunkError::TooLarge => Error::RequestBodyTooLarge,
+            ChunkError::Incomplete(bytes_read) => Error::IncompleteRequest(bytes_read),
+            ChunkError::ConnectionError(err) => Error::ConnectionError(err),
+        }
+    }
+}
+
+/// This function appends to a header value (adding a new header if the header is not already
+/// present). This is used to add the client's IP address to the end of the X-Forwarded-For list,
+/// or to add a new X-Forwarded-For header if one is not already present.
+pub fn extend_header_value(
+    request: &mut http::Request<Vec<u8>>,
+    name: &'static str,
+    extend_value: &str,
+) {
+    let new_value = match request.headers().get(name) {
+        Some(existing_value) => {
+            [existing_value.as_bytes(), b", ", extend_value.as_bytes()].concat()
+        }
+        None => extend_value.as_bytes().to_owned(),
+    };
+    request
+        .headers_mut()
+        .insert(name, http::HeaderValue::from_bytes(&new_value).unwrap());
+}
+
+/// A complete request's headers, along with how many bytes of the buffer they took up, if there
+/// were any yet
+type ParseResult = Result<Option<(http::Request<Vec<u8>>, usize)>, Error>;
+
+/// Attempts to parse the data in the supplied buffer as an HTTP request. Returns one of the
+/// following:
+///
+/// * If there is a complete and valid request in the buffer, returns Ok(Some(http::Request))
+/// * If there is an incomplete but valid-so-far request in the buffer, returns Ok(None)
+/// * If there is data in the buffer that is definitely not a valid HTTP request, returns Err(Error)
+fn parse_request(buffer: &[u8]) -> ParseResult {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_NUM_HEADERS];
+    let mut req = httparse::Request::new(&mut headers);
+    let res = req.parse(buffer).map_err(Error::MalformedRequest)?;
+
+    if let httparse::Status::Complete(len) = res {
+        let mut request = http::Request::builder()
+            .method(req.method.unwrap())
+            .uri(req.path.unwrap())
+            .version(http::Version::HTTP_11);
+        for header in req.headers {
+            request = request.header(header.name, header.value);
+        }
+        let request = request.body(Vec::new()).unwrap();
+        Ok(Some((request, len)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Reads an HTTP request from the provided stream, waiting until a complete set of headers is sent.
+/// This function only reads the request line and headers; the read_body function can subsequently
+/// be called in order to read the request body (for a POST request).
+///
+/// Returns Ok((http::Request, bytes read)) if a valid request is received, or Error if not.
+async fn read_headers<S>(stream: &mut S) -> Result<(http::Request<Vec<u8>>, usize), Error>
+where
+    S: AsyncRead + Unpin,
+{
+    // Try reading the headers from the request. We may not receive all the headers in one shot
+    // (e.g. we might receive the first few bytes of a request, and then the rest follows later).
+    // Try parsing repeatedly until we read a valid HTTP request
+    let mut request_buffer = [0_u8; MAX_HEADERS_SIZE];
+    let mut bytes_read = 0;
+    loop {
+        // Read bytes from the connection into the buffer, starting at position bytes_read
+        let new_bytes = stream
+            .read(&mut request_buffer[bytes_read..])
+            .await
+            .map_err(Error::ConnectionError)?;
+        if new_bytes == 0 {
+            // We didn't manage to read a complete request
+            return Err(Error::IncompleteRequest(bytes_read));
+        }
+        bytes_read += new_bytes;
+
+        // See if we've read a valid request so far
+        if let Some((mut request, headers_len)) = parse_request(&request_buffer[..bytes_read])? {
+            // We've read a complete set of headers. However, if this was a POST request, a request
+            // body might have been included as well, and we might have read part of the body out of
+            // the stream into header_buffer. We need to add those bytes to the Request body so that
+            // we don't lose them
+            request
+                .body_mut()
+                .extend_from_slice(&request_buffer[headers_len..bytes_read]);
+            return Ok((request, bytes_read));
+        }
+    }
+}
+
+/// This function reads the body for a request from the stream. The client only sends a body if the
+/// Content-Length header is present; this function reads that number of bytes from the stream. It
+/// returns Ok(()) if successful, or Err(Error) if Content-Length bytes couldn't be read.
+async fn read_body<S>(
+    stream: &mut S,
+    request: &mut http::Request<Vec<u8>>,
+    content_length: usize,
+) -> Result<(), Error>
+where
+    S: AsyncRead + Unpin,
+{
+    // Keep reading data until we read the full body length, or until we hit an error.
+    while request.body().len() < content_length {
+        // Read up to 512 bytes at a time. (If the client only sent a small body, then only allocate
+        // space to read that body.)
+        let mut buffer = vec![0_u8; min(512, content_length)];
+        let bytes_read = stream
+            .read(&mut buffer)
+            .await
+            .map_err(Error::ConnectionError)?;
+
+        // Make sure the client is still sending us bytes
+        if bytes_read == 0 {
+            log::debug!(
+                "Client hung up after sending a body of length {}, even though it said the content \
+                length is {}",
+                request.body().len(),
+                content_length
+            );
+            return Err(Error::ContentLengthMismatch);
+        }
+
+        // Make sure the client didn't send us *too many* bytes
+        if request.body().len() + bytes_read > content_length {
+            log::debug!(
+                "Client sent more bytes than we expected based on the given content length!"
+            );
+            return Err(Error::ContentLengthMismatch);
+        }
+
+        // Store the received bytes in the request body
+        request.body_mut().extend_from_slice(&buffer[..bytes_read]);
+    }
+    Ok(())
+}
+
+/// This function reads and returns an HTTP request from a stream, returning an Error if the client
+/// closes the connection prematurely or sends an invalid request.
+pub async fn read_request<S>(stream: &mut S) -> Result<http::Request<Vec<u8>>, Error>
+where
+    S: AsyncRead + Unpin,
+{
+    // Read headers
+    let (mut request, bytes_read) = read_headers(stream).await?;
+    if chunked::has_transfer_encoding(request.headers()) {
+        // Any Content-Length is ignored. Unless the body is chunked, there's no telling where it
+        // ends, and guessing differently from the upstream would let one request hide another.
+        if !chunked::is_chunked(request.headers()) {
+            return Err(Error::MalformedRequest(httparse::Error::Token));
+        }
+        // Read the whole chunked body, so that it can be passed on with a Content-Length instead
+        let start = std::mem::take(request.body_mut());
+        let body = chunked::read_chunked_body(stream, start)
+            .await
+            .map_err(|err| match err {
+                ChunkError::Incomplete(body_bytes) => {
+                    Error::IncompleteRequest(bytes_read + body_bytes)
+                }
+                err => Error::from(err),
+            })?;
+        chunked::unchunk_headers(request.headers_mut(), body.len());
+        *request.body_mut() = body;
+    } else if let Some(content_length) =
+        get_content_length(request.headers()).or(Err(Error::InvalidContentLength))?
+    {
+        // Read body if the client supplied the Content-Length header (which it does for POST
+        // requests)
+        if content_length > MAX_BODY_SIZE {
+            return Err(Error::RequestBodyTooLarge);
+        } else {
+            read_body(stream, &mut request, content_length).await?;
+        }
+    }
+    Ok(request)
+}
+
+/// This function serializes a request to bytes and writes those bytes to the provided stream.
+pub async fn write_request<S>(
+    request: &http::Request<Vec<u8>>,
+    stream: &mut S,
+) -> Result<(), std::io::Error>
+where
+    S: AsyncWrite + Unpin,
+{
+    write_message(
+        stream,
+        format_request_line(request),
+        request.headers(),
+        request.body(),
+    )
+    .await
+}
+
+pub fn format_request_line(request: &http::Request<Vec<u8>>) -> String {
+    format!(
+        "{} {} {:?}",
+        request.method(),
+        request.uri(),
+        request.version()
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::Trickle;
+
+    #[tokio::test]
+    async fn test_read_get() {
+        let mut stream: &[u8] = b"GET /index.html HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        let request = read_request(&mut stream).await.unwrap();
+        assert_eq!(request.method(), http::Method::GET);
+        assert_eq!(request.uri(), "/index.html");
+        assert_eq!(request.headers()["host"], "example.com");
+        assert!(request.body().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_post_body() {
+        let mut stream: &[u8] = b"POST /form HTTP/1.1\r\nContent-Length: 11\r\n\r\nhello world";
+        let request = read_request(&mut stream).await.unwrap();
+        assert_eq!(request.method(), http::Method::POST);
+        assert_eq!(request.body(), b"hello world");
+    }
+
+    #[tokio::test]
+    async fn test_read_in_pieces() {
+        // Headers split mid-line, and the body partly read along with them.
+        let mut stream = Trickle::new(&[
+            b"PUT /x HTTP/1.1\r\nConte",
+            b"nt-Length: 10\r\n\r\n0123",
+            b"456",
+            b"789",
+        ]);
+        let request = read_request(&mut stream).await.unwrap();
+        assert_eq!(request.body(), b"0123456789");
+    }
+
+    #[tokio::test]
+    async fn test_read_chunked_body() {
+        let mut stream = Trickle::new(&[
+            b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki",
+            b"\r\n5;ext\r\npedia\r\n0\r\n\r\n",
+        ]);
+        let request = read_request(&mut stream).await.unwrap();
+        assert_eq!(request.body(), b"Wikipedia");
+        // Passed on with a Content-Length, the way it would have come without chunking.
+        assert!(request.headers().get("transfer-encoding").is_none());
+        assert_eq!(request.headers()["content-length"], "9");
+    }
+
+    #[tokio::test]
+    async fn test_read_chunked_after_other_codings() {
+        let mut stream: &[u8] = b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n\
+            3\r\nabc\r\n0\r\n\r\n";
+        let request = read_request(&mut stream).await.unwrap();
+        assert_eq!(request.body(), b"abc");
+        // Only the chunking has been undone.
+        assert_eq!(request.headers()["transfer-encoding"], "gzip");
+        assert_eq!(request.headers()["content-length"], "3");
+
+        // The same, with the codings on separate lines.
+        let mut stream: &[u8] = b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\
+            Transfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n";
+        let request = read_request(&mut stream).await.unwrap();
+        assert_eq!(request.body(), b"abc");
+        assert_eq!(request.headers()["transfer-encoding"], "gzip");
+    }
+
+    #[tokio::test]
+    async fn test_transfer_encoding_overrides_content_length() {
+        let mut stream: &[u8] = b"POST / HTTP/1.1\r\nContent-Length: 3\r\n\
+            Transfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n";
+        let request = read_request(&mut stream).await.unwrap();
+        assert_eq!(request.body(), b"hello");
+        assert_eq!(request.headers()["content-length"], "5");
+
+        // Without chunked as the last coding, the body can't be framed at all.
+        for te in &["gzip", "chunked, gzip"] {
+            let raw = format!(
+                "POST / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: {}\r\n\r\nhello",
+                te
+            );
+            let error = read_request(&mut raw.as_bytes()).await.unwrap_err();
+            assert!(matches!(error, Error::MalformedRequest(_)), "{:?}", error);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bad_chunked_body() {
+        let mut stream: &[u8] =
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nnope\r\n\r\n";
+        let error = read_request(&mut stream).await.unwrap_err();
+        assert!(matches!(error, Error::MalformedRequest(_)), "{:?}", error);
+
+        let mut stream: &[u8] = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWi";
+        match read_request(&mut stream).await {
+            Err(Error::IncompleteRequest(52)) => {}
+            other => panic!("expected IncompleteRequest(52), got {:?}", other),
+        }
+
+        let mut stream: &[u8] = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nFFFFFFF\r\n";
+        assert!(matches!(
+            read_request(&mut stream).await,
+            Err(Error::RequestBodyTooLarge)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_incomplete_request() {
+        let mut stream: &[u8] = b"";
+        match read_request(&mut stream).await {
+            Err(Error::IncompleteRequest(0)) => {}
+            other => panic!("expected IncompleteRequest(0), got {:?}", other),
+        }
+        let mut stream: &[u8] = b"GET / HTTP/1.1\r\nHost: a";
+        match read_request(&mut stream).await {
+            Err(Error::IncompleteRequest(23)) => {}
+            other => panic!("expected IncompleteRequest(23), got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_malformed_request() {
+        let mut stream: &[u8] = b"GET / HTTP/1.1\r\nBad Header\r\n\r\n";
+        let error = read_request(&mut stream).await.unwrap_err();
+        assert!(matches!(error, Error::MalformedRequest(_)), "{:?}", error);
+        assert!(std::error::Error::source(&error).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_bad_content_length() {
+        let mut stream: &[u8] = b"POST / HTTP/1.1\r\nContent-Length: ten\r\n\r\n";
+        assert!(matches!(
+            read_request(&mut stream).await,
+            Err(Error::InvalidContentLength)
+        ));
+
+        let mut stream: &[u8] = b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort";
+        assert!(matches!(
+            read_request(&mut stream).await,
+            Err(Error::ContentLengthMismatch)
+        ));
+
+        let mut stream: &[u8] = b"POST / HTTP/1.1\r\nContent-Length: 99999999\r\n\r\n";
+        assert!(matches!(
+            read_request(&mut stream).await,
+            Err(Error::RequestBodyTooLarge)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let request = http::Request::builder()
+            .method(http::Method::POST)
+            .uri("/submit?x=1")
+            .header("Content-Length", "4")
+            .header("X-Custom", "yes")
+            .body(b"data".to_vec())
+            .unwrap();
+        let mut written = Vec::new();
+        write_request(&request, &mut written).await.unwrap();
+        assert_eq!(
+            written,
+            b"POST /submit?x=1 HTTP/1.1\r\ncontent-length: 4\r\nx-custom: yes\r\n\r\ndata".to_vec()
+        );
+
+        let read = read_request(&mut written.as_slice()).await.unwrap();
+        assert_eq!(read.method(), request.method());
+        assert_eq!(read.uri(), request.uri());
+        assert_eq!(read.headers(), request.headers());
+        assert_eq!(read.body(), request.body());
+    }
+
+    #[test]
+    fn test_extend_header_value() {
+        let mut request = http::Request::new(Vec::new());
+        extend_header_value(&mut request, "x-forwarded-for", "1.1.1.1");
+        extend_header_value(&mut request, "x-forwarded-for", "2.2.2.2");
+        assert_eq!(request.headers()["x-forwarded-for"], "1.1.1.1, 2.2.2.2");
+    }
+}
@@ -0,0 +1,427 @@
+use crate::chunked::{self, ChunkError};
+use crate::{get_content_length, write_message, MAX_BODY_SIZE, MAX_HEADERS_SIZE, MAX_NUM_HEADERS};
+use std::fmt;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+
+#[derive(Debug)]
+pub enum Error {
+    /// Server hung up before sending a complete response
+    IncompleteResponse,
+    /// Server sent an invalid HTTP response. httparse::Error contains more details
+    MalformedResponse(httparse::Error),
+    /// The Content-Length header is present, but does not contain a valid numeric value
+    InvalidContentLength,
+    /// The Content-Length header does not match the size of the response body that was sent
+    ContentLengthMismatch,
+    /// The response body is bigger than MAX_BODY_SIZE
+    ResponseBodyTooLarge,
+    /// Encountered an I/O error when reading/writing the stream
+    ConnectionError(std::io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::IncompleteResponse => {
+                write!(f, "Server hung up before sending a complete response")
+            }
+            Error::MalformedResponse(err) => write!(f, "Malformed response: {}", err),
+            Error::InvalidContentLength => write!(f, "Invalid Content-Length header"),
+            Error::ContentLengthMismatch => {
+                write!(f, "Response body doesn't match its Content-Length")
+            }
+            Error::ResponseBodyTooLarge => write!(f, "Response body is too large"),
+            Error::ConnectionError(err) => write!(f, "Connection error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::MalformedResponse(err) => Some(err),
+            Error::ConnectionError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ChunkError> for Error {
+    fn from(err: ChunkError) -> Self {
+        match err {
+            // httparse has no error for bad chunks, and Token is the closest
+            ChunkError::Malformed => Error::MalformedResponse(httparse::Error::Token),
+            ChunkError::TooLarge => Error::ResponseBodyTooLarge,
+            ChunkError::Incomplete(_) => Error::IncompleteResponse,
+            ChunkError::ConnectionError(err) => Error::ConnectionError(err),
+        }
+    }
+}
+
+/// A complete response's headers, along with how many bytes of the buffer they took up, if there
+/// were any yet
+type ParseResult = Result<Option<(http::Response<Vec<u8>>, usize)>, Error>;
+
+/// Attempts to parse the data in the supplied buffer as an HTTP response. Returns one of the
+/// following:
+///
+/// * If there is a complete and valid response in the buffer, returns Ok(Some(http::Request))
+/// * If there is an incomplete but valid-so-far response in the buffer, returns Ok(None)
+/// * If there is data in the buffer that is definitely not a valid HTTP response, returns
+///   Err(Error)
+fn parse_response(buffer: &[u8]) -> ParseResult {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_NUM_HEADERS];
+    let mut resp = httparse::Response::new(&mut headers);
+    let res = resp.parse(buffer).map_err(Error::MalformedResponse)?;
+
+    if let httparse::Status::Complete(len) = res {
+        let mut response = http::Response::builder()
+            .status(resp.code.unwrap())
+            .version(http::Version::HTTP_11);
+        for header in resp.headers {
+            response = response.header(header.name, header.value);
+        }
+        let response = response.body(Vec::new()).unwrap();
+        Ok(Some((response, len)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Reads an HTTP response from the provided stream, waiting until a complete set of headers is
+/// sent. This function only reads the response line and headers; the read_body function can
+/// subsequently be called in order to read the response body.
+///
+/// Returns Ok(http::Response) if a valid response is received, or Error if not.
+async fn read_headers<S>(stream: &mut S) -> Result<http::Response<Vec<u8>>, Error>
+where
+    S: AsyncRead + Unpin,
+{
+    // Try reading the headers from the response. We may not receive all the headers in one shot
+    // (e.g. we might receive the first few bytes of a response, and then the rest follows later).
+    // Try parsing repeatedly until we read a valid HTTP response
+    let mut response_buffer = [0_u8; MAX_HEADERS_SIZE];
+    let mut bytes_read = 0;
+    loop {
+        // Read bytes from the connection into the buffer, starting at position bytes_read
+        let new_bytes = stream
+            .read(&mut response_buffer[bytes_read..])
+            .await
+            .map_err(Error::ConnectionError)?;
+        if new_bytes == 0 {
+            // We didn't manage to read a complete response
+            return Err(Error::IncompleteResponse);
+        }
+        bytes_read += new_bytes;
+
+        // See if we've read a valid response so far
+        if let Some((mut response, headers_len)) = parse_response(&response_buffer[..bytes_read])? {
+            // We've read a complete set of headers. We may have also read the first part of the
+            // response body; take whatever is left over in the response buffer and save that as
+            // the start of the response body.
+            response
+                .body_mut()
+                .extend_from_slice(&response_buffer[headers_len..bytes_read]);
+            return Ok(response);
+        }
+    }
+}
+
+/// This function reads the body for a response from the stream. If the Content-Length header is
+/// present, it reads that many bytes; otherwise, it reads bytes until the connection is closed.
+async fn read_body<S>(stream: &mut S, response: &mut http::Response<Vec<u8>>) -> Result<(), Error>
+where
+    S: AsyncRead + Unpin,
+{
+    // The response may or may not supply a Content-Length header. If it provides the header, then
+    // we want to read that number of bytes; if it does not, we want to keep reading bytes until
+    // the connection is closed.
+    let content_length =
+        get_content_length(response.headers()).or(Err(Error::InvalidContentLength))?;
+
+    while content_length.is_none_or(|content_length| response.body().len() < content_length) {
+        let mut buffer = [0_u8; 512];
+        let bytes_read = stream
+            .read(&mut buffer)
+            .await
+            .map_err(Error::ConnectionError)?;
+        if bytes_read == 0 {
+            // The server has hung up!
+            if content_length.is_none() {
+                // We've reached the end of the response
+                break;
+            } else {
+                // Content-Length was set, but the server hung up before we managed to read that
+                // number of bytes
+                return Err(Error::ContentLengthMismatch);
+            }
+        }
+
+        // Make sure the server doesn't send more bytes than it promised to send
+        if content_length
+            .is_some_and(|content_length| response.body().len() + bytes_read > content_length)
+        {
+            return Err(Error::ContentLengthMismatch);
+        }
+
+        // Make sure server doesn't send more bytes than we allow
+        if response.body().len() + bytes_read > MAX_BODY_SIZE {
+            return Err(Error::ResponseBodyTooLarge);
+        }
+
+        // Append received bytes to the response body
+        response.body_mut().extend_from_slice(&buffer[..bytes_read]);
+    }
+    Ok(())
+}
+
+/// This function reads and returns an HTTP response from a stream, returning an Error if the server
+/// closes the connection prematurely or sends an invalid response. `request_method` is the method
+/// of the request being responded to, since responses to HEAD requests have no body.
+pub async fn read_response<S>(
+    stream: &mut S,
+    request_method: &http::Method,
+) -> Result<http::Response<Vec<u8>>, Error>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut response = read_headers(stream).await?;
+    // A response may have a body as long as it is not responding to a HEAD request and as long as
+    // the response status code is not 1xx, 204 (no content), or 304 (not modified).
+    if !(request_method == http::Method::HEAD
+        || response.status().as_u16() < 200
+        || response.status() == http::StatusCode::NO_CONTENT
+        || response.status() == http::StatusCode::NOT_MODIFIED)
+    {
+        if chunked::is_chunked(response.headers()) {
+            // Read the whole chunked body, so that it can be passed on with a Content-Length
+            // instead
+            let start = std::mem::take(response.body_mut());
+            let body = chunked::read_chunked_body(stream, start).await?;
+            chunked::unchunk_headers(response.headers_mut(), body.len());
+            *response.body_mut() = body;
+        } else {
+            if chunked::has_transfer_encoding(response.headers()) {
+                // The body isn't chunked, so it ends when the server hangs up, whatever the
+                // Content-Length says
+                response.headers_mut().remove(http::header::CONTENT_LENGTH);
+            }
+            read_body(stream, &mut response).await?;
+        }
+    }
+    Ok(response)
+}
+
+/// This function serializes a response to bytes and writes those bytes to the provided stream.
+pub async fn write_response<S>(
+    response: &http::Response<Vec<u8>>,
+    stream: &mut S,
+) -> Result<(), std::io::Error>
+where
+    S: AsyncWrite + Unpin,
+{
+    write_message(
+        stream,
+        format_response_line(response),
+        response.headers(),
+        response.body(),
+    )
+    .await
+}
+
+pub fn format_response_line(response: &http::Response<Vec<u8>>) -> String {
+    format!(
+        "{:?} {} {}",
+        response.version(),
+        response.status().as_str(),
+        response.status().canonical_reason().unwrap_or("")
+    )
+}
+
+/// This is a helper function that creates an http::Response containing an HTTP error that can be
+/// sent to a client.
+pub fn make_http_error(status: http::StatusCode) -> http::Response<Vec<u8>> {
+    let body = format!(
+        "HTTP {} {}",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or("")
+    )
+    .into_bytes();
+    http::Response::builder()
+        .status(status)
+        .header("Content-Type", "text/plain")
+        .header("Content-Length", body.len().to_string())
+        .version(http::Version::HTTP_11)
+        .body(body)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::Trickle;
+
+    #[tokio::test]
+    async fn test_read_with_content_length() {
+        let mut stream: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+        let response = read_response(&mut stream, &http::Method::GET)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(response.body(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_read_until_hangup() {
+        // Without a Content-Length, the body is whatever comes before the server hangs up.
+        let mut stream = Trickle::new(&[b"HTTP/1.1 200 OK\r\n\r\nsome ", b"more ", b"bytes"]);
+        let response = read_response(&mut stream, &http::Method::GET)
+            .await
+            .unwrap();
+        assert_eq!(response.body(), b"some more bytes");
+    }
+
+    #[tokio::test]
+    async fn test_read_chunked_body() {
+        // Chunked even though there's a Content-Length, which the chunking takes precedence over.
+        let mut stream = Trickle::new(&[
+            b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\nTransfer-Encoding: chunked\r\n",
+            b"Trailer: Expires\r\n\r\n6\r\nstream\r",
+            b"\n3\r\ning\r\n0\r\nExpires: never\r\n\r\n",
+        ]);
+        let response = read_response(&mut stream, &http::Method::GET)
+            .await
+            .unwrap();
+        assert_eq!(response.body(), b"streaming");
+        assert!(response.headers().get("transfer-encoding").is_none());
+        assert!(response.headers().get("trailer").is_none());
+        assert_eq!(response.headers()["content-length"], "9");
+
+        let mut stream: &[u8] =
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabcd\r\n";
+        let error = read_response(&mut stream, &http::Method::GET)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::MalformedResponse(_)), "{:?}", error);
+
+        let mut stream: &[u8] = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nab";
+        assert!(matches!(
+            read_response(&mut stream, &http::Method::GET).await,
+            Err(Error::IncompleteResponse)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_chunked_after_other_codings() {
+        let mut stream: &[u8] = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, chunked\r\n\r\n\
+            3\r\nabc\r\n0\r\n\r\n";
+        let response = read_response(&mut stream, &http::Method::GET)
+            .await
+            .unwrap();
+        assert_eq!(response.body(), b"abc");
+        assert_eq!(response.headers()["transfer-encoding"], "gzip");
+        assert_eq!(response.headers()["content-length"], "3");
+
+        let mut stream: &[u8] = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip\r\n\
+            Transfer-Encoding: chunked\r\nContent-Length: 1\r\n\r\n3\r\nabc\r\n0\r\n\r\n";
+        let response = read_response(&mut stream, &http::Method::GET)
+            .await
+            .unwrap();
+        assert_eq!(response.body(), b"abc");
+        assert_eq!(response.headers()["transfer-encoding"], "gzip");
+    }
+
+    #[tokio::test]
+    async fn test_read_other_codings_until_hangup() {
+        // Not chunked, so the Content-Length doesn't count and the body runs until the hangup.
+        let mut stream = Trickle::new(&[
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip\r\nContent-Length: 2\r\n\r\n",
+            b"some bytes",
+        ]);
+        let response = read_response(&mut stream, &http::Method::GET)
+            .await
+            .unwrap();
+        assert_eq!(response.body(), b"some bytes");
+        assert!(response.headers().get("content-length").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_no_body_expected() {
+        // Whatever follows the headers is left in the stream for the next response.
+        for (status_line, method) in &[
+            ("HTTP/1.1 200 OK", http::Method::HEAD),
+            ("HTTP/1.1 204 No Content", http::Method::GET),
+            ("HTTP/1.1 304 Not Modified", http::Method::GET),
+            ("HTTP/1.1 100 Continue", http::Method::POST),
+        ] {
+            let raw = format!("{}\r\nContent-Length: 5\r\n\r\n", status_line);
+            let mut stream = Trickle::new(&[raw.as_bytes(), b"hello"]);
+            let response = read_response(&mut stream, method).await.unwrap();
+            assert!(response.body().is_empty(), "{}", status_line);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_errors() {
+        let mut stream: &[u8] = b"HTTP/1.1 200 OK\r\nContent-";
+        assert!(matches!(
+            read_response(&mut stream, &http::Method::GET).await,
+            Err(Error::IncompleteResponse)
+        ));
+
+        let mut stream: &[u8] = b"HTTTP/1.1 200 OK\r\n\r\n";
+        let error = read_response(&mut stream, &http::Method::GET)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::MalformedResponse(_)), "{:?}", error);
+
+        let mut stream: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: -1\r\n\r\n";
+        assert!(matches!(
+            read_response(&mut stream, &http::Method::GET).await,
+            Err(Error::InvalidContentLength)
+        ));
+
+        let mut stream = Trickle::new(&[b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\nabc"]);
+        assert!(matches!(
+            read_response(&mut stream, &http::Method::GET).await,
+            Err(Error::ContentLengthMismatch)
+        ));
+
+        let mut stream = Trickle::new(&[b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n", b"abc"]);
+        assert!(matches!(
+            read_response(&mut stream, &http::Method::GET).await,
+            Err(Error::ContentLengthMismatch)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_body_too_large() {
+        let chunk = vec![b'x'; 1 << 20];
+        let mut chunks: Vec<&[u8]> = vec![b"HTTP/1.1 200 OK\r\n\r\n"];
+        chunks.extend(std::iter::repeat_n(chunk.as_slice(), 11));
+        let mut stream = Trickle::new(&chunks);
+        assert!(matches!(
+            read_response(&mut stream, &http::Method::GET).await,
+            Err(Error::ResponseBodyTooLarge)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let response = make_http_error(http::StatusCode::BAD_GATEWAY);
+        let mut written = Vec::new();
+        write_response(&response, &mut written).await.unwrap();
+        assert_eq!(
+            String::from_utf8(written.clone()).unwrap(),
+            "HTTP/1.1 502 Bad Gateway\r\ncontent-type: text/plain\r\ncontent-length: 20\r\n\r\n\
+             HTTP 502 Bad Gateway"
+        );
+
+        let read = read_response(&mut written.as_slice(), &http::Method::GET)
+            .await
+            .unwrap();
+        assert_eq!(read.status(), response.status());
+        assert_eq!(read.headers(), response.headers());
+        assert_eq!(read.body(), response.body());
+    }
+}
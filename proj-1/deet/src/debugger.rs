@@ -1,24 +1,34 @@
 use crate::debugger_command::DebuggerCommand;
 use crate::inferior::{Inferior, Status as InferiorStatus};
-use crate::dwarf_data::{DwarfData, Error as DwarfError};
+use crate::dwarf_data::{DwarfData, Error as DwarfError, File};
+use crate::run_log::RunLog;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 pub struct Debugger {
     target: String,
     debug_data: DwarfData,
     history_path: String,
     readline: Editor<()>,
+    // Every breakpoint, by address. Only the enabled ones are planted in the inferior.
     breakpoints: HashMap<usize, Breakpoint>,
+    next_breakpoint_id: usize,
     inferior: Option<Inferior>,
     running: bool,
+    // Source files already warned about being newer than the target
+    stale_files: HashSet<String>,
+    run_log: RunLog,
+    // Number of the run the inferior's output is being recorded under
+    current_run: Option<usize>,
 }
 
 #[derive(Clone)]
 pub struct Breakpoint {
+    pub id: usize, // Number the user refers to the breakpoint by
     pub addr: usize,
-    pub inst: u8,
+    pub inst: u8, // Instruction byte the 0xcc replaced
+    pub enabled: bool,
 }
 
 impl Debugger {
@@ -48,8 +58,12 @@ impl Debugger {
             history_path,
             readline,
             breakpoints: HashMap::new(),
+            next_breakpoint_id: 0,
             inferior: None,
             running: false,
+            stale_files: HashSet::new(),
+            run_log: RunLog::new(),
+            current_run: None,
         }
     }
 
@@ -57,17 +71,29 @@ impl Debugger {
         loop {
             match self.get_next_command() {
                 DebuggerCommand::Run(args) => {
+                    let output = match self.run_log.start_run(&self.target, &args) {
+                        Ok(output) => Some(output),
+                        Err(err) => {
+                            println!("Warning: not recording the output of this run: {}", err);
+                            None
+                        }
+                    };
+                    let number = output.as_ref().map(|output| output.number);
                     if let Some(inferior)
-                            = Inferior::new(&self.target, &args, &mut self.breakpoints) {
+                            = Inferior::new(&self.target, &args, &mut self.breakpoints, output) {
                         // Kill old inferior
                         self.try_kill_inferior();
                         // Bind the inferior
                         self.inferior = Some(inferior);
+                        self.current_run = number;
                         self.running = true;
                         // Run the inferior
                         self.cont_inferior();
                     } else {
                         println!("Error starting subprocess");
+                        if let Some(number) = number {
+                            let _ = self.run_log.finish_run(number, "failed to start");
+                        }
                     }
                 }
                 DebuggerCommand::Quit => {
@@ -77,6 +103,15 @@ impl Debugger {
                 DebuggerCommand::Continue => {
                     self.cont_inferior();
                 }
+                DebuggerCommand::Next => {
+                    self.resume_inferior(Inferior::next);
+                }
+                DebuggerCommand::Step => {
+                    self.resume_inferior(Inferior::step);
+                }
+                DebuggerCommand::Finish => {
+                    self.resume_inferior(Inferior::finish);
+                }
                 DebuggerCommand::Kill => {
                     if self.running {
                         self.try_kill_inferior();
@@ -87,29 +122,76 @@ impl Debugger {
                 DebuggerCommand::Backtrace => {
                     self.print_inferior_backtrace();
                 }
+                DebuggerCommand::Print(name) => {
+                    self.print_value(&name);
+                }
                 DebuggerCommand::Breakpoint(location) => {
                     if let Some(addr) = self.parse_location(&location) {
+                        self.warn_if_stale(addr);
                         self.try_add_breakpoint(addr);
                     } else {
                         println!("Invalid break location format!");
                     }
                 }
+                DebuggerCommand::BreakpointList => {
+                    self.print_breakpoints();
+                }
+                DebuggerCommand::Delete(id) => {
+                    self.delete_breakpoint(id);
+                }
+                DebuggerCommand::Enable(id) => {
+                    self.set_breakpoint_enabled(id, true);
+                }
+                DebuggerCommand::Disable(id) => {
+                    self.set_breakpoint_enabled(id, false);
+                }
+                DebuggerCommand::InfoSource => {
+                    self.print_source_info();
+                }
+                DebuggerCommand::LineTable(func_name) => {
+                    self.print_line_table(func_name);
+                }
+                DebuggerCommand::Runs => {
+                    if let Err(err) = self.run_log.print_runs() {
+                        println!("Error listing runs: {}", err);
+                    }
+                }
+                DebuggerCommand::RunsDiff(a, b) => {
+                    if let Err(err) = self.run_log.print_diff(a, b) {
+                        println!("Error diffing runs: {}", err);
+                    }
+                }
             }
         }
     }
 
     fn cont_inferior(&mut self) {
+        self.resume_inferior(|inferior, _, breakpoints| inferior.cont(breakpoints));
+    }
+
+    /// Lets the inferior run with `resume` (continuing or stepping it), and reports where it
+    /// stopped or how it ended.
+    fn resume_inferior<F>(&mut self, resume: F)
+    where
+        F: FnOnce(&mut Inferior, &DwarfData, &HashMap<usize, Breakpoint>)
+            -> Result<InferiorStatus, nix::Error>,
+    {
         if self.running {
             let inferior = self.inferior.as_mut().unwrap();
-            match inferior.cont(&self.breakpoints) {
+            match resume(inferior, &self.debug_data, &self.breakpoints) {
                 Ok(status) => {
-                    println!("{}", status);
                     match status {
                         InferiorStatus::Exited(_) |
-                        InferiorStatus::Signaled(_) => self.running = false,
+                        InferiorStatus::Signaled(_) => {
+                            self.finish_run(&status);
+                            println!("{}", status);
+                            self.running = false;
+                        }
                         InferiorStatus::Stopped(_, ip) => {
+                            println!("{}", status);
                             print!("Stopped at ");
-                            inferior.try_print_location(&self.debug_data, Some(ip))
+                            self.inferior.as_ref().unwrap()
+                                    .try_print_location(&self.debug_data, Some(ip))
                                     .expect("Error printing stopped location");
                         }
                     }
@@ -129,6 +211,7 @@ impl Debugger {
             println!("Killing running subprocess (pid {})", inferior.pid());
             match inferior.kill() {
                 Ok(status) => {
+                    self.finish_run(&status);
                     println!("{}", status);
                     self.running = false;
                 }
@@ -140,6 +223,19 @@ impl Debugger {
         self.inferior = None;
     }
 
+    /// Waits for the rest of the inferior's output to be recorded, and then records how its run
+    /// ended.
+    fn finish_run(&mut self, status: &InferiorStatus) {
+        if let Some(inferior) = self.inferior.as_mut() {
+            inferior.finish_output();
+        }
+        if let Some(number) = self.current_run.take() {
+            if let Err(err) = self.run_log.finish_run(number, &status.to_string()) {
+                println!("Warning: failed to record how run {} ended: {}", number, err);
+            }
+        }
+    }
+
     fn print_inferior_backtrace(&self) {
         if self.running {
             let inferior = self.inferior.as_ref().unwrap();
@@ -154,6 +250,127 @@ impl Debugger {
         }
     }
 
+    /// Prints the value of a register (when `name` starts with $) or of a variable, as seen from
+    /// where the inferior is stopped.
+    fn print_value(&self, name: &str) {
+        if !self.running {
+            println!("No subprocess running");
+            return;
+        }
+        let inferior = self.inferior.as_ref().unwrap();
+        if name.starts_with('$') {
+            match inferior.read_register(&name[1..]) {
+                Ok(Some(value)) => println!("{} = {:#x}", name, value),
+                Ok(None) => println!("No register named {}", name),
+                Err(err) => println!("Error reading {}: {}", name, err),
+            }
+            return;
+        }
+        let var = match inferior.instruction_pointer() {
+            Ok(rip) => self.debug_data.get_variable(rip, name),
+            Err(err) => {
+                println!("Error reading the instruction pointer: {}", err);
+                return;
+            }
+        };
+        let var = match var {
+            Some(var) => var,
+            None => {
+                println!("No such variable in current frame: {}", name);
+                return;
+            }
+        };
+        match inferior.read_variable(&self.debug_data, var) {
+            Ok(Some(word)) => match var.entity_type.format(word) {
+                Some(value) => println!("{} = ({}) {}", name, var.entity_type.name, value),
+                None => println!("{}: can't print values of type {}", name, var.entity_type.name),
+            },
+            Ok(None) => println!("{}: unsupported location", name),
+            Err(err) => println!("Error reading {}: {}", name, err),
+        }
+    }
+
+    /// Returns the source file the inferior is stopped in, or the first one if it isn't stopped in
+    /// any of them.
+    fn current_file(&self) -> Option<&File> {
+        if self.running {
+            if let Ok(rip) = self.inferior.as_ref().unwrap().instruction_pointer() {
+                if let Some(file) = self.debug_data.get_file_for_addr(rip) {
+                    return Some(file);
+                }
+            }
+        }
+        self.debug_data.get_file(None)
+    }
+
+    fn current_function(&self) -> Option<String> {
+        if self.running {
+            let rip = self.inferior.as_ref().unwrap().instruction_pointer().ok()?;
+            self.debug_data.get_function_from_addr(rip)
+        }
+        else {
+            None
+        }
+    }
+
+    fn print_source_info(&self) {
+        let file = match self.current_file() {
+            Some(file) => file,
+            None => {
+                println!("No source file");
+                return;
+            }
+        };
+        println!("Current source file is {}", file.name);
+        if let Some(comp_dir) = &file.comp_dir {
+            println!("Compilation directory is {}", comp_dir);
+        }
+        println!("Located in {}", file.source_path().display());
+        match &file.producer {
+            Some(producer) => println!("Produced by {}", producer),
+            None => println!("Producer is unknown"),
+        }
+        match file.is_newer_than(&self.target) {
+            Some(true) => println!("Source file is newer than {}; line numbers may be out of date",
+                self.target),
+            Some(false) => println!("Source file is older than {}", self.target),
+            None => println!("Could not compare the source file's modification time with {}",
+                self.target),
+        }
+    }
+
+    fn print_line_table(&self, func_name: Option<String>) {
+        let func_name = match func_name.or_else(|| self.current_function()) {
+            Some(func_name) => func_name,
+            None => {
+                println!("No function given, and no subprocess stopped in one");
+                return;
+            }
+        };
+        match self.debug_data.get_function_lines(&func_name) {
+            Some((func, lines)) => {
+                println!("Line table for {} ({:#x}-{:#x}):",
+                    func.name, func.address, func.address + func.text_length);
+                for line in lines {
+                    println!("  {:#x}  line {}", line.address, line.number);
+                }
+            }
+            None => println!("No function named {}", func_name),
+        }
+    }
+
+    /// Warns the first time a breakpoint lands in a source file modified after the target was
+    /// built, since its line numbers may no longer match the code.
+    fn warn_if_stale(&mut self, addr: usize) {
+        if let Some(file) = self.debug_data.get_file_for_addr(addr) {
+            if file.is_newer_than(&self.target) == Some(true)
+                    && self.stale_files.insert(file.name.clone()) {
+                println!("Warning: {} was modified after {} was built; line numbers may be out of \
+                    date", file.source_path().display(), self.target);
+            }
+        }
+    }
+
     fn parse_location(&self, loc: &str) -> Option<usize> {
         if loc.starts_with("*") {
             let loc = if loc.to_lowercase().starts_with("*0x") {
@@ -177,15 +394,83 @@ impl Debugger {
     }
 
     fn try_add_breakpoint(&mut self, addr: usize) {
-        let mut bp = Breakpoint {addr: addr, inst: 0};
+        if let Some(bp) = self.breakpoints.get(&addr) {
+            println!("Breakpoint {} is already set at {:#x}", bp.id, addr);
+            return;
+        }
+        let mut bp = Breakpoint {id: self.next_breakpoint_id, addr: addr, inst: 0, enabled: true};
         if !self.running || self.inferior.as_mut().unwrap().add_breakpoint(&mut bp) {
+            println!("Set breakpoint {} at {:#x}", bp.id, bp.addr);
+            self.next_breakpoint_id += 1;
             self.breakpoints.insert(addr, bp);
-            println!("Set breakpoint {} at {:#x}",
-                self.breakpoints.len() - 1,
-                self.breakpoints.get(&addr).unwrap().addr);
         }
     }
 
+    /// Returns the address of the breakpoint numbered `id`.
+    fn find_breakpoint(&self, id: usize) -> Option<usize> {
+        let addr = self.breakpoints.values().find(|bp| bp.id == id).map(|bp| bp.addr);
+        if addr.is_none() {
+            println!("No breakpoint number {}", id);
+        }
+        addr
+    }
+
+    fn print_breakpoints(&self) {
+        if self.breakpoints.is_empty() {
+            println!("No breakpoints");
+            return;
+        }
+        let mut breakpoints: Vec<&Breakpoint> = self.breakpoints.values().collect();
+        breakpoints.sort_by_key(|bp| bp.id);
+        println!("Num  Enb  Address             What");
+        for bp in breakpoints {
+            let func = self.debug_data.get_function_from_addr(bp.addr);
+            let line = self.debug_data.get_line_from_addr(bp.addr);
+            let what = match (func, line) {
+                (Some(func), Some(line)) => format!("in {} at {}", func, line),
+                (Some(func), None) => format!("in {}", func),
+                (None, Some(line)) => format!("at {}", line),
+                (None, None) => String::new(),
+            };
+            println!("{:<4} {:<4} {:#018x}  {}",
+                bp.id, if bp.enabled { "y" } else { "n" }, bp.addr, what);
+        }
+    }
+
+    fn delete_breakpoint(&mut self, id: usize) {
+        let addr = match self.find_breakpoint(id) {
+            Some(addr) => addr,
+            None => return,
+        };
+        let bp = &self.breakpoints[&addr];
+        if self.running && bp.enabled && !self.inferior.as_mut().unwrap().remove_breakpoint(bp) {
+            return;
+        }
+        self.breakpoints.remove(&addr);
+        println!("Deleted breakpoint {}", id);
+    }
+
+    fn set_breakpoint_enabled(&mut self, id: usize, enabled: bool) {
+        let addr = match self.find_breakpoint(id) {
+            Some(addr) => addr,
+            None => return,
+        };
+        let bp = self.breakpoints.get_mut(&addr).unwrap();
+        if bp.enabled != enabled && self.running {
+            let inferior = self.inferior.as_mut().unwrap();
+            let changed = if enabled {
+                inferior.add_breakpoint(bp)
+            }
+            else {
+                inferior.remove_breakpoint(bp)
+            };
+            if !changed {
+                return;
+            }
+        }
+        bp.enabled = enabled;
+    }
+
     /// This function prompts the user to enter a command, and continues re-prompting until the user
     /// enters a valid command. It uses DebuggerCommand::from_tokens to do the command parsing.
     ///
@@ -2,9 +2,21 @@ pub enum DebuggerCommand {
     Quit,
     Run(Vec<String>),
     Continue,
+    Next,
+    Step,
+    Finish,
     Kill,
     Backtrace,
+    Print(String),
     Breakpoint(String),
+    BreakpointList,
+    Delete(usize),
+    Enable(usize),
+    Disable(usize),
+    InfoSource,
+    LineTable(Option<String>),
+    Runs,
+    RunsDiff(usize, usize),
 }
 
 impl DebuggerCommand {
@@ -18,12 +30,42 @@ impl DebuggerCommand {
                 ))
             }
             "c" | "cont" | "continue" => Some(DebuggerCommand::Continue),
+            "n" | "next" => Some(DebuggerCommand::Next),
+            "s" | "step" => Some(DebuggerCommand::Step),
+            "fin" | "finish" => Some(DebuggerCommand::Finish),
             "k" | "kill" => Some(DebuggerCommand::Kill),
             "bt" | "back" | "backtrace" => Some(DebuggerCommand::Backtrace),
-            "b" | "break" => {
-                let location = tokens[1];
-                Some(DebuggerCommand::Breakpoint(location.to_string()))
-            }
+            "p" | "print" => Some(DebuggerCommand::Print(tokens.get(1)?.to_string())),
+            "b" | "break" => match tokens.get(1) {
+                Some(&"list") => Some(DebuggerCommand::BreakpointList),
+                Some(location) => Some(DebuggerCommand::Breakpoint(location.to_string())),
+                None => None,
+            },
+            "d" | "delete" => Some(DebuggerCommand::Delete(tokens.get(1)?.parse().ok()?)),
+            "enable" => Some(DebuggerCommand::Enable(tokens.get(1)?.parse().ok()?)),
+            "disable" => Some(DebuggerCommand::Disable(tokens.get(1)?.parse().ok()?)),
+            "info" => match tokens.get(1) {
+                Some(&"source") => Some(DebuggerCommand::InfoSource),
+                Some(&"b") | Some(&"break") | Some(&"breakpoints") => {
+                    Some(DebuggerCommand::BreakpointList)
+                }
+                _ => None,
+            },
+            "mt" | "maint" | "maintenance" => match (tokens.get(1), tokens.get(2)) {
+                (Some(&"info"), Some(&"line-table")) => Some(DebuggerCommand::LineTable(
+                    tokens.get(3).map(|func| func.to_string()),
+                )),
+                _ => None,
+            },
+            "runs" => match tokens.get(1) {
+                None => Some(DebuggerCommand::Runs),
+                Some(&"diff") => {
+                    let a = tokens.get(2)?.parse().ok()?;
+                    let b = tokens.get(3)?.parse().ok()?;
+                    Some(DebuggerCommand::RunsDiff(a, b))
+                }
+                _ => None,
+            },
             // Default case:
             _ => None,
         }
@@ -2,6 +2,7 @@ use crate::gimli_wrapper;
 use addr2line::Context;
 use object::Object;
 use std::convert::TryInto;
+use std::path::{Path, PathBuf};
 use std::{fmt, fs};
 
 #[derive(Debug)]
@@ -87,6 +88,48 @@ impl DwarfData {
         }
     }
 
+    /// Returns the compilation unit for `file`, or the first one if no file is given.
+    pub fn get_file(&self, file: Option<&str>) -> Option<&File> {
+        match file {
+            Some(filename) => self.get_target_file(filename),
+            None => self.files.get(0),
+        }
+    }
+
+    /// Returns the compilation unit containing the code at `addr`.
+    pub fn get_file_for_addr(&self, addr: usize) -> Option<&File> {
+        self.files.iter().find(|file| {
+            file.functions.iter().any(|func| func.contains(addr))
+                || file.lines.iter().any(|line| line.address == addr)
+        })
+    }
+
+    /// Returns the function named `func_name`, along with the line table rows for its code, in the
+    /// order the line program lists them. These are the addresses a breakpoint on one of its lines
+    /// can end up at.
+    pub fn get_function_lines(&self, func_name: &str) -> Option<(&Function, Vec<&Line>)> {
+        self.files.iter().find_map(|file| {
+            let func = file.functions.iter().find(|func| func.name == func_name)?;
+            let lines = file.lines.iter().filter(|line| func.contains(line.address)).collect();
+            Some((func, lines))
+        })
+    }
+
+    /// Returns the variable named `name` as seen from the code at `addr`: one of the variables or
+    /// parameters of the function containing it, or else a global variable.
+    pub fn get_variable(&self, addr: usize, name: &str) -> Option<&Variable> {
+        let local = self.files.iter()
+            .flat_map(|file| file.functions.iter())
+            .filter(|func| func.contains(addr))
+            .flat_map(|func| func.variables.iter())
+            .find(|var| var.name == name);
+        local.or_else(|| {
+            self.files.iter()
+                .flat_map(|file| file.global_variables.iter())
+                .find(|var| var.name == name)
+        })
+    }
+
     #[allow(dead_code)]
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
         let location = self
@@ -148,17 +191,70 @@ impl DwarfData {
     }
 }
 
+/// How values of a type are represented, which decides how they're printed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Signed,
+    Unsigned,
+    SignedChar,
+    UnsignedChar,
+    Bool,
+    Float,
+    Pointer,
+    Other,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Other
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Type {
     pub name: String,
     pub size: usize,
+    pub encoding: Encoding,
 }
 
 impl Type {
-    pub fn new(name: String, size: usize) -> Self {
+    pub fn new(name: String, size: usize, encoding: Encoding) -> Self {
         Type {
             name: name,
             size: size,
+            encoding: encoding,
+        }
+    }
+
+    /// Formats a value of this type, given the word of memory it starts at. Returns None for types
+    /// that can't be printed yet, e.g. structs, or anything bigger than a word.
+    pub fn format(&self, word: u64) -> Option<String> {
+        if self.size == 0 || self.size > 8 {
+            return None;
+        }
+        let shift = 64 - 8 * self.size as u32;
+        let unsigned = (word << shift) >> shift;
+        let signed = ((word << shift) as i64) >> shift;
+        match self.encoding {
+            Encoding::Signed => Some(signed.to_string()),
+            Encoding::Unsigned => Some(unsigned.to_string()),
+            Encoding::SignedChar | Encoding::UnsignedChar => {
+                let num = if self.encoding == Encoding::SignedChar {
+                    signed.to_string()
+                }
+                else {
+                    unsigned.to_string()
+                };
+                Some(format!("{} '{}'", num, (unsigned as u8 as char).escape_default()))
+            }
+            Encoding::Bool => Some((unsigned != 0).to_string()),
+            Encoding::Float => match self.size {
+                4 => Some(f32::from_bits(unsigned as u32).to_string()),
+                8 => Some(f64::from_bits(unsigned).to_string()),
+                _ => None,
+            },
+            Encoding::Pointer => Some(format!("{:#x}", unsigned)),
+            Encoding::Other => None,
         }
     }
 }
@@ -167,6 +263,8 @@ impl Type {
 pub enum Location {
     Address(usize),
     FramePointerOffset(isize),
+    // Optimized out, in a register, or described by an expression we can't evaluate
+    Unsupported,
 }
 
 impl fmt::Display for Location {
@@ -174,6 +272,7 @@ impl fmt::Display for Location {
         match *self {
             Location::Address(addr) => write!(f, "Address({:#x})", addr),
             Location::FramePointerOffset(offset) => write!(f, "FramePointerOffset({})", offset),
+            Location::Unsupported => write!(f, "Unsupported"),
         }
     }
 }
@@ -202,14 +301,43 @@ pub struct Function {
     pub variables: Vec<Variable>,
 }
 
+impl Function {
+    /// Returns whether `addr` falls within this function's code.
+    pub fn contains(&self, addr: usize) -> bool {
+        addr >= self.address && addr < self.address + self.text_length
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct File {
     pub name: String,
+    pub comp_dir: Option<String>, // Directory the compiler was run from
+    pub producer: Option<String>, // Compiler that produced this compilation unit
     pub global_variables: Vec<Variable>,
     pub functions: Vec<Function>,
     pub lines: Vec<Line>,
 }
 
+impl File {
+    /// Returns where the source file should be on disk: its name if that is absolute, or else its
+    /// name relative to the compilation directory.
+    pub fn source_path(&self) -> PathBuf {
+        match &self.comp_dir {
+            Some(dir) => Path::new(dir).join(&self.name),
+            None => PathBuf::from(&self.name),
+        }
+    }
+
+    /// Returns whether the source file was modified after `binary`, which likely means that the
+    /// line numbers recorded for it no longer match what is on disk. Returns None if either of
+    /// the two can't be looked at.
+    pub fn is_newer_than(&self, binary: &str) -> Option<bool> {
+        let source_mtime = fs::metadata(self.source_path()).ok()?.modified().ok()?;
+        let binary_mtime = fs::metadata(binary).ok()?.modified().ok()?;
+        Some(source_mtime > binary_mtime)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Line {
     pub file: String,
@@ -10,7 +10,7 @@ use gimli::{UnitOffset, UnitSectionOffset};
 use object::Object;
 use std::borrow;
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::dwarf_data::{Encoding, File, Function, Line, Location, Type, Variable};
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Write;
@@ -41,6 +41,8 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
 
     // Define a mapping from type offsets to type structs
     let mut offset_to_type: HashMap<usize, Type> = HashMap::new();
+    // Define a mapping from pointer type offsets to the offsets of the types they point to
+    let mut pointee_offsets: HashMap<usize, Option<usize>> = HashMap::new();
 
     let mut compilation_units: Vec<File> = Vec::new();
 
@@ -58,17 +60,12 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
             // Update the variable list for formal params/variables
             match entry.tag() {
                 gimli::DW_TAG_compile_unit => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
-                    } else {
-                        "<unknown>".to_string()
-                    };
+                    let name = get_str_attr(entry, gimli::DW_AT_name, &unit, &dwarf)
+                        .unwrap_or_else(|| "<unknown>".to_string());
                     compilation_units.push(File {
                         name,
+                        comp_dir: get_str_attr(entry, gimli::DW_AT_comp_dir, &unit, &dwarf),
+                        producer: get_str_attr(entry, gimli::DW_AT_producer, &unit, &dwarf),
                         global_variables: Vec::new(),
                         functions: Vec::new(),
                         lines: Vec::new(),
@@ -97,9 +94,44 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                         // TODO: report error?
                         0
                     };
+                    let encoding = match entry.attr_value(gimli::DW_AT_encoding) {
+                        Ok(Some(gimli::AttributeValue::Encoding(encoding))) => match encoding {
+                            gimli::DW_ATE_signed => Encoding::Signed,
+                            gimli::DW_ATE_unsigned | gimli::DW_ATE_UTF => Encoding::Unsigned,
+                            gimli::DW_ATE_signed_char => Encoding::SignedChar,
+                            gimli::DW_ATE_unsigned_char => Encoding::UnsignedChar,
+                            gimli::DW_ATE_boolean => Encoding::Bool,
+                            gimli::DW_ATE_float => Encoding::Float,
+                            _ => Encoding::Other,
+                        },
+                        _ => Encoding::Other,
+                    };
                     let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
+                    offset_to_type.insert(
+                        type_offset,
+                        Type::new(name, byte_size.try_into().unwrap(), encoding),
+                    );
+                }
+                gimli::DW_TAG_pointer_type => {
+                    let byte_size = match entry.attr_value(gimli::DW_AT_byte_size) {
+                        Ok(Some(gimli::AttributeValue::Udata(byte_size))) => byte_size,
+                        _ => 8,
+                    };
+                    // No DW_AT_type means a void pointer
+                    let pointee = match entry.attr(gimli::DW_AT_type) {
+                        Ok(Some(attr)) => match get_attr_value(&attr, &unit, &dwarf) {
+                            Ok(DebugValue::Size(offset)) => Some(offset),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    let type_offset = entry.offset().0;
+                    // The name depends on the pointee, which may come later; see resolve_type
+                    offset_to_type.insert(
+                        type_offset,
+                        Type::new(String::new(), byte_size.try_into().unwrap(), Encoding::Pointer),
+                    );
+                    pointee_offsets.insert(type_offset, pointee);
                 }
                 gimli::DW_TAG_subprogram => {
                     let mut func: Function = Default::default();
@@ -151,9 +183,8 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                             }
                             gimli::DW_AT_type => {
                                 if let Ok(DebugValue::Size(offset)) = val {
-                                    if let Some(dtype) = offset_to_type.get(&offset).clone() {
-                                        entity_type = Some(dtype.clone());
-                                    }
+                                    entity_type =
+                                        resolve_type(offset, &offset_to_type, &pointee_offsets);
                                 }
                             }
                             gimli::DW_AT_location => {
@@ -169,6 +200,11 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                             _ => {}
                         }
                     }
+                    // Locals without a location we understand are kept, so that they can be
+                    // reported as such rather than as missing
+                    if location.is_none() && depth > 1 && !name.is_empty() {
+                        location = Some(Location::Unsupported);
+                    }
                     if entity_type.is_some() && location.is_some() {
                         let var = Variable {
                             name,
@@ -291,6 +327,29 @@ impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
 
 trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
 
+/// Returns the type at `offset`. Pointer types are named after the types they point to, which can
+/// come after them, so their names are only worked out once a variable refers to them.
+fn resolve_type(
+    offset: usize,
+    offset_to_type: &HashMap<usize, Type>,
+    pointee_offsets: &HashMap<usize, Option<usize>>,
+) -> Option<Type> {
+    let mut dtype = offset_to_type.get(&offset)?.clone();
+    if let Some(pointee) = pointee_offsets.get(&offset) {
+        let pointee_name = match pointee {
+            Some(pointee) => resolve_type(*pointee, offset_to_type, pointee_offsets)
+                .map_or("<unknown>".to_string(), |pointee| pointee.name),
+            None => "void".to_string(),
+        };
+        dtype.name = if pointee_name.ends_with('*') {
+            format!("{}*", pointee_name)
+        } else {
+            format!("{} *", pointee_name)
+        };
+    }
+    Some(dtype)
+}
+
 fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
     if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
         let encoding = unit.encoding();
@@ -313,6 +372,20 @@ fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) ->
 }
 
 // based on dwarf_dump.rs
+/// Returns the value of the string attribute `name` of `entry`, if it has one.
+fn get_str_attr<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    name: gimli::DwAt,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<String> {
+    let attr = entry.attr(name).ok()??;
+    match get_attr_value(&attr, unit, dwarf) {
+        Ok(DebugValue::Str(value)) => Some(value),
+        _ => None,
+    }
+}
+
 fn get_attr_value<R: Reader>(
     attr: &gimli::Attribute<R>,
     unit: &gimli::Unit<R>,
@@ -1,13 +1,15 @@
 
-use crate::dwarf_data::DwarfData;
+use crate::dwarf_data::{DwarfData, Line, Location, Variable};
 use crate::debugger::Breakpoint;
+use crate::run_log::{self, RunOutput};
 use nix::sys::ptrace;
 use nix::sys::signal;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
 use std::os::unix::process::CommandExt;
 use std::process::Child;
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::thread::JoinHandle;
 use std::fmt;
 use std::mem::size_of;
 use std::collections::HashMap;
@@ -55,26 +57,45 @@ fn align_addr_to_word(addr: usize) -> usize {
     addr & (-(size_of::<usize>() as isize) as usize)
 }
 
+/// Returns the breakpoint at `addr`, if there is one and it's enabled. Only those are planted in
+/// the inferior.
+fn planted(breakpoints: &HashMap<usize, Breakpoint>, addr: usize) -> Option<&Breakpoint> {
+    breakpoints.get(&addr).filter(|bp| bp.enabled)
+}
+
 pub struct Inferior {
     child: Child,
+    // Threads copying the inferior's output to the terminal and to the run's logs, one per
+    // stream (see run_log::tee for what that means for their order)
+    tees: Vec<JoinHandle<()>>,
 }
 
 impl Inferior {
     /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
-    /// an error is encountered.
-    pub fn new(target: &str, args: &Vec<String>, breakpoints: &mut HashMap<usize, Breakpoint>)
-            -> Option<Inferior> {
-        let child;
+    /// an error is encountered. If `output` is given, whatever the inferior writes to stdout and
+    /// stderr is also copied to its logs.
+    pub fn new(target: &str, args: &Vec<String>, breakpoints: &mut HashMap<usize, Breakpoint>,
+            output: Option<RunOutput>) -> Option<Inferior> {
+        let mut command = Command::new(target);
+        command.args(args);
+        if output.is_some() {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+        let mut child;
         unsafe {
-            child = Command::new(target)
-                .args(args)
+            child = command
                 .pre_exec(child_traceme)
                 .spawn()
                 .ok()?;
         }
-        let mut inferior = Inferior {child};
+        let mut tees = Vec::new();
+        if let Some(output) = output {
+            tees.push(run_log::tee(child.stdout.take().unwrap(), std::io::stdout(), output.stdout));
+            tees.push(run_log::tee(child.stderr.take().unwrap(), std::io::stderr(), output.stderr));
+        }
+        let mut inferior = Inferior {child, tees};
         inferior.wait(None).ok()?;
-        for (_, bp) in breakpoints.iter_mut() {
+        for bp in breakpoints.values_mut().filter(|bp| bp.enabled) {
             inferior.add_breakpoint(bp);
         }
         Some(inferior)
@@ -95,8 +116,23 @@ impl Inferior {
         Ok(orig_byte as u8)
     }
 
+    fn read_byte(&self, addr: usize) -> Result<u8, nix::Error> {
+        let aligned_addr = align_addr_to_word(addr);
+        let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
+        Ok((word >> 8 * (addr - aligned_addr)) as u8)
+    }
+
     pub fn add_breakpoint(&mut self, bp: &mut Breakpoint) -> bool {
-        match self.write_byte(bp.addr, 0xcc) {
+        // The instruction the inferior is stopped at has to stay in place until it's been
+        // stepped over, at which point cont and step plant the breakpoint.
+        let stopped_at = self.instruction_pointer().ok() == Some(bp.addr);
+        let inst = if stopped_at {
+            self.read_byte(bp.addr)
+        }
+        else {
+            self.write_byte(bp.addr, 0xcc)
+        };
+        match inst {
             Ok(inst) => {
                 bp.inst = inst;
                 true
@@ -108,11 +144,28 @@ impl Inferior {
         }
     }
 
+    /// Puts back the instruction a breakpoint replaced. If the inferior is stopped at the
+    /// breakpoint, the instruction is back in place already, so writing it again does no harm.
+    pub fn remove_breakpoint(&mut self, bp: &Breakpoint) -> bool {
+        match self.write_byte(bp.addr, bp.inst) {
+            Ok(_) => true,
+            Err(_) => {
+                println!("Could not remove breakpoint at {:#x}", bp.addr);
+                false
+            }
+        }
+    }
+
     /// Returns the pid of this inferior.
     pub fn pid(&self) -> Pid {
         nix::unistd::Pid::from_raw(self.child.id() as i32)
     }
 
+    /// Returns the instruction pointer this inferior is stopped at.
+    pub fn instruction_pointer(&self) -> Result<usize, nix::Error> {
+        Ok(ptrace::getregs(self.pid())?.rip as usize)
+    }
+
     /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
     /// after the waitpid call.
     pub fn wait(&self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
@@ -130,7 +183,7 @@ impl Inferior {
     pub fn cont(&mut self, breakpoints: &HashMap<usize, Breakpoint>) -> Result<Status, nix::Error> {
         let regs = ptrace::getregs(self.pid())?;
         let rip = regs.rip as usize;
-        if breakpoints.contains_key(&rip) {
+        if planted(breakpoints, rip).is_some() {
             ptrace::step(self.pid(), None)?;
             match self.wait(None)? {
                 Status::Stopped(sign, ip) => {
@@ -145,17 +198,205 @@ impl Inferior {
         }
 
         ptrace::cont(self.pid(), None)?;
-        let status = self.wait(None);
+        let status = self.wait(None)?;
+
+        // There are no registers left to fix up once the inferior has exited
+        if let Status::Stopped(sign, _) = status {
+            let mut regs = ptrace::getregs(self.pid())?;
+            let rip = (regs.rip - 1) as usize;
+            if let Some(bp) = planted(breakpoints, rip) {
+                self.write_byte(rip, bp.inst)?;
+                regs.rip -= 1;
+                ptrace::setregs(self.pid(), regs)?;
+                return Ok(Status::Stopped(sign, rip));
+            }
+        }
 
-        let mut regs = ptrace::getregs(self.pid())?;
-        let rip = (regs.rip - 1) as usize;
-        if breakpoints.contains_key(&rip) {
-            self.write_byte(rip, breakpoints.get(&rip).unwrap().inst)?;
-            regs.rip -= 1;
-            ptrace::setregs(self.pid(), regs)?;
+        Ok(status)
+    }
+
+    /// Executes a single instruction. As with `cont`, a breakpoint at the current instruction is
+    /// stepped over, and if the instruction stepped to has a breakpoint, its original byte is put
+    /// back in place, so that the inferior can be stepped or continued from there.
+    fn step_instruction(&mut self, breakpoints: &HashMap<usize, Breakpoint>)
+            -> Result<Status, nix::Error> {
+        let rip = self.instruction_pointer()?;
+        ptrace::step(self.pid(), None)?;
+        let status = self.wait(None)?;
+        if let Status::Stopped(_, ip) = status {
+            if planted(breakpoints, rip).is_some() {
+                self.write_byte(rip, 0xcc)?;
+            }
+            if let Some(bp) = planted(breakpoints, ip) {
+                self.write_byte(ip, bp.inst)?;
+            }
         }
+        Ok(status)
+    }
+
+    /// Returns whether the instruction at `addr` is a call, either relative (e8) or indirect
+    /// (ff /2), with or without a REX prefix.
+    fn is_call(&self, addr: usize) -> Result<bool, nix::Error> {
+        let word = ptrace::read(self.pid(), addr as ptrace::AddressType)? as u64;
+        let bytes = word.to_le_bytes();
+        let inst = if bytes[0] & 0xf0 == 0x40 { &bytes[1..] } else { &bytes[..] };
+        Ok(inst[0] == 0xe8 || (inst[0] == 0xff && (inst[1] >> 3) & 0x7 == 2))
+    }
 
-        status
+    /// Continues until the inferior gets back to `addr` with its stack pointer at `rsp` or above,
+    /// using a temporary breakpoint. Getting to `addr` with a lower stack pointer means a deeper,
+    /// recursive call got there, so the inferior is continued again. Stops early if the inferior
+    /// hits one of `breakpoints` or gets a signal on the way. The temporary breakpoint never makes
+    /// it into `breakpoints`, and is gone by the time this returns.
+    fn run_to(&mut self, addr: usize, rsp: usize, breakpoints: &HashMap<usize, Breakpoint>)
+            -> Result<Status, nix::Error> {
+        let mut with_temp = breakpoints.clone();
+        if planted(breakpoints, addr).is_none() {
+            let inst = self.write_byte(addr, 0xcc)?;
+            with_temp.insert(addr, Breakpoint {id: usize::MAX, addr, inst, enabled: true});
+        }
+        loop {
+            let status = self.cont(&with_temp)?;
+            match status {
+                Status::Stopped(signal::SIGTRAP, ip) if ip == addr => {
+                    // cont already put the original byte back
+                    if ptrace::getregs(self.pid())?.rsp as usize >= rsp {
+                        return Ok(status);
+                    }
+                }
+                Status::Stopped(..) => {
+                    if planted(breakpoints, addr).is_none() {
+                        self.write_byte(addr, with_temp[&addr].inst)?;
+                    }
+                    return Ok(status);
+                }
+                Status::Exited(_) | Status::Signaled(_) => return Ok(status),
+            }
+        }
+    }
+
+    /// Steps until the inferior gets to a different source line. If `over_calls` is set, or the
+    /// function called has no line information, calls are run through in one go rather than
+    /// stepped into. Leaving the code that has line information altogether, e.g. by returning
+    /// from main, continues the inferior.
+    fn step_line(&mut self, data: &DwarfData, breakpoints: &HashMap<usize, Breakpoint>,
+            over_calls: bool) -> Result<Status, nix::Error> {
+        let same_line = |a: &Line, b: &Line| a.file == b.file && a.number == b.number;
+        let start_line = data.get_line_from_addr(self.instruction_pointer()?);
+        loop {
+            let is_call = self.is_call(self.instruction_pointer()?)?;
+            let mut status = self.step_instruction(breakpoints)?;
+            let ip = match status {
+                Status::Stopped(signal::SIGTRAP, ip) => ip,
+                _ => return Ok(status),
+            };
+            let mut line = data.get_line_from_addr(ip);
+            if is_call && (over_calls || line.is_none()) {
+                // The call just pushed the return address
+                let rsp = ptrace::getregs(self.pid())?.rsp as usize;
+                let ret = ptrace::read(self.pid(), rsp as ptrace::AddressType)? as usize;
+                status = self.run_to(ret, rsp + 8, breakpoints)?;
+                match status {
+                    Status::Stopped(signal::SIGTRAP, ip) if ip == ret => {}
+                    _ => return Ok(status),
+                }
+                // If the call was the last thing on its line, we're already at the start of the
+                // next one
+                line = data.get_line_from_addr(ret);
+            }
+            match (line, &start_line) {
+                (None, _) => return self.cont(breakpoints),
+                (Some(line), Some(start_line)) if same_line(&line, start_line) => {}
+                (Some(_), _) => return Ok(status),
+            }
+        }
+    }
+
+    /// Steps to the next source line, stepping into any function called on the way.
+    pub fn step(&mut self, data: &DwarfData, breakpoints: &HashMap<usize, Breakpoint>)
+            -> Result<Status, nix::Error> {
+        self.step_line(data, breakpoints, false)
+    }
+
+    /// Steps to the next source line, running through any function called on the way.
+    pub fn next(&mut self, data: &DwarfData, breakpoints: &HashMap<usize, Breakpoint>)
+            -> Result<Status, nix::Error> {
+        self.step_line(data, breakpoints, true)
+    }
+
+    /// Continues until the current function returns to its caller.
+    pub fn finish(&mut self, data: &DwarfData, breakpoints: &HashMap<usize, Breakpoint>)
+            -> Result<Status, nix::Error> {
+        let frame_base = self.frame_base(data)?;
+        let ret = ptrace::read(self.pid(), (frame_base - 8) as ptrace::AddressType)? as usize;
+        self.run_to(ret, frame_base, breakpoints)
+    }
+
+    /// Returns the frame base of the function the inferior is stopped in, i.e. the value rsp had
+    /// before the call to it, with the return address just below. gcc's frame base offsets for
+    /// variables are relative to this.
+    fn frame_base(&self, data: &DwarfData) -> Result<usize, nix::Error> {
+        let regs = ptrace::getregs(self.pid())?;
+        let rip = regs.rip as usize;
+        // On the function's first instruction, rbp hasn't been pushed yet and the return address
+        // is on top of the stack. After that, it sits above the saved rbp, as print_backtrace
+        // expects.
+        let entry = data.get_function_from_addr(rip)
+            .and_then(|func| data.get_addr_for_function(None, &func));
+        if entry == Some(rip) {
+            Ok(regs.rsp as usize + 8)
+        }
+        else {
+            Ok(regs.rbp as usize + 16)
+        }
+    }
+
+    /// Returns the value of register `name`, or None if there is no such register.
+    pub fn read_register(&self, name: &str) -> Result<Option<u64>, nix::Error> {
+        let regs = ptrace::getregs(self.pid())?;
+        Ok(Some(match name {
+            "rip" | "pc" => regs.rip,
+            "rsp" | "sp" => regs.rsp,
+            "rbp" | "fp" => regs.rbp,
+            "rax" => regs.rax,
+            "rbx" => regs.rbx,
+            "rcx" => regs.rcx,
+            "rdx" => regs.rdx,
+            "rsi" => regs.rsi,
+            "rdi" => regs.rdi,
+            "r8" => regs.r8,
+            "r9" => regs.r9,
+            "r10" => regs.r10,
+            "r11" => regs.r11,
+            "r12" => regs.r12,
+            "r13" => regs.r13,
+            "r14" => regs.r14,
+            "r15" => regs.r15,
+            "eflags" => regs.eflags,
+            _ => return Ok(None),
+        }))
+    }
+
+    /// Returns the word of memory `var` starts at, in the frame the inferior is stopped in. Returns
+    /// None if where the variable is can't be worked out.
+    pub fn read_variable(&self, data: &DwarfData, var: &Variable)
+            -> Result<Option<u64>, nix::Error> {
+        let addr = match var.location {
+            Location::Address(addr) => addr,
+            Location::FramePointerOffset(offset) => {
+                (self.frame_base(data)? as isize + offset) as usize
+            }
+            Location::Unsupported => return Ok(None),
+        };
+        Ok(Some(ptrace::read(self.pid(), addr as ptrace::AddressType)? as u64))
+    }
+
+    /// Waits until all of the inferior's output has been copied, which is once it has exited and
+    /// closed its stdout and stderr.
+    pub fn finish_output(&mut self) {
+        for tee in self.tees.drain(..) {
+            let _ = tee.join();
+        }
     }
 
     pub fn kill(&mut self) -> Result<Status, nix::Error> {
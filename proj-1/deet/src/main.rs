@@ -3,6 +3,7 @@ mod debugger_command;
 mod inferior;
 mod dwarf_data;
 mod gimli_wrapper;
+mod run_log;
 
 use crate::debugger::Debugger;
 use nix::sys::signal::{signal, SigHandler, Signal};
@@ -0,0 +1,192 @@
+//! Keeps a record of every run of the inferior under `.deet_runs/`: everything it wrote to stdout
+//! and stderr, the command line and environment it was started with, and how it ended. This makes
+//! it possible to compare a failing run against a passing one after the fact.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread;
+
+/// Directory the runs are recorded in, relative to where deet was started.
+const RUNS_DIR: &str = ".deet_runs";
+
+/// Each output log stops growing once it reaches this size. The output still reaches the terminal.
+const MAX_LOG_BYTES: usize = 1 << 20;
+
+/// Only this many of the most recent runs are kept around.
+const MAX_KEPT_RUNS: usize = 20;
+
+/// The logs a new run writes its output to.
+pub struct RunOutput {
+    pub number: usize,
+    pub stdout: fs::File,
+    pub stderr: fs::File,
+}
+
+pub struct RunLog {
+    dir: PathBuf,
+}
+
+impl RunLog {
+    pub fn new() -> RunLog {
+        RunLog {
+            dir: PathBuf::from(RUNS_DIR),
+        }
+    }
+
+    fn info_path(&self, number: usize) -> PathBuf {
+        self.dir.join(format!("run-{}-info.txt", number))
+    }
+
+    fn log_path(&self, number: usize, stream: &str) -> PathBuf {
+        self.dir.join(format!("run-{}-{}.log", number, stream))
+    }
+
+    /// Returns the numbers of the recorded runs, oldest first.
+    fn run_numbers(&self) -> io::Result<Vec<usize>> {
+        let mut numbers = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let name = name.to_string_lossy();
+            if let Some(number) = name
+                .strip_prefix("run-")
+                .and_then(|rest| rest.strip_suffix("-info.txt"))
+                .and_then(|number| number.parse().ok())
+            {
+                numbers.push(number);
+            }
+        }
+        numbers.sort();
+        Ok(numbers)
+    }
+
+    fn remove_run(&self, number: usize) {
+        for path in &[
+            self.info_path(number),
+            self.log_path(number, "stdout"),
+            self.log_path(number, "stderr"),
+        ] {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    /// Records the start of a new run of `target` with `args`, making room for it by removing the
+    /// oldest runs if needed, and returns the logs for its output.
+    pub fn start_run(&self, target: &str, args: &[String]) -> io::Result<RunOutput> {
+        fs::create_dir_all(&self.dir)?;
+        let numbers = self.run_numbers()?;
+        let number = numbers.last().map_or(1, |last| last + 1);
+        let num_stale = (numbers.len() + 1).saturating_sub(MAX_KEPT_RUNS);
+        for stale in &numbers[..num_stale] {
+            self.remove_run(*stale);
+        }
+
+        let mut info = fs::File::create(self.info_path(number))?;
+        writeln!(info, "argv: {}", [&[target.to_string()], args].concat().join(" "))?;
+        for (key, value) in std::env::vars() {
+            writeln!(info, "env: {}={}", key, value)?;
+        }
+        Ok(RunOutput {
+            number,
+            stdout: fs::File::create(self.log_path(number, "stdout"))?,
+            stderr: fs::File::create(self.log_path(number, "stderr"))?,
+        })
+    }
+
+    /// Records how run `number` ended.
+    pub fn finish_run(&self, number: usize, status: &str) -> io::Result<()> {
+        let mut info = fs::OpenOptions::new()
+            .append(true)
+            .open(self.info_path(number))?;
+        writeln!(info, "status: {}", status)
+    }
+
+    /// Prints a one-line summary of each recorded run.
+    pub fn print_runs(&self) -> io::Result<()> {
+        let numbers = match self.run_numbers() {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            other => other?,
+        };
+        if numbers.is_empty() {
+            println!("No runs recorded");
+        }
+        for number in numbers {
+            let info = fs::read_to_string(self.info_path(number))?;
+            let field = |name: &str| {
+                info.lines()
+                    .find_map(|line| line.strip_prefix(name))
+                    .map(|value| value.trim().to_string())
+            };
+            let count_lines = |stream| {
+                fs::read(self.log_path(number, stream))
+                    .map_or(0, |log| log.iter().filter(|byte| **byte == b'\n').count())
+            };
+            println!(
+                "run {}: {} ({}, {} lines of stdout, {} lines of stderr)",
+                number,
+                field("argv:").unwrap_or_default(),
+                field("status:").unwrap_or_else(|| "no exit status recorded".to_string()),
+                count_lines("stdout"),
+                count_lines("stderr")
+            );
+        }
+        Ok(())
+    }
+
+    /// Prints the differences between the output of runs `a` and `b`, using `diff`.
+    pub fn print_diff(&self, a: usize, b: usize) -> io::Result<()> {
+        for number in &[a, b] {
+            if !self.info_path(*number).exists() {
+                println!("No run {} recorded", number);
+                return Ok(());
+            }
+        }
+        for stream in &["stdout", "stderr"] {
+            println!("{}:", stream);
+            // diff exits with 1 when the files differ, which is what we're after.
+            Command::new("diff")
+                .arg("-u")
+                .arg(self.log_path(a, stream))
+                .arg(self.log_path(b, stream))
+                .status()?;
+        }
+        Ok(())
+    }
+}
+
+/// Copies everything read from `from` to `terminal` as it arrives, and to `log` until it reaches
+/// MAX_LOG_BYTES, on a thread of its own. The thread finishes once `from` is closed.
+///
+/// Each stream keeps the order it was written in, but stdout and stderr go through pipes and
+/// threads of their own, so on the terminal their output may interleave differently than the
+/// inferior wrote it: a line written to stderr right after one to stdout can show up first.
+/// Keeping the two in order would take a single pipe for both, and then they couldn't be logged
+/// separately.
+pub fn tee<R, W>(mut from: R, mut terminal: W, mut log: fs::File) -> thread::JoinHandle<()>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        let mut logged = 0;
+        loop {
+            let len = match from.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            let _ = terminal.write_all(&buf[..len]).and_then(|_| terminal.flush());
+            if logged < MAX_LOG_BYTES {
+                let to_log = len.min(MAX_LOG_BYTES - logged);
+                let _ = log.write_all(&buf[..to_log]);
+                logged += to_log;
+                if logged == MAX_LOG_BYTES {
+                    let _ = writeln!(log, "\n[deet: log truncated at {} bytes]", MAX_LOG_BYTES);
+                }
+            }
+        }
+    })
+}
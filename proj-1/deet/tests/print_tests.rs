@@ -0,0 +1,119 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Builds `sample` from samples/, runs deet on it with `commands` as input, and returns everything
+/// deet printed.
+fn run_deet(sample: &str, commands: &[&str]) -> String {
+    let dir = env!("CARGO_MANIFEST_DIR");
+    let sample = format!("samples/{}", sample);
+    let status = Command::new("make")
+        .arg(&sample)
+        .current_dir(dir)
+        .status()
+        .expect("Could not run make");
+    assert!(status.success(), "Could not build {}", sample);
+
+    let mut deet = Command::new(env!("CARGO_BIN_EXE_deet"))
+        .arg(&sample)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Could not start deet");
+    let input = format!("{}\nquit\n", commands.join("\n"));
+    deet.stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = deet.wait_with_output().expect("deet failed");
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+#[test]
+fn test_print_locals_and_globals() {
+    let output = run_deet(
+        "function_calls",
+        &[
+            "print a",
+            "break func2",
+            "run",
+            "next",
+            "next",
+            "next",
+            "print a",
+            "print b",
+            "print sum",
+            "print global",
+            "print nope",
+        ],
+    );
+    assert!(output.contains("No subprocess running"), "{}", output);
+    assert!(output.contains("a = (int) 42"), "{}", output);
+    assert!(output.contains("b = (int) 5"), "{}", output);
+    assert!(output.contains("sum = (int) 47"), "{}", output);
+    assert!(output.contains("global = (int) 5"), "{}", output);
+    assert!(
+        output.contains("No such variable in current frame: nope"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn test_print_pointers_and_registers() {
+    let output = run_deet(
+        "sleepy_print",
+        &[
+            "break main",
+            "run 1",
+            "next",
+            "print argc",
+            "print argv",
+            "next",
+            "print num_seconds",
+            "print $rip",
+            "print $bogus",
+        ],
+    );
+    assert!(output.contains("argc = (int) 2"), "{}", output);
+    assert!(output.contains("argv = (char **) 0x"), "{}", output);
+    assert!(
+        output.contains("num_seconds = (long unsigned int) 1"),
+        "{}",
+        output
+    );
+    assert!(output.contains("$rip = 0x"), "{}", output);
+    assert!(output.contains("No register named $bogus"), "{}", output);
+}
+
+#[test]
+fn test_next_over_void_call() {
+    let output = run_deet(
+        "sleepy_print",
+        &[
+            "break main",
+            "run 2",
+            "next",
+            "next",
+            "next",
+            "next",
+            "print i",
+            "next",
+            "print i",
+            "next",
+            "print i",
+        ],
+    );
+    assert!(output.contains("sleepy_print.c:13"), "{}", output);
+    // sleep(1) is the last thing on line 13, so it returns to the start of the loop's increment,
+    // where `next` has to stop before running any of it...
+    assert_eq!(
+        output.matches("i = (long unsigned int) 0").count(),
+        2,
+        "{}",
+        output
+    );
+    // ...and the one after that runs the increment and the condition, back to the loop's body.
+    assert!(output.contains("i = (long unsigned int) 1"), "{}", output);
+}
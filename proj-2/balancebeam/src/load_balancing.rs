@@ -0,0 +1,232 @@
+use rand::Rng;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// How an upstream is picked for each new client connection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    /// Any live upstream, at random
+    Random,
+    /// Each live upstream in turn, in the order they were given on the command line
+    RoundRobin,
+    /// The live upstream with the fewest client connections open, breaking ties at random
+    LeastConnections,
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Strategy, String> {
+        match name {
+            "random" => Ok(Strategy::Random),
+            "round-robin" => Ok(Strategy::RoundRobin),
+            "least-connections" => Ok(Strategy::LeastConnections),
+            _ => Err(format!(
+                "Unknown load balancing strategy {} (expected random, round-robin or \
+                least-connections)",
+                name
+            )),
+        }
+    }
+}
+
+/// Picks upstreams according to a Strategy, and keeps track of what it needs to across
+/// connections.
+pub struct Balancer {
+    strategy: Strategy,
+    /// Every upstream, in the order round-robin goes through them. The list of live upstreams is
+    /// reordered as they die and come back, so the rotation can't be kept as an index into it.
+    rotation: Vec<String>,
+    /// Index in `rotation` of the next upstream round-robin picks, if it's alive
+    next: Mutex<usize>,
+    /// Number of client connections currently bound to each upstream
+    active: Mutex<HashMap<String, usize>>,
+}
+
+impl Balancer {
+    pub fn new(strategy: Strategy, upstreams: &[String]) -> Balancer {
+        Balancer {
+            strategy,
+            rotation: upstreams.to_vec(),
+            next: Mutex::new(0),
+            active: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Picks one of the `alive` upstreams, and returns its index. Returns None if there are none.
+    pub fn pick(&self, alive: &[String]) -> Option<usize> {
+        if alive.is_empty() {
+            return None;
+        }
+        let mut rng = rand::thread_rng();
+        match self.strategy {
+            Strategy::Random => Some(rng.gen_range(0, alive.len())),
+            Strategy::RoundRobin => {
+                let mut next = self.next.lock().unwrap();
+                for offset in 0..self.rotation.len() {
+                    let candidate = (*next + offset) % self.rotation.len();
+                    if let Some(idx) = alive
+                        .iter()
+                        .position(|addr| *addr == self.rotation[candidate])
+                    {
+                        *next = candidate + 1;
+                        return Some(idx);
+                    }
+                }
+                // None of the live upstreams are in the rotation, which can't happen as long as
+                // upstreams only come from the command line.
+                Some(0)
+            }
+            Strategy::LeastConnections => {
+                let active = self.active.lock().unwrap();
+                let count = |addr: &String| active.get(addr).copied().unwrap_or(0);
+                let fewest = alive.iter().map(count).min()?;
+                let idle: Vec<usize> = (0..alive.len())
+                    .filter(|idx| count(&alive[*idx]) == fewest)
+                    .collect();
+                Some(idle[rng.gen_range(0, idle.len())])
+            }
+        }
+    }
+
+    /// Counts a client connection as bound to `upstream` until the returned ActiveConnection is
+    /// dropped, however the connection ends.
+    pub fn connected(&self, upstream: &str) -> ActiveConnection<'_> {
+        *self
+            .active
+            .lock()
+            .unwrap()
+            .entry(upstream.to_string())
+            .or_insert(0) += 1;
+        ActiveConnection {
+            balancer: self,
+            upstream: upstream.to_string(),
+        }
+    }
+
+    /// Returns the number of client connections currently bound to `upstream`.
+    #[cfg(test)]
+    fn num_active(&self, upstream: &str) -> usize {
+        self.active
+            .lock()
+            .unwrap()
+            .get(upstream)
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// A client connection bound to an upstream, which the Balancer counts until this is dropped
+pub struct ActiveConnection<'a> {
+    balancer: &'a Balancer,
+    upstream: String,
+}
+
+impl ActiveConnection<'_> {
+    /// Address of the upstream the connection is bound to
+    pub fn upstream(&self) -> &str {
+        &self.upstream
+    }
+}
+
+impl Drop for ActiveConnection<'_> {
+    fn drop(&mut self) {
+        let mut active = self.balancer.active.lock().unwrap();
+        if let Some(count) = active.get_mut(&self.upstream) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.upstream);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn addrs(addrs: &[&str]) -> Vec<String> {
+        addrs.iter().map(|addr| addr.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_strategy() {
+        assert_eq!("random".parse(), Ok(Strategy::Random));
+        assert_eq!("round-robin".parse(), Ok(Strategy::RoundRobin));
+        assert_eq!("least-connections".parse(), Ok(Strategy::LeastConnections));
+        assert!("weighted"
+            .parse::<Strategy>()
+            .unwrap_err()
+            .contains("weighted"));
+    }
+
+    #[test]
+    fn test_random() {
+        let upstreams = addrs(&["a:80", "b:80", "c:80"]);
+        let balancer = Balancer::new(Strategy::Random, &upstreams);
+        assert_eq!(balancer.pick(&[]), None);
+        let mut picked = [0; 3];
+        for _ in 0..300 {
+            picked[balancer.pick(&upstreams).unwrap()] += 1;
+        }
+        assert!(picked.iter().all(|count| *count > 0), "{:?}", picked);
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let upstreams = addrs(&["a:80", "b:80", "c:80"]);
+        let balancer = Balancer::new(Strategy::RoundRobin, &upstreams);
+        let picked: Vec<usize> = (0..6).map(|_| balancer.pick(&upstreams).unwrap()).collect();
+        assert_eq!(picked, vec![0, 1, 2, 0, 1, 2]);
+        assert_eq!(balancer.pick(&[]), None);
+    }
+
+    #[test]
+    fn test_round_robin_survives_reordering() {
+        let upstreams = addrs(&["a:80", "b:80", "c:80", "d:80"]);
+        let balancer = Balancer::new(Strategy::RoundRobin, &upstreams);
+        let pick = |alive: &[String]| alive[balancer.pick(alive).unwrap()].clone();
+        assert_eq!(pick(&upstreams), "a:80");
+        // b died and got swap_removed, so d took its place.
+        let alive = addrs(&["a:80", "d:80", "c:80"]);
+        assert_eq!(pick(&alive), "c:80");
+        assert_eq!(pick(&alive), "d:80");
+        assert_eq!(pick(&alive), "a:80");
+        // b came back at the end, and is picked in its turn.
+        let alive = addrs(&["a:80", "d:80", "c:80", "b:80"]);
+        assert_eq!(pick(&alive), "b:80");
+        assert_eq!(pick(&alive), "c:80");
+    }
+
+    #[test]
+    fn test_least_connections() {
+        let upstreams = addrs(&["a:80", "b:80", "c:80"]);
+        let balancer = Balancer::new(Strategy::LeastConnections, &upstreams);
+        let first = balancer.connected("a:80");
+        let _second = balancer.connected("a:80");
+        let _third = balancer.connected("b:80");
+        assert_eq!(balancer.pick(&upstreams), Some(2));
+        assert_eq!(balancer.num_active("a:80"), 2);
+
+        drop(first);
+        let _fourth = balancer.connected("c:80");
+        // a, b and c have one connection apiece, so they're all tied.
+        let mut picked = [0; 3];
+        for _ in 0..300 {
+            picked[balancer.pick(&upstreams).unwrap()] += 1;
+        }
+        assert!(picked.iter().all(|count| *count > 0), "{:?}", picked);
+    }
+
+    #[test]
+    fn test_connections_counted_until_dropped() {
+        let balancer = Balancer::new(Strategy::LeastConnections, &addrs(&["a:80"]));
+        {
+            let conn = balancer.connected("a:80");
+            assert_eq!(conn.upstream(), "a:80");
+            assert_eq!(balancer.num_active("a:80"), 1);
+        }
+        assert_eq!(balancer.num_active("a:80"), 0);
+    }
+}
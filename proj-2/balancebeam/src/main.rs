@@ -1,12 +1,14 @@
-mod request;
-mod response;
+mod load_balancing;
+mod source_addr;
 
 use clap::Parser;
-use rand::{Rng, SeedableRng};
+use load_balancing::{ActiveConnection, Balancer, Strategy};
+use mini_http::{request, response};
+use source_addr::SourceAddrs;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{RwLock, Mutex};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::collections::{HashSet, HashMap};
 use futures::stream::{self, StreamExt};
 
@@ -42,6 +44,41 @@ struct CmdOptions {
         default_value = "0"
     )]
     max_requests_per_minute: usize,
+    #[clap(
+        long,
+        multiple_occurrences = true,
+        about = "Local address to connect to upstreams from (at most one IPv4 and one IPv6)"
+    )]
+    upstream_bind_addr: Vec<std::net::IpAddr>,
+    #[clap(
+        long,
+        about = "Maximum number of response body bytes to send per IP per hour (0 = unlimited)",
+        default_value = "0"
+    )]
+    max_bytes_per_hour_per_ip: u64,
+    #[clap(
+        long,
+        about = "HTTP status to respond with once an IP has used up its bytes for the hour",
+        default_value = "403"
+    )]
+    quota_exceeded_status: u16,
+    #[clap(
+        long,
+        about = "How to pick an upstream for each connection: random, round-robin or \
+        least-connections",
+        default_value = "random"
+    )]
+    load_balancing: Strategy,
+}
+
+/// How long a client's transfer quota lasts before it's refilled
+const TRANSFER_QUOTA_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Response body bytes sent to each client in the current transfer quota window
+struct TransferCounter {
+    /// When the current window started
+    window_start: SystemTime,
+    bytes_sent: HashMap<String, u64>,
 }
 
 /// Contains information about the state of balancebeam (e.g. what servers we are currently proxying
@@ -61,6 +98,16 @@ struct ProxyState {
     dead_addresses: RwLock<Vec<String>>,
     /// Requests per minutes
     requests_counter: Mutex<HashMap<String, usize>>,
+    /// Local addresses to connect to upstreams from, for both proxied requests and health checks
+    upstream_source: SourceAddrs,
+    /// Maximum number of response body bytes an individual IP can be sent in an hour
+    max_bytes_per_hour_per_ip: u64,
+    /// Status to respond with once an IP has been sent its bytes for the hour
+    quota_exceeded_status: http::StatusCode,
+    /// Response body bytes sent per IP this hour
+    bytes_counter: Mutex<TransferCounter>,
+    /// Picks the upstream for each client connection
+    balancer: Balancer,
 }
 
 #[tokio::main]
@@ -80,6 +127,24 @@ async fn main() {
         std::process::exit(1);
     }
 
+    let upstream_source = match SourceAddrs::new(&options.upstream_bind_addr)
+        .and_then(|source| source.check_local().map(|()| source))
+    {
+        Ok(source) => source,
+        Err(err) => {
+            log::error!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let quota_exceeded_status = match http::StatusCode::from_u16(options.quota_exceeded_status) {
+        Ok(status) => status,
+        Err(_) => {
+            log::error!("Invalid --quota-exceeded-status {}", options.quota_exceeded_status);
+            std::process::exit(1);
+        }
+    };
+
     // Start listening for connections
     let mut listener = match TcpListener::bind(&options.bind).await {
         Ok(listener) => listener,
@@ -92,12 +157,20 @@ async fn main() {
 
     // Handle incoming connections
     let state = Arc::new(ProxyState {
+        balancer: Balancer::new(options.load_balancing, &options.upstream),
         upstream_addresses: RwLock::new(options.upstream),
         dead_addresses: RwLock::new(Vec::new()),
         active_health_check_interval: options.active_health_check_interval,
         active_health_check_path: options.active_health_check_path,
         requests_counter: Mutex::new(HashMap::new()),
         max_requests_per_minute: options.max_requests_per_minute,
+        upstream_source,
+        max_bytes_per_hour_per_ip: options.max_bytes_per_hour_per_ip,
+        quota_exceeded_status,
+        bytes_counter: Mutex::new(TransferCounter {
+            window_start: SystemTime::now(),
+            bytes_sent: HashMap::new(),
+        }),
     });
 
     let state_ref = state.clone();
@@ -112,6 +185,13 @@ async fn main() {
         });
     }
 
+    if options.max_bytes_per_hour_per_ip > 0 {
+        let state_ref = state.clone();
+        tokio::spawn(async move {
+            bytes_counter_timer(&state_ref, TRANSFER_QUOTA_WINDOW).await;
+        });
+    }
+
     while let Some(Ok(stream)) = listener.next().await {
         let state_ref = state.clone();
         tokio::spawn(async move {
@@ -128,24 +208,75 @@ async fn requests_counter_timer(state: &ProxyState, duration: Duration) {
     }
 }
 
-async fn get_random_upstream(state: &ProxyState) -> Option<(usize, String)> {
-    let upstream = state.upstream_addresses.read().await;
-    if upstream.len() > 0 {
-        let mut rng = rand::rngs::StdRng::from_entropy();
-        let upstream_idx = rng.gen_range(0, upstream.len());
-        let upstream_ip = &upstream[upstream_idx];
-        Some((upstream_idx, upstream_ip.to_string()))
-    } else {
-        None
+async fn bytes_counter_timer(state: &ProxyState, duration: Duration) {
+    loop {
+        tokio::time::delay_for(duration).await;
+        let mut counter = state.bytes_counter.lock().await;
+        counter.bytes_sent.clear();
+        counter.window_start = SystemTime::now();
+        log::debug!("Bytes counter reset!");
     }
 }
 
-async fn delete_upstream(state: &ProxyState, idx: usize) {
+/// If `client_ip` has already been sent its bytes for the hour, returns the response to send it
+/// instead of forwarding its request: a JSON description of the quota and when it's refilled.
+async fn check_transfer_quota(
+    state: &ProxyState,
+    client_ip: &str,
+) -> Option<http::Response<Vec<u8>>> {
+    let counter = state.bytes_counter.lock().await;
+    let bytes_sent = *counter.bytes_sent.get(client_ip)?;
+    if bytes_sent < state.max_bytes_per_hour_per_ip {
+        return None;
+    }
+    let reset_at = counter.window_start + TRANSFER_QUOTA_WINDOW;
+    let body = format!(
+        "{{\"error\":\"transfer quota exceeded\",\"limit_bytes\":{},\"used_bytes\":{},\
+        \"window_secs\":{},\"reset_at\":{},\"reset_in_secs\":{}}}",
+        state.max_bytes_per_hour_per_ip,
+        bytes_sent,
+        TRANSFER_QUOTA_WINDOW.as_secs(),
+        reset_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        reset_at
+            .duration_since(SystemTime::now())
+            .unwrap_or_default()
+            .as_secs(),
+    )
+    .into_bytes();
+    Some(
+        http::Response::builder()
+            .status(state.quota_exceeded_status)
+            .header("Content-Type", "application/json")
+            .header("Content-Length", body.len().to_string())
+            .version(http::Version::HTTP_11)
+            .body(body)
+            .unwrap(),
+    )
+}
+
+/// Counts the body of a response forwarded from an upstream against `client_ip`'s transfer quota.
+/// Responses balancebeam makes up itself, such as errors and refusals, don't count.
+async fn count_transfer(state: &ProxyState, client_ip: &str, response: &http::Response<Vec<u8>>) {
+    if state.max_bytes_per_hour_per_ip > 0 {
+        let mut counter = state.bytes_counter.lock().await;
+        *counter.bytes_sent.entry(client_ip.to_string()).or_insert(0) +=
+            response.body().len() as u64;
+    }
+}
+
+async fn pick_upstream(state: &ProxyState) -> Option<String> {
+    let upstream = state.upstream_addresses.read().await;
+    let upstream_idx = state.balancer.pick(&upstream)?;
+    Some(upstream[upstream_idx].to_string())
+}
+
+/// Moves `ip` to the dead upstreams, where the active health checks can bring it back from. Does
+/// nothing if another connection has already done so.
+async fn delete_upstream(state: &ProxyState, ip: &str) {
     let mut upstream = state.upstream_addresses.write().await;
     let mut dead = state.dead_addresses.write().await;
-    if idx < upstream.len() {
-        dead.push(std::mem::take(&mut upstream[idx]));
-        upstream.swap_remove(idx);
+    if let Some(idx) = upstream.iter().position(|addr| addr == ip) {
+        dead.push(upstream.swap_remove(idx));
     }
 }
 
@@ -156,10 +287,10 @@ async fn is_alive(state: &ProxyState, ip: &String) -> Option<()> {
         .header("Host", ip)
         .body(Vec::new())
         .unwrap();
-    let mut stream = TcpStream::connect(ip).await.ok()?;
+    let mut stream = state.upstream_source.connect(ip).await.ok()?;
 
-    request::write_to_stream(&request, &mut stream).await.ok()?;
-    if response::read_from_stream(&mut stream, &http::Method::GET)
+    request::write_request(&request, &mut stream).await.ok()?;
+    if response::read_response(&mut stream, &http::Method::GET)
         .await
         .ok()?
         .status() == http::StatusCode::OK {
@@ -203,13 +334,17 @@ async fn active_health_check(state: &ProxyState) {
     }
 }
 
-async fn connect_to_upstream(state: &ProxyState) -> Result<TcpStream, std::io::Error> {
+/// Connects to an upstream picked by the load balancing strategy. The connection counts as bound to
+/// that upstream until the returned ActiveConnection is dropped.
+async fn connect_to_upstream(
+    state: &ProxyState,
+) -> Result<(TcpStream, ActiveConnection<'_>), std::io::Error> {
     loop {
-        if let Some((idx, ip)) = get_random_upstream(state).await {
-            match TcpStream::connect(ip).await {
-                Ok(stream) => return Ok(stream),
+        if let Some(ip) = pick_upstream(state).await {
+            match state.upstream_source.connect(&ip).await {
+                Ok(stream) => return Ok((stream, state.balancer.connected(&ip))),
                 Err(_) => {
-                    delete_upstream(state, idx).await;
+                    delete_upstream(state, &ip).await;
                 }
             }
         } else {
@@ -221,10 +356,45 @@ async fn connect_to_upstream(state: &ProxyState) -> Result<TcpStream, std::io::E
     }
 }
 
+/// Why a request couldn't be forwarded to an upstream
+#[derive(Debug)]
+enum ForwardError {
+    /// Couldn't send the request
+    Write(std::io::Error),
+    /// Couldn't read back a response
+    Read(response::Error),
+}
+
+impl ForwardError {
+    /// Whether the upstream seems to have died, as opposed to having sent back something invalid
+    fn upstream_died(&self) -> bool {
+        match self {
+            ForwardError::Write(_) => true,
+            ForwardError::Read(response::Error::IncompleteResponse)
+            | ForwardError::Read(response::Error::ConnectionError(_)) => true,
+            ForwardError::Read(_) => false,
+        }
+    }
+}
+
+/// Sends `request` to the upstream and reads back its response.
+async fn forward_request(
+    request: &http::Request<Vec<u8>>,
+    upstream_conn: &mut TcpStream,
+) -> Result<http::Response<Vec<u8>>, ForwardError> {
+    request::write_request(request, upstream_conn)
+        .await
+        .map_err(ForwardError::Write)?;
+    log::debug!("Forwarded request to server");
+    response::read_response(upstream_conn, request.method())
+        .await
+        .map_err(ForwardError::Read)
+}
+
 async fn send_response(client_conn: &mut TcpStream, response: &http::Response<Vec<u8>>) {
     let client_ip = client_conn.peer_addr().unwrap().ip().to_string();
     log::info!("{} <- {}", client_ip, response::format_response_line(&response));
-    if let Err(error) = response::write_to_stream(&response, client_conn).await {
+    if let Err(error) = response::write_response(&response, client_conn).await {
         log::warn!("Failed to send response to client: {}", error);
         return;
     }
@@ -234,22 +404,21 @@ async fn handle_connection(mut client_conn: TcpStream, state: &ProxyState) {
     let client_ip = client_conn.peer_addr().unwrap().ip().to_string();
     log::info!("Connection received from {}", client_ip);
 
-    // Open a connection to a random destination server
-    let mut upstream_conn = match connect_to_upstream(state).await {
-        Ok(stream) => stream,
+    // Open a connection to a destination server
+    let (mut upstream_conn, mut upstream) = match connect_to_upstream(state).await {
+        Ok(connection) => connection,
         Err(_error) => {
             let response = response::make_http_error(http::StatusCode::BAD_GATEWAY);
             send_response(&mut client_conn, &response).await;
             return;
         }
     };
-    let upstream_ip = client_conn.peer_addr().unwrap().ip().to_string();
 
     // The client may now send us one or more requests. Keep trying to read requests until the
     // client hangs up or we get an error.
     loop {
         // Read a request from the client
-        let mut request = match request::read_from_stream(&mut client_conn).await {
+        let mut request = match request::read_request(&mut client_conn).await {
             Ok(request) => request,
             // Handle case where client closed connection and is no longer sending requests
             Err(request::Error::IncompleteRequest(0)) => {
@@ -278,7 +447,7 @@ async fn handle_connection(mut client_conn: TcpStream, state: &ProxyState) {
         log::info!(
             "{} -> {}: {}",
             client_ip,
-            upstream_ip,
+            upstream.upstream(),
             request::format_request_line(&request)
         );
 
@@ -290,7 +459,15 @@ async fn handle_connection(mut client_conn: TcpStream, state: &ProxyState) {
             if *count > state.max_requests_per_minute {
                 let response = response::make_http_error(http::StatusCode::TOO_MANY_REQUESTS);
                 send_response(&mut client_conn, &response).await;
-                // response::write_to_stream(&response, &mut client_conn).await.unwrap();
+                // response::write_response(&response, &mut client_conn).await.unwrap();
+                return;
+            }
+        }
+
+        if state.max_bytes_per_hour_per_ip > 0 {
+            if let Some(response) = check_transfer_quota(state, &client_ip).await {
+                log::debug!("{} has used up its transfer quota", client_ip);
+                send_response(&mut client_conn, &response).await;
                 return;
             }
         }
@@ -300,26 +477,47 @@ async fn handle_connection(mut client_conn: TcpStream, state: &ProxyState) {
         // upstream server will only know our IP, not the client's.)
         request::extend_header_value(&mut request, "x-forwarded-for", &client_ip);
 
-        // Forward the request to the server
-        if let Err(error) = request::write_to_stream(&request, &mut upstream_conn).await {
-            log::error!("Failed to send request to upstream {}: {}", upstream_ip, error);
-            let response = response::make_http_error(http::StatusCode::BAD_GATEWAY);
-            send_response(&mut client_conn, &response).await;
-            return;
-        }
-        log::debug!("Forwarded request to server");
-
-        // Read the server's response
-        let response = match response::read_from_stream(&mut upstream_conn, request.method()).await {
-            Ok(response) => response,
-            Err(error) => {
-                log::error!("Error reading response from server: {:?}", error);
+        // Forward the request to the server. If it has died, mark it dead and replay the request
+        // (which already has its X-Forwarded-For) on another one, trying at most as many upstreams
+        // as are alive right now.
+        let mut attempts_left = state.upstream_addresses.read().await.len().max(1);
+        let response = loop {
+            let error = match forward_request(&request, &mut upstream_conn).await {
+                Ok(response) => break Some(response),
+                Err(error) => error,
+            };
+            log::error!(
+                "Failed to forward request to upstream {}: {:?}",
+                upstream.upstream(),
+                error
+            );
+            if !error.upstream_died() {
+                break None;
+            }
+            delete_upstream(state, upstream.upstream()).await;
+            attempts_left -= 1;
+            if attempts_left == 0 {
+                break None;
+            }
+            match connect_to_upstream(state).await {
+                Ok((new_conn, new_upstream)) => {
+                    log::info!("Retrying request on upstream {}", new_upstream.upstream());
+                    upstream_conn = new_conn;
+                    upstream = new_upstream;
+                }
+                Err(_) => break None,
+            }
+        };
+        let response = match response {
+            Some(response) => response,
+            None => {
                 let response = response::make_http_error(http::StatusCode::BAD_GATEWAY);
                 send_response(&mut client_conn, &response).await;
                 return;
             }
         };
         // Forward the response to the client
+        count_transfer(state, &client_ip, &response).await;
         send_response(&mut client_conn, &response).await;
         log::debug!("Forwarded response to client");
     }
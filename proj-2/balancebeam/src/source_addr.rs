@@ -0,0 +1,161 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::TcpStream;
+
+/// Local addresses to connect to upstreams from, at most one per address family. On a host with
+/// several network interfaces, this picks the one that connections to upstreams go out of.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SourceAddrs {
+    v4: Option<IpAddr>,
+    v6: Option<IpAddr>,
+}
+
+impl SourceAddrs {
+    /// Sorts `addrs` by address family. Returns an error if there's more than one of either.
+    pub fn new(addrs: &[IpAddr]) -> Result<SourceAddrs, String> {
+        let mut source = SourceAddrs::default();
+        for addr in addrs {
+            let slot = if addr.is_ipv4() {
+                &mut source.v4
+            } else {
+                &mut source.v6
+            };
+            if let Some(existing) = slot {
+                return Err(format!(
+                    "Only one upstream bind address per address family is allowed, but got both {} \
+                    and {}",
+                    existing, addr
+                ));
+            }
+            *slot = Some(*addr);
+        }
+        Ok(source)
+    }
+
+    /// Makes sure every address is one this host has, by binding a socket to it. This way, a
+    /// mistyped address fails at startup, rather than on every connection to an upstream.
+    pub fn check_local(&self) -> Result<(), String> {
+        for addr in self.v4.iter().chain(self.v6.iter()) {
+            bind_socket(*addr).map_err(|err| {
+                format!("Could not bind to upstream bind address {}: {}", addr, err)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The address to connect to `upstream` from, if there is one for its address family.
+    pub fn for_upstream(&self, upstream: &SocketAddr) -> Option<IpAddr> {
+        match upstream {
+            SocketAddr::V4(_) => self.v4,
+            SocketAddr::V6(_) => self.v6,
+        }
+    }
+
+    /// Connects to `upstream` (anything `TcpStream::connect` takes, like "host:port"), from the
+    /// source address for each of its addresses' family. Addresses of a family without a source
+    /// address are connected to from wherever the OS picks.
+    pub async fn connect(&self, upstream: &str) -> io::Result<TcpStream> {
+        if self.v4.is_none() && self.v6.is_none() {
+            return TcpStream::connect(upstream).await;
+        }
+        let mut last_error = None;
+        for upstream_addr in tokio::net::lookup_host(upstream).await? {
+            let result = match self.for_upstream(&upstream_addr) {
+                Some(source) => connect_from(source, upstream_addr).await,
+                None => TcpStream::connect(upstream_addr).await,
+            };
+            match result {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_error = Some(err),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} did not resolve to any addresses", upstream),
+            )
+        }))
+    }
+}
+
+/// A TCP socket bound to `addr`, on a port the OS picks.
+fn bind_socket(addr: IpAddr) -> io::Result<socket2::Socket> {
+    let addr = SocketAddr::new(addr, 0);
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(addr),
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::TCP),
+    )?;
+    socket.bind(&addr.into())?;
+    Ok(socket)
+}
+
+/// Connects to `upstream` from `source`. The socket has to be bound before connecting, which
+/// tokio's `TcpStream` has no way to do, so this connects a blocking socket on the blocking pool
+/// and hands it to tokio once connected.
+async fn connect_from(source: IpAddr, upstream: SocketAddr) -> io::Result<TcpStream> {
+    let stream = tokio::task::spawn_blocking(move || -> io::Result<std::net::TcpStream> {
+        let socket = bind_socket(source)?;
+        socket.connect(&upstream.into())?;
+        socket.set_nonblocking(true)?;
+        Ok(socket.into())
+    })
+    .await
+    .map_err(|err| io::Error::other(err))??;
+    TcpStream::from_std(stream)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_one_per_family() {
+        let v4: IpAddr = "10.1.2.3".parse().unwrap();
+        let v6: IpAddr = "fd00::1".parse().unwrap();
+        let source = SourceAddrs::new(&[v6, v4]).unwrap();
+        assert_eq!(
+            source.for_upstream(&"192.168.0.5:80".parse().unwrap()),
+            Some(v4)
+        );
+        assert_eq!(
+            source.for_upstream(&"[fd00::5]:80".parse().unwrap()),
+            Some(v6)
+        );
+
+        let source = SourceAddrs::new(&[v4]).unwrap();
+        assert_eq!(source.for_upstream(&"[fd00::5]:80".parse().unwrap()), None);
+
+        let error = SourceAddrs::new(&[v4, "10.9.9.9".parse().unwrap()]).unwrap_err();
+        assert!(
+            error.contains("10.1.2.3") && error.contains("10.9.9.9"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_check_local() {
+        assert!(SourceAddrs::new(&["127.0.0.1".parse().unwrap()])
+            .unwrap()
+            .check_local()
+            .is_ok());
+        // Reserved for documentation, so no host has it.
+        let error = SourceAddrs::new(&["192.0.2.1".parse().unwrap()])
+            .unwrap()
+            .check_local()
+            .unwrap_err();
+        assert!(error.contains("192.0.2.1"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_connect_from_source() {
+        let mut listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = listener.local_addr().unwrap().to_string();
+        let source = SourceAddrs::new(&["127.0.0.1".parse().unwrap()]).unwrap();
+        let stream = source.connect(&upstream).await.unwrap();
+        let (_, peer) = listener.accept().await.unwrap();
+        assert_eq!(peer, stream.local_addr().unwrap());
+        assert_eq!(peer.ip(), "127.0.0.1".parse::<IpAddr>().unwrap());
+    }
+}
@@ -99,3 +99,55 @@ async fn test_multiple_requests_per_connection() {
 
     log::info!("All done :)");
 }
+
+/// Connections to the upstream can be made from a given local address, and requests still go
+/// through.
+#[tokio::test]
+async fn test_upstream_bind_addr() {
+    init_logging();
+    let upstream = EchoServer::new().await;
+    let balancebeam =
+        BalanceBeam::with_args(&[&upstream.address], &["--upstream-bind-addr", "127.0.0.1"]).await;
+
+    let response_text = balancebeam
+        .get("/bound")
+        .await
+        .expect("Error sending request to balancebeam");
+    assert!(response_text.contains("GET /bound HTTP/1.1"));
+
+    let num_requests_received = Box::new(upstream).stop().await;
+    assert_eq!(num_requests_received, 1);
+}
+
+/// A request with a chunked body is passed on whole, with a Content-Length instead of the chunking
+#[tokio::test]
+async fn test_chunked_request() {
+    use tokio::io::AsyncWriteExt;
+
+    let (balancebeam, upstream) = setup().await;
+
+    let mut stream = tokio::net::TcpStream::connect(&balancebeam.address)
+        .await
+        .expect("Error connecting to balancebeam");
+    stream
+        .write_all(b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nHello ")
+        .await
+        .unwrap();
+    // The rest of the body comes separately, in chunks with extensions.
+    stream
+        .write_all(b"\r\n6;ext=1\r\nworld!\r\n0\r\n\r\n")
+        .await
+        .unwrap();
+    let response = mini_http::read_response(&mut stream, &http::Method::POST)
+        .await
+        .expect("Error reading response from balancebeam");
+    assert_eq!(response.status(), http::StatusCode::OK);
+    let response_text = String::from_utf8(response.body().to_vec()).unwrap();
+    assert!(response_text.contains("POST /upload HTTP/1.1"));
+    assert!(response_text.contains("content-length: 12"));
+    assert!(!response_text.contains("transfer-encoding"));
+    assert!(response_text.contains("\n\nHello world!"));
+
+    let num_requests_received = Box::new(upstream).stop().await;
+    assert_eq!(num_requests_received, 1);
+}
@@ -80,6 +80,50 @@ async fn test_load_distribution() {
     log::info!("All done :)");
 }
 
+/// Select round-robin load balancing and ensure the requests are spread exactly evenly across the
+/// upstream servers
+#[tokio::test]
+async fn test_round_robin() {
+    init_logging();
+    let n_upstreams = 3;
+    let n_requests = 30;
+    let mut upstreams: Vec<Box<dyn Server>> = Vec::new();
+    for _ in 0..n_upstreams {
+        upstreams.push(Box::new(EchoServer::new().await));
+    }
+    let upstream_addresses: Vec<String> = upstreams
+        .iter()
+        .map(|upstream| upstream.address())
+        .collect();
+    let upstream_addresses: Vec<&str> = upstream_addresses
+        .iter()
+        .map(|addr| addr.as_str())
+        .collect();
+    let balancebeam =
+        BalanceBeam::with_args(&upstream_addresses, &["--load-balancing", "round-robin"]).await;
+
+    for i in 0..n_requests {
+        let path = format!("/request-{}", i);
+        let response_text = balancebeam
+            .get(&path)
+            .await
+            .expect("Error sending request to balancebeam");
+        assert!(response_text.contains(&format!("GET {} HTTP/1.1", path)));
+    }
+
+    let mut request_counters = Vec::new();
+    while let Some(upstream) = upstreams.pop() {
+        request_counters.insert(0, upstream.stop().await);
+    }
+    log::info!(
+        "Number of requests received by each upstream: {:?}",
+        request_counters
+    );
+    assert_eq!(request_counters, vec![n_requests / n_upstreams; n_upstreams]);
+
+    log::info!("All done :)");
+}
+
 async fn try_failover(balancebeam: &BalanceBeam, upstreams: &mut Vec<Box<dyn Server>>) {
     // Send some initial requests. Everything should work
     log::info!("Sending some initial requests. These should definitely work.");
@@ -131,6 +175,64 @@ async fn test_passive_health_checks() {
     log::info!("All done :)");
 }
 
+/// Make sure passive failover also works on a connection that is being kept alive: send a request,
+/// kill the upstream the connection is bound to, and make sure later requests on the same
+/// connection go through to the other upstream
+#[tokio::test]
+async fn test_passive_failover_keep_alive() {
+    init_logging();
+    let mut upstreams: Vec<Box<dyn Server>> = Vec::new();
+    for _ in 0..2 {
+        upstreams.push(Box::new(EchoServer::new().await));
+    }
+    let upstream_addresses: Vec<String> = upstreams
+        .iter()
+        .map(|upstream| upstream.address())
+        .collect();
+    let upstream_addresses: Vec<&str> = upstream_addresses
+        .iter()
+        .map(|addr| addr.as_str())
+        .collect();
+    // Round-robin, so that the first connection is bound to the first upstream.
+    let balancebeam =
+        BalanceBeam::with_args(&upstream_addresses, &["--load-balancing", "round-robin"]).await;
+
+    // The client keeps its connection to balancebeam open between requests.
+    let client = reqwest::Client::new();
+    let get = |path: String| {
+        client
+            .get(&format!("http://{}{}", balancebeam.address, path))
+            .header("x-sent-by", "balancebeam-tests")
+            .send()
+    };
+    let response = get("/before".to_string())
+        .await
+        .expect("Error sending request to balancebeam");
+    assert_eq!(response.status().as_u16(), 200);
+
+    log::info!("Killing the upstream the connection is bound to");
+    assert_eq!(upstreams.remove(0).stop().await, 1);
+
+    for i in 0..4 {
+        let path = format!("/after-{}", i);
+        let response = get(path.clone())
+            .await
+            .expect("Error sending request to balancebeam");
+        assert_eq!(
+            response.status().as_u16(),
+            200,
+            "Request on the kept-alive connection failed. Passive failover may not be working."
+        );
+        let response_text = response.text().await.unwrap();
+        assert!(response_text.contains(&format!("GET {} HTTP/1.1", path)));
+        // The header was only added once, even if the request was replayed.
+        assert_eq!(response_text.matches("x-forwarded-for").count(), 1);
+    }
+    assert_eq!(upstreams.pop().unwrap().stop().await, 4);
+
+    log::info!("All done :)");
+}
+
 /// Verify that the active health checks are monitoring HTTP status, rather than simply depending
 /// on whether connections can be established to determine whether an upstream is up:
 ///
@@ -294,3 +396,64 @@ async fn test_rate_limiting() {
 
     log::info!("All done :)");
 }
+
+/// Enable transfer quotas and ensure requests are refused, without reaching the upstream, once a
+/// client has been sent its bytes for the hour
+#[tokio::test]
+async fn test_transfer_quota() {
+    init_logging();
+    let quota: usize = 1000;
+    let upstream = EchoServer::new().await;
+    let balancebeam = BalanceBeam::with_args(
+        &[&upstream.address],
+        &["--max-bytes-per-hour-per-ip", &quota.to_string()],
+    )
+    .await;
+
+    log::info!("Downloading the same body until the quota runs out");
+    let client = reqwest::Client::new();
+    let mut body_len = None;
+    let mut num_served = 0;
+    let quota_response = loop {
+        let response = client
+            .get(&format!("http://{}/download", balancebeam.address))
+            .header("x-sent-by", "balancebeam-tests")
+            .send()
+            .await
+            .expect("Error sending request to balancebeam");
+        if response.status().as_u16() != 200 {
+            break response;
+        }
+        // Every request is the same, so every echoed body is the same size.
+        let body = response.text().await.unwrap();
+        assert_eq!(*body_len.get_or_insert(body.len()), body.len());
+        num_served += 1;
+        assert!(num_served <= quota, "The quota never ran out");
+    };
+    let body_len = body_len.expect("Not even the first request got through");
+
+    log::info!("Checking the cutoff point and the quota response");
+    // Requests go through until the bytes sent reach the quota, so the last one may overshoot it.
+    assert_eq!(num_served, quota.div_ceil(body_len));
+    assert_eq!(quota_response.status().as_u16(), 403);
+    let quota_text = quota_response.text().await.unwrap();
+    assert!(quota_text.contains(&format!("\"limit_bytes\":{}", quota)));
+    assert!(quota_text.contains(&format!("\"used_bytes\":{}", num_served * body_len)));
+    assert!(quota_text.contains("\"reset_in_secs\":"));
+
+    log::info!("Checking that refusals don't count against the quota");
+    let response = client
+        .get(&format!("http://{}/download", balancebeam.address))
+        .header("x-sent-by", "balancebeam-tests")
+        .send()
+        .await
+        .expect("Error sending request to balancebeam");
+    assert_eq!(response.status().as_u16(), 403);
+    let quota_text = response.text().await.unwrap();
+    assert!(quota_text.contains(&format!("\"used_bytes\":{}", num_served * body_len)));
+
+    log::info!("Ensuring the refused requests didn't go through to the upstream server");
+    assert_eq!(Box::new(upstream).stop().await, num_served);
+
+    log::info!("All done :)");
+}
@@ -24,6 +24,21 @@ impl BalanceBeam {
         active_health_check_interval: Option<usize>,
         max_requests_per_minute: Option<usize>,
     ) -> BalanceBeam {
+        let mut args = Vec::new();
+        if let Some(active_health_check_interval) = active_health_check_interval {
+            args.push(String::from("--active-health-check-interval"));
+            args.push(active_health_check_interval.to_string());
+        }
+        if let Some(max_requests_per_minute) = max_requests_per_minute {
+            args.push(String::from("--max-requests-per-minute"));
+            args.push(max_requests_per_minute.to_string());
+        }
+        BalanceBeam::with_args(upstreams, &args).await
+    }
+
+    /// Starts balancebeam in front of `upstreams`, passing it `args` as well.
+    #[allow(dead_code)]
+    pub async fn with_args<S: AsRef<str>>(upstreams: &[&str], args: &[S]) -> BalanceBeam {
         let mut rng = rand::thread_rng();
         let address = format!("127.0.0.1:{}", rng.gen_range(1024, 65535));
         let mut cmd = Command::new(BalanceBeam::target_bin_path());
@@ -31,13 +46,8 @@ impl BalanceBeam {
         for upstream in upstreams {
             cmd.arg("--upstream").arg(upstream);
         }
-        if let Some(active_health_check_interval) = active_health_check_interval {
-            cmd.arg("--active-health-check-interval")
-                .arg(active_health_check_interval.to_string());
-        }
-        if let Some(max_requests_per_minute) = max_requests_per_minute {
-            cmd.arg("--max-requests-per-minute")
-                .arg(max_requests_per_minute.to_string());
+        for arg in args {
+            cmd.arg(arg.as_ref());
         }
         cmd.kill_on_drop(true);
         cmd.stdout(std::process::Stdio::piped());
@@ -0,0 +1,76 @@
+//! Graded checks for the warmup, run by `cargo run -p exercises --bin grade`.
+
+use crate::set_ops::{difference, intersection, union};
+use crate::{add_n, add_n_inplace, dedup, dedup_by_key};
+use grading::{expect_eq, Registry};
+
+fn strings(words: &[&str]) -> Vec<String> {
+    words.iter().map(|word| String::from(*word)).collect()
+}
+
+pub fn register(registry: &mut Registry) {
+    registry.register_fn("warmup::add_n_empty", || {
+        expect_eq(add_n(Vec::<i32>::new(), 5), vec![])
+    });
+    registry.register_fn("warmup::add_n_negative", || {
+        expect_eq(add_n(vec![-3, 0, 3], -3), vec![-6, -3, 0])
+    });
+    registry.register_fn("warmup::add_n_leaves_input_order", || {
+        expect_eq(add_n(vec![9, 1, 5], 1), vec![10, 2, 6])
+    });
+    registry.register_fn("warmup::add_n_inplace_empty", || {
+        let mut v: Vec<u8> = Vec::new();
+        add_n_inplace(&mut v, 1);
+        expect_eq(v, vec![])
+    });
+    registry.register_fn("warmup::add_n_inplace_floats", || {
+        let mut v = vec![0.5, -0.5];
+        add_n_inplace(&mut v, 1.0);
+        expect_eq(v, vec![1.5, 0.5])
+    });
+    registry.register_fn("warmup::dedup_already_unique", || {
+        let mut v = vec![5, 3, 9, 1];
+        dedup(&mut v);
+        expect_eq(v, vec![5, 3, 9, 1])
+    });
+    registry.register_fn("warmup::dedup_single", || {
+        let mut v = vec![42];
+        dedup(&mut v);
+        expect_eq(v, vec![42])
+    });
+    registry.register_fn("warmup::dedup_non_adjacent_strings", || {
+        let mut v = strings(&["x", "y", "x", "z", "y", "x"]);
+        dedup(&mut v);
+        expect_eq(v, strings(&["x", "y", "z"]))
+    });
+    registry.register_fn("warmup::dedup_by_key_keeps_first_spelling", || {
+        let mut v = strings(&["Rust", "rust", "RUST", "Go", "go"]);
+        dedup_by_key(&mut v, |word| word.to_lowercase());
+        expect_eq(v, strings(&["Rust", "Go"]))
+    });
+    registry.register_fn("warmup::dedup_by_key_empty", || {
+        let mut v: Vec<i32> = Vec::new();
+        dedup_by_key(&mut v, |_| 0);
+        expect_eq(v, vec![])
+    });
+    registry.register_fn("warmup::set_ops_both_empty", || {
+        let empty: [i32; 0] = [];
+        expect_eq(intersection(&empty, &empty), vec![])?;
+        expect_eq(union(&empty, &empty), vec![])?;
+        expect_eq(difference(&empty, &empty), vec![])
+    });
+    registry.register_fn("warmup::set_ops_one_empty", || {
+        expect_eq(intersection(&[], &[1, 2]), vec![])?;
+        expect_eq(union(&[], &[2, 1, 2]), vec![2, 1])?;
+        expect_eq(difference(&[1, 1, 2], &[]), vec![1, 2])
+    });
+    registry.register_fn("warmup::intersection_follows_a", || {
+        expect_eq(intersection(&[3, 2, 1], &[1, 2, 3]), vec![3, 2, 1])
+    });
+    registry.register_fn("warmup::union_appends_new_from_b", || {
+        expect_eq(union(&[1, 2], &[2, 3, 3, 1, 4]), vec![1, 2, 3, 4])
+    });
+    registry.register_fn("warmup::difference_with_itself", || {
+        expect_eq(difference(&[4, 4, 2], &[4, 4, 2]), vec![])
+    });
+}
@@ -0,0 +1,114 @@
+/* The following exercises were borrowed from Will Crichton's CS 242 Rust lab. */
+
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::ops::{Add, AddAssign};
+
+pub mod checks;
+pub mod set_ops;
+
+/// Returns a new vector with `n` added to each element of `v`.
+pub fn add_n<T: Add<Output = T> + Copy>(v: Vec<T>, n: T) -> Vec<T> {
+    let mut new_v: Vec<T> = Vec::with_capacity(v.len());
+    for e in v.iter() {
+        new_v.push(*e + n);
+    }
+    new_v
+}
+
+/// Adds `n` to each element of `v`.
+pub fn add_n_inplace<T: AddAssign + Copy>(v: &mut [T], n: T) {
+    for e in v.iter_mut() {
+        *e += n;
+    }
+}
+
+/// Removes every element that equals one before it, keeping the first occurrence of each in
+/// place. Only the set of elements seen so far takes extra memory; `v` keeps its buffer.
+pub fn dedup<T: Eq + Hash + Clone>(v: &mut Vec<T>) {
+    let mut set: HashSet<T> = HashSet::new();
+    v.retain(|e| set.insert(e.clone()));
+}
+
+/// Like `dedup`, but treats elements as duplicates when `key` maps them to the same value.
+pub fn dedup_by_key<T, K, F>(v: &mut Vec<T>, mut key: F)
+where
+    K: Eq + Hash,
+    F: FnMut(&T) -> K,
+{
+    let mut set: HashSet<K> = HashSet::new();
+    v.retain(|e| set.insert(key(e)));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_add_n() {
+        assert_eq!(add_n(vec![1], 2), vec![3]);
+        assert_eq!(add_n(vec![0.5, 1.5], 0.25), vec![0.75, 1.75]);
+        assert_eq!(add_n(Vec::<u8>::new(), 1), vec![]);
+    }
+
+    #[test]
+    fn test_add_n_inplace() {
+        let mut v = vec![1];
+        add_n_inplace(&mut v, 2);
+        assert_eq!(v, vec![3]);
+
+        let mut v: Vec<u64> = vec![10, 20, 30];
+        add_n_inplace(&mut v, 5);
+        assert_eq!(v, vec![15, 25, 35]);
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut v = vec![3, 1, 0, 1, 4, 4];
+        dedup(&mut v);
+        assert_eq!(v, vec![3, 1, 0, 4]);
+    }
+
+    #[test]
+    fn test_dedup_strings() {
+        let mut v: Vec<String> = vec!["b", "a", "b", "c", "a"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        dedup(&mut v);
+        assert_eq!(v, vec!["b", "a", "c"]);
+    }
+
+    #[test]
+    fn test_dedup_edge_cases() {
+        let mut empty: Vec<i32> = Vec::new();
+        dedup(&mut empty);
+        assert_eq!(empty, vec![]);
+
+        let mut same = vec![7; 10];
+        dedup(&mut same);
+        assert_eq!(same, vec![7]);
+    }
+
+    #[test]
+    fn test_dedup_keeps_buffer() {
+        let mut v: Vec<i32> = Vec::with_capacity(100);
+        v.extend((0..50).chain(0..50));
+        let buffer = v.as_ptr();
+        dedup(&mut v);
+        assert_eq!(v, (0..50).collect::<Vec<i32>>());
+        assert_eq!(v.capacity(), 100);
+        assert_eq!(v.as_ptr(), buffer);
+    }
+
+    #[test]
+    fn test_dedup_by_key() {
+        let mut words = vec!["apple", "avocado", "banana", "blueberry", "cherry"];
+        dedup_by_key(&mut words, |word| word.chars().next());
+        assert_eq!(words, vec!["apple", "banana", "cherry"]);
+
+        let mut nums = vec![1, -1, 2, -2, 3];
+        dedup_by_key(&mut nums, |num: &i32| num.abs());
+        assert_eq!(nums, vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,109 @@
+/* Follow-on exercises: set operations over slices. Each one runs in O(n + m) time, treats its
+ * inputs as sets (so duplicates within an input only show up once in the output), and keeps
+ * elements in the order they first appear in `a`. */
+
+use std::collections::HashSet;
+use std::hash::Hash;
+
+/// Returns the elements of `a` that are also in `b`.
+pub fn intersection<T: Eq + Hash + Clone>(a: &[T], b: &[T]) -> Vec<T> {
+    let in_b: HashSet<&T> = b.iter().collect();
+    let mut seen: HashSet<&T> = HashSet::new();
+    a.iter()
+        .filter(|e| in_b.contains(e) && seen.insert(e))
+        .cloned()
+        .collect()
+}
+
+/// Returns the elements of `a`, followed by the elements of `b` that aren't in `a`, in the order
+/// they first appear in `b`.
+pub fn union<T: Eq + Hash + Clone>(a: &[T], b: &[T]) -> Vec<T> {
+    let mut seen: HashSet<&T> = HashSet::new();
+    a.iter()
+        .chain(b.iter())
+        .filter(|e| seen.insert(e))
+        .cloned()
+        .collect()
+}
+
+/// Returns the elements of `a` that aren't in `b`.
+pub fn difference<T: Eq + Hash + Clone>(a: &[T], b: &[T]) -> Vec<T> {
+    let mut seen: HashSet<&T> = b.iter().collect();
+    a.iter().filter(|e| seen.insert(e)).cloned().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_intersection() {
+        assert_eq!(intersection(&[3, 1, 4, 1, 5], &[5, 1, 9]), vec![1, 5]);
+        assert_eq!(intersection(&[1, 1, 1], &[1, 1]), vec![1]);
+        assert_eq!(intersection(&[1, 2], &[3, 4]), vec![]);
+        assert_eq!(intersection(&[2, 7, 2, 1], &[2, 7, 2, 1]), vec![2, 7, 1]);
+        assert_eq!(intersection::<i32>(&[], &[1]), vec![]);
+    }
+
+    #[test]
+    fn test_union() {
+        assert_eq!(union(&[3, 1, 4, 1], &[5, 1, 9, 5]), vec![3, 1, 4, 5, 9]);
+        assert_eq!(union(&[1, 2], &[3, 4]), vec![1, 2, 3, 4]);
+        assert_eq!(union(&[2, 7, 2, 1], &[2, 7, 2, 1]), vec![2, 7, 1]);
+        assert_eq!(union::<i32>(&[], &[]), vec![]);
+    }
+
+    #[test]
+    fn test_difference() {
+        assert_eq!(difference(&[3, 1, 4, 1, 5], &[5, 9]), vec![3, 1, 4]);
+        assert_eq!(difference(&[1, 2, 2], &[3, 4]), vec![1, 2]);
+        assert_eq!(difference(&[2, 7, 2, 1], &[2, 7, 2, 1]), vec![]);
+        assert_eq!(difference(&[1, 2], &[]), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_strings() {
+        let a: Vec<String> = vec!["pear", "fig", "pear", "kiwi"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let b: Vec<String> = vec!["kiwi", "plum", "fig"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(intersection(&a, &b), vec!["fig", "kiwi"]);
+        assert_eq!(union(&a, &b), vec!["pear", "fig", "kiwi", "plum"]);
+        assert_eq!(difference(&a, &b), vec!["pear"]);
+    }
+
+    fn distinct(v: &[u8]) -> usize {
+        v.iter().collect::<HashSet<_>>().len()
+    }
+
+    proptest! {
+        #[test]
+        fn union_len_is_bounded(a: Vec<u8>, b: Vec<u8>) {
+            let len = union(&a, &b).len();
+            prop_assert!(len >= distinct(&a).max(distinct(&b)));
+            prop_assert!(len <= distinct(&a) + distinct(&b));
+        }
+
+        #[test]
+        fn intersection_is_subset_of_a(a: Vec<u8>, b: Vec<u8>) {
+            let in_a: HashSet<&u8> = a.iter().collect();
+            let in_b: HashSet<&u8> = b.iter().collect();
+            for e in intersection(&a, &b).iter() {
+                prop_assert!(in_a.contains(e) && in_b.contains(e));
+            }
+        }
+
+        #[test]
+        fn difference_and_intersection_split_a(a: Vec<u8>, b: Vec<u8>) {
+            prop_assert_eq!(
+                difference(&a, &b).len() + intersection(&a, &b).len(),
+                distinct(&a)
+            );
+        }
+    }
+}
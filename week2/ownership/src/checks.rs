@@ -0,0 +1,121 @@
+//! Graded checks for the borrow-checker exercises, run by `cargo run -p exercises --bin grade`.
+//! Where the UI tests compare the compiler's whole output, these only look at which errors it
+//! reports, so a problem that fails for some other reason (or a newer compiler that words things
+//! differently) is told apart from one the borrow checker really rejects.
+
+use grading::Registry;
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output};
+
+fn ui_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/ui")
+}
+
+/// The names of the `.rs` files in `dir`, without the extension, sorted.
+fn programs(dir: &Path) -> Result<Vec<String>, String> {
+    let entries = fs::read_dir(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+    let mut names = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(|err| err.to_string())?.path();
+        if path.extension().is_some_and(|ext| ext == "rs") {
+            names.push(path.file_stem().unwrap().to_string_lossy().into_owned());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// The codes of the errors in compiler output, e.g. `E0502` for `error[E0502]: cannot borrow...`.
+fn error_codes(output: &str) -> BTreeSet<String> {
+    output
+        .split("error[")
+        .skip(1)
+        .filter_map(|rest| rest.split(']').next())
+        .map(String::from)
+        .collect()
+}
+
+/// Compiles the program at `source` into `out` with whatever else is in `args`.
+fn rustc(source: &Path, out: &Path, args: &[&str]) -> Result<Output, String> {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    Command::new(&rustc)
+        .args(["--edition", "2018", "-o"])
+        .arg(out)
+        .args(args)
+        .arg(source)
+        .output()
+        .map_err(|err| format!("couldn't run {}: {}", rustc, err))
+}
+
+/// Where the programs get compiled to, one directory per run of the grader.
+fn out_dir() -> Result<PathBuf, String> {
+    let dir = env::temp_dir().join(format!("ownership-checks-{}", process::id()));
+    fs::create_dir_all(&dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+    Ok(dir)
+}
+
+/// Checks that the problem `name` is rejected with the same errors as in its `.stderr`.
+fn check_rejected(name: &str) -> Result<(), String> {
+    let expected = fs::read_to_string(ui_dir().join(format!("{}.stderr", name)))
+        .map_err(|err| format!("no expected errors: {}", err))?;
+    let source = ui_dir().join(format!("{}.rs", name));
+    let output = rustc(&source, &out_dir()?.join(name), &["--emit=metadata"])?;
+    if output.status.success() {
+        return Err(String::from("compiled, but shouldn't have"));
+    }
+    let expected = error_codes(&expected);
+    let actual = error_codes(&String::from_utf8_lossy(&output.stderr));
+    if actual != expected {
+        return Err(format!("expected errors {:?}, got {:?}", expected, actual));
+    }
+    Ok(())
+}
+
+/// Checks that the fix for the problem `name` compiles, and runs successfully.
+fn check_fix_runs(name: &str) -> Result<(), String> {
+    let source = ui_dir().join("fixed").join(format!("{}.rs", name));
+    let binary = out_dir()?.join(format!("{}-fixed", name));
+    let output = rustc(&source, &binary, &[])?;
+    if !output.status.success() {
+        return Err(format!(
+            "doesn't compile: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    let status = Command::new(&binary)
+        .status()
+        .map_err(|err| format!("couldn't run it: {}", err));
+    let _ = fs::remove_file(&binary);
+    match status? {
+        status if status.success() => Ok(()),
+        status => Err(format!("ran, but {}", status)),
+    }
+}
+
+pub fn register(registry: &mut Registry) {
+    registry.register_fn("ownership::every_problem_has_a_fix", || {
+        let problems = programs(&ui_dir())?;
+        let fixes = programs(&ui_dir().join("fixed"))?;
+        if problems.is_empty() {
+            return Err(String::from("no problems found"));
+        }
+        if problems != fixes {
+            return Err(format!("problems {:?}, but fixes {:?}", problems, fixes));
+        }
+        Ok(())
+    });
+    // A problem that's missing its fix still gets its own checks; it just fails the one above.
+    let problems = programs(&ui_dir()).unwrap_or_default();
+    for name in problems {
+        let rejected = name.clone();
+        registry.register_fn(&format!("ownership::{}_is_rejected", name), move || {
+            check_rejected(&rejected)
+        });
+        registry.register_fn(&format!("ownership::{}_fix_runs", name), move || {
+            check_fix_runs(&name)
+        });
+    }
+}
@@ -0,0 +1,10 @@
+//! Borrow-checker exercises. Each problem is a small program under `tests/ui/`, checked in along
+//! with the errors the compiler gives for it, and `tests/ui/fixed/` holds a version of each that
+//! compiles and runs. `cargo test` checks both, so the exercises can't drift from what the compiler
+//! actually says about them.
+//!
+//! To add a problem, drop `problemN_<what_goes_wrong>.rs` into `tests/ui/` and its fix into
+//! `tests/ui/fixed/`, then run `TRYBUILD=overwrite cargo test` to write out its `.stderr`, and
+//! read it over before checking it in.
+
+pub mod checks;
@@ -0,0 +1,12 @@
+//! Proves which of the exercise programs the borrow checker rejects, and with what errors, and that
+//! the fixed versions compile and run.
+
+#[test]
+fn problems_do_not_compile() {
+    trybuild::TestCases::new().compile_fail("tests/ui/problem*.rs");
+}
+
+#[test]
+fn fixes_compile_and_run() {
+    trybuild::TestCases::new().pass("tests/ui/fixed/problem*.rs");
+}
@@ -0,0 +1,10 @@
+// The borrows end after their last use, so `s` can be assigned to after that.
+fn main() {
+    let mut s = String::from("hello");
+    let ref1 = &s;
+    let ref2 = &ref1;
+    let ref3 = &ref2;
+    assert_eq!(ref3.to_uppercase(), "HELLO");
+    s = String::from("goodbye");
+    assert_eq!(s, "goodbye");
+}
@@ -0,0 +1,9 @@
+// Returning the `String` itself hands ownership of it to the caller.
+fn drip_drop() -> String {
+    let s = String::from("hello world!");
+    return s;
+}
+
+fn main() {
+    assert_eq!(drip_drop(), "hello world!");
+}
@@ -0,0 +1,8 @@
+// Borrowing the string leaves it in `v`.
+fn main() {
+    let s1 = String::from("hello");
+    let mut v = Vec::new();
+    v.push(s1);
+    let s2: &String = &v[0];
+    assert_eq!(s2, "hello");
+}
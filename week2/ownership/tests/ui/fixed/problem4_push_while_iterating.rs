@@ -0,0 +1,11 @@
+// Collecting the new elements first means `v` is only borrowed by one thing at a time.
+fn main() {
+    let mut v = vec![1, 2, 3];
+    let extra: Vec<i32> = v
+        .iter()
+        .filter(|num| *num % 2 == 1)
+        .map(|num| num * 10)
+        .collect();
+    v.extend(extra);
+    assert_eq!(v, vec![1, 2, 3, 10, 30]);
+}
@@ -0,0 +1,12 @@
+// Returning an owned `String` keeps the uppercased line alive for the caller.
+fn loudest_line(text: &str) -> String {
+    text.lines()
+        .max_by_key(|line| line.len())
+        .unwrap_or("")
+        .to_uppercase()
+}
+
+fn main() {
+    assert_eq!(loudest_line("hi\nhello there"), "HELLO THERE");
+    assert_eq!(loudest_line(""), "");
+}
@@ -0,0 +1,12 @@
+// Two separate vectors can be borrowed mutably at the same time.
+fn transfer(from: &mut Vec<i32>, to: &mut Vec<i32>) {
+    to.append(from);
+}
+
+fn main() {
+    let mut from = vec![1, 2, 3];
+    let mut to = vec![0];
+    transfer(&mut from, &mut to);
+    assert_eq!(from, vec![]);
+    assert_eq!(to, vec![0, 1, 2, 3]);
+}
@@ -0,0 +1,10 @@
+// `s` can't be assigned to while `ref1` (reached through `ref3`) still borrows it.
+#![allow(unused_assignments)]
+fn main() {
+    let mut s = String::from("hello");
+    let ref1 = &s;
+    let ref2 = &ref1;
+    let ref3 = &ref2;
+    s = String::from("goodbye");
+    println!("{}", ref3.to_uppercase());
+}
@@ -0,0 +1,9 @@
+// `s` is dropped when `drip_drop` returns, so no lifetime can make a reference to it outlive that.
+fn drip_drop<'a>() -> &'a String {
+    let s = String::from("hello world!");
+    return &s;
+}
+
+fn main() {
+    println!("{}", drip_drop());
+}
@@ -0,0 +1,8 @@
+// `String` isn't `Copy`, so this would move the string out of `v`, which still owns it.
+fn main() {
+    let s1 = String::from("hello");
+    let mut v = Vec::new();
+    v.push(s1);
+    let s2: String = v[0];
+    println!("{}", s2);
+}
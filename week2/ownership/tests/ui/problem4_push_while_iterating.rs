@@ -0,0 +1,10 @@
+// Pushing may reallocate `v` out from under the iterator that's borrowing it.
+fn main() {
+    let mut v = vec![1, 2, 3];
+    for num in v.iter() {
+        if *num % 2 == 1 {
+            v.push(*num * 10);
+        }
+    }
+    println!("{:?}", v);
+}
@@ -0,0 +1,12 @@
+// `to_uppercase` makes a new `String` that only lives until the end of the function, and the
+// returned line points into it.
+fn loudest_line(text: &str) -> &str {
+    text.to_uppercase()
+        .lines()
+        .max_by_key(|line| line.len())
+        .unwrap_or("")
+}
+
+fn main() {
+    println!("{}", loudest_line("hi\nhello there"));
+}
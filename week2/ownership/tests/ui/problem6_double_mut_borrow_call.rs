@@ -0,0 +1,11 @@
+// Both arguments would be mutable borrows of `v` at once, which `transfer` must be able to assume
+// never alias.
+fn transfer(from: &mut Vec<i32>, to: &mut Vec<i32>) {
+    to.append(from);
+}
+
+fn main() {
+    let mut v = vec![1, 2, 3];
+    transfer(&mut v, &mut v);
+    println!("{:?}", v);
+}
@@ -0,0 +1,74 @@
+//! Graded checks for rdiff, run by `cargo run -p exercises --bin grade`.
+
+use super::grid::Grid;
+use super::{lcs, read_file_lines};
+use grading::{expect_eq, Registry};
+use std::path::Path;
+
+fn lines(text: &str) -> Vec<String> {
+    text.chars().map(|c| c.to_string()).collect()
+}
+
+/// The length of the longest common subsequence, from the bottom right of the table.
+fn lcs_len(seq1: &[String], seq2: &[String]) -> Option<usize> {
+    lcs(&seq1.to_vec(), &seq2.to_vec()).get(seq1.len(), seq2.len())
+}
+
+/// Path to one of rdiff's sample files, wherever this is run from.
+fn sample(name: &str) -> String {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join(name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub fn register(registry: &mut Registry) {
+    registry.register_fn("rdiff::grid_out_of_bounds", || {
+        let mut grid = Grid::new(2, 3);
+        expect_eq(grid.get(2, 0), None)?;
+        expect_eq(grid.get(0, 3), None)?;
+        expect_eq(grid.set(2, 0, 1).is_err(), true)?;
+        expect_eq(grid.set(0, 3, 1).is_err(), true)
+    });
+    registry.register_fn("rdiff::grid_empty", || {
+        let mut grid = Grid::new(0, 0);
+        expect_eq(grid.size(), (0, 0))?;
+        expect_eq(grid.get(0, 0), None)?;
+        expect_eq(grid.set(0, 0, 1).is_err(), true)
+    });
+    registry.register_fn("rdiff::grid_clear", || {
+        let mut grid = Grid::new(2, 2);
+        grid.set(1, 1, 7).map_err(String::from)?;
+        grid.clear();
+        expect_eq(grid.get(1, 1), Some(0))
+    });
+    registry.register_fn("rdiff::lcs_both_empty", || {
+        let table = lcs(&Vec::new(), &Vec::new());
+        expect_eq(table.size(), (1, 1))?;
+        expect_eq(table.get(0, 0), Some(0))
+    });
+    registry.register_fn("rdiff::lcs_one_empty", || {
+        let table = lcs(&lines("abc"), &Vec::new());
+        expect_eq(table.size(), (4, 1))?;
+        expect_eq(table.get(3, 0), Some(0))
+    });
+    registry.register_fn("rdiff::lcs_identical", || {
+        expect_eq(lcs_len(&lines("abcdef"), &lines("abcdef")), Some(6))
+    });
+    registry.register_fn("rdiff::lcs_disjoint", || {
+        expect_eq(lcs_len(&lines("abc"), &lines("xyz")), Some(0))
+    });
+    registry.register_fn("rdiff::lcs_repeated_lines", || {
+        expect_eq(lcs_len(&lines("aaa"), &lines("a")), Some(1))?;
+        expect_eq(lcs_len(&lines("abab"), &lines("baba")), Some(3))
+    });
+    registry.register_fn("rdiff::read_missing_file", || {
+        expect_eq(read_file_lines(&sample("no-such-file.txt")).is_err(), true)
+    });
+    registry.register_fn("rdiff::lcs_of_samples", || {
+        let lines1 = read_file_lines(&sample("simple-a.txt")).map_err(|err| err.to_string())?;
+        let lines2 = read_file_lines(&sample("simple-b.txt")).map_err(|err| err.to_string())?;
+        expect_eq((lines1.len(), lines2.len()), (5, 8))?;
+        expect_eq(lcs_len(&lines1, &lines2), Some(5))
+    });
+}
@@ -0,0 +1,94 @@
+// The signatures and index loops follow the handout, which students are writing against.
+#![allow(clippy::ptr_arg, clippy::needless_range_loop, clippy::redundant_field_names)]
+
+extern crate grading;
+
+use grid::Grid; // For lcs()
+use std::fs::File; // For read_file_lines()
+use std::io::{self, BufRead}; // For read_file_lines()
+
+pub mod checks;
+pub mod grid;
+
+/// Reads the file at the supplied path, and returns a vector of strings.
+pub fn read_file_lines(filename: &String) -> Result<Vec<String>, io::Error> {
+    let file = File::open(filename)?;
+    let mut line_vec: Vec<String> = Vec::new();
+    for line in io::BufReader::new(file).lines() {
+        let line_str: String = line?;
+        line_vec.push(line_str);
+    }
+    Ok(line_vec)
+}
+
+/// Returns the table of longest common subsequence lengths: the element at (i, j) is the LCS length
+/// of the first i lines of seq1 and the first j lines of seq2.
+pub fn lcs(seq1: &Vec<String>, seq2: &Vec<String>) -> Grid {
+    // Note: Feel free to use unwrap() in this code, as long as you're basically certain it'll
+    // never happen. Conceptually, unwrap() is justified here, because there's not really any error
+    // condition you're watching out for (i.e. as long as your code is written correctly, nothing
+    // external can go wrong that we would want to handle in higher-level functions). The unwrap()
+    // calls act like having asserts in C code, i.e. as guards against programming error.
+    let mut grid = Grid::new(seq1.len() + 1, seq2.len() + 1);
+    for i in 0..seq1.len() {
+        for j in 0..seq2.len() {
+            let val = if seq1[i] == seq2[j] {
+                grid.get(i, j).unwrap() + 1
+            }
+            else {
+                std::cmp::max(grid.get(i + 1, j).unwrap(), grid.get(i, j + 1).unwrap())
+            };
+            grid.set(i + 1, j + 1, val).unwrap();
+        }
+    }
+    grid
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_file_lines() {
+        let lines_result = read_file_lines(&String::from("handout-a.txt"));
+        assert!(lines_result.is_ok());
+        let lines = lines_result.unwrap();
+        assert_eq!(lines.len(), 8);
+        assert_eq!(
+            lines[0],
+            "This week's exercises will continue easing you into Rust and will feature some"
+        );
+    }
+
+    #[test]
+    fn test_lcs() {
+        let mut expected = Grid::new(5, 4);
+        expected.set(1, 1, 1).unwrap();
+        expected.set(1, 2, 1).unwrap();
+        expected.set(1, 3, 1).unwrap();
+        expected.set(2, 1, 1).unwrap();
+        expected.set(2, 2, 1).unwrap();
+        expected.set(2, 3, 2).unwrap();
+        expected.set(3, 1, 1).unwrap();
+        expected.set(3, 2, 1).unwrap();
+        expected.set(3, 3, 2).unwrap();
+        expected.set(4, 1, 1).unwrap();
+        expected.set(4, 2, 2).unwrap();
+        expected.set(4, 3, 2).unwrap();
+
+        println!("Expected:");
+        expected.display();
+        let result = lcs(
+            &"abcd".chars().map(|c| c.to_string()).collect(),
+            &"adb".chars().map(|c| c.to_string()).collect(),
+        );
+        println!("Got:");
+        result.display();
+        assert_eq!(result.size(), expected.size());
+        for row in 0..expected.size().0 {
+            for col in 0..expected.size().1 {
+                assert_eq!(result.get(row, col), expected.get(row, col));
+            }
+        }
+    }
+}
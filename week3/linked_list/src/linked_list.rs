@@ -1,62 +1,114 @@
 use std::fmt;
+use std::marker::PhantomData;
 use std::option::Option;
+use std::ptr::NonNull;
 
+/// A doubly linked list, so that both ends can be pushed to and popped from in O(1). The nodes are
+/// allocated with Box and linked with raw pointers, since each of them is pointed to from both
+/// sides; the list owns them, and frees them when they're popped or the list is dropped.
 pub struct LinkedList<T> {
-    head: Option<Box<Node<T>>>,
+    head: Link<T>,
+    tail: Link<T>,
     size: usize,
+    marker: PhantomData<Box<Node<T>>>,
 }
 
+type Link<T> = Option<NonNull<Node<T>>>;
+
 struct Node<T> {
     value: T,
-    next: Option<Box<Node<T>>>,
+    prev: Link<T>,
+    next: Link<T>,
 }
 
 impl<T> Node<T> {
-    pub fn new(value: T, next: Option<Box<Node<T>>>) -> Node<T> {
-        Node {value: value, next: next}
+    /// Allocates a new node, which is owned by whoever ends up linking to it.
+    pub fn new(value: T, prev: Link<T>, next: Link<T>) -> NonNull<Node<T>> {
+        let node = Box::new(Node {value, prev, next});
+        unsafe { NonNull::new_unchecked(Box::into_raw(node)) }
     }
 }
 
+// The list owns its values just like a Vec would, so it can be sent and shared just the same.
+unsafe impl<T: Send> Send for LinkedList<T> {}
+unsafe impl<T: Sync> Sync for LinkedList<T> {}
+
 impl<T> LinkedList<T> {
     pub fn new() -> LinkedList<T> {
-        LinkedList {head: None, size: 0}
+        LinkedList {head: None, tail: None, size: 0, marker: PhantomData}
     }
-    
+
     pub fn get_size(&self) -> usize {
         self.size
     }
-    
+
     pub fn is_empty(&self) -> bool {
         self.get_size() == 0
     }
-    
+
     pub fn push_front(&mut self, value: T) {
-        let new_node: Box<Node<T>> = Box::new(Node::new(value, self.head.take()));
+        let new_node = Node::new(value, None, self.head);
+        match self.head {
+            Some(head) => unsafe { (*head.as_ptr()).prev = Some(new_node) },
+            None => self.tail = Some(new_node),
+        }
         self.head = Some(new_node);
         self.size += 1;
     }
-    
+
     pub fn pop_front(&mut self) -> Option<T> {
-        let node: Box<Node<T>> = self.head.take()?;
+        let node: Box<Node<T>> = unsafe { Box::from_raw(self.head?.as_ptr()) };
         self.head = node.next;
+        match self.head {
+            Some(head) => unsafe { (*head.as_ptr()).prev = None },
+            None => self.tail = None,
+        }
         self.size -= 1;
         Some(node.value)
     }
+
+    pub fn push_back(&mut self, value: T) {
+        let new_node = Node::new(value, self.tail, None);
+        match self.tail {
+            Some(tail) => unsafe { (*tail.as_ptr()).next = Some(new_node) },
+            None => self.head = Some(new_node),
+        }
+        self.tail = Some(new_node);
+        self.size += 1;
+    }
+
+    pub fn pop_back(&mut self) -> Option<T> {
+        let node: Box<Node<T>> = unsafe { Box::from_raw(self.tail?.as_ptr()) };
+        self.tail = node.prev;
+        match self.tail {
+            Some(tail) => unsafe { (*tail.as_ptr()).next = None },
+            None => self.head = None,
+        }
+        self.size -= 1;
+        Some(node.value)
+    }
+
+    pub fn iter(&self) -> LinkedListIter<'_, T> {
+        LinkedListIter {current: self.head, marker: PhantomData}
+    }
+
+    pub fn iter_mut(&mut self) -> LinkedListIterMut<'_, T> {
+        LinkedListIterMut {current: self.head, marker: PhantomData}
+    }
 }
 
 
+impl<T> Default for LinkedList<T> {
+    fn default() -> Self {
+        LinkedList::new()
+    }
+}
+
 impl<T> fmt::Display for LinkedList<T> where T: fmt::Display {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut current: &Option<Box<Node<T>>> = &self.head;
         let mut result = String::new();
-        loop {
-            match current {
-                Some(node) => {
-                    result = format!("{} {}", result, node.value);
-                    current = &node.next;
-                },
-                None => break,
-            }
+        for value in self {
+            result = format!("{} {}", result, value);
         }
         write!(f, "{}", result)
     }
@@ -64,63 +116,89 @@ impl<T> fmt::Display for LinkedList<T> where T: fmt::Display {
 
 impl<T> Drop for LinkedList<T> {
     fn drop(&mut self) {
-        let mut current = self.head.take();
-        while let Some(mut node) = current {
-            current = node.next.take();
-        }
-    }
-}
-
-impl<T: Clone> Clone for Node<T> {
-    fn clone(&self) -> Self {
-        Node::new(self.value.clone(), self.next.clone())
+        // One node at a time, so that long lists don't take a deep recursion to free
+        while self.pop_front().is_some() {}
     }
 }
 
 impl<T: Clone> Clone for LinkedList<T> {
     fn clone(&self) -> Self {
         let mut new_self = LinkedList::new();
-        new_self.head = self.head.clone();
-        new_self.size = self.size.clone();
+        for value in self {
+            new_self.push_back(value.clone());
+        }
         new_self
     }
 }
 
-impl<T: PartialEq> PartialEq for Node<T> {
+impl<T: PartialEq> PartialEq for LinkedList<T> {
     fn eq(&self, other: &Self) -> bool {
-        self.value == other.value && self.next == other.next
+        self.size == other.size && self.iter().eq(other.iter())
     }
 }
 
-impl<T: PartialEq> PartialEq for LinkedList<T> {
-    fn eq(&self, other: &Self) -> bool {
-        self.size == other.size && self.head == other.head
+
+pub struct LinkedListIter<'a, T> {
+    current: Link<T>,
+    marker: PhantomData<&'a Node<T>>,
+}
+
+impl<'a, T> Iterator for LinkedListIter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        let node: &'a Node<T> = unsafe { &*self.current?.as_ptr() };
+        self.current = node.next;
+        Some(&node.value)
     }
 }
 
+impl<'a, T> IntoIterator for &'a LinkedList<T> {
+    type Item = &'a T;
+    type IntoIter = LinkedListIter<'a, T>;
+    fn into_iter(self) -> LinkedListIter<'a, T> {
+        self.iter()
+    }
+}
 
-pub struct LinkedListIter<'a, T> {
-    current: &'a Option<Box<Node<T>>>,
+pub struct LinkedListIterMut<'a, T> {
+    current: Link<T>,
+    marker: PhantomData<&'a mut Node<T>>,
 }
 
-impl<T: Clone> Iterator for LinkedListIter<'_, T> {
+impl<'a, T> Iterator for LinkedListIterMut<'a, T> {
+    type Item = &'a mut T;
+    fn next(&mut self) -> Option<&'a mut T> {
+        // Each node is only visited once, so the references handed out never alias
+        let node: &'a mut Node<T> = unsafe { &mut *self.current?.as_ptr() };
+        self.current = node.next;
+        Some(&mut node.value)
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut LinkedList<T> {
+    type Item = &'a mut T;
+    type IntoIter = LinkedListIterMut<'a, T>;
+    fn into_iter(self) -> LinkedListIterMut<'a, T> {
+        self.iter_mut()
+    }
+}
+
+pub struct LinkedListIntoIter<T> {
+    list: LinkedList<T>,
+}
+
+impl<T> Iterator for LinkedListIntoIter<T> {
     type Item = T;
     fn next(&mut self) -> Option<T> {
-        match self.current {
-            Some(node) => {
-                self.current = &node.next;
-                Some(node.value.clone())
-            },
-            None => None
-        }
+        self.list.pop_front()
     }
 }
 
-impl<'a, T: Clone> IntoIterator for &'a LinkedList<T> {
+impl<T> IntoIterator for LinkedList<T> {
     type Item = T;
-    type IntoIter = LinkedListIter<'a, T>;
-    fn into_iter(self) -> LinkedListIter<'a, T> {
-        LinkedListIter {current: &self.head}
+    type IntoIter = LinkedListIntoIter<T>;
+    fn into_iter(self) -> LinkedListIntoIter<T> {
+        LinkedListIntoIter {list: self}
     }
 }
 
@@ -142,3 +220,96 @@ impl ComputeNorm for LinkedList<f64> {
 }
 
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn from_vec<T>(values: Vec<T>) -> LinkedList<T> {
+        let mut list = LinkedList::new();
+        for value in values {
+            list.push_back(value);
+        }
+        list
+    }
+
+    #[test]
+    fn test_pop_empty() {
+        let mut list: LinkedList<i32> = LinkedList::new();
+        assert_eq!(list.pop_back(), None);
+        assert_eq!(list.pop_front(), None);
+        list.push_back(1);
+        assert_eq!(list.pop_back(), Some(1));
+        assert_eq!(list.pop_back(), None);
+        assert_eq!(list.pop_front(), None);
+        assert!(list.is_empty());
+        // Both ends are still usable after emptying the list from the back
+        list.push_front(2);
+        assert_eq!(list.pop_back(), Some(2));
+    }
+
+    #[test]
+    fn test_push_both_ends() {
+        let mut list = LinkedList::new();
+        for i in 0..3 {
+            list.push_front(-i);
+            list.push_back(i + 1);
+        }
+        assert_eq!(list.get_size(), 6);
+        assert_eq!(list.to_string(), " -2 -1 0 1 2 3");
+        assert_eq!(list.pop_back(), Some(3));
+        assert_eq!(list.pop_front(), Some(-2));
+        assert_eq!(list.pop_back(), Some(2));
+        assert_eq!(list.get_size(), 3);
+        assert_eq!(list.into_iter().collect::<Vec<i32>>(), vec![-1, 0, 1]);
+    }
+
+    #[test]
+    fn test_iter_mut() {
+        let mut list = from_vec(vec![1, 2, 3]);
+        for value in list.iter_mut() {
+            *value += 1;
+        }
+        for value in &mut list {
+            *value *= 10;
+        }
+        assert_eq!(list.iter().copied().collect::<Vec<i32>>(), vec![20, 30, 40]);
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let list = from_vec(vec!["a".to_string(), "b".to_string()]);
+        let values: Vec<String> = list.into_iter().collect();
+        assert_eq!(values, vec!["a", "b"]);
+
+        // Dropping the iterator part way drops the rest of the list with it
+        let mut iter = from_vec(vec!["c".to_string(), "d".to_string()]).into_iter();
+        assert_eq!(iter.next(), Some("c".to_string()));
+    }
+
+    #[test]
+    fn test_clone_and_eq() {
+        let list = from_vec(vec![1.0, 2.0, 2.0]);
+        let mut copy = list.clone();
+        assert!(list == copy);
+        assert_eq!(copy.compute_norm(), 3.0);
+        copy.push_back(0.0);
+        assert!(list != copy);
+        copy.pop_back();
+        copy.pop_front();
+        copy.push_front(-1.0);
+        assert!(list != copy);
+    }
+
+    #[test]
+    fn test_long_list() {
+        let mut list = LinkedList::new();
+        for i in 0..1_000_000 {
+            list.push_back(i);
+        }
+        assert_eq!(list.get_size(), 1_000_000);
+        let copy = list.clone();
+        assert!(list == copy);
+        drop(copy);
+        drop(list);
+    }
+}
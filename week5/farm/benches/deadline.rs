@@ -0,0 +1,33 @@
+//! Compares trial division with and without the deadline checks behind `--timeout-ms`, on primes
+//! large enough that every run goes through the whole loop.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use farm::{factor_number, factor_number_until};
+use std::time::{Duration, Instant};
+
+const PRIMES: [u64; 3] = [999_999_999_989, 3_999_999_999_967, 9_999_999_999_971];
+
+fn bench_deadline(c: &mut Criterion) {
+    let mut group = c.benchmark_group("factor 3 large primes");
+    group.sample_size(10);
+
+    group.bench_function("without deadline", |b| {
+        b.iter(|| {
+            for &prime in PRIMES.iter() {
+                factor_number(prime);
+            }
+        })
+    });
+    let far_away = Some(Instant::now() + Duration::from_secs(3600));
+    group.bench_function("with deadline", |b| {
+        b.iter(|| {
+            for &prime in PRIMES.iter() {
+                factor_number_until(prime, far_away);
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_deadline);
+criterion_main!(benches);
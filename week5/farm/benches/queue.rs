@@ -0,0 +1,74 @@
+//! Compares the channel-based work queue against the mutex-guarded deque farm used to have, on
+//! inputs so small that handing out the work is most of the cost.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use crossbeam_channel as channel;
+use farm::{factor_number, factor_with, Options};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const LEN: u64 = 100_000;
+const THREADS: usize = 4;
+
+fn inputs() -> Vec<u64> {
+    (0..LEN).map(|n| n % 64).collect()
+}
+
+/// The old work distribution: every worker pops from one mutex-guarded deque that holds the
+/// entire input. Returns how many results were produced.
+fn factor_with_mutex_queue(nums: Vec<u64>, threads: usize) -> usize {
+    let numbers = Arc::new(Mutex::new(nums.into_iter().collect::<VecDeque<_>>()));
+    let (result_sender, result_receiver) = channel::unbounded();
+    let mut workers = Vec::new();
+    for _ in 0..threads {
+        let numbers = numbers.clone();
+        let result_sender = result_sender.clone();
+        workers.push(thread::spawn(move || loop {
+            let num = numbers.lock().unwrap().pop_front();
+            match num {
+                Some(num) => result_sender.send(factor_number(num)).unwrap(),
+                None => break,
+            }
+        }));
+    }
+    drop(result_sender);
+    let count = result_receiver.iter().count();
+    for handle in workers {
+        handle.join().unwrap();
+    }
+    count
+}
+
+fn bench_queues(c: &mut Criterion) {
+    let mut group = c.benchmark_group("factor 100K tiny numbers");
+    group.throughput(Throughput::Elements(LEN));
+    group.sample_size(10);
+
+    group.bench_function("mutex queue", |b| {
+        b.iter_batched(
+            inputs,
+            |nums| factor_with_mutex_queue(nums, THREADS),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("channel queue", |b| {
+        let options = Options {
+            threads: THREADS,
+            ..Options::default()
+        };
+        b.iter_batched(
+            inputs,
+            |nums| {
+                let mut count = 0;
+                factor_with(nums, &options, |_| count += 1);
+                count
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_queues);
+criterion_main!(benches);
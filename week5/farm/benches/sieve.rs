@@ -0,0 +1,53 @@
+//! Compares trial division with and without the shared prime sieve on smooth numbers, which are
+//! the bulk of our real inputs.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use farm::{factor_with, Options, Sieve};
+use std::sync::Arc;
+
+/// Products of three primes between 10,000 and 100,000: every factor is below the default sieve
+/// bound, but plain trial division has to try every odd candidate on the way to them.
+fn smooth_numbers() -> Vec<u64> {
+    let primes: Vec<u64> = Sieve::new(100_000)
+        .primes()
+        .iter()
+        .cloned()
+        .filter(|&p| p > 10_000)
+        .collect();
+    (0..300)
+        .map(|i| {
+            primes[i * 7 % primes.len()]
+                * primes[i * 13 % primes.len()]
+                * primes[i * 29 % primes.len()]
+        })
+        .collect()
+}
+
+fn bench_sieve(c: &mut Criterion) {
+    let nums = smooth_numbers();
+    let plain = Options {
+        threads: 2,
+        ..Options::default()
+    };
+    let sieved = Options {
+        sieve: Some(Arc::new(Sieve::new(1 << 20))),
+        ..plain.clone()
+    };
+
+    let mut group = c.benchmark_group("factor 300 smooth numbers");
+    group.throughput(Throughput::Elements(nums.len() as u64));
+    group.sample_size(10);
+    for (name, options) in [("without sieve", &plain), ("with sieve", &sieved)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut count = 0;
+                factor_with(nums.clone(), options, |_| count += 1);
+                count
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_sieve);
+criterion_main!(benches);
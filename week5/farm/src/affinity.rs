@@ -0,0 +1,133 @@
+use std::io;
+
+/// The range of nice levels `set_nice` accepts, from highest priority to lowest.
+pub const NICE_RANGE: std::ops::RangeInclusive<i32> = -20..=19;
+
+/// Whether a worker thread ended up pinned to a CPU.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Pinning {
+    /// Pinning wasn't asked for
+    #[default]
+    Off,
+    /// The worker ran only on this CPU
+    Pinned(usize),
+    /// Pinning was asked for but isn't supported here, or the OS refused it
+    Failed,
+}
+
+/// Sets the nice level of the calling thread, which threads spawned afterwards inherit. Call it
+/// before starting any workers. Lowering the level below the current one usually needs root.
+pub fn set_nice(level: i32) -> io::Result<()> {
+    if !NICE_RANGE.contains(&level) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("nice level {} is outside {:?}", level, NICE_RANGE),
+        ));
+    }
+    // The `which` argument's type differs between platforms, hence the casts.
+    #[allow(clippy::useless_conversion)]
+    let result = unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, level) };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Returns the CPUs this process may run on, in increasing order. Empty if that can't be found
+/// out on this platform.
+#[cfg(target_os = "linux")]
+pub(crate) fn allowed_cpus() -> Vec<usize> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Vec::new();
+        }
+        (0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+            .collect()
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn allowed_cpus() -> Vec<usize> {
+    Vec::new()
+}
+
+/// Restricts the calling thread to `cpu`.
+#[cfg(target_os = "linux")]
+pub(crate) fn pin_current_thread(cpu: usize) -> Pinning {
+    if cpu >= libc::CPU_SETSIZE as usize {
+        return Pinning::Failed;
+    }
+    let result = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+    };
+    if result == 0 {
+        Pinning::Pinned(cpu)
+    } else {
+        Pinning::Failed
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn pin_current_thread(_cpu: usize) -> Pinning {
+    Pinning::Failed
+}
+
+/// Pins worker `id` to a CPU of its own, as far as there are enough CPUs to go around; beyond
+/// that, workers share CPUs round-robin. `cpus` comes from `allowed_cpus`.
+pub(crate) fn pin_worker(id: usize, cpus: &[usize]) -> Pinning {
+    if cpus.is_empty() {
+        return Pinning::Failed;
+    }
+    pin_current_thread(cpus[id % cpus.len()])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_set_nice_validates_level() {
+        for &level in [-21, 20, 100].iter() {
+            let err = set_nice(level).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_set_nice() {
+        // Lowering our own priority never needs privileges; do it on a thread of its own so the
+        // other tests aren't slowed down.
+        thread::spawn(|| set_nice(19).unwrap()).join().unwrap();
+    }
+
+    #[test]
+    fn test_pin_worker_fallback() {
+        // Nothing to pin to, or a CPU that can't exist: the worker just runs unpinned.
+        thread::spawn(|| {
+            assert_eq!(pin_worker(0, &[]), Pinning::Failed);
+            assert_eq!(pin_current_thread(usize::MAX), Pinning::Failed);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_pin_worker() {
+        let cpus = allowed_cpus();
+        assert!(!cpus.is_empty());
+        let pinned = thread::spawn(move || {
+            let pinning = pin_worker(cpus.len(), &cpus);
+            (pinning, allowed_cpus())
+        })
+        .join()
+        .unwrap();
+        let first = allowed_cpus()[0];
+        assert_eq!(pinned, (Pinning::Pinned(first), vec![first]));
+    }
+}
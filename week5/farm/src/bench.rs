@@ -0,0 +1,245 @@
+use crate::{factor_all, Format};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Number of timed runs per thread count; the fastest one is reported.
+const RUNS_PER_CONFIG: usize = 3;
+
+/// How long one thread count took on the benchmark workload, compared to a single thread.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BenchResult {
+    pub threads: usize,
+    /// Fastest wall-clock time over the timed runs
+    pub wall: Duration,
+    /// Single-threaded wall time divided by this one
+    pub speedup: f64,
+    /// Speedup per thread: 1.0 means every extra thread pulled its full weight
+    pub efficiency: f64,
+}
+
+/// Returns the thread counts to benchmark: powers of two up to `cpus`, `cpus` itself, and any
+/// `extra` counts asked for, in increasing order. Always includes 1, since that is what every
+/// other count is compared against.
+pub fn bench_thread_counts(cpus: usize, extra: &[usize]) -> Vec<usize> {
+    let mut counts: Vec<usize> = std::iter::successors(Some(1usize), |&n| n.checked_mul(2))
+        .take_while(|&n| n <= cpus)
+        .collect();
+    counts.push(cpus.max(1));
+    counts.extend(extra.iter().filter(|&&n| n > 0));
+    counts.sort_unstable();
+    counts.dedup();
+    counts
+}
+
+/// A reproducible default workload: numbers between 10^10 and 10^11, whose factorizations cost
+/// anything from a few divisions to a few hundred thousand.
+pub fn synthetic_workload(len: usize) -> Vec<u64> {
+    // A linear congruential generator (Knuth's MMIX constants) is plenty random for this.
+    let mut state: u64 = 0x5eed;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            10_000_000_000 + (state >> 11) % 90_000_000_000
+        })
+        .collect()
+}
+
+/// Factors `nums` with each of `thread_counts` (which must include 1) and reports how each
+/// compares to a single thread. One untimed warm-up run comes first, so that the first
+/// configuration doesn't pay for cold caches.
+pub fn run_bench(nums: &[u64], thread_counts: &[usize]) -> Vec<BenchResult> {
+    assert!(
+        thread_counts.contains(&1),
+        "benchmark needs a single-threaded baseline"
+    );
+    factor_all(nums.to_vec(), thread_counts[0]);
+    let walls: Vec<(usize, Duration)> = thread_counts
+        .iter()
+        .map(|&threads| {
+            let wall = (0..RUNS_PER_CONFIG)
+                .map(|_| {
+                    let start = Instant::now();
+                    factor_all(nums.to_vec(), threads);
+                    start.elapsed()
+                })
+                .min()
+                .unwrap();
+            (threads, wall)
+        })
+        .collect();
+    let baseline = walls
+        .iter()
+        .find(|&&(threads, _)| threads == 1)
+        .map(|&(_, wall)| wall.as_secs_f64())
+        .unwrap();
+    walls
+        .into_iter()
+        .map(|(threads, wall)| {
+            let speedup = if wall.as_secs_f64() > 0.0 {
+                baseline / wall.as_secs_f64()
+            } else {
+                1.0
+            };
+            BenchResult {
+                threads,
+                wall,
+                speedup,
+                efficiency: speedup / threads as f64,
+            }
+        })
+        .collect()
+}
+
+/// Writes the benchmark results as a table for people, or for the machine-readable formats as
+/// one record per thread count.
+pub fn write_bench_results<W: Write + ?Sized>(
+    results: &[BenchResult],
+    format: Format,
+    out: &mut W,
+) -> io::Result<()> {
+    let wall_ms = |result: &BenchResult| format!("{:.3}", result.wall.as_secs_f64() * 1000.0);
+    match format {
+        Format::Text | Format::Exp => {
+            writeln!(
+                out,
+                "{:>7}  {:>12}  {:>7}  {:>10}",
+                "threads", "wall", "speedup", "efficiency"
+            )?;
+            for result in results {
+                writeln!(
+                    out,
+                    "{:>7}  {:>12}  {:>7}  {:>10}",
+                    result.threads,
+                    format!("{}ms", wall_ms(result)),
+                    format!("{:.2}x", result.speedup),
+                    format!("{:.1}%", result.efficiency * 100.0)
+                )?;
+            }
+        }
+        Format::Csv => {
+            writeln!(out, "threads,wall_ms,speedup,efficiency")?;
+            for result in results {
+                writeln!(
+                    out,
+                    "{},{},{:.3},{:.3}",
+                    result.threads,
+                    wall_ms(result),
+                    result.speedup,
+                    result.efficiency
+                )?;
+            }
+        }
+        Format::Json | Format::Ndjson => {
+            let records: Vec<String> = results
+                .iter()
+                .map(|result| {
+                    format!(
+                        "{{\"threads\":{},\"wall_ms\":{},\"speedup\":{:.3},\"efficiency\":{:.3}}}",
+                        result.threads,
+                        wall_ms(result),
+                        result.speedup,
+                        result.efficiency
+                    )
+                })
+                .collect();
+            if format == Format::Json {
+                writeln!(out, "[{}]", records.join(",\n"))?;
+            } else {
+                for record in records {
+                    writeln!(out, "{}", record)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn results() -> Vec<BenchResult> {
+        vec![
+            BenchResult {
+                threads: 1,
+                wall: Duration::from_millis(120),
+                speedup: 1.0,
+                efficiency: 1.0,
+            },
+            BenchResult {
+                threads: 4,
+                wall: Duration::from_millis(40),
+                speedup: 3.0,
+                efficiency: 0.75,
+            },
+        ]
+    }
+
+    fn render(format: Format) -> String {
+        let mut output = Vec::new();
+        write_bench_results(&results(), format, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_bench_thread_counts() {
+        assert_eq!(bench_thread_counts(8, &[]), vec![1, 2, 4, 8]);
+        assert_eq!(bench_thread_counts(6, &[]), vec![1, 2, 4, 6]);
+        assert_eq!(bench_thread_counts(1, &[]), vec![1]);
+        assert_eq!(bench_thread_counts(4, &[3, 16, 0, 4]), vec![1, 2, 3, 4, 16]);
+    }
+
+    #[test]
+    fn test_synthetic_workload_is_reproducible() {
+        let nums = synthetic_workload(100);
+        assert_eq!(nums, synthetic_workload(100));
+        assert!(nums
+            .iter()
+            .all(|&n| (10_000_000_000..100_000_000_000).contains(&n)));
+        let mut distinct = nums.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        assert_eq!(distinct.len(), 100);
+    }
+
+    #[test]
+    fn test_run_bench() {
+        let results = run_bench(&synthetic_workload(20), &[1, 2]);
+        let threads: Vec<usize> = results.iter().map(|result| result.threads).collect();
+        assert_eq!(threads, vec![1, 2]);
+        assert_eq!(results[0].speedup, 1.0);
+        assert_eq!(results[0].efficiency, 1.0);
+        assert!((results[1].efficiency * 2.0 - results[1].speedup).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_write_table() {
+        assert_eq!(
+            render(Format::Text),
+            "threads          wall  speedup  efficiency\n      \
+             1     120.000ms    1.00x      100.0%\n      \
+             4      40.000ms    3.00x       75.0%\n"
+        );
+    }
+
+    #[test]
+    fn test_write_csv() {
+        assert_eq!(
+            render(Format::Csv),
+            "threads,wall_ms,speedup,efficiency\n\
+             1,120.000,1.000,1.000\n\
+             4,40.000,3.000,0.750\n"
+        );
+    }
+
+    #[test]
+    fn test_write_ndjson() {
+        assert_eq!(
+            render(Format::Ndjson),
+            "{\"threads\":1,\"wall_ms\":120.000,\"speedup\":1.000,\"efficiency\":1.000}\n\
+             {\"threads\":4,\"wall_ms\":40.000,\"speedup\":3.000,\"efficiency\":0.750}\n"
+        );
+    }
+}
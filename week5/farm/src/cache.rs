@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Number of independently locked shards. Workers looking up different numbers mostly hit
+/// different shards, so they rarely wait on each other.
+const NUM_SHARDS: usize = 16;
+
+/// Factorizations computed so far, shared between workers so that repeated inputs are only
+/// factored once.
+#[derive(Debug)]
+pub struct FactorCache {
+    shards: Vec<Mutex<HashMap<u64, Vec<u64>>>>,
+}
+
+impl FactorCache {
+    pub fn new() -> FactorCache {
+        FactorCache {
+            shards: (0..NUM_SHARDS)
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+        }
+    }
+
+    fn shard(&self, num: u64) -> &Mutex<HashMap<u64, Vec<u64>>> {
+        &self.shards[(num % NUM_SHARDS as u64) as usize]
+    }
+
+    /// Returns the factors of `num`, if they have been computed before.
+    pub fn get(&self, num: u64) -> Option<Vec<u64>> {
+        self.shard(num).lock().unwrap().get(&num).cloned()
+    }
+
+    /// Remembers the factors of `num`.
+    pub fn insert(&self, num: u64, factors: Vec<u64>) {
+        self.shard(num).lock().unwrap().insert(num, factors);
+    }
+
+    /// Returns the number of distinct inputs in the cache.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for FactorCache {
+    fn default() -> FactorCache {
+        FactorCache::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_get_and_insert() {
+        let cache = FactorCache::new();
+        assert!(cache.is_empty());
+        assert_eq!(cache.get(12), None);
+        cache.insert(12, vec![2, 2, 3]);
+        cache.insert(28, vec![2, 2, 7]);
+        assert_eq!(cache.get(12), Some(vec![2, 2, 3]));
+        assert_eq!(cache.get(28), Some(vec![2, 2, 7]));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        let cache = Arc::new(FactorCache::new());
+        let threads: Vec<_> = (0..4u64)
+            .map(|t| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    for num in (t * 100)..(t * 100 + 100) {
+                        cache.insert(num, vec![num]);
+                    }
+                })
+            })
+            .collect();
+        for handle in threads {
+            handle.join().unwrap();
+        }
+        assert_eq!(cache.len(), 400);
+        assert_eq!(cache.get(399), Some(vec![399]));
+    }
+}
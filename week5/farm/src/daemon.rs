@@ -0,0 +1,390 @@
+use crate::{
+    factor_one, parse_token, Factorization, Format, Options, RecordWriter, TokenError,
+    QUEUE_DEPTH_PER_WORKER,
+};
+use crossbeam_channel as channel;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How often the daemon checks whether it has been asked to shut down while it waits for new
+/// connections or for clients to send more numbers.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Identifies a connected client, so that results find their way back to it.
+type ClientId = u64;
+
+/// Something to write back to a client.
+enum Response {
+    Result(Factorization),
+    Invalid(String, TokenError),
+}
+
+/// Where a client's responses go, and how much is still owed to it.
+struct Route {
+    responses: channel::Sender<Response>,
+    /// Numbers queued for this client whose results haven't been routed back yet
+    pending: usize,
+    /// Whether the client may still send more numbers
+    reading: bool,
+}
+
+/// The routes of every connected client. A client's route is removed once it is done sending and
+/// every result has been routed back to it, which closes its connection; or as soon as it turns
+/// out to have gone away, which makes the workers skip whatever it still has queued.
+#[derive(Default)]
+struct Routes(Mutex<HashMap<ClientId, Route>>);
+
+impl Routes {
+    fn add(&self, id: ClientId, responses: channel::Sender<Response>) {
+        let route = Route {
+            responses,
+            pending: 0,
+            reading: true,
+        };
+        self.0.lock().unwrap().insert(id, route);
+    }
+
+    fn remove(&self, id: ClientId) {
+        self.0.lock().unwrap().remove(&id);
+    }
+
+    fn is_connected(&self, id: ClientId) -> bool {
+        self.0.lock().unwrap().contains_key(&id)
+    }
+
+    /// Counts one more number queued for `id`. Returns false if the client is gone.
+    fn job_queued(&self, id: ClientId) -> bool {
+        match self.0.lock().unwrap().get_mut(&id) {
+            Some(route) => {
+                route.pending += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn send_invalid(&self, id: ClientId, token: &str, reason: TokenError) {
+        if let Some(route) = self.0.lock().unwrap().get(&id) {
+            let _ = route
+                .responses
+                .send(Response::Invalid(token.to_string(), reason));
+        }
+    }
+
+    /// Hands a finished factorization to the writer of the client that asked for it.
+    fn route_result(&self, id: ClientId, factorization: Factorization) {
+        let mut routes = self.0.lock().unwrap();
+        let done = match routes.get_mut(&id) {
+            Some(route) => {
+                let _ = route.responses.send(Response::Result(factorization));
+                route.pending -= 1;
+                !route.reading && route.pending == 0
+            }
+            None => false,
+        };
+        if done {
+            routes.remove(&id);
+        }
+    }
+
+    /// Notes that `id` won't send any more numbers.
+    fn done_reading(&self, id: ClientId) {
+        let mut routes = self.0.lock().unwrap();
+        let done = match routes.get_mut(&id) {
+            Some(route) => {
+                route.reading = false;
+                route.pending == 0
+            }
+            None => false,
+        };
+        if done {
+            routes.remove(&id);
+        }
+    }
+}
+
+/// Writes a client's responses back to it as they are routed to `responses`, until its route is
+/// removed. If the client has gone away, its route is removed right away.
+fn write_responses(
+    id: ClientId,
+    stream: UnixStream,
+    responses: channel::Receiver<Response>,
+    format: Format,
+    routes: &Routes,
+) {
+    let written = RecordWriter::new(format, &stream).and_then(|mut records| {
+        for response in responses {
+            match response {
+                Response::Result(factorization) => records.write_result(&factorization)?,
+                Response::Invalid(token, reason) => {
+                    records.write_error(&token, &reason.to_string())?
+                }
+            }
+        }
+        records.finish()
+    });
+    if written.is_err() {
+        routes.remove(id);
+    }
+    let _ = stream.shutdown(Shutdown::Write);
+}
+
+/// Reads newline-separated numbers (or ranges) from a client and queues them for the workers,
+/// until the client is done sending or `shutdown` is set.
+fn read_jobs(
+    id: ClientId,
+    stream: &UnixStream,
+    job_sender: &channel::Sender<(ClientId, u64)>,
+    routes: &Routes,
+    shutdown: &AtomicBool,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL))?;
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+    while !shutdown.load(Ordering::SeqCst) {
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            // Nothing more yet; whatever was read so far stays in `line`.
+            Err(err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(err) => return Err(err),
+        }
+        for token in String::from_utf8_lossy(&line).split_whitespace() {
+            let range = match parse_token(token) {
+                Ok(Some(range)) => range,
+                Ok(None) => continue,
+                Err(reason) => {
+                    routes.send_invalid(id, token, reason);
+                    continue;
+                }
+            };
+            for num in range {
+                if !routes.job_queued(id) {
+                    return Ok(());
+                }
+                if job_sender.send((id, num)).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        line.clear();
+    }
+    Ok(())
+}
+
+/// Serves one client connection from start to finish.
+fn handle_client(
+    id: ClientId,
+    stream: UnixStream,
+    job_sender: channel::Sender<(ClientId, u64)>,
+    routes: Arc<Routes>,
+    format: Format,
+    shutdown: &AtomicBool,
+) -> io::Result<()> {
+    let (response_sender, response_receiver) = channel::unbounded();
+    routes.add(id, response_sender);
+    let writer = {
+        let stream = stream.try_clone()?;
+        let routes = routes.clone();
+        thread::spawn(move || write_responses(id, stream, response_receiver, format, &routes))
+    };
+    let read = read_jobs(id, &stream, &job_sender, &routes, shutdown);
+    drop(job_sender);
+    routes.done_reading(id);
+    writer.join().expect("Err waiting client writer joining!");
+    read
+}
+
+/// Runs farm as a service: listens for connections on a Unix domain socket at `path`, reads
+/// newline-separated numbers from each client, and writes each result back to the client that
+/// asked for it (in `format`) as soon as it is done. Clients are served concurrently by one pool
+/// of `options.threads` workers. A client that shuts down its sending side gets its remaining
+/// results and then sees the connection close; the numbers of a client that disconnects
+/// altogether are dropped.
+///
+/// Once `shutdown` is set, the daemon stops accepting connections and reading numbers, finishes
+/// the numbers already queued, and removes the socket file.
+pub fn serve(
+    path: &Path,
+    options: &Options,
+    format: Format,
+    shutdown: Arc<AtomicBool>,
+) -> io::Result<()> {
+    let listener = UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    let num_threads = options.threads.max(1);
+    let (job_sender, job_receiver) = channel::bounded(num_threads * QUEUE_DEPTH_PER_WORKER);
+    let routes = Arc::new(Routes::default());
+
+    let mut workers = Vec::new();
+    for _ in 0..num_threads {
+        let job_receiver: channel::Receiver<(ClientId, u64)> = job_receiver.clone();
+        let routes = routes.clone();
+        let options = options.clone();
+        workers.push(thread::spawn(move || {
+            for (id, num) in job_receiver {
+                if routes.is_connected(id) {
+                    routes.route_result(id, factor_one(num, &options));
+                }
+            }
+        }));
+    }
+
+    let mut clients = Vec::new();
+    let mut next_id: ClientId = 0;
+    let mut accepted = Ok(());
+    while !shutdown.load(Ordering::SeqCst) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(SHUTDOWN_POLL_INTERVAL);
+                continue;
+            }
+            Err(err) => {
+                accepted = Err(err);
+                break;
+            }
+        };
+        let id = next_id;
+        next_id += 1;
+        let job_sender = job_sender.clone();
+        let routes = routes.clone();
+        let shutdown = shutdown.clone();
+        clients.push(thread::spawn(move || {
+            let served = stream
+                .set_nonblocking(false)
+                .and_then(|_| handle_client(id, stream, job_sender, routes, format, &shutdown));
+            if let Err(err) = served {
+                eprintln!("Error serving client {}: {}", id, err);
+            }
+        }));
+    }
+
+    drop(listener);
+    let removed = fs::remove_file(path);
+    drop(job_sender);
+    for handle in clients {
+        handle.join().expect("Err waiting client joining!");
+    }
+    for handle in workers {
+        handle.join().expect("Err waiting threads joining!");
+    }
+    accepted.and(removed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+    use std::process;
+
+    /// Starts a daemon with two workers on a fresh socket, and returns what's needed to talk to
+    /// it and shut it down.
+    fn start(name: &str) -> (PathBuf, Arc<AtomicBool>, thread::JoinHandle<io::Result<()>>) {
+        let path = std::env::temp_dir().join(format!("farm-{}-{}.sock", process::id(), name));
+        let _ = fs::remove_file(&path);
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let options = Options {
+            threads: 2,
+            ..Options::default()
+        };
+        let daemon = {
+            let path = path.clone();
+            let shutdown = shutdown.clone();
+            thread::spawn(move || serve(&path, &options, Format::Text, shutdown))
+        };
+        while !path.exists() {
+            thread::sleep(Duration::from_millis(5));
+        }
+        (path, shutdown, daemon)
+    }
+
+    /// Sends `input`, signals that nothing more is coming, and returns the result lines with
+    /// their timings cut off, sorted.
+    fn request(path: &Path, input: &str) -> Vec<String> {
+        let mut stream = UnixStream::connect(path).unwrap();
+        stream.write_all(input.as_bytes()).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut output = String::new();
+        stream.read_to_string(&mut output).unwrap();
+        let mut lines: Vec<String> = output
+            .lines()
+            .map(|line| line.split(" [time").next().unwrap().to_string())
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn test_serves_concurrent_clients() {
+        let (path, shutdown, daemon) = start("concurrent");
+        let clients: Vec<_> = ["12\n15 7\n", "1..=3\nfoo\n"]
+            .iter()
+            .map(|&input| {
+                let path = path.clone();
+                thread::spawn(move || request(&path, input))
+            })
+            .collect();
+        let outputs: Vec<Vec<String>> = clients.into_iter().map(|c| c.join().unwrap()).collect();
+        assert_eq!(outputs[0], vec!["12 = 2 * 2 * 3", "15 = 3 * 5", "7 = 7"]);
+        assert_eq!(
+            outputs[1],
+            vec!["1 = 1", "2 = 2", "3 = 3", "foo: not a valid number"]
+        );
+
+        shutdown.store(true, Ordering::SeqCst);
+        daemon.join().unwrap().unwrap();
+        assert!(!path.exists());
+        assert!(UnixStream::connect(&path).is_err());
+    }
+
+    #[test]
+    fn test_client_disconnecting_mid_batch() {
+        let (path, shutdown, daemon) = start("disconnect");
+        {
+            let mut stream = UnixStream::connect(&path).unwrap();
+            let slow = "9999999999971\n".repeat(20);
+            stream.write_all(slow.as_bytes()).unwrap();
+            // Dropped here without reading anything back.
+        }
+        assert_eq!(request(&path, "10\n"), vec!["10 = 2 * 5"]);
+
+        shutdown.store(true, Ordering::SeqCst);
+        daemon.join().unwrap().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_shutdown_finishes_queued_numbers() {
+        let (path, shutdown, daemon) = start("shutdown");
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(b"9999999999971\n21\n").unwrap();
+        // The client never closes its side; the daemon shuts down regardless, but only after
+        // delivering what it had already been sent.
+        thread::sleep(Duration::from_millis(200));
+        shutdown.store(true, Ordering::SeqCst);
+        let mut output = String::new();
+        stream.read_to_string(&mut output).unwrap();
+        let mut lines: Vec<&str> = output
+            .lines()
+            .map(|line| line.split(" [time").next().unwrap())
+            .collect();
+        lines.sort_unstable();
+        assert_eq!(lines, vec!["21 = 3 * 7", "9999999999971 = 9999999999971"]);
+        daemon.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// A number to factor, tagged with its position in the input.
+pub(crate) type Job = (usize, u64);
+
+/// Decides how many jobs a worker should take from the work queue at once, given how many are
+/// waiting in it. While the queue is long, workers take a share of it so the per-job cost of the
+/// queue disappears on tiny inputs; as it runs dry they take one job at a time, so that a slow
+/// number doesn't hold a batch back from idle workers.
+pub(crate) fn batch_size(queued: usize, num_workers: usize) -> usize {
+    (queued / (2 * num_workers)).max(1)
+}
+
+/// The batches each worker has taken from the work queue but not yet finished. A worker takes jobs
+/// from the front of its own deque; a worker that runs out of work takes the back half of someone
+/// else's, so a batch stuck behind one slow number still gets done in parallel.
+pub(crate) struct WorkerQueues {
+    local: Vec<Mutex<VecDeque<Job>>>,
+}
+
+impl WorkerQueues {
+    pub(crate) fn new(num_workers: usize) -> WorkerQueues {
+        WorkerQueues {
+            local: (0..num_workers)
+                .map(|_| Mutex::new(VecDeque::new()))
+                .collect(),
+        }
+    }
+
+    pub(crate) fn num_workers(&self) -> usize {
+        self.local.len()
+    }
+
+    /// Takes the next job from `worker`'s own deque.
+    pub(crate) fn pop(&self, worker: usize) -> Option<Job> {
+        self.local[worker].lock().unwrap().pop_front()
+    }
+
+    /// Adds a batch fresh from the work queue to `worker`'s deque.
+    pub(crate) fn push_batch<I: IntoIterator<Item = Job>>(&self, worker: usize, batch: I) {
+        self.local[worker].lock().unwrap().extend(batch);
+    }
+
+    /// Moves the back half of the first non-empty deque belonging to another worker into
+    /// `thief`'s deque, and returns the first of the stolen jobs. Only one lock is held at a time.
+    pub(crate) fn steal(&self, thief: usize) -> Option<Job> {
+        let num_workers = self.num_workers();
+        for offset in 1..num_workers {
+            let victim = (thief + offset) % num_workers;
+            let mut stolen = {
+                let mut victim_jobs = self.local[victim].lock().unwrap();
+                let len = victim_jobs.len();
+                if len == 0 {
+                    continue;
+                }
+                victim_jobs.split_off(len / 2)
+            };
+            let job = stolen.pop_front();
+            self.local[thief].lock().unwrap().extend(stolen);
+            return job;
+        }
+        None
+    }
+
+    /// Empties `worker`'s deque, returning the jobs it never got to.
+    pub(crate) fn drain(&self, worker: usize) -> Vec<Job> {
+        self.local[worker].lock().unwrap().drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_batch_size() {
+        assert_eq!(batch_size(256, 4), 32);
+        assert_eq!(batch_size(7, 4), 1);
+        assert_eq!(batch_size(0, 4), 1);
+        assert_eq!(batch_size(64, 1), 32);
+    }
+
+    #[test]
+    fn test_steal_takes_back_half() {
+        let queues = WorkerQueues::new(3);
+        queues.push_batch(1, (0..5).map(|i| (i, i as u64 * 10)));
+        assert_eq!(queues.pop(1), Some((0, 0)));
+
+        // Worker 1 has 4 jobs left; worker 0 takes the last 2.
+        assert_eq!(queues.steal(0), Some((3, 30)));
+        assert_eq!(queues.pop(0), Some((4, 40)));
+        assert_eq!(queues.pop(0), None);
+        assert_eq!(queues.drain(1), vec![(1, 10), (2, 20)]);
+
+        // Nothing left anywhere.
+        assert_eq!(queues.steal(2), None);
+    }
+
+    #[test]
+    fn test_steal_single_job() {
+        let queues = WorkerQueues::new(2);
+        queues.push_batch(0, vec![(7, 70)]);
+        assert_eq!(queues.steal(1), Some((7, 70)));
+        assert_eq!(queues.pop(0), None);
+        assert_eq!(queues.drain(1), vec![]);
+    }
+}
@@ -0,0 +1,354 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How many trial divisions happen between two deadline checks. Reading the clock is much more
+/// expensive than a division, so it is only done once in a while.
+const DEADLINE_CHECK_INTERVAL: u32 = 4096;
+
+/// The prime factors of a single input number, together with how long it took to find them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Factorization {
+    /// The number that was factored
+    pub input: u64,
+    /// Prime factors in nondecreasing order. Empty for 0 and 1, which have no prime factors.
+    pub factors: Vec<u64>,
+    /// Time spent factoring `input`
+    pub duration: Duration,
+    /// Whether the factors came from the cache of earlier results rather than being computed
+    pub cached: bool,
+    /// Whether factoring was abandoned because it took longer than allowed. In that case
+    /// `factors` only holds the factors found before giving up.
+    pub timed_out: bool,
+    /// The panic message, if factoring `input` panicked. `factors` is empty in that case.
+    pub failure: Option<String>,
+}
+
+impl Factorization {
+    /// Returns true if the input was a prime number.
+    pub fn is_prime(&self) -> bool {
+        self.factors.len() == 1
+    }
+
+    /// Returns the distinct prime factors with their exponents, e.g. `[(2, 3), (3, 2), (5, 1)]`
+    /// for 360.
+    pub fn powers(&self) -> Vec<(u64, u32)> {
+        let mut powers: Vec<(u64, u32)> = Vec::new();
+        for &factor in &self.factors {
+            match powers.last_mut() {
+                Some((last, exponent)) if *last == factor => *exponent += 1,
+                _ => powers.push((factor, 1)),
+            }
+        }
+        powers
+    }
+}
+
+impl fmt::Display for Factorization {
+    /// Formats the factorization the same way factor.py does, e.g. `12 = 2 * 2 * 3`, followed by
+    /// the time it took.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} = ", self.input)?;
+        if let Some(failure) = &self.failure {
+            write!(f, "(failed: {})", failure)?;
+        } else if self.timed_out {
+            write!(f, "(timed out)")?;
+        } else if self.factors.is_empty() {
+            write!(f, "{}", self.input)?;
+        } else {
+            let factors_str = self
+                .factors
+                .iter()
+                .map(|factor| factor.to_string())
+                .collect::<Vec<String>>()
+                .join(" * ");
+            write!(f, "{}", factors_str)?;
+        }
+        write!(f, " [time: {:?}]", self.duration)
+    }
+}
+
+/// Divides `num` by every prime in `primes`, then by every candidate from `next_candidate` upwards
+/// (2 and then odd numbers only), until the candidate passes the square root of whatever is left;
+/// at that point the remainder must itself be prime. `next_candidate` has to be larger than every
+/// number in `primes`.
+///
+/// Calls `expired` once every `DEADLINE_CHECK_INTERVAL` divisions, and gives up as soon as it
+/// returns true, returning the factors found so far and `true`.
+fn trial_division<E>(
+    num: u64,
+    primes: &[u64],
+    next_candidate: u64,
+    mut expired: E,
+) -> (Vec<u64>, bool)
+where
+    E: FnMut() -> bool,
+{
+    let mut factors = Vec::new();
+    if num <= 1 {
+        return (factors, false);
+    }
+
+    let mut curr_num = num;
+    let mut until_check = DEADLINE_CHECK_INTERVAL;
+    // Divides out every power of `factor`, or returns false if it's time to give up.
+    let mut divide_out = |factor: u64, curr_num: &mut u64| {
+        while curr_num.is_multiple_of(factor) {
+            factors.push(factor);
+            *curr_num /= factor;
+        }
+        until_check -= 1;
+        if until_check == 0 {
+            until_check = DEADLINE_CHECK_INTERVAL;
+            return !expired();
+        }
+        true
+    };
+    for &prime in primes {
+        if prime > curr_num / prime {
+            break;
+        }
+        if !divide_out(prime, &mut curr_num) {
+            return (factors, true);
+        }
+    }
+    let mut factor = next_candidate;
+    while factor <= curr_num / factor {
+        if !divide_out(factor, &mut curr_num) {
+            return (factors, true);
+        }
+        factor += if factor == 2 { 1 } else { 2 };
+    }
+    if curr_num > 1 {
+        factors.push(curr_num);
+    }
+    (factors, false)
+}
+
+/// Runs trial division and wraps the outcome up with the time it took.
+fn timed_trial_division(
+    num: u64,
+    primes: &[u64],
+    next_candidate: u64,
+    deadline: Option<Instant>,
+) -> Factorization {
+    let start = Instant::now();
+    let expired = || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+    let (factors, timed_out) = trial_division(num, primes, next_candidate, expired);
+    Factorization {
+        input: num,
+        factors,
+        duration: start.elapsed(),
+        cached: false,
+        timed_out,
+        failure: None,
+    }
+}
+
+/// Determines the prime factors of a number by trial division.
+pub fn factor_number(num: u64) -> Factorization {
+    timed_trial_division(num, &[], 2, None)
+}
+
+/// Like `factor_number`, but gives up once `deadline` has passed.
+pub fn factor_number_until(num: u64, deadline: Option<Instant>) -> Factorization {
+    timed_trial_division(num, &[], 2, deadline)
+}
+
+/// The primes up to some bound, computed once and then shared read-only between workers so trial
+/// division only ever tries prime candidates below that bound.
+#[derive(Debug)]
+pub struct Sieve {
+    primes: Vec<u64>,
+    /// First candidate to try once the primes run out: odd and larger than every sieved prime
+    next_candidate: u64,
+}
+
+impl Sieve {
+    /// Runs the sieve of Eratosthenes over every number up to and including `bound`.
+    pub fn new(bound: u64) -> Sieve {
+        let bound = bound as usize;
+        let mut composite = vec![false; bound + 1];
+        let mut primes = Vec::new();
+        for num in 2..=bound {
+            if composite[num] {
+                continue;
+            }
+            primes.push(num as u64);
+            let mut multiple = num * num;
+            while multiple <= bound {
+                composite[multiple] = true;
+                multiple += num;
+            }
+        }
+        let next_candidate = if primes.is_empty() {
+            2
+        } else {
+            (bound as u64 + 1) | 1
+        };
+        Sieve {
+            primes,
+            next_candidate,
+        }
+    }
+
+    /// Returns the sieved primes.
+    pub fn primes(&self) -> &[u64] {
+        &self.primes
+    }
+
+    /// Determines the prime factors of a number by trial division, using the sieved primes first.
+    pub fn factor(&self, num: u64) -> Factorization {
+        self.factor_until(num, None)
+    }
+
+    /// Like `factor`, but gives up once `deadline` has passed.
+    pub fn factor_until(&self, num: u64, deadline: Option<Instant>) -> Factorization {
+        timed_trial_division(num, &self.primes, self.next_candidate, deadline)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_factor_number() {
+        assert_eq!(factor_number(0).factors, Vec::<u64>::new());
+        assert_eq!(factor_number(1).factors, Vec::<u64>::new());
+        assert_eq!(factor_number(2).factors, vec![2]);
+        assert_eq!(factor_number(4).factors, vec![2, 2]);
+        assert_eq!(factor_number(9).factors, vec![3, 3]);
+        assert_eq!(factor_number(15).factors, vec![3, 5]);
+        assert_eq!(factor_number(360).factors, vec![2, 2, 2, 3, 3, 5]);
+        assert_eq!(
+            factor_number(600_851_475_143).factors,
+            vec![71, 839, 1471, 6857]
+        );
+        assert_eq!(
+            factor_number(999_999_999_989).factors,
+            vec![999_999_999_989]
+        );
+    }
+
+    #[test]
+    fn test_is_prime() {
+        assert!(!factor_number(1).is_prime());
+        assert!(factor_number(2).is_prime());
+        assert!(factor_number(13).is_prime());
+        assert!(!factor_number(25).is_prime());
+    }
+
+    #[test]
+    fn test_sieve() {
+        assert_eq!(
+            Sieve::new(30).primes(),
+            &[2, 3, 5, 7, 11, 13, 17, 19, 23, 29]
+        );
+        assert_eq!(Sieve::new(1).primes(), &[] as &[u64]);
+        assert_eq!(Sieve::new(1 << 20).primes().len(), 82_025);
+    }
+
+    #[test]
+    fn test_sieve_factor_matches_plain_trial_division() {
+        for &bound in [0, 1, 2, 10, 97, 1000].iter() {
+            let sieve = Sieve::new(bound);
+            for num in (0..2000).chain(vec![
+                600_851_475_143,
+                999_999_999_989,
+                1009 * 1009,
+                1013 * 1019 * 2,
+            ]) {
+                assert_eq!(
+                    sieve.factor(num).factors,
+                    factor_number(num).factors,
+                    "factoring {} with a sieve up to {}",
+                    num,
+                    bound
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_deadline() {
+        let passed = Some(Instant::now());
+        let factorization = factor_number_until(9_999_999_999_971, passed);
+        assert!(factorization.timed_out);
+        assert!(factorization.factors.is_empty());
+
+        // Small inputs finish before the first deadline check.
+        let factorization = factor_number_until(360, passed);
+        assert!(!factorization.timed_out);
+        assert_eq!(factorization.factors, vec![2, 2, 2, 3, 3, 5]);
+
+        let later = Some(Instant::now() + Duration::from_secs(3600));
+        let factorization = Sieve::new(1000).factor_until(999_999_999_989, later);
+        assert!(!factorization.timed_out);
+        assert_eq!(factorization.factors, vec![999_999_999_989]);
+    }
+
+    #[test]
+    fn test_deadline_checks_are_rare() {
+        // A prime goes through every candidate up to its square root: 2, and then the odd ones.
+        let prime = 999_999_999_989;
+        let divisions = 1 + ((prime as f64).sqrt() as u32 - 1) / 2;
+        let mut checks = 0;
+        let (factors, timed_out) = trial_division(prime, &[], 2, || {
+            checks += 1;
+            false
+        });
+        assert_eq!((factors, timed_out), (vec![prime], false));
+        assert_eq!(checks, divisions / DEADLINE_CHECK_INTERVAL);
+
+        // Gives up at the first check that finds the deadline passed.
+        let mut checks = 0;
+        let (_, timed_out) = trial_division(prime, &[], 2, || {
+            checks += 1;
+            checks == 3
+        });
+        assert!(timed_out);
+        assert_eq!(checks, 3);
+    }
+
+    #[test]
+    fn test_powers() {
+        assert_eq!(factor_number(360).powers(), vec![(2, 3), (3, 2), (5, 1)]);
+        assert_eq!(factor_number(13).powers(), vec![(13, 1)]);
+        assert_eq!(factor_number(1).powers(), vec![]);
+    }
+
+    #[test]
+    fn test_display() {
+        let factorization = Factorization {
+            input: 12,
+            factors: vec![2, 2, 3],
+            duration: Duration::from_micros(5),
+            cached: false,
+            timed_out: false,
+            failure: None,
+        };
+        assert_eq!(factorization.to_string(), "12 = 2 * 2 * 3 [time: 5µs]");
+        let factorization = Factorization {
+            input: 1,
+            factors: vec![],
+            duration: Duration::from_micros(5),
+            cached: false,
+            timed_out: false,
+            failure: None,
+        };
+        assert_eq!(factorization.to_string(), "1 = 1 [time: 5µs]");
+        let factorization = Factorization {
+            timed_out: true,
+            ..factorization
+        };
+        assert_eq!(factorization.to_string(), "1 = (timed out) [time: 5µs]");
+        let factorization = Factorization {
+            failure: Some("attempt to multiply with overflow".to_string()),
+            ..factorization
+        };
+        assert_eq!(
+            factorization.to_string(),
+            "1 = (failed: attempt to multiply with overflow) [time: 5µs]"
+        );
+    }
+}
@@ -0,0 +1,435 @@
+use crate::Factorization;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// How result records are written out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// `360 = 2 * 2 * 2 * 3 * 3 * 5 [time: ...]`, the way factor.py prints it
+    Text,
+    /// `360 = 2^3 * 3^2 * 5 [time: ...]`
+    Exp,
+    /// A single JSON array with one object per input
+    Json,
+    /// One JSON object per line
+    Ndjson,
+    /// Comma-separated values with a header row
+    Csv,
+}
+
+impl Format {
+    /// Returns true for the formats meant for other programs rather than people, which need
+    /// stdout to themselves.
+    pub fn is_machine_readable(self) -> bool {
+        !matches!(self, Format::Text | Format::Exp)
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "exp" => Ok(Format::Exp),
+            "json" => Ok(Format::Json),
+            "ndjson" => Ok(Format::Ndjson),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!(
+                "unknown format \"{}\" (expected text, exp, json, ndjson or csv)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Format::Text => "text",
+            Format::Exp => "exp",
+            Format::Json => "json",
+            Format::Ndjson => "ndjson",
+            Format::Csv => "csv",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Quotes and escapes `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Quotes a CSV field if it contains anything that would otherwise break the row apart.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Formats the factors of `factorization` as `2^3 * 3^2 * 5`.
+fn exponent_form(factorization: &Factorization) -> String {
+    if factorization.factors.is_empty() {
+        return factorization.input.to_string();
+    }
+    factorization
+        .powers()
+        .into_iter()
+        .map(|(factor, exponent)| {
+            if exponent == 1 {
+                factor.to_string()
+            } else {
+                format!("{}^{}", factor, exponent)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" * ")
+}
+
+fn duration_ms(factorization: &Factorization) -> String {
+    format!("{:.3}", factorization.duration.as_secs_f64() * 1000.0)
+}
+
+/// Writes result records to `out` one at a time in a given `Format`, taking care of whatever
+/// surrounds the records (the CSV header, the brackets and commas of a JSON array).
+pub struct RecordWriter<W: Write> {
+    out: W,
+    format: Format,
+    records: usize,
+}
+
+impl<W: Write> RecordWriter<W> {
+    /// Creates the writer and writes any header the format needs.
+    pub fn new(format: Format, mut out: W) -> io::Result<RecordWriter<W>> {
+        match format {
+            Format::Json => write!(out, "[")?,
+            Format::Csv => writeln!(out, "input,factors,prime,duration_ms,error")?,
+            _ => {}
+        }
+        Ok(RecordWriter {
+            out,
+            format,
+            records: 0,
+        })
+    }
+
+    /// Starts a new JSON array element, or does nothing for other formats.
+    fn separate(&mut self) -> io::Result<()> {
+        if self.format == Format::Json {
+            if self.records > 0 {
+                write!(self.out, ",")?;
+            }
+            writeln!(self.out)?;
+        }
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Writes the record for one successfully factored input.
+    pub fn write_result(&mut self, factorization: &Factorization) -> io::Result<()> {
+        if let Some(failure) = &factorization.failure {
+            return self.write_failed(factorization, &format!("panicked: {}", failure));
+        }
+        if factorization.timed_out {
+            return self.write_failed(factorization, "timed out");
+        }
+        self.separate()?;
+        match self.format {
+            Format::Text => writeln!(self.out, "{}", factorization),
+            Format::Exp => writeln!(
+                self.out,
+                "{} = {} [time: {:?}]",
+                factorization.input,
+                exponent_form(factorization),
+                factorization.duration
+            ),
+            Format::Json | Format::Ndjson => {
+                let factors = factorization
+                    .powers()
+                    .into_iter()
+                    .map(|(factor, exponent)| {
+                        format!("{{\"factor\":{},\"exponent\":{}}}", factor, exponent)
+                    })
+                    .collect::<Vec<String>>()
+                    .join(",");
+                write!(
+                    self.out,
+                    "{{\"input\":{},\"factors\":[{}],\"prime\":{},\"duration_ms\":{}}}",
+                    factorization.input,
+                    factors,
+                    factorization.is_prime(),
+                    duration_ms(factorization)
+                )?;
+                if self.format == Format::Ndjson {
+                    writeln!(self.out)?;
+                }
+                Ok(())
+            }
+            Format::Csv => {
+                let factors = if factorization.factors.is_empty() {
+                    String::new()
+                } else {
+                    exponent_form(factorization)
+                };
+                writeln!(
+                    self.out,
+                    "{},{},{},{},",
+                    factorization.input,
+                    factors,
+                    factorization.is_prime(),
+                    duration_ms(factorization)
+                )
+            }
+        }
+    }
+
+    /// Writes the record for an input that was abandoned, because it hit the timeout or because
+    /// factoring it panicked. This is an error record that still carries how long was spent on it.
+    fn write_failed(&mut self, factorization: &Factorization, error: &str) -> io::Result<()> {
+        self.separate()?;
+        match self.format {
+            Format::Text | Format::Exp => writeln!(self.out, "{}", factorization),
+            Format::Json | Format::Ndjson => {
+                write!(
+                    self.out,
+                    "{{\"input\":{},\"error\":{},\"duration_ms\":{}}}",
+                    factorization.input,
+                    json_string(error),
+                    duration_ms(factorization)
+                )?;
+                if self.format == Format::Ndjson {
+                    writeln!(self.out)?;
+                }
+                Ok(())
+            }
+            Format::Csv => writeln!(
+                self.out,
+                "{},,,{},{}",
+                factorization.input,
+                duration_ms(factorization),
+                csv_field(error)
+            ),
+        }
+    }
+
+    /// Writes the record for an input that couldn't be factored, such as one that isn't a valid
+    /// number.
+    pub fn write_error(&mut self, input: &str, error: &str) -> io::Result<()> {
+        self.separate()?;
+        match self.format {
+            Format::Text | Format::Exp => writeln!(self.out, "{}: {}", input, error),
+            Format::Json | Format::Ndjson => {
+                write!(
+                    self.out,
+                    "{{\"input\":{},\"error\":{}}}",
+                    json_string(input),
+                    json_string(error)
+                )?;
+                if self.format == Format::Ndjson {
+                    writeln!(self.out)?;
+                }
+                Ok(())
+            }
+            Format::Csv => writeln!(self.out, "{},,,,{}", csv_field(input), csv_field(error)),
+        }
+    }
+
+    /// Writes any trailer the format needs, flushes, and hands back the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.format == Format::Json {
+            if self.records > 0 {
+                writeln!(self.out)?;
+            }
+            writeln!(self.out, "]")?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::factor_number;
+    use std::time::Duration;
+
+    fn factorization(input: u64) -> Factorization {
+        Factorization {
+            duration: Duration::from_micros(1500),
+            ..factor_number(input)
+        }
+    }
+
+    /// Writes 360, 13 and 1 followed by an unparsable token, and returns the output.
+    fn render(format: Format) -> String {
+        let mut writer = RecordWriter::new(format, Vec::new()).unwrap();
+        for &input in [360, 13, 1].iter() {
+            writer.write_result(&factorization(input)).unwrap();
+        }
+        writer.write_error("4x\"2", "not a valid number").unwrap();
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!("exp".parse::<Format>(), Ok(Format::Exp));
+        assert_eq!("ndjson".parse::<Format>(), Ok(Format::Ndjson));
+        assert!("yaml".parse::<Format>().is_err());
+        for &format in [
+            Format::Text,
+            Format::Exp,
+            Format::Json,
+            Format::Ndjson,
+            Format::Csv,
+        ]
+        .iter()
+        {
+            assert_eq!(format.to_string().parse::<Format>(), Ok(format));
+        }
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(
+            render(Format::Text),
+            "360 = 2 * 2 * 2 * 3 * 3 * 5 [time: 1.5ms]\n\
+             13 = 13 [time: 1.5ms]\n\
+             1 = 1 [time: 1.5ms]\n\
+             4x\"2: not a valid number\n"
+        );
+    }
+
+    #[test]
+    fn test_exp() {
+        assert_eq!(
+            render(Format::Exp),
+            "360 = 2^3 * 3^2 * 5 [time: 1.5ms]\n\
+             13 = 13 [time: 1.5ms]\n\
+             1 = 1 [time: 1.5ms]\n\
+             4x\"2: not a valid number\n"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(
+            render(Format::Json),
+            "[\n\
+             {\"input\":360,\"factors\":[{\"factor\":2,\"exponent\":3},{\"factor\":3,\"exponent\":2},\
+             {\"factor\":5,\"exponent\":1}],\"prime\":false,\"duration_ms\":1.500},\n\
+             {\"input\":13,\"factors\":[{\"factor\":13,\"exponent\":1}],\"prime\":true,\
+             \"duration_ms\":1.500},\n\
+             {\"input\":1,\"factors\":[],\"prime\":false,\"duration_ms\":1.500},\n\
+             {\"input\":\"4x\\\"2\",\"error\":\"not a valid number\"}\n\
+             ]\n"
+        );
+    }
+
+    #[test]
+    fn test_empty_json_is_valid() {
+        let writer = RecordWriter::new(Format::Json, Vec::new()).unwrap();
+        assert_eq!(writer.finish().unwrap(), b"[]\n");
+    }
+
+    #[test]
+    fn test_ndjson() {
+        assert_eq!(
+            render(Format::Ndjson),
+            "{\"input\":360,\"factors\":[{\"factor\":2,\"exponent\":3},{\"factor\":3,\"exponent\":2},\
+             {\"factor\":5,\"exponent\":1}],\"prime\":false,\"duration_ms\":1.500}\n\
+             {\"input\":13,\"factors\":[{\"factor\":13,\"exponent\":1}],\"prime\":true,\
+             \"duration_ms\":1.500}\n\
+             {\"input\":1,\"factors\":[],\"prime\":false,\"duration_ms\":1.500}\n\
+             {\"input\":\"4x\\\"2\",\"error\":\"not a valid number\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_csv() {
+        assert_eq!(
+            render(Format::Csv),
+            "input,factors,prime,duration_ms,error\n\
+             360,2^3 * 3^2 * 5,false,1.500,\n\
+             13,13,true,1.500,\n\
+             1,,false,1.500,\n\
+             \"4x\"\"2\",,,,not a valid number\n"
+        );
+    }
+
+    #[test]
+    fn test_timed_out_records() {
+        let timed_out = Factorization {
+            timed_out: true,
+            factors: vec![2],
+            ..factorization(9_999_999_999_942)
+        };
+        let render_timed_out = |format| {
+            let mut writer = RecordWriter::new(format, Vec::new()).unwrap();
+            writer.write_result(&timed_out).unwrap();
+            String::from_utf8(writer.finish().unwrap()).unwrap()
+        };
+        assert_eq!(
+            render_timed_out(Format::Text),
+            "9999999999942 = (timed out) [time: 1.5ms]\n"
+        );
+        assert_eq!(
+            render_timed_out(Format::Ndjson),
+            "{\"input\":9999999999942,\"error\":\"timed out\",\"duration_ms\":1.500}\n"
+        );
+        assert_eq!(
+            render_timed_out(Format::Csv),
+            "input,factors,prime,duration_ms,error\n\
+             9999999999942,,,1.500,timed out\n"
+        );
+    }
+
+    #[test]
+    fn test_failed_records() {
+        let failed = Factorization {
+            failure: Some("bad \"input\", sorry".to_string()),
+            factors: vec![],
+            ..factorization(12)
+        };
+        let render_failed = |format| {
+            let mut writer = RecordWriter::new(format, Vec::new()).unwrap();
+            writer.write_result(&failed).unwrap();
+            String::from_utf8(writer.finish().unwrap()).unwrap()
+        };
+        assert_eq!(
+            render_failed(Format::Text),
+            "12 = (failed: bad \"input\", sorry) [time: 1.5ms]\n"
+        );
+        assert_eq!(
+            render_failed(Format::Ndjson),
+            "{\"input\":12,\"error\":\"panicked: bad \\\"input\\\", sorry\",\"duration_ms\":1.500}\n"
+        );
+        assert_eq!(
+            render_failed(Format::Csv),
+            "input,factors,prime,duration_ms,error\n\
+             12,,,1.500,\"panicked: bad \"\"input\"\", sorry\"\n"
+        );
+    }
+
+    #[test]
+    fn test_json_string_escapes() {
+        assert_eq!(json_string("a\"b\\c\nd\u{1}"), "\"a\\\"b\\\\c\\nd\\u0001\"");
+    }
+}
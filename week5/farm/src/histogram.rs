@@ -0,0 +1,170 @@
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// Boundaries between histogram buckets: one bucket per power of ten from 1µs to 10s, plus one
+/// below 1µs and one for anything from 10s up.
+const BUCKET_BOUNDS: [Duration; 8] = [
+    Duration::from_micros(1),
+    Duration::from_micros(10),
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+    Duration::from_secs(10),
+];
+
+/// Number of histogram buckets.
+pub const NUM_BUCKETS: usize = BUCKET_BOUNDS.len() + 1;
+
+/// Counts of per-number times on a log scale.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Histogram {
+    counts: [usize; NUM_BUCKETS],
+}
+
+/// Returns the index of the bucket `duration` falls into. Each bucket includes its lower bound.
+fn bucket(duration: Duration) -> usize {
+    BUCKET_BOUNDS
+        .iter()
+        .take_while(|&&bound| duration >= bound)
+        .count()
+}
+
+/// Describes the times that fall into bucket `index`, e.g. `10µs-100µs`.
+fn bucket_label(index: usize) -> String {
+    if index == 0 {
+        format!("<{:?}", BUCKET_BOUNDS[0])
+    } else if index == BUCKET_BOUNDS.len() {
+        format!(">={:?}", BUCKET_BOUNDS[index - 1])
+    } else {
+        format!("{:?}-{:?}", BUCKET_BOUNDS[index - 1], BUCKET_BOUNDS[index])
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram::default()
+    }
+
+    pub fn record(&mut self, duration: Duration) {
+        self.counts[bucket(duration)] += 1;
+    }
+
+    /// How many times fell into each bucket, from fastest to slowest.
+    pub fn counts(&self) -> &[usize; NUM_BUCKETS] {
+        &self.counts
+    }
+
+    /// Draws the histogram as one bar per bucket, scaled so the fullest bucket is `width`
+    /// characters wide. Empty buckets at either end are left out; nothing is drawn if there are
+    /// no times at all.
+    pub fn write_chart<W: Write + ?Sized>(&self, width: usize, out: &mut W) -> io::Result<()> {
+        let first = match self.counts.iter().position(|&count| count > 0) {
+            Some(first) => first,
+            None => return Ok(()),
+        };
+        let last = self.counts.iter().rposition(|&count| count > 0).unwrap();
+        let max = self.counts.iter().copied().max().unwrap();
+        let labels: Vec<String> = (first..=last).map(bucket_label).collect();
+        let label_width = labels
+            .iter()
+            .map(|label| label.chars().count())
+            .max()
+            .unwrap();
+        for (label, &count) in labels.iter().zip(&self.counts[first..=last]) {
+            // Round up, so that no bucket with anything in it looks empty.
+            let bar = (count * width).div_ceil(max);
+            writeln!(
+                out,
+                "{:>label_width$} |{:<width$}| {}",
+                label,
+                "#".repeat(bar),
+                count,
+                label_width = label_width,
+                width = width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        assert_eq!(bucket(Duration::from_nanos(0)), 0);
+        assert_eq!(bucket(Duration::from_nanos(999)), 0);
+        assert_eq!(bucket(Duration::from_micros(1)), 1);
+        assert_eq!(bucket(Duration::from_nanos(9_999)), 1);
+        assert_eq!(bucket(Duration::from_micros(10)), 2);
+        assert_eq!(bucket(Duration::from_micros(250)), 3);
+        assert_eq!(bucket(Duration::from_millis(1)), 4);
+        assert_eq!(bucket(Duration::from_millis(99)), 5);
+        assert_eq!(bucket(Duration::from_millis(100)), 6);
+        assert_eq!(bucket(Duration::from_secs(9)), 7);
+        assert_eq!(bucket(Duration::from_secs(10)), 8);
+        assert_eq!(bucket(Duration::from_secs(3600)), 8);
+    }
+
+    #[test]
+    fn test_bucket_labels() {
+        let labels: Vec<String> = (0..NUM_BUCKETS).map(bucket_label).collect();
+        assert_eq!(
+            labels,
+            vec![
+                "<1µs",
+                "1µs-10µs",
+                "10µs-100µs",
+                "100µs-1ms",
+                "1ms-10ms",
+                "10ms-100ms",
+                "100ms-1s",
+                "1s-10s",
+                ">=10s"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_record() {
+        let mut histogram = Histogram::new();
+        for &micros in [0, 3, 5, 40, 2_000, 2_000_000].iter() {
+            histogram.record(Duration::from_micros(micros));
+        }
+        assert_eq!(histogram.counts(), &[1, 2, 1, 0, 1, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_write_chart() {
+        let mut histogram = Histogram::new();
+        // 20 numbers around 5µs, 10 around 50µs, none around 500µs, 1 around 5ms.
+        for _ in 0..20 {
+            histogram.record(Duration::from_micros(5));
+        }
+        for _ in 0..10 {
+            histogram.record(Duration::from_micros(50));
+        }
+        histogram.record(Duration::from_millis(5));
+        let mut output = Vec::new();
+        histogram.write_chart(10, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                "  1µs-10µs |##########| 20\n",
+                "10µs-100µs |#####     | 10\n",
+                " 100µs-1ms |          | 0\n",
+                "  1ms-10ms |#         | 1\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_write_empty_chart() {
+        let mut output = Vec::new();
+        Histogram::new().write_chart(10, &mut output).unwrap();
+        assert!(output.is_empty());
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::fmt;
+use std::io;
+use std::ops::RangeInclusive;
+use thiserror::Error;
+
+/// Why a token couldn't be turned into numbers.
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum TokenError {
+    #[error("not a valid number")]
+    InvalidNumber,
+    #[error("not a valid range")]
+    InvalidRange,
+    #[error("too large (numbers go up to {})", u64::MAX)]
+    TooLarge,
+}
+
+/// Where an input token came from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    /// The nth number argument, counting from 1
+    Argument(usize),
+    /// The nth `--range` option, counting from 1
+    RangeOption(usize),
+    /// A line of stdin, counting from 1
+    Stdin { line: usize },
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Argument(position) => write!(f, "argument {}", position),
+            Source::RangeOption(position) => write!(f, "--range #{}", position),
+            Source::Stdin { line } => write!(f, "stdin line {}", line),
+        }
+    }
+}
+
+/// An input token that isn't a valid number or range, and where it came from.
+#[derive(Clone, Debug, Error, PartialEq)]
+#[error("{origin}: {token:?} is {reason}")]
+pub struct InvalidToken {
+    pub token: String,
+    pub origin: Source,
+    pub reason: TokenError,
+}
+
+/// Why the input numbers couldn't be read.
+#[derive(Debug, Error)]
+pub enum InputError {
+    /// Some tokens weren't valid. `valid` has the numbers from all the other tokens, for callers
+    /// that want to carry on without the bad ones.
+    #[error("{} invalid input{}", .errors.len(), if .errors.len() == 1 { "" } else { "s" })]
+    Invalid {
+        errors: Vec<InvalidToken>,
+        valid: Inputs,
+    },
+    #[error("error reading {origin}: {error}")]
+    Read {
+        origin: Source,
+        #[source]
+        error: io::Error,
+    },
+}
+
+/// Parses an integer literal: plain digits (`1000`), scientific notation (`1e6`, `2.5E3`), or a
+/// sum of those (`1e6+1000`). Scientific notation has to come out as a whole number.
+fn parse_literal(literal: &str) -> Result<u64, TokenError> {
+    literal.split('+').try_fold(0u64, |sum, term| {
+        let term = match term.find(['e', 'E']) {
+            None => parse_digits(term)?,
+            Some(e) => {
+                let (mantissa, exponent) = (&term[..e], &term[e + 1..]);
+                let exponent: u32 = parse_digits(exponent)?
+                    .try_into()
+                    .map_err(|_| TokenError::TooLarge)?;
+                let (whole, fraction) = match mantissa.find('.') {
+                    Some(dot) => (&mantissa[..dot], mantissa[dot + 1..].trim_end_matches('0')),
+                    None => (mantissa, ""),
+                };
+                let digits = parse_digits(&format!("{}{}", whole, fraction))?;
+                let shift = exponent
+                    .checked_sub(fraction.len() as u32)
+                    .ok_or(TokenError::InvalidNumber)?;
+                10u64
+                    .checked_pow(shift)
+                    .and_then(|power| digits.checked_mul(power))
+                    .ok_or(TokenError::TooLarge)?
+            }
+        };
+        sum.checked_add(term).ok_or(TokenError::TooLarge)
+    })
+}
+
+/// Parses a non-empty string of decimal digits, without the sign `u64::from_str` would accept.
+fn parse_digits(digits: &str) -> Result<u64, TokenError> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(TokenError::InvalidNumber);
+    }
+    digits.parse().map_err(|_| TokenError::TooLarge)
+}
+
+/// Parses one input token: a single number, `start..end` for the numbers from `start` up to but
+/// not including `end`, or `start..=end` to include `end` too. Each end is a literal as accepted
+/// by `parse_literal`.
+///
+/// Returns the numbers the token stands for, or None for a well-formed range with no numbers in
+/// it (such as `5..5`).
+pub fn parse_token(token: &str) -> Result<Option<RangeInclusive<u64>>, TokenError> {
+    if let Some(dots) = token.find("..") {
+        let bound = |literal: &str| {
+            parse_literal(literal).map_err(|err| match err {
+                TokenError::TooLarge => TokenError::TooLarge,
+                _ => TokenError::InvalidRange,
+            })
+        };
+        let start = bound(&token[..dots])?;
+        let rest = &token[dots + 2..];
+        let end = match rest.strip_prefix('=') {
+            Some(end) => bound(end)?,
+            None => match bound(rest)?.checked_sub(1) {
+                Some(end) => end,
+                None => return Ok(None),
+            },
+        };
+        return Ok(if end < start { None } else { Some(start..=end) });
+    }
+    let num = parse_literal(token)?;
+    Ok(Some(num..=num))
+}
+
+/// The numbers given on the command line, expanded from their ranges one at a time as they are
+/// needed, so that a range like `0..100000000` never has to be held in memory.
+#[derive(Clone, Debug, Default)]
+pub struct Inputs {
+    ranges: VecDeque<RangeInclusive<u64>>,
+}
+
+impl Inputs {
+    pub fn new() -> Inputs {
+        Inputs::default()
+    }
+
+    /// Appends the numbers in `range` after everything added so far.
+    pub fn push(&mut self, range: RangeInclusive<u64>) {
+        self.ranges.push_back(range);
+    }
+}
+
+impl Iterator for Inputs {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        loop {
+            let range = self.ranges.front_mut()?;
+            if let Some(num) = range.next() {
+                return Some(num);
+            }
+            self.ranges.pop_front();
+        }
+    }
+
+    /// Exact as long as the count fits in a usize, which the workers rely on to decide how many
+    /// threads are worth starting.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let upper = self.ranges.iter().try_fold(0usize, |count, range| {
+            count.checked_add(range.size_hint().1?)
+        });
+        (upper.unwrap_or(usize::MAX), upper)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_literal() {
+        assert_eq!(parse_literal("1000"), Ok(1000));
+        assert_eq!(parse_literal("1e6"), Ok(1_000_000));
+        assert_eq!(parse_literal("2.5E3"), Ok(2500));
+        assert_eq!(parse_literal("1.50e1"), Ok(15));
+        assert_eq!(parse_literal("1e6+1000"), Ok(1_001_000));
+        assert_eq!(parse_literal("18446744073709551615"), Ok(u64::MAX));
+        assert_eq!(parse_literal("1.25e1"), Err(TokenError::InvalidNumber));
+        assert_eq!(parse_literal("1e20"), Err(TokenError::TooLarge));
+        assert_eq!(
+            parse_literal("18446744073709551616"),
+            Err(TokenError::TooLarge)
+        );
+        assert_eq!(
+            parse_literal("18446744073709551615+1"),
+            Err(TokenError::TooLarge)
+        );
+        assert_eq!(parse_literal("+5"), Err(TokenError::InvalidNumber));
+        assert_eq!(parse_literal("-5"), Err(TokenError::InvalidNumber));
+        assert_eq!(parse_literal("1e"), Err(TokenError::InvalidNumber));
+        assert_eq!(parse_literal("e6"), Err(TokenError::InvalidNumber));
+        assert_eq!(parse_literal("12x"), Err(TokenError::InvalidNumber));
+    }
+
+    #[test]
+    fn test_parse_token() {
+        assert_eq!(parse_token("42"), Ok(Some(42..=42)));
+        assert_eq!(parse_token("1000..2000"), Ok(Some(1000..=1999)));
+        assert_eq!(parse_token("1000..=2000"), Ok(Some(1000..=2000)));
+        assert_eq!(parse_token("1e6..1e6+3"), Ok(Some(1_000_000..=1_000_002)));
+        assert_eq!(parse_token("7..=7"), Ok(Some(7..=7)));
+        assert_eq!(parse_token("4x2"), Err(TokenError::InvalidNumber));
+        assert_eq!(parse_token("..5"), Err(TokenError::InvalidRange));
+        assert_eq!(parse_token("5.."), Err(TokenError::InvalidRange));
+        assert_eq!(parse_token("1...5"), Err(TokenError::InvalidRange));
+        assert_eq!(parse_token("1..1e30"), Err(TokenError::TooLarge));
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let invalid = InvalidToken {
+            token: "4x2".to_string(),
+            origin: Source::Argument(3),
+            reason: TokenError::InvalidNumber,
+        };
+        assert_eq!(
+            invalid.to_string(),
+            "argument 3: \"4x2\" is not a valid number"
+        );
+        let invalid = InvalidToken {
+            token: "1..1e30".to_string(),
+            origin: Source::Stdin { line: 12 },
+            reason: TokenError::TooLarge,
+        };
+        assert_eq!(
+            invalid.to_string(),
+            "stdin line 12: \"1..1e30\" is too large (numbers go up to 18446744073709551615)"
+        );
+        let error = InputError::Invalid {
+            errors: vec![invalid.clone(), invalid],
+            valid: Inputs::new(),
+        };
+        assert_eq!(error.to_string(), "2 invalid inputs");
+    }
+
+    #[test]
+    fn test_parse_degenerate_ranges() {
+        assert_eq!(parse_token("5..5"), Ok(None));
+        assert_eq!(parse_token("5..3"), Ok(None));
+        assert_eq!(parse_token("5..=4"), Ok(None));
+        assert_eq!(parse_token("0..0"), Ok(None));
+    }
+
+    #[test]
+    fn test_inputs_expand_in_order() {
+        let mut inputs = Inputs::new();
+        inputs.push(10..=12);
+        inputs.push(3..=3);
+        inputs.push(1..=2);
+        assert_eq!(inputs.size_hint(), (6, Some(6)));
+        assert_eq!(inputs.collect::<Vec<u64>>(), vec![10, 11, 12, 3, 1, 2]);
+    }
+
+    #[test]
+    fn test_inputs_are_lazy() {
+        let mut inputs = Inputs::new();
+        inputs.push(0..=99_999_999);
+        inputs.push(5..=5);
+        assert_eq!(inputs.size_hint(), (100_000_001, Some(100_000_001)));
+        assert_eq!(inputs.next(), Some(0));
+        assert_eq!(inputs.size_hint(), (100_000_000, Some(100_000_000)));
+
+        // Too many numbers to count: treated like a stream of unknown length.
+        let mut inputs = Inputs::new();
+        inputs.push(0..=u64::MAX);
+        assert_eq!(inputs.size_hint().1, None);
+        assert_eq!(inputs.take(2).collect::<Vec<u64>>(), vec![0, 1]);
+    }
+}
@@ -0,0 +1,784 @@
+//! Factors numbers on a pool of worker threads. The `farm` binary is a thin command-line wrapper
+//! around `factor_with`.
+
+mod affinity;
+mod bench;
+mod cache;
+mod daemon;
+mod dispatch;
+mod factor;
+mod format;
+mod histogram;
+mod input;
+mod progress;
+mod summary;
+
+pub use affinity::{set_nice, Pinning, NICE_RANGE};
+pub use bench::{
+    bench_thread_counts, run_bench, synthetic_workload, write_bench_results, BenchResult,
+};
+pub use cache::FactorCache;
+pub use daemon::serve;
+pub use factor::{factor_number, factor_number_until, Factorization, Sieve};
+pub use format::{Format, RecordWriter};
+pub use histogram::{Histogram, NUM_BUCKETS};
+pub use input::{parse_token, InputError, Inputs, InvalidToken, Source, TokenError};
+pub use progress::ProgressReporter;
+pub use summary::{Summary, WorkerStats};
+
+use crossbeam_channel as channel;
+use dispatch::{batch_size, Job, WorkerQueues};
+use std::collections::HashMap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How many numbers may wait in the work queue per worker before the producer blocks. This keeps
+/// a streaming producer from reading arbitrarily far ahead of the workers.
+const QUEUE_DEPTH_PER_WORKER: usize = 64;
+
+/// How long an idle worker waits on the queue before it looks for work to steal and re-checks
+/// the stop flag.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How long to wait for the producer after a stop before giving up on it. A producer reading from
+/// a stream may be blocked on input that will never come.
+const PRODUCER_GRACE_PERIOD: Duration = Duration::from_millis(200);
+
+/// A function to factor numbers with in place of the built-in trial division, such as an
+/// experimental algorithm. It is called from every worker thread at once.
+#[derive(Clone)]
+pub struct Factorizer(Arc<dyn Fn(u64) -> Factorization + Send + Sync>);
+
+impl Factorizer {
+    pub fn new<F>(factor: F) -> Factorizer
+    where
+        F: Fn(u64) -> Factorization + Send + Sync + 'static,
+    {
+        Factorizer(Arc::new(factor))
+    }
+}
+
+impl fmt::Debug for Factorizer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Factorizer")
+    }
+}
+
+/// Settings for a single factoring run.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Maximum number of worker threads to spawn
+    pub threads: usize,
+    /// Hand results over in input order rather than as they finish
+    pub ordered: bool,
+    /// Counter that workers increment each time they finish a number, for progress reporting
+    pub completed: Option<Arc<AtomicUsize>>,
+    /// Flag that, once set, makes workers stop taking new numbers. Numbers already being factored
+    /// are finished and reported as usual.
+    pub stop: Option<Arc<AtomicBool>>,
+    /// Primes to use for trial division, shared by all workers
+    pub sieve: Option<Arc<Sieve>>,
+    /// Earlier results, used to answer repeated inputs without factoring them again
+    pub cache: Option<Arc<FactorCache>>,
+    /// How long a worker may spend on one number before abandoning it as timed out
+    pub timeout: Option<Duration>,
+    /// Factors numbers instead of trial division, in which case `sieve` and `timeout` are
+    /// ignored
+    pub factorizer: Option<Factorizer>,
+    /// Pin each worker thread to a CPU of its own, where the platform supports it
+    pub pin_threads: bool,
+}
+
+/// Returns the message a panic was started with, if it was given one.
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Factors a single number the way `options` asks for. If that panics, the panic is caught and
+/// reported as a failed result, so that one bad input can't take a worker down with it.
+fn factor_one(num: u64, options: &Options) -> Factorization {
+    let start = Instant::now();
+    // Nothing shared is left half-updated by a panic: the cache is only written to after a
+    // number has been factored successfully.
+    panic::catch_unwind(AssertUnwindSafe(|| factor_unguarded(num, options, start))).unwrap_or_else(
+        |payload| Factorization {
+            input: num,
+            factors: Vec::new(),
+            duration: start.elapsed(),
+            cached: false,
+            timed_out: false,
+            failure: Some(panic_message(&*payload)),
+        },
+    )
+}
+
+/// Factors a single number: from the cache if it has been seen before, otherwise with the
+/// factorizer or the sieve if there is one. Numbers that time out aren't cached, since their
+/// factors are incomplete.
+fn factor_unguarded(num: u64, options: &Options, start: Instant) -> Factorization {
+    if let Some(factors) = options.cache.as_ref().and_then(|cache| cache.get(num)) {
+        return Factorization {
+            input: num,
+            factors,
+            duration: start.elapsed(),
+            cached: true,
+            timed_out: false,
+            failure: None,
+        };
+    }
+    let deadline = options.timeout.map(|timeout| start + timeout);
+    let factorization = match (&options.factorizer, &options.sieve) {
+        (Some(factorizer), _) => (factorizer.0)(num),
+        (None, Some(sieve)) => sieve.factor_until(num, deadline),
+        (None, None) => factor_number_until(num, deadline),
+    };
+    if let Some(cache) = &options.cache {
+        if !factorization.timed_out {
+            cache.insert(num, factorization.factors.clone());
+        }
+    }
+    factorization
+}
+
+fn is_stopped(stop: &Option<Arc<AtomicBool>>) -> bool {
+    stop.as_ref()
+        .is_some_and(|stop| stop.load(Ordering::SeqCst))
+}
+
+/// Returns how many workers are worth spawning for `num_inputs` numbers: there is no point in
+/// having more workers than queued numbers, but we always keep at least one.
+pub fn effective_threads(configured: usize, num_inputs: usize) -> usize {
+    configured.min(num_inputs).max(1)
+}
+
+/// Spawns a thread that feeds `nums`, tagged with their position, into the work queue. The
+/// producer gives up as soon as every receiver has dropped its end of the queue, so it can never
+/// stay blocked on a full queue after the workers are gone.
+///
+/// When `stop` is set the producer stops feeding the queue and returns the numbers it never sent,
+/// as long as `nums` has a known length; an open-ended stream is simply abandoned.
+fn spawn_producer<I>(
+    nums: I,
+    work_sender: channel::Sender<Job>,
+    stop: Option<Arc<AtomicBool>>,
+) -> thread::JoinHandle<Vec<Job>>
+where
+    I: Iterator<Item = u64> + Send + 'static,
+{
+    thread::spawn(move || {
+        let bounded = nums.size_hint().1.is_some();
+        let mut jobs = nums.enumerate();
+        while let Some(job) = jobs.next() {
+            if is_stopped(&stop) {
+                if bounded {
+                    return std::iter::once(job).chain(jobs).collect();
+                }
+                break;
+            }
+            if work_sender.send(job).is_err() {
+                break;
+            }
+        }
+        Vec::new()
+    })
+}
+
+/// Runs one worker until the queue is closed and drained and there is nothing left to steal, or
+/// until `options.stop` is set. Returns what the worker did, along with the jobs left in its own
+/// deque when it stopped.
+fn run_worker(
+    id: usize,
+    queues: &WorkerQueues,
+    work_receiver: &channel::Receiver<Job>,
+    result_sender: &channel::Sender<(usize, Factorization)>,
+    options: &Options,
+) -> (WorkerStats, Vec<Job>) {
+    // Having received one job from the queue, takes more along with it while the queue is long.
+    let take_batch = |first: Job| {
+        let more = batch_size(work_receiver.len(), queues.num_workers()) - 1;
+        if more > 0 {
+            queues.push_batch(id, work_receiver.try_iter().take(more));
+        }
+        first
+    };
+    let mut stats = WorkerStats::default();
+    while !is_stopped(&options.stop) {
+        let (index, num) = match queues.pop(id) {
+            Some(job) => job,
+            None => match work_receiver.recv_timeout(IDLE_POLL_INTERVAL) {
+                Ok(job) => take_batch(job),
+                // Only steal once the queue has stayed empty for a while (or will stay empty for
+                // good), so that workers don't shuffle batches of quick numbers between each
+                // other while the producer keeps up.
+                Err(channel::RecvTimeoutError::Timeout) => match queues.steal(id) {
+                    Some(job) => job,
+                    None => continue,
+                },
+                Err(channel::RecvTimeoutError::Disconnected) => match queues.steal(id) {
+                    Some(job) => job,
+                    None => break,
+                },
+            },
+        };
+        let factorization = factor_one(num, options);
+        stats.busy += factorization.duration;
+        stats.items += 1;
+        if let Some(completed) = &options.completed {
+            completed.fetch_add(1, Ordering::Relaxed);
+        }
+        result_sender
+            .send((index, factorization))
+            .expect("Missing result receiver!");
+    }
+    (stats, queues.drain(id))
+}
+
+/// Collects the numbers that were never factored after a stop: whatever the workers had left in
+/// their deques, whatever is left in the queue, and whatever the producer didn't get to send.
+/// Returned in input order.
+fn collect_skipped(
+    mut skipped: Vec<Job>,
+    work_receiver: channel::Receiver<Job>,
+    producer: thread::JoinHandle<Vec<Job>>,
+) -> Vec<u64> {
+    let deadline = Instant::now() + PRODUCER_GRACE_PERIOD;
+    // Keep draining while we wait, in case the producer is blocked on a full queue.
+    while !producer.is_finished() && Instant::now() < deadline {
+        skipped.extend(work_receiver.try_iter());
+        thread::sleep(Duration::from_millis(1));
+    }
+    if producer.is_finished() {
+        skipped.extend(work_receiver.try_iter());
+        skipped.extend(producer.join().unwrap_or_default());
+    } else {
+        // The producer is stuck reading its input and can't be interrupted; leave it behind.
+        skipped.extend(work_receiver.try_iter());
+    }
+    skipped.sort_unstable_by_key(|&(index, _)| index);
+    skipped.into_iter().map(|(_, num)| num).collect()
+}
+
+/// Holds results that arrived ahead of their turn until every earlier index has been handed out,
+/// so it only ever holds as many results as the fastest worker has run ahead of the slowest one.
+struct ReorderBuffer<T> {
+    pending: HashMap<usize, T>,
+    next_index: usize,
+}
+
+impl<T> ReorderBuffer<T> {
+    fn new() -> ReorderBuffer<T> {
+        ReorderBuffer {
+            pending: HashMap::new(),
+            next_index: 0,
+        }
+    }
+
+    fn insert(&mut self, index: usize, item: T) {
+        self.pending.insert(index, item);
+    }
+
+    /// Returns the next result in index order, if it has arrived.
+    fn pop_ready(&mut self) -> Option<T> {
+        let item = self.pending.remove(&self.next_index)?;
+        self.next_index += 1;
+        Some(item)
+    }
+
+    /// Returns everything still held, in index order. After a stop some indexes never arrive, and
+    /// the results behind them would otherwise be stuck here.
+    fn drain_remaining(&mut self) -> Vec<T> {
+        let mut remaining: Vec<(usize, T)> = self.pending.drain().collect();
+        remaining.sort_unstable_by_key(|&(index, _)| index);
+        remaining.into_iter().map(|(_, item)| item).collect()
+    }
+}
+
+/// What happened during a run of `factor_with`, apart from the results themselves.
+#[derive(Debug, Default)]
+pub struct Outcome {
+    /// Numbers skipped because `options.stop` was set before a worker got to them, in input order
+    pub skipped: Vec<u64>,
+    /// What each worker did, indexed by worker
+    pub workers: Vec<WorkerStats>,
+}
+
+/// Factors `nums` on up to `options.threads` worker threads and hands each result to `on_result` on
+/// the calling thread as soon as it is available. With `options.ordered` set, results are handed
+/// over strictly in the order of `nums`; otherwise in whatever order the workers finish them.
+///
+/// `nums` is consumed lazily on a producer thread, so it may be a stream (such as lines read from
+/// stdin) that is still being produced while the first numbers are factored. Workers take numbers
+/// from the queue in batches, and idle workers steal from busy ones; see `dispatch`.
+pub fn factor_with<I, F>(nums: I, options: &Options, mut on_result: F) -> Outcome
+where
+    I: IntoIterator<Item = u64>,
+    I::IntoIter: Send + 'static,
+    F: FnMut(Factorization),
+{
+    let nums = nums.into_iter();
+    let num_threads = effective_threads(options.threads, nums.size_hint().1.unwrap_or(usize::MAX));
+    let (work_sender, work_receiver) = channel::bounded(num_threads * QUEUE_DEPTH_PER_WORKER);
+    let (result_sender, result_receiver) = channel::unbounded();
+
+    let producer = spawn_producer(nums, work_sender, options.stop.clone());
+
+    // Spawn `num_threads` threads, each of which takes batches of numbers from the queue and
+    // factors them until the queue is closed and drained and there is nothing left to steal
+    let queues = Arc::new(WorkerQueues::new(num_threads));
+    let cpus = Arc::new(if options.pin_threads {
+        affinity::allowed_cpus()
+    } else {
+        Vec::new()
+    });
+    let mut workers = Vec::new();
+    for id in 0..num_threads {
+        let queues = queues.clone();
+        let work_receiver = work_receiver.clone();
+        let result_sender = result_sender.clone();
+        let options = options.clone();
+        let cpus = cpus.clone();
+        workers.push(thread::spawn(move || {
+            let pinning = if options.pin_threads {
+                affinity::pin_worker(id, &cpus)
+            } else {
+                Pinning::Off
+            };
+            let (stats, left) = run_worker(id, &queues, &work_receiver, &result_sender, &options);
+            (WorkerStats { pinning, ..stats }, left)
+        }))
+    }
+    drop(result_sender);
+
+    let mut reorder = ReorderBuffer::new();
+    for (index, factorization) in result_receiver {
+        if !options.ordered {
+            on_result(factorization);
+            continue;
+        }
+        reorder.insert(index, factorization);
+        while let Some(factorization) = reorder.pop_ready() {
+            on_result(factorization);
+        }
+    }
+    for factorization in reorder.drain_remaining() {
+        on_result(factorization);
+    }
+
+    // Join all the threads you created
+    let mut outcome = Outcome::default();
+    let mut leftovers = Vec::new();
+    // Panics while factoring are caught in `factor_one`, so a worker can only have died of a bug
+    // in the worker loop itself; all there is to report then is that it did nothing.
+    for handle in workers {
+        let (stats, left) = handle.join().unwrap_or_default();
+        outcome.workers.push(stats);
+        leftovers.extend(left);
+    }
+    if is_stopped(&options.stop) {
+        outcome.skipped = collect_skipped(leftovers, work_receiver, producer);
+    } else {
+        // A producer that panicked has already stopped feeding the queue, so the run simply
+        // ends early.
+        let _ = producer.join();
+    }
+    outcome
+}
+
+/// Factors `nums` on up to `threads` worker threads and returns the results in input order.
+pub fn factor_all(nums: Vec<u64>, threads: usize) -> Vec<Factorization> {
+    let mut results = Vec::with_capacity(nums.len());
+    let options = Options {
+        threads,
+        ordered: true,
+        ..Options::default()
+    };
+    factor_with(nums, &options, |factorization| results.push(factorization));
+    results
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Large primes cost millions of trial divisions, while the small numbers queued right behind
+    /// them are done almost immediately.
+    fn skewed_workload() -> Vec<u64> {
+        vec![
+            9_999_999_999_971,
+            2,
+            3,
+            4,
+            3_999_999_999_967,
+            5,
+            6,
+            7,
+            8,
+            999_999_999_989,
+            9,
+            10,
+            12,
+            14,
+            15,
+            9_999_999_999_971,
+            16,
+            18,
+            20,
+        ]
+    }
+
+    #[test]
+    fn test_factor_all() {
+        let results = factor_all(vec![12, 7, 1, 600_851_475_143], 2);
+        let factors: Vec<Vec<u64>> = results.into_iter().map(|f| f.factors).collect();
+        assert_eq!(
+            factors,
+            vec![vec![2, 2, 3], vec![7], vec![], vec![71, 839, 1471, 6857]]
+        );
+        assert!(factor_all(vec![], 4).is_empty());
+    }
+
+    #[test]
+    fn test_factor_all_follows_input_order() {
+        let nums = skewed_workload();
+        let inputs: Vec<u64> = factor_all(nums.clone(), 4)
+            .into_iter()
+            .map(|f| f.input)
+            .collect();
+        assert_eq!(inputs, nums);
+    }
+
+    #[test]
+    fn test_unordered_results_have_every_input() {
+        let nums = skewed_workload();
+        let mut inputs = Vec::new();
+        let options = Options {
+            threads: 4,
+            ..Options::default()
+        };
+        factor_with(nums.clone(), &options, |f| inputs.push(f.input));
+        inputs.sort_unstable();
+        let mut expected = nums;
+        expected.sort_unstable();
+        assert_eq!(inputs, expected);
+    }
+
+    #[test]
+    fn test_ordered_ranges_include_their_bounds() {
+        let mut nums = Inputs::new();
+        for token in ["20..=24", "7", "1e3..1e3+2", "9..9"].iter() {
+            if let Some(range) = parse_token(token).unwrap() {
+                nums.push(range);
+            }
+        }
+        let options = Options {
+            threads: 4,
+            ordered: true,
+            ..Options::default()
+        };
+        let mut inputs = Vec::new();
+        factor_with(nums, &options, |f| inputs.push(f.input));
+        assert_eq!(inputs, vec![20, 21, 22, 23, 24, 7, 1000, 1001]);
+    }
+
+    #[test]
+    fn test_factor_with_streaming_source() {
+        let mut inputs = Vec::new();
+        let options = Options {
+            threads: 4,
+            ordered: true,
+            ..Options::default()
+        };
+        factor_with((1..=1000).map(|n| n * 3), &options, |f| {
+            inputs.push(f.input)
+        });
+        assert_eq!(inputs, (1..=1000).map(|n| n * 3).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn test_workers_count_completions() {
+        let completed = Arc::new(AtomicUsize::new(0));
+        let options = Options {
+            threads: 3,
+            completed: Some(completed.clone()),
+            ..Options::default()
+        };
+        let mut delivered = 0;
+        let outcome = factor_with(1..=500, &options, |_| delivered += 1);
+        assert_eq!(delivered, 500);
+        assert_eq!(completed.load(Ordering::SeqCst), 500);
+        assert_eq!(outcome.workers.len(), 3);
+        let items: usize = outcome.workers.iter().map(|worker| worker.items).sum();
+        assert_eq!(items, 500);
+    }
+
+    #[test]
+    fn test_pinned_workers() {
+        let options = Options {
+            threads: 3,
+            pin_threads: true,
+            ..Options::default()
+        };
+        let mut delivered = 0;
+        let outcome = factor_with(1..=100, &options, |_| delivered += 1);
+        assert_eq!(delivered, 100);
+        let cpus = affinity::allowed_cpus();
+        for (id, worker) in outcome.workers.iter().enumerate() {
+            if cpus.is_empty() {
+                assert_eq!(worker.pinning, Pinning::Failed);
+            } else {
+                assert_eq!(worker.pinning, Pinning::Pinned(cpus[id % cpus.len()]));
+            }
+        }
+
+        let outcome = factor_with(1..=100, &Options::default(), |_| {});
+        assert_eq!(outcome.workers[0].pinning, Pinning::Off);
+    }
+
+    #[test]
+    fn test_producer_stops_when_workers_are_gone() {
+        // An endless source would block the producer forever on the bounded queue if it didn't
+        // notice that nobody is left to receive.
+        let (work_sender, work_receiver) = channel::bounded(2);
+        let producer = spawn_producer(0.., work_sender, None);
+        assert_eq!(work_receiver.recv(), Ok((0, 0)));
+        assert_eq!(work_receiver.recv(), Ok((1, 1)));
+        drop(work_receiver);
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn test_stop_finishes_in_flight_numbers_and_reports_skipped() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let options = Options {
+            threads: 1,
+            ordered: true,
+            stop: Some(stop.clone()),
+            ..Options::default()
+        };
+        let nums = vec![9_999_999_999_971, 12, 9_999_999_999_971, 15, 16];
+        let trigger = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                // Fires while the single worker is busy with the first slow prime.
+                thread::sleep(Duration::from_millis(5));
+                stop.store(true, Ordering::SeqCst);
+            })
+        };
+        let mut results = Vec::new();
+        let skipped = factor_with(nums.clone(), &options, |f| results.push(f)).skipped;
+        trigger.join().unwrap();
+
+        // The in-flight number was finished properly, and everything else is accounted for.
+        assert!(!results.is_empty());
+        assert_eq!(results[0].factors, vec![9_999_999_999_971]);
+        let mut seen: Vec<u64> = results.iter().map(|f| f.input).collect();
+        seen.extend(skipped.iter());
+        assert_eq!(seen, nums);
+        assert!(!skipped.is_empty());
+    }
+
+    #[test]
+    fn test_ordered_stop_still_delivers_results_behind_gaps() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let options = Options {
+            threads: 4,
+            ordered: true,
+            stop: Some(stop.clone()),
+            ..Options::default()
+        };
+        let nums: Vec<u64> = (0..5_000).collect();
+        let mut inputs = Vec::new();
+        let skipped = factor_with(nums, &options, |f| {
+            inputs.push(f.input);
+            stop.store(true, Ordering::SeqCst);
+        })
+        .skipped;
+        assert_eq!(inputs.len() + skipped.len(), 5_000);
+        let mut sorted = inputs.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, inputs);
+        let mut all = inputs;
+        all.extend(skipped);
+        all.sort_unstable();
+        assert_eq!(all, (0..5_000).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn test_stop_from_result_callback() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let options = Options {
+            threads: 2,
+            stop: Some(stop.clone()),
+            ..Options::default()
+        };
+        let nums: Vec<u64> = (0..10_000).collect();
+        let mut done = 0;
+        let skipped = factor_with(nums, &options, |_| {
+            done += 1;
+            stop.store(true, Ordering::SeqCst);
+        })
+        .skipped;
+        assert_eq!(done + skipped.len(), 10_000);
+        assert!(skipped.len() > 9_000, "only {} skipped", skipped.len());
+        let mut sorted = skipped.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, skipped);
+    }
+
+    #[test]
+    fn test_stop_abandons_blocked_stream() {
+        // A source that blocks forever after two numbers, like stdin with nothing more to read.
+        let (line_sender, line_receiver) = channel::unbounded::<u64>();
+        line_sender.send(4).unwrap();
+        line_sender.send(6).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let options = Options {
+            threads: 2,
+            stop: Some(stop.clone()),
+            ..Options::default()
+        };
+        let mut inputs = Vec::new();
+        let skipped = factor_with(line_receiver, &options, |f| {
+            inputs.push(f.input);
+            if inputs.len() == 2 {
+                stop.store(true, Ordering::SeqCst);
+            }
+        })
+        .skipped;
+        inputs.sort_unstable();
+        assert_eq!(inputs, vec![4, 6]);
+        assert!(skipped.is_empty());
+        drop(line_sender);
+    }
+
+    #[test]
+    fn test_repeated_inputs_come_from_cache() {
+        let cache = Arc::new(FactorCache::new());
+        let options = Options {
+            threads: 1,
+            ordered: true,
+            sieve: Some(Arc::new(Sieve::new(1000))),
+            cache: Some(cache.clone()),
+            ..Options::default()
+        };
+        let mut results = Vec::new();
+        factor_with(vec![360, 97, 360, 360, 97], &options, |f| results.push(f));
+
+        // Every occurrence still produces its own result.
+        let inputs: Vec<u64> = results.iter().map(|f| f.input).collect();
+        assert_eq!(inputs, vec![360, 97, 360, 360, 97]);
+        let cached: Vec<bool> = results.iter().map(|f| f.cached).collect();
+        assert_eq!(cached, vec![false, false, true, true, true]);
+        assert_eq!(results[3].factors, vec![2, 2, 2, 3, 3, 5]);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_timeout_abandons_slow_numbers() {
+        let cache = Arc::new(FactorCache::new());
+        let options = Options {
+            threads: 2,
+            ordered: true,
+            cache: Some(cache.clone()),
+            timeout: Some(Duration::from_micros(1)),
+            ..Options::default()
+        };
+        let mut results = Vec::new();
+        factor_with(vec![12, 9_999_999_999_971, 15], &options, |f| {
+            results.push(f)
+        });
+
+        let timed_out: Vec<bool> = results.iter().map(|f| f.timed_out).collect();
+        assert_eq!(timed_out, vec![false, true, false]);
+        assert_eq!(results[2].factors, vec![3, 5]);
+        // The incomplete result must not be handed out again for a repeated input.
+        assert_eq!(cache.get(9_999_999_999_971), None);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_panics_become_failed_results() {
+        let cache = Arc::new(FactorCache::new());
+        let options = Options {
+            threads: 2,
+            ordered: true,
+            cache: Some(cache.clone()),
+            factorizer: Some(Factorizer::new(|num| {
+                if num % 10 == 7 {
+                    panic!("no sevens allowed, got {}", num);
+                }
+                factor_number(num)
+            })),
+            ..Options::default()
+        };
+        let nums: Vec<u64> = (1..=30).collect();
+        let mut results = Vec::new();
+        let outcome = factor_with(nums.clone(), &options, |f| results.push(f));
+
+        // Every input has a result, and the workers kept going after each panic.
+        let inputs: Vec<u64> = results.iter().map(|f| f.input).collect();
+        assert_eq!(inputs, nums);
+        let failed: Vec<(u64, &str)> = results
+            .iter()
+            .filter_map(|f| Some((f.input, f.failure.as_deref()?)))
+            .collect();
+        assert_eq!(
+            failed,
+            vec![
+                (7, "no sevens allowed, got 7"),
+                (17, "no sevens allowed, got 17"),
+                (27, "no sevens allowed, got 27"),
+            ]
+        );
+        assert!(results.iter().filter(|f| f.failure.is_none()).all(|f| f
+            .factors
+            .iter()
+            .product::<u64>()
+            == f.input.max(1)));
+        let items: usize = outcome.workers.iter().map(|w| w.items).sum();
+        assert_eq!(items, 30);
+        assert_eq!(cache.get(7), None);
+        assert_eq!(cache.len(), 27);
+    }
+
+    #[test]
+    fn test_reorder_buffer() {
+        let mut reorder = ReorderBuffer::new();
+        let mut delivered = Vec::new();
+        for &(index, item) in [(2, "c"), (0, "a"), (3, "d"), (1, "b")].iter() {
+            reorder.insert(index, item);
+            while let Some(item) = reorder.pop_ready() {
+                delivered.push(item);
+            }
+            if index == 0 {
+                assert_eq!(reorder.pending.len(), 1);
+            }
+        }
+        assert_eq!(delivered, vec!["a", "b", "c", "d"]);
+        assert!(reorder.pending.is_empty());
+
+        // Index 4 never arrives.
+        reorder.insert(7, "h");
+        reorder.insert(5, "f");
+        assert_eq!(reorder.pop_ready(), None);
+        assert_eq!(reorder.drain_remaining(), vec!["f", "h"]);
+        assert!(reorder.pending.is_empty());
+    }
+
+    #[test]
+    fn test_effective_threads() {
+        assert_eq!(effective_threads(64, 3), 3);
+        assert_eq!(effective_threads(2, 3), 2);
+        assert_eq!(effective_threads(4, 0), 1);
+    }
+}
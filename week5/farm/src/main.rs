@@ -1,94 +1,764 @@
-use std::collections::VecDeque;
+use clap::{CommandFactory, ErrorKind, Parser};
+use farm::{
+    bench_thread_counts, effective_threads, factor_with, parse_token, run_bench, serve, set_nice,
+    synthetic_workload, write_bench_results, FactorCache, Format, InputError, Inputs, InvalidToken,
+    Options, ProgressReporter, RecordWriter, Sieve, Source, Summary, NICE_RANGE,
+};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
-use std::{env, process, thread};
+use std::time::{Duration, Instant};
 
-/// Determines whether a number is prime. This function is taken from CS 110 factor.py.
-fn is_prime(num: u32) -> bool {
-    if num <= 1 {
-        return false;
+/// Contains information parsed from the command-line invocation of farm.
+#[derive(Parser, Debug)]
+#[clap(about = "Factors numbers on a pool of worker threads")]
+struct CmdOptions {
+    #[clap(
+        help = "Numbers to factor, or ranges such as 1000..2000 and 1e6..=1e6+10 (read from stdin \
+                if none are given)"
+    )]
+    numbers: Vec<String>,
+    #[clap(
+        long = "range",
+        value_name = "START..END",
+        help = "Also factor the numbers in this range, after the ones given as arguments"
+    )]
+    ranges: Vec<String>,
+    #[clap(
+        short,
+        long,
+        use_value_delimiter = true,
+        help = "Number of worker threads (0 = number of CPUs); with --bench, a comma-separated list \
+                of thread counts to try on top of the default ones",
+        default_value = "0"
+    )]
+    threads: Vec<usize>,
+    #[clap(
+        long,
+        help = "Time the inputs (or a synthetic workload if none are given) on 1, 2, 4, ... \
+                threads up to the number of CPUs, and print how they compare"
+    )]
+    bench: bool,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Run as a service: read numbers from clients connecting to a Unix socket at PATH \
+                and write their results back"
+    )]
+    listen: Option<PathBuf>,
+    #[clap(long, help = "Print results in the order the numbers were given")]
+    ordered: bool,
+    #[clap(long, help = "Report progress on stderr every second")]
+    progress: bool,
+    #[clap(
+        long,
+        help = "Output format: text, exp, json, ndjson or csv",
+        default_value = "text"
+    )]
+    format: Format,
+    #[clap(
+        long,
+        help = "Sieve primes up to this bound for trial division (0 = no sieve)",
+        default_value = "1048576"
+    )]
+    sieve_bound: u64,
+    #[clap(
+        long,
+        help = "Factor repeated inputs again instead of reusing earlier results"
+    )]
+    no_cache: bool,
+    #[clap(
+        long,
+        help = "Give up on a number after this many milliseconds (0 = no timeout)",
+        default_value = "0"
+    )]
+    timeout_ms: u64,
+    #[clap(
+        long,
+        help = "Factor the valid inputs even if some are not numbers (by default nothing is \
+                factored if any argument is invalid)"
+    )]
+    skip_invalid: bool,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Write results to this file instead of stdout, leaving the terminal for progress \
+                and the summary"
+    )]
+    output: Option<PathBuf>,
+    #[clap(
+        long,
+        requires = "output",
+        help = "Add to the end of the --output file instead of replacing it"
+    )]
+    append: bool,
+    #[clap(
+        long,
+        value_name = "N",
+        allow_hyphen_values = true,
+        value_parser = parse_nice,
+        help = "Run at this nice level, from -20 (highest priority) to 19 (lowest)"
+    )]
+    nice: Option<i32>,
+    #[clap(long, help = "Pin each worker thread to a CPU of its own")]
+    pin_threads: bool,
+    #[clap(
+        long,
+        value_name = "K",
+        help = "Number of slowest inputs to list after the run",
+        default_value = "5"
+    )]
+    top: usize,
+}
+
+/// Parses a `--nice` level, which has to be within `NICE_RANGE`.
+fn parse_nice(s: &str) -> Result<i32, String> {
+    let level: i32 = s
+        .parse()
+        .map_err(|_| format!("\"{}\" is not a nice level", s))?;
+    if !NICE_RANGE.contains(&level) {
+        return Err(format!(
+            "nice level {} is out of range ({} to {})",
+            level,
+            NICE_RANGE.start(),
+            NICE_RANGE.end()
+        ));
     }
-    for factor in 2..((num as f64).sqrt().floor() as u32) {
-        if num % factor == 0 {
-            return false;
+    Ok(level)
+}
+
+/// Number of numbers in the synthetic `--bench` workload.
+const BENCH_WORKLOAD_SIZE: usize = 2000;
+
+/// Width in characters of the bars in the end-of-run histogram.
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// Exit status when every number was factored but some of them hit the timeout.
+const EXIT_TIMED_OUT: i32 = 2;
+
+/// Exit status when factoring some of the numbers failed with a panic.
+const EXIT_FAILED: i32 = 3;
+
+/// Problems with stdin, which only come to light as the workers read it.
+#[derive(Debug, Default)]
+struct StdinErrors {
+    invalid: Vec<InvalidToken>,
+    read: Option<InputError>,
+}
+
+/// Parses one input token into the numbers it stands for, adding it to `invalid` if it isn't a
+/// valid number or range. Empty ranges are skipped with a warning.
+fn parse_input(
+    token: &str,
+    origin: Source,
+    invalid: &mut Vec<InvalidToken>,
+) -> Option<RangeInclusive<u64>> {
+    match parse_token(token) {
+        Ok(Some(range)) => Some(range),
+        Ok(None) => {
+            eprintln!("Warning: {} is an empty range, skipping it", token);
+            None
+        }
+        Err(reason) => {
+            invalid.push(InvalidToken {
+                token: token.to_string(),
+                origin,
+                reason,
+            });
+            None
         }
     }
-    true
 }
 
-/// Determines the prime factors of a number and prints them to stdout. This function is taken
-/// from CS 110 factor.py.
-fn factor_number(num: u32) {
-    let start = Instant::now();
+/// Returns the numbers supplied via argv: the number arguments, then the `--range` options.
+/// Ranges are expanded lazily as the workers ask for more numbers. If any token is invalid, the
+/// error lists all of them, along with the numbers from the valid ones.
+fn get_input_numbers(numbers: &[String], ranges: &[String]) -> Result<Inputs, InputError> {
+    let tokens = numbers
+        .iter()
+        .enumerate()
+        .map(|(i, token)| (token, Source::Argument(i + 1)))
+        .chain(
+            ranges
+                .iter()
+                .enumerate()
+                .map(|(i, token)| (token, Source::RangeOption(i + 1))),
+        );
+    let mut inputs = Inputs::new();
+    let mut invalid = Vec::new();
+    for (token, origin) in tokens {
+        if let Some(range) = parse_input(token, origin, &mut invalid) {
+            inputs.push(range);
+        }
+    }
+    if invalid.is_empty() {
+        Ok(inputs)
+    } else {
+        Err(InputError::Invalid {
+            errors: invalid,
+            valid: inputs,
+        })
+    }
+}
 
-    if num == 1 || is_prime(num) {
-        println!("{} = {} [time: {:?}]", num, num, start.elapsed());
-        return;
+/// Returns a stream of the whitespace-separated numbers read from stdin. Lines are read as the
+/// workers ask for more numbers, so factoring starts before stdin is closed. Invalid tokens are
+/// skipped and recorded in `errors`; an error reading stdin ends the stream.
+fn read_stdin_numbers(errors: Arc<Mutex<StdinErrors>>) -> impl Iterator<Item = u64> + Send {
+    BufReader::new(io::stdin())
+        .lines()
+        .enumerate()
+        .map_while(move |(i, line)| {
+            let origin = Source::Stdin { line: i + 1 };
+            let mut errors = errors.lock().unwrap();
+            match line {
+                Ok(line) => Some(
+                    line.split_whitespace()
+                        .filter_map(|token| parse_input(token, origin, &mut errors.invalid))
+                        .collect::<Vec<RangeInclusive<u64>>>(),
+                ),
+                Err(error) => {
+                    errors.read = Some(InputError::Read { origin, error });
+                    None
+                }
+            }
+        })
+        .flatten()
+        .flatten()
+}
+
+/// Opens the `--output` file, replacing what is in it unless `append` is set.
+fn create_output(path: &Path, append: bool) -> io::Result<File> {
+    let mut file = OpenOptions::new();
+    if append {
+        file.append(true);
+    } else {
+        file.write(true).truncate(true);
+    }
+    file.create(true).open(path)
+}
+
+/// Prints every invalid input to stderr, prefixed with `prefix`.
+fn report_invalid(prefix: &str, invalid: &[InvalidToken]) {
+    for token in invalid {
+        eprintln!("{}: {}", prefix, token);
     }
+}
 
-    let mut factors = Vec::new();
-    let mut curr_num = num;
-    for factor in 2..num {
-        while curr_num % factor == 0 {
-            factors.push(factor);
-            curr_num /= factor;
+/// Resolves the `--threads` option into the number of worker threads to use, where 0 stands for
+/// the number of CPUs on this machine.
+fn configured_threads(requested: usize) -> usize {
+    let threads = if requested == 0 {
+        num_cpus::get()
+    } else {
+        requested
+    };
+    assert!(threads >= 1, "at least one worker thread is required");
+    threads
+}
+
+/// Installs a Ctrl-C (and SIGTERM) handler that asks the workers to stop after the numbers they
+/// are working on. A second Ctrl-C exits immediately.
+fn install_interrupt_handler() -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    ctrlc::set_handler(move || {
+        if handler_stop.swap(true, Ordering::SeqCst) {
+            process::exit(130);
         }
+        eprintln!("Interrupted; finishing numbers in progress (press Ctrl-C again to quit now)");
+    })
+    .expect("Error setting Ctrl-C handler");
+    stop
+}
+
+/// Returns the `Options` for factoring numbers the way the command line asks for, on
+/// `num_threads` workers.
+fn factoring_options(options: &CmdOptions, num_threads: usize) -> Options {
+    Options {
+        threads: num_threads,
+        sieve: if options.sieve_bound > 0 {
+            Some(Arc::new(Sieve::new(options.sieve_bound)))
+        } else {
+            None
+        },
+        cache: if options.no_cache {
+            None
+        } else {
+            Some(Arc::new(FactorCache::new()))
+        },
+        timeout: if options.timeout_ms > 0 {
+            Some(Duration::from_millis(options.timeout_ms))
+        } else {
+            None
+        },
+        pin_threads: options.pin_threads,
+        ..Options::default()
+    }
+}
+
+/// Runs `--listen`: serves clients on a Unix socket until interrupted.
+fn listen(options: &CmdOptions, path: &Path) {
+    let num_threads = configured_threads(options.threads[0]);
+    let shutdown = install_interrupt_handler();
+    eprintln!(
+        "Farm listening on {} with {} threads",
+        path.display(),
+        num_threads
+    );
+    let run_options = factoring_options(options, num_threads);
+    if let Err(err) = serve(path, &run_options, options.format, shutdown) {
+        eprintln!("Error serving on {}: {}", path.display(), err);
+        process::exit(1);
+    }
+}
+
+/// Runs `--bench`: times the same workload on a series of thread counts and prints a comparison
+/// in the requested format.
+fn bench(options: CmdOptions) {
+    let nums: Vec<u64> = if options.numbers.is_empty() && options.ranges.is_empty() {
+        synthetic_workload(BENCH_WORKLOAD_SIZE)
+    } else {
+        match get_input_numbers(&options.numbers, &options.ranges) {
+            Ok(inputs) => inputs.collect(),
+            Err(InputError::Invalid { errors, valid }) if options.skip_invalid => {
+                report_invalid("Skipping", &errors);
+                valid.collect()
+            }
+            Err(err) => {
+                if let InputError::Invalid { errors, .. } = &err {
+                    report_invalid("Error", errors);
+                }
+                eprintln!("Error: {}", err);
+                process::exit(1);
+            }
+        }
+    };
+    let requested: Vec<usize> = options
+        .threads
+        .iter()
+        .map(|&threads| configured_threads(threads))
+        .collect();
+    let thread_counts = bench_thread_counts(num_cpus::get(), &requested);
+    eprintln!(
+        "Benchmarking {} numbers on {:?} threads",
+        nums.len(),
+        thread_counts
+    );
+    let results = run_bench(&nums, &thread_counts);
+    if let Err(err) = write_bench_results(&results, options.format, &mut io::stdout()) {
+        eprintln!("Error writing results: {}", err);
+        process::exit(1);
+    }
+}
+
+fn main() {
+    let options = CmdOptions::parse();
+    // Set before any other thread is started, since threads inherit the level they start with.
+    if let Some(level) = options.nice {
+        if let Err(err) = set_nice(level) {
+            eprintln!("Warning: couldn't set nice level {}: {}", level, err);
+        }
+    }
+    if options.bench {
+        bench(options);
+        return;
+    }
+    if let Some(path) = &options.listen {
+        listen(&options, path);
+        return;
+    }
+    if options.threads.len() > 1 {
+        CmdOptions::command()
+            .error(
+                ErrorKind::TooManyValues,
+                "--threads takes a list of values only with --bench",
+            )
+            .exit();
     }
-    factors.sort();
-    let factors_str = factors
-        .into_iter()
-        .map(|f| f.to_string())
-        .collect::<Vec<String>>()
-        .join(" * ");
-    println!("{} = {} [time: {:?}]", num, factors_str, start.elapsed());
-}
-
-/// Returns a list of numbers supplied via argv.
-fn get_input_numbers() -> VecDeque<u32> {
-    let mut numbers = VecDeque::new();
-    for arg in env::args().skip(1) {
-        if let Ok(val) = arg.parse::<u32>() {
-            numbers.push_back(val);
+
+    // Machine-readable output needs stdout to itself, so everything meant for people goes to
+    // stderr instead, and unparsable inputs become error records rather than aborting the run.
+    // The same goes for people watching the terminal while results go to a file.
+    let machine_readable = options.format.is_machine_readable();
+    let keep_going = machine_readable || options.skip_invalid;
+    let mut human: Box<dyn Write> = if machine_readable || options.output.is_some() {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    };
+
+    // Numbers come either from argv or, streamed, from stdin. Invalid arguments are all found
+    // before anything is factored; invalid lines of stdin only once the workers get to them.
+    let stdin_errors = Arc::new(Mutex::new(StdinErrors::default()));
+    let mut invalid = Vec::new();
+    let numbers: Box<dyn Iterator<Item = u64> + Send> =
+        if options.numbers.is_empty() && options.ranges.is_empty() {
+            Box::new(read_stdin_numbers(stdin_errors.clone()))
         } else {
-            println!("{} is not a valid number", arg);
+            match get_input_numbers(&options.numbers, &options.ranges) {
+                Ok(inputs) => Box::new(inputs),
+                Err(InputError::Invalid { errors, valid }) if keep_going => {
+                    invalid = errors;
+                    Box::new(valid)
+                }
+                Err(err) => {
+                    if let InputError::Invalid { errors, .. } = &err {
+                        report_invalid("Error", errors);
+                    }
+                    eprintln!("Error: {}", err);
+                    process::exit(1);
+                }
+            }
+        };
+
+    // All results are written from this thread as they come off the results channel, so lines
+    // never interleave. A file is only flushed once the run finishes or is interrupted.
+    let out: Box<dyn Write> = match &options.output {
+        Some(path) => match create_output(path, options.append) {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(err) => {
+                eprintln!("Error creating {}: {}", path.display(), err);
+                process::exit(1);
+            }
+        },
+        None => Box::new(io::stdout()),
+    };
+
+    let configured = configured_threads(options.threads[0]);
+    let num_inputs = numbers.size_hint().1;
+    let num_threads = effective_threads(configured, num_inputs.unwrap_or(usize::MAX));
+    let _ = writeln!(human, "Farm starting on {} threads", num_threads);
+    let start = Instant::now();
+
+    let completed = Arc::new(AtomicUsize::new(0));
+    let reporter = if options.progress {
+        let stderr = io::stderr();
+        let in_place = stderr.is_terminal();
+        Some(ProgressReporter::spawn(
+            completed.clone(),
+            num_inputs,
+            Duration::from_secs(1),
+            in_place,
+            stderr,
+        ))
+    } else {
+        None
+    };
+
+    let stop = install_interrupt_handler();
+    let run_options = Options {
+        ordered: options.ordered,
+        completed: Some(completed),
+        stop: Some(stop.clone()),
+        ..factoring_options(&options, num_threads)
+    };
+    let mut records = RecordWriter::new(options.format, out).unwrap_or_else(|err| {
+        eprintln!("Error writing results: {}", err);
+        process::exit(1);
+    });
+    let mut summary = Summary::with_top(options.top);
+    let outcome = factor_with(numbers, &run_options, |factorization| {
+        if let Err(err) = records.write_result(&factorization) {
+            eprintln!("Error writing results: {}", err);
             process::exit(1);
         }
+        summary.record(&factorization);
+    });
+    summary.workers = outcome.workers;
+    if let Some(reporter) = reporter {
+        reporter.finish();
+    }
+    let stdin_errors = std::mem::take(&mut *stdin_errors.lock().unwrap());
+    invalid.extend(stdin_errors.invalid);
+    let written = if machine_readable {
+        invalid
+            .iter()
+            .try_for_each(|token| records.write_error(&token.token, &token.reason.to_string()))
+    } else {
+        Ok(())
+    }
+    .and_then(|_| records.finish());
+    if let Err(err) = written {
+        eprintln!("Error writing results: {}", err);
+        process::exit(1);
+    }
+
+    if let Err(err) = write_report(&mut human, &summary, start.elapsed(), &outcome.skipped) {
+        eprintln!("Error writing summary: {}", err);
+        process::exit(1);
+    }
+    let _ = writeln!(
+        human,
+        "Threads: {} configured, {} effective",
+        configured, num_threads
+    );
+    let _ = summary.write_timings(HISTOGRAM_WIDTH, &mut io::stderr());
+
+    if !machine_readable {
+        let prefix = if options.skip_invalid {
+            "Skipped"
+        } else {
+            "Error"
+        };
+        report_invalid(prefix, &invalid);
+    }
+    if let Some(err) = &stdin_errors.read {
+        eprintln!("Error: {}", err);
+    }
+
+    if stop.load(Ordering::SeqCst) {
+        process::exit(130);
+    }
+    if (!invalid.is_empty() && !keep_going) || stdin_errors.read.is_some() {
+        process::exit(1);
+    }
+    if !summary.failed.is_empty() {
+        process::exit(EXIT_FAILED);
+    }
+    if !summary.timed_out.is_empty() {
+        process::exit(EXIT_TIMED_OUT);
     }
-    numbers
 }
 
-fn get_a_number<T>(numbers: &mut Arc<Mutex<VecDeque<T>>>) -> Option<T> {
-    let mut locked = numbers.lock().unwrap();
-    locked.pop_front()
+/// Writes the end-of-run summary, including the inputs skipped because of a Ctrl-C.
+fn write_report(
+    out: &mut dyn Write,
+    summary: &Summary,
+    elapsed: Duration,
+    skipped: &[u64],
+) -> io::Result<()> {
+    summary.write_report(elapsed, out)?;
+    if !skipped.is_empty() {
+        let skipped_str = skipped
+            .iter()
+            .map(|num| num.to_string())
+            .collect::<Vec<String>>()
+            .join(" ");
+        writeln!(out, "Skipped {} inputs: {}", skipped.len(), skipped_str)?;
+    }
+    Ok(())
 }
 
-fn main() {
-    let num_threads = num_cpus::get();
-    println!("Farm starting on {} CPUs", num_threads);
-    let start = Instant::now();
+#[cfg(test)]
+mod test {
+    use super::*;
+    use farm::TokenError;
 
-    // Call get_input_numbers() and store a queue of numbers to factor
-    let numbers = Arc::new(Mutex::new(get_input_numbers()));
-
-    // Spawn `num_threads` threads, each of which pops numbers off the queue and calls
-    // factor_number() until the queue is empty
-    let mut threads = Vec::new();
-    for _ in 0..num_threads {
-        let mut numbers_ref = numbers.clone();
-        threads.push(thread::spawn(move || {
-            loop {
-                match get_a_number(&mut numbers_ref) {
-                    Some(num) => factor_number(num),
-                    None => break,
-                }
+    #[test]
+    fn test_parse_options() {
+        let options = CmdOptions::try_parse_from(["farm", "--threads", "3", "12", "15"]).unwrap();
+        assert_eq!(options.threads, vec![3]);
+        assert_eq!(options.numbers, vec!["12", "15"]);
+        assert!(!options.ordered);
+
+        let options = CmdOptions::try_parse_from(["farm", "12", "--ordered", "-t", "2"]).unwrap();
+        assert_eq!(options.threads, vec![2]);
+        assert_eq!(options.numbers, vec!["12"]);
+        assert!(options.ordered);
+        assert!(!options.progress);
+
+        let options = CmdOptions::try_parse_from(["farm", "--progress", "7"]).unwrap();
+        assert!(options.progress);
+
+        let options =
+            CmdOptions::try_parse_from(["farm", "--sieve-bound", "0", "--no-cache", "7"]).unwrap();
+        assert_eq!(options.sieve_bound, 0);
+        assert!(options.no_cache);
+
+        let options =
+            CmdOptions::try_parse_from(["farm", "1..=5", "--range", "1e6..1e6+1000", "7"]).unwrap();
+        assert_eq!(options.numbers, vec!["1..=5", "7"]);
+        assert_eq!(options.ranges, vec!["1e6..1e6+1000"]);
+
+        let options = CmdOptions::try_parse_from(["farm", "--bench", "-t", "3,6"]).unwrap();
+        assert!(options.bench);
+        assert_eq!(options.threads, vec![3, 6]);
+
+        let options = CmdOptions::try_parse_from(["farm", "--listen", "/tmp/farm.sock"]).unwrap();
+        assert_eq!(options.listen, Some(PathBuf::from("/tmp/farm.sock")));
+
+        let options = CmdOptions::try_parse_from(["farm", "--skip-invalid", "x", "7"]).unwrap();
+        assert!(options.skip_invalid);
+        assert_eq!(options.numbers, vec!["x", "7"]);
+
+        let options =
+            CmdOptions::try_parse_from(["farm", "--output", "out.txt", "--append", "7"]).unwrap();
+        assert_eq!(options.output, Some(PathBuf::from("out.txt")));
+        assert!(options.append);
+        assert!(CmdOptions::try_parse_from(["farm", "--append", "7"]).is_err());
+
+        let options =
+            CmdOptions::try_parse_from(["farm", "--nice", "-5", "--pin-threads", "7"]).unwrap();
+        assert_eq!(options.nice, Some(-5));
+        assert!(options.pin_threads);
+        assert_eq!(options.numbers, vec!["7"]);
+
+        let options = CmdOptions::try_parse_from(["farm", "--top", "0", "7"]).unwrap();
+        assert_eq!(options.top, 0);
+
+        let options = CmdOptions::try_parse_from(["farm", "--timeout-ms", "250", "7"]).unwrap();
+        assert_eq!(options.timeout_ms, 250);
+
+        let options = CmdOptions::try_parse_from(["farm", "--format", "ndjson", "7"]).unwrap();
+        assert_eq!(options.format, Format::Ndjson);
+        assert!(CmdOptions::try_parse_from(["farm", "--format", "yaml", "7"]).is_err());
+
+        let options = CmdOptions::try_parse_from(["farm"]).unwrap();
+        assert_eq!(options.format, Format::Text);
+        assert_eq!(options.threads, vec![0]);
+        assert!(!options.bench);
+        assert_eq!(options.listen, None);
+        assert_eq!(options.sieve_bound, 1 << 20);
+        assert!(!options.no_cache);
+        assert_eq!(options.timeout_ms, 0);
+        assert!(!options.skip_invalid);
+        assert_eq!(options.output, None);
+        assert!(!options.append);
+        assert_eq!(options.nice, None);
+        assert!(!options.pin_threads);
+        assert_eq!(options.top, 5);
+        assert!(options.numbers.is_empty());
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_thread_counts() {
+        assert!(CmdOptions::try_parse_from(["farm", "--threads", "-1", "12"]).is_err());
+        assert!(CmdOptions::try_parse_from(["farm", "--threads", "many", "12"]).is_err());
+        assert!(CmdOptions::try_parse_from(["farm", "--threads"]).is_err());
+    }
+
+    fn strings(tokens: &[&str]) -> Vec<String> {
+        tokens.iter().map(|token| token.to_string()).collect()
+    }
+
+    #[test]
+    fn test_get_input_numbers_mixes_ranges_and_numbers() {
+        let inputs = get_input_numbers(
+            &strings(&["3..6", "100", "8..=9", "10..10"]),
+            &strings(&["2e1"]),
+        )
+        .unwrap();
+        assert_eq!(inputs.size_hint(), (7, Some(7)));
+        assert_eq!(inputs.collect::<Vec<u64>>(), vec![3, 4, 5, 100, 8, 9, 20]);
+    }
+
+    #[test]
+    fn test_get_input_numbers_reports_every_invalid_token() {
+        let numbers = strings(&["12", "4x2", "1..3", "-5", "99999999999999999999", "7"]);
+        let ranges = strings(&["1..=2", "3..x"]);
+        let (errors, valid) = match get_input_numbers(&numbers, &ranges) {
+            Err(InputError::Invalid { errors, valid }) => (errors, valid),
+            other => panic!("expected invalid inputs, got {:?}", other),
+        };
+        assert_eq!(valid.collect::<Vec<u64>>(), vec![12, 1, 2, 7, 1, 2]);
+        let reported: Vec<(&str, Source, TokenError)> = errors
+            .iter()
+            .map(|err| (err.token.as_str(), err.origin, err.reason.clone()))
+            .collect();
+        assert_eq!(
+            reported,
+            vec![
+                ("4x2", Source::Argument(2), TokenError::InvalidNumber),
+                ("-5", Source::Argument(4), TokenError::InvalidNumber),
+                (
+                    "99999999999999999999",
+                    Source::Argument(5),
+                    TokenError::TooLarge
+                ),
+                ("3..x", Source::RangeOption(2), TokenError::InvalidRange),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_input_numbers_all_invalid() {
+        match get_input_numbers(&strings(&["x", "y"]), &[]) {
+            Err(InputError::Invalid { errors, valid }) => {
+                assert_eq!(errors.len(), 2);
+                assert_eq!(valid.count(), 0);
             }
-        }))
+            other => panic!("expected invalid inputs, got {:?}", other),
+        }
+    }
+
+    /// Factors 90..=100 on four threads in order, writing the results to `path` the way `--output`
+    /// does. Durations are pinned so that the file comes out the same every time.
+    fn write_output(path: &Path, append: bool, format: Format) {
+        let file = BufWriter::new(create_output(path, append).unwrap());
+        let mut records = RecordWriter::new(format, file).unwrap();
+        let options = Options {
+            threads: 4,
+            ordered: true,
+            ..Options::default()
+        };
+        factor_with(90..=100, &options, |factorization| {
+            let factorization = farm::Factorization {
+                duration: Duration::from_micros(250),
+                ..factorization
+            };
+            records.write_result(&factorization).unwrap();
+        });
+        records.finish().unwrap();
     }
 
-    // Join all the threads you created
-    for handle in threads {
-        handle.join().expect("Err waiting threads joining!");
+    #[test]
+    fn test_output_file() {
+        let path = std::env::temp_dir().join(format!("farm-{}-output.csv", process::id()));
+        std::fs::write(
+            &path,
+            "left over from an earlier run, longer than the new output\n".repeat(20),
+        )
+        .unwrap();
+        let expected = "input,factors,prime,duration_ms,error\n\
+                        90,2 * 3^2 * 5,false,0.250,\n\
+                        91,7 * 13,false,0.250,\n\
+                        92,2^2 * 23,false,0.250,\n\
+                        93,3 * 31,false,0.250,\n\
+                        94,2 * 47,false,0.250,\n\
+                        95,5 * 19,false,0.250,\n\
+                        96,2^5 * 3,false,0.250,\n\
+                        97,97,true,0.250,\n\
+                        98,2 * 7^2,false,0.250,\n\
+                        99,3^2 * 11,false,0.250,\n\
+                        100,2^2 * 5^2,false,0.250,\n";
+
+        write_output(&path, false, Format::Csv);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+
+        write_output(&path, true, Format::Csv);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), expected.repeat(2));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_output_file_not_creatable() {
+        let path = std::env::temp_dir()
+            .join(format!("farm-{}-missing", process::id()))
+            .join("output.txt");
+        assert!(create_output(&path, false).is_err());
+        assert!(create_output(&path, true).is_err());
     }
 
-    println!("Total execution time: {:?}", start.elapsed());
+    #[test]
+    fn test_parse_nice() {
+        assert_eq!(parse_nice("-20"), Ok(-20));
+        assert_eq!(parse_nice("0"), Ok(0));
+        assert_eq!(parse_nice("19"), Ok(19));
+        assert!(parse_nice("20").is_err());
+        assert!(parse_nice("-21").is_err());
+        assert!(parse_nice("low").is_err());
+        assert!(CmdOptions::try_parse_from(["farm", "--nice", "20", "7"]).is_err());
+        assert!(CmdOptions::try_parse_from(["farm", "--nice", "-21", "7"]).is_err());
+    }
+
+    #[test]
+    fn test_configured_threads() {
+        assert_eq!(configured_threads(0), num_cpus::get());
+        assert_eq!(configured_threads(5), 5);
+    }
 }
@@ -0,0 +1,173 @@
+use crossbeam_channel as channel;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Formats one progress line. `total` is unknown when numbers are streamed in from stdin, in which
+/// case there is no percentage or ETA to show.
+fn format_progress(completed: usize, total: Option<usize>, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs_f64();
+    let throughput = if secs > 0.0 {
+        completed as f64 / secs
+    } else {
+        0.0
+    };
+    match total {
+        Some(total) => {
+            let percent = if total == 0 {
+                100.0
+            } else {
+                completed as f64 * 100.0 / total as f64
+            };
+            let eta = if throughput > 0.0 {
+                let remaining = total.saturating_sub(completed) as f64 / throughput;
+                format!("{:.0}s", remaining.ceil())
+            } else {
+                "?".to_string()
+            };
+            format!(
+                "Progress: {}/{} ({:.1}%), {:.1}/s, ETA {}",
+                completed, total, percent, throughput, eta
+            )
+        }
+        None => format!("Progress: {} done, {:.1}/s", completed, throughput),
+    }
+}
+
+/// A background thread that periodically reports how many numbers the workers have finished.
+pub struct ProgressReporter {
+    /// Dropping this wakes the reporter up and tells it to stop
+    done_sender: channel::Sender<()>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl ProgressReporter {
+    /// Starts reporting progress to `out` every `interval`, reading the completion count from
+    /// `completed`. With `in_place` set (meant for terminals), each report overwrites the previous
+    /// one; otherwise every report goes on its own line.
+    pub fn spawn<W>(
+        completed: Arc<AtomicUsize>,
+        total: Option<usize>,
+        interval: Duration,
+        in_place: bool,
+        mut out: W,
+    ) -> ProgressReporter
+    where
+        W: Write + Send + 'static,
+    {
+        let (done_sender, done_receiver) = channel::bounded::<()>(0);
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            let report = |out: &mut W| {
+                let line =
+                    format_progress(completed.load(Ordering::Relaxed), total, start.elapsed());
+                // Progress is best-effort; there's nothing useful to do if stderr is gone.
+                let _ = if in_place {
+                    write!(out, "\r{}\x1b[K", line)
+                } else {
+                    writeln!(out, "{}", line)
+                };
+                let _ = out.flush();
+            };
+            // The sender is never used to send, so this only returns early once it is dropped.
+            while let Err(channel::RecvTimeoutError::Timeout) = done_receiver.recv_timeout(interval)
+            {
+                report(&mut out);
+            }
+            report(&mut out);
+            if in_place {
+                let _ = writeln!(out);
+            }
+        });
+        ProgressReporter {
+            done_sender,
+            handle,
+        }
+    }
+
+    /// Prints a final report and waits for the reporter thread to exit.
+    pub fn finish(self) {
+        drop(self.done_sender);
+        self.handle
+            .join()
+            .expect("Err waiting progress reporter joining!");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io;
+    use std::sync::Mutex;
+
+    /// A writer whose contents can still be inspected after it was moved into the reporter.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_format_progress() {
+        assert_eq!(
+            format_progress(50, Some(200), Duration::from_secs(2)),
+            "Progress: 50/200 (25.0%), 25.0/s, ETA 6s"
+        );
+        assert_eq!(
+            format_progress(0, Some(10), Duration::from_secs(1)),
+            "Progress: 0/10 (0.0%), 0.0/s, ETA ?"
+        );
+        assert_eq!(
+            format_progress(30, None, Duration::from_secs(3)),
+            "Progress: 30 done, 10.0/s"
+        );
+    }
+
+    #[test]
+    fn test_finish_does_not_wait_for_interval() {
+        let completed = Arc::new(AtomicUsize::new(0));
+        let buffer = SharedBuffer::default();
+        let reporter = ProgressReporter::spawn(
+            completed.clone(),
+            Some(4),
+            Duration::from_secs(3600),
+            false,
+            buffer.clone(),
+        );
+        completed.store(4, Ordering::SeqCst);
+        let start = Instant::now();
+        reporter.finish();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(output.starts_with("Progress: 4/4 (100.0%)"), "{}", output);
+        assert_eq!(output.lines().count(), 1);
+    }
+
+    #[test]
+    fn test_in_place_reports_rewrite_the_line() {
+        let completed = Arc::new(AtomicUsize::new(1));
+        let buffer = SharedBuffer::default();
+        let reporter = ProgressReporter::spawn(
+            completed,
+            None,
+            Duration::from_millis(10),
+            true,
+            buffer.clone(),
+        );
+        thread::sleep(Duration::from_millis(100));
+        reporter.finish();
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(output.matches('\r').count() >= 2, "{:?}", output);
+        assert!(output.ends_with('\n'));
+        assert_eq!(output.matches('\n').count(), 1);
+    }
+}
@@ -0,0 +1,368 @@
+use crate::{Factorization, Histogram, Pinning};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// What one worker thread did during a run.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WorkerStats {
+    /// Number of inputs the worker factored
+    pub items: usize,
+    /// Time the worker spent factoring, as opposed to waiting for work
+    pub busy: Duration,
+    /// Whether the worker was pinned to a CPU
+    pub pinning: Pinning,
+}
+
+/// Aggregate timing statistics over every factorization of a run. Results are recorded one at a
+/// time as they come in, so the individual factorizations don't need to be kept around.
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Number of factorizations recorded
+    pub count: usize,
+    /// Sum of the per-number times
+    pub total: Duration,
+    /// Shortest per-number time
+    pub min: Option<Duration>,
+    /// Input that took the longest, and how long it took
+    pub slowest: Option<(u64, Duration)>,
+    /// Number of results answered from the cache of earlier results
+    pub cache_hits: usize,
+    /// Inputs that were abandoned because they hit the timeout, in the order they came in
+    pub timed_out: Vec<u64>,
+    /// Inputs whose factoring panicked, with the panic message, in the order they came in
+    pub failed: Vec<(u64, String)>,
+    /// What each worker did, if known, so the run's load balance can be checked
+    pub workers: Vec<WorkerStats>,
+    /// Per-number times on a log scale
+    pub histogram: Histogram,
+    /// How many of the slowest inputs to keep track of
+    top: usize,
+    /// The `top` slowest inputs so far, with the fastest of them on top so it can be replaced
+    slowest_inputs: BinaryHeap<Reverse<(Duration, u64)>>,
+}
+
+impl Summary {
+    pub fn new() -> Summary {
+        Summary::default()
+    }
+
+    /// Creates a summary that also keeps track of the `top` slowest inputs.
+    pub fn with_top(top: usize) -> Summary {
+        Summary {
+            top,
+            ..Summary::default()
+        }
+    }
+
+    /// Adds one factorization to the statistics.
+    pub fn record(&mut self, factorization: &Factorization) {
+        let duration = factorization.duration;
+        self.count += 1;
+        self.total += duration;
+        if factorization.cached {
+            self.cache_hits += 1;
+        }
+        if factorization.timed_out {
+            self.timed_out.push(factorization.input);
+        }
+        if let Some(failure) = &factorization.failure {
+            self.failed.push((factorization.input, failure.clone()));
+        }
+        self.histogram.record(duration);
+        if self.top > 0 {
+            self.slowest_inputs
+                .push(Reverse((duration, factorization.input)));
+            if self.slowest_inputs.len() > self.top {
+                self.slowest_inputs.pop();
+            }
+        }
+        self.min = Some(self.min.map_or(duration, |min| min.min(duration)));
+        match self.slowest {
+            Some((_, max)) if max >= duration => {}
+            _ => self.slowest = Some((factorization.input, duration)),
+        }
+    }
+
+    /// Longest per-number time.
+    pub fn max(&self) -> Option<Duration> {
+        self.slowest.map(|(_, duration)| duration)
+    }
+
+    /// Average per-number time.
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.total / self.count as u32)
+        }
+    }
+
+    /// Returns the slowest inputs kept track of (see `with_top`), slowest first.
+    pub fn slowest_inputs(&self) -> Vec<(u64, Duration)> {
+        let mut slowest: Vec<(u64, Duration)> = self
+            .slowest_inputs
+            .iter()
+            .map(|&Reverse((duration, input))| (input, duration))
+            .collect();
+        slowest.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        slowest
+    }
+
+    /// Writes the histogram of per-number times as a bar chart `width` characters wide, followed
+    /// by the slowest inputs.
+    pub fn write_timings<W: Write + ?Sized>(&self, width: usize, out: &mut W) -> io::Result<()> {
+        if self.count == 0 {
+            return Ok(());
+        }
+        writeln!(out, "Per-number times:")?;
+        self.histogram.write_chart(width, out)?;
+        let slowest = self.slowest_inputs();
+        if !slowest.is_empty() {
+            writeln!(out, "Slowest {} inputs:", slowest.len())?;
+            for (input, duration) in slowest {
+                writeln!(out, "  {} ({:?})", input, duration)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the human-readable report, given the wall-clock time the whole run took.
+    pub fn write_report<W: Write + ?Sized>(
+        &self,
+        elapsed: Duration,
+        out: &mut W,
+    ) -> io::Result<()> {
+        writeln!(out, "Factored {} numbers in {:?}", self.count, elapsed)?;
+        if let (Some(min), Some(mean), Some((input, max))) = (self.min, self.mean(), self.slowest) {
+            writeln!(
+                out,
+                "Per-number time: min {:?}, max {:?}, mean {:?}",
+                min, max, mean
+            )?;
+            writeln!(out, "Slowest input: {} ({:?})", input, max)?;
+        }
+        if self.cache_hits > 0 {
+            writeln!(out, "Cache hits: {}", self.cache_hits)?;
+        }
+        if !self.timed_out.is_empty() {
+            let timed_out_str = self
+                .timed_out
+                .iter()
+                .map(|num| num.to_string())
+                .collect::<Vec<String>>()
+                .join(" ");
+            writeln!(
+                out,
+                "Timed out on {} inputs: {}",
+                self.timed_out.len(),
+                timed_out_str
+            )?;
+        }
+        if !self.failed.is_empty() {
+            writeln!(out, "Failed on {} inputs:", self.failed.len())?;
+            for (input, failure) in &self.failed {
+                writeln!(out, "  {}: {}", input, failure)?;
+            }
+        }
+        for (id, worker) in self.workers.iter().enumerate() {
+            let busy_percent = if elapsed.as_secs_f64() > 0.0 {
+                worker.busy.as_secs_f64() * 100.0 / elapsed.as_secs_f64()
+            } else {
+                0.0
+            };
+            write!(
+                out,
+                "Worker {}: {} numbers, busy {:?} ({:.1}%)",
+                id, worker.items, worker.busy, busy_percent
+            )?;
+            match worker.pinning {
+                Pinning::Off => writeln!(out)?,
+                Pinning::Pinned(cpu) => writeln!(out, ", pinned to CPU {}", cpu)?,
+                Pinning::Failed => writeln!(out, ", not pinned")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn factorization(input: u64, micros: u64) -> Factorization {
+        Factorization {
+            input,
+            factors: vec![input],
+            duration: Duration::from_micros(micros),
+            cached: false,
+            timed_out: false,
+            failure: None,
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        let mut summary = Summary::new();
+        assert_eq!(summary.mean(), None);
+        assert_eq!(summary.max(), None);
+
+        summary.record(&factorization(7, 30));
+        summary.record(&factorization(11, 10));
+        summary.record(&factorization(13, 50));
+        summary.record(&factorization(17, 30));
+        assert_eq!(summary.count, 4);
+        assert_eq!(summary.min, Some(Duration::from_micros(10)));
+        assert_eq!(summary.max(), Some(Duration::from_micros(50)));
+        assert_eq!(summary.mean(), Some(Duration::from_micros(30)));
+        assert_eq!(summary.slowest, Some((13, Duration::from_micros(50))));
+        assert_eq!(summary.cache_hits, 0);
+
+        summary.record(&Factorization {
+            cached: true,
+            ..factorization(13, 1)
+        });
+        assert_eq!(summary.cache_hits, 1);
+        let mut output = Vec::new();
+        summary
+            .write_report(Duration::from_micros(200), &mut output)
+            .unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .ends_with("Cache hits: 1\n"));
+
+        summary.record(&Factorization {
+            timed_out: true,
+            ..factorization(19, 2000)
+        });
+        summary.record(&Factorization {
+            timed_out: true,
+            ..factorization(23, 2000)
+        });
+        assert_eq!(summary.timed_out, vec![19, 23]);
+        let mut output = Vec::new();
+        summary
+            .write_report(Duration::from_micros(5000), &mut output)
+            .unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .ends_with("Cache hits: 1\nTimed out on 2 inputs: 19 23\n"));
+
+        summary.record(&Factorization {
+            failure: Some("boom".to_string()),
+            factors: vec![],
+            ..factorization(29, 5)
+        });
+        assert_eq!(summary.failed, vec![(29, "boom".to_string())]);
+        let mut output = Vec::new();
+        summary
+            .write_report(Duration::from_micros(5000), &mut output)
+            .unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .ends_with("Timed out on 2 inputs: 19 23\nFailed on 1 inputs:\n  29: boom\n"));
+    }
+
+    #[test]
+    fn test_slowest_inputs() {
+        let mut summary = Summary::with_top(3);
+        for &(input, micros) in [(2, 5), (3, 40), (5, 7), (7, 900), (11, 40), (13, 1)].iter() {
+            summary.record(&factorization(input, micros));
+        }
+        assert_eq!(
+            summary.slowest_inputs(),
+            vec![
+                (7, Duration::from_micros(900)),
+                (3, Duration::from_micros(40)),
+                (11, Duration::from_micros(40)),
+            ]
+        );
+        assert!(Summary::new().slowest_inputs().is_empty());
+    }
+
+    #[test]
+    fn test_write_timings() {
+        let mut summary = Summary::with_top(2);
+        for &(input, micros) in [(2, 5), (3, 6), (5, 7), (7, 2_000)].iter() {
+            summary.record(&factorization(input, micros));
+        }
+        let mut output = Vec::new();
+        summary.write_timings(6, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                "Per-number times:\n",
+                "  1µs-10µs |######| 3\n",
+                "10µs-100µs |      | 0\n",
+                " 100µs-1ms |      | 0\n",
+                "  1ms-10ms |##    | 1\n",
+                "Slowest 2 inputs:\n",
+                "  7 (2ms)\n",
+                "  5 (7µs)\n",
+            )
+        );
+
+        let mut output = Vec::new();
+        Summary::new().write_timings(6, &mut output).unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_write_report() {
+        let mut summary = Summary::new();
+        summary.record(&factorization(7, 30));
+        summary.record(&factorization(11, 10));
+        let mut output = Vec::new();
+        summary
+            .write_report(Duration::from_micros(45), &mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Factored 2 numbers in 45µs\n\
+             Per-number time: min 10µs, max 30µs, mean 20µs\n\
+             Slowest input: 7 (30µs)\n"
+        );
+
+        let mut output = Vec::new();
+        Summary::new()
+            .write_report(Duration::from_micros(1), &mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Factored 0 numbers in 1µs\n"
+        );
+
+        summary.workers = vec![
+            WorkerStats {
+                items: 1,
+                busy: Duration::from_micros(30),
+                ..WorkerStats::default()
+            },
+            WorkerStats {
+                items: 1,
+                busy: Duration::from_micros(10),
+                ..WorkerStats::default()
+            },
+        ];
+        let mut output = Vec::new();
+        summary
+            .write_report(Duration::from_micros(40), &mut output)
+            .unwrap();
+        assert!(String::from_utf8(output).unwrap().ends_with(
+            "Worker 0: 1 numbers, busy 30µs (75.0%)\n\
+             Worker 1: 1 numbers, busy 10µs (25.0%)\n"
+        ));
+
+        summary.workers[0].pinning = Pinning::Pinned(2);
+        summary.workers[1].pinning = Pinning::Failed;
+        let mut output = Vec::new();
+        summary
+            .write_report(Duration::from_micros(40), &mut output)
+            .unwrap();
+        assert!(String::from_utf8(output).unwrap().ends_with(
+            "Worker 0: 1 numbers, busy 30µs (75.0%), pinned to CPU 2\n\
+             Worker 1: 1 numbers, busy 10µs (25.0%), not pinned\n"
+        ));
+    }
+}
@@ -0,0 +1,73 @@
+//! Checks that a handful of slow numbers at the front of the input don't end up serialized behind
+//! one worker just because they were taken from the queue in the same batch.
+
+use farm::{factor_number, factor_with, Factorization, Factorizer, Options};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const THREADS: usize = 4;
+
+/// How long each slow number takes. It's spent sleeping rather than computing, so that the slow
+/// numbers can all run at once even on a machine with fewer CPUs than workers.
+const SLOW_TIME: Duration = Duration::from_millis(100);
+
+/// Four slow numbers followed by lots of trivial ones. The first batch is large enough to hold all
+/// four slow numbers, so without stealing a single worker would factor them one after another.
+fn skewed_workload() -> Vec<u64> {
+    let mut nums = vec![
+        9_999_999_999_971,
+        9_999_999_999_971,
+        9_999_999_999_971,
+        9_999_999_999_971,
+    ];
+    nums.extend((0..1000).map(|n| n % 64));
+    nums
+}
+
+#[test]
+fn slow_numbers_are_spread_over_workers() {
+    let nums = skewed_workload();
+    let slow_threads = Arc::new(Mutex::new(Vec::new()));
+    let options = Options {
+        threads: THREADS,
+        factorizer: Some(Factorizer::new({
+            let slow_threads = slow_threads.clone();
+            move |num| {
+                let start = Instant::now();
+                if num > 64 {
+                    slow_threads.lock().unwrap().push(thread::current().id());
+                    thread::sleep(SLOW_TIME);
+                }
+                Factorization {
+                    duration: start.elapsed(),
+                    ..factor_number(num)
+                }
+            }
+        })),
+        ..Options::default()
+    };
+    let mut count = 0;
+    let outcome = factor_with(nums.clone(), &options, |_| count += 1);
+    assert_eq!(count, nums.len());
+
+    // Every worker ended up with one of the slow numbers...
+    let slow_threads = slow_threads.lock().unwrap();
+    assert_eq!(slow_threads.len(), 4);
+    assert_eq!(
+        slow_threads.iter().collect::<HashSet<_>>().len(),
+        THREADS,
+        "slow numbers went to {:?}",
+        slow_threads
+    );
+    // ...so each was busy for a fair share of the run, rather than one worker doing all the work.
+    assert_eq!(outcome.workers.len(), THREADS);
+    for worker in &outcome.workers {
+        assert!(
+            worker.busy >= SLOW_TIME,
+            "unbalanced workers: {:?}",
+            outcome.workers
+        );
+    }
+}
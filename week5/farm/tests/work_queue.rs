@@ -0,0 +1,16 @@
+//! Runs lots of tiny inputs through the channel-based work queue; how it compares with the old
+//! mutex-guarded deque is measured by `cargo bench --bench queue`.
+
+use farm::{factor_with, Options};
+
+#[test]
+fn channel_queue_factors_every_number() {
+    let nums: Vec<u64> = (0..100_000).map(|n| n % 64).collect();
+    let mut count = 0;
+    let options = Options {
+        threads: 4,
+        ..Options::default()
+    };
+    factor_with(nums.clone(), &options, |_| count += 1);
+    assert_eq!(count, nums.len());
+}
@@ -0,0 +1,44 @@
+//! Compares handing the workers one input at a time against handing them chunks, on a function so
+//! cheap that the dispatch is nearly all of the work.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use parallel_map::{parallel_map, parallel_map_chunked};
+
+const LEN: u64 = 1_000_000;
+const THREADS: usize = 4;
+
+fn inputs() -> Vec<u64> {
+    (0..LEN).collect()
+}
+
+fn bench_dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("double 1M numbers");
+    group.throughput(Throughput::Elements(LEN));
+    group.sample_size(10);
+
+    group.bench_function("sequential", |b| {
+        b.iter_batched(
+            inputs,
+            |input_vec| input_vec.into_iter().map(|x| x * 2).collect::<Vec<u64>>(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("one at a time", |b| {
+        b.iter_batched(
+            inputs,
+            |input_vec| parallel_map_chunked(input_vec, THREADS, 1, |x| x * 2),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("default chunks", |b| {
+        b.iter_batched(
+            inputs,
+            |input_vec| parallel_map(input_vec, THREADS, |x| x * 2),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_dispatch);
+criterion_main!(benches);
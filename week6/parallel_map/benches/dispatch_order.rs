@@ -0,0 +1,51 @@
+//! Compares handing out inputs first to last against last to first, on a function that's bound by
+//! memory bandwidth: each input names a block of one big buffer, which gets summed up, the way
+//! inputs might name regions of a memory-mapped file. How much mapping the blocks in the order
+//! they're laid out helps depends on the machine's prefetcher and on how many cores the workers
+//! actually get; with only one, the two come out within noise of each other.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use parallel_map::{parallel_map_with, DispatchOrder, Options};
+
+/// 128MB of buffer, far more than fits in any cache
+const BUFFER_LEN: usize = 16 * 1024 * 1024;
+/// Words per block: 4KB, a page
+const BLOCK_LEN: usize = 512;
+const THREADS: usize = 4;
+
+fn bench_dispatch_order(c: &mut Criterion) {
+    let buffer: Vec<u64> = (0..BUFFER_LEN as u64).collect();
+    let buffer = &buffer;
+    let blocks = BUFFER_LEN / BLOCK_LEN;
+
+    let mut group = c.benchmark_group("sum 128MB in 4KB blocks");
+    group.throughput(Throughput::Bytes((BUFFER_LEN * 8) as u64));
+    group.sample_size(10);
+    for &(name, order) in [
+        ("forward", DispatchOrder::Forward),
+        ("reverse", DispatchOrder::Reverse),
+    ]
+    .iter()
+    {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let options = Options {
+                    threads: THREADS,
+                    // Small chunks, so that the order of the chunks is most of the order.
+                    chunk_size: Some(1),
+                    dispatch_order: order,
+                    ..Options::default()
+                };
+                parallel_map_with((0..blocks).collect(), options, |block: usize| {
+                    buffer[block * BLOCK_LEN..(block + 1) * BLOCK_LEN]
+                        .iter()
+                        .sum::<u64>()
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_dispatch_order);
+criterion_main!(benches);
@@ -0,0 +1,38 @@
+//! Compares mapping lots of tiny vectors on a `ParallelMapper`'s threads against spawning new
+//! threads for every one of them, which is most of what a call costs at that size.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use parallel_map::{parallel_map, ParallelMapper};
+
+const CALLS: u64 = 1000;
+const THREADS: usize = 4;
+
+fn inputs() -> Vec<u64> {
+    (0..8).collect()
+}
+
+fn bench_pool(c: &mut Criterion) {
+    let mut group = c.benchmark_group("1000 maps of 8 numbers");
+    group.throughput(Throughput::Elements(CALLS));
+    group.sample_size(10);
+
+    group.bench_function("spawning per call", |b| {
+        b.iter(|| {
+            for _ in 0..CALLS {
+                parallel_map(inputs(), THREADS, |num: u64| num + 1);
+            }
+        })
+    });
+    let mapper = ParallelMapper::new(THREADS);
+    group.bench_function("pool", |b| {
+        b.iter(|| {
+            for _ in 0..CALLS {
+                mapper.map(inputs(), |num: u64| num + 1);
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_pool);
+criterion_main!(benches);
@@ -0,0 +1,103 @@
+use parallel_map::{
+    parallel_filter_map, parallel_find, parallel_for_each, parallel_map, parallel_map_iter,
+    parallel_map_keyed, parallel_map_reduce, parallel_map_scoped, parallel_map_with,
+    parallel_try_map, parallel_try_map_all, Options, ParallelMapper,
+};
+use std::{thread, time};
+
+fn main() {
+    let v = vec![6, 7, 8, 9, 10, 1, 2, 3, 4, 5, 12, 18, 11, 5, 20];
+    let squares = parallel_map(v, 10, |num| {
+        println!("{} squared is {}", num, num * num);
+        thread::sleep(time::Duration::from_millis(500));
+        num * num
+    });
+    println!("squares: {:?}", squares);
+
+    let greeting = String::from("Hello");
+    let names = vec!["Ada", "Grace", "Barbara"];
+    let greetings = parallel_map(names, 2, move |name| format!("{}, {}!", greeting, name));
+    println!("greetings: {:?}", greetings);
+
+    let words = vec!["12", "7", "forty-two", "3", "eleventy"];
+    let words: Vec<String> = words.into_iter().map(String::from).collect();
+    match parallel_try_map(words.clone(), 4, |word: String| word.parse::<u32>()) {
+        Ok(numbers) => println!("numbers: {:?}", numbers),
+        Err(err) => println!("couldn't parse the words: {}", err),
+    }
+    let results = parallel_try_map_all(words, 4, |word: String| word.parse::<u32>());
+    println!(
+        "numbers: {:?}, errors: {:?}",
+        results.successes, results.failures
+    );
+
+    // One pool of threads for many small maps
+    let mapper = ParallelMapper::new(4);
+    let rows: Vec<Vec<u32>> = (1..=4)
+        .map(|row| mapper.map((1..=4).collect(), move |col| row * col))
+        .collect();
+    println!(
+        "times table from {} threads: {:?}",
+        mapper.num_threads(),
+        rows
+    );
+
+    // Borrowing from the caller instead of moving into the closure
+    let vowels = String::from("aeiou");
+    let counts = parallel_map_scoped(vec!["parallel", "map", "queue"], 2, |word: &str| {
+        word.chars().filter(|c| vowels.contains(*c)).count()
+    });
+    println!("vowel counts: {:?}", counts);
+
+    // Progress is reported on this thread as each result comes in.
+    let options = Options {
+        threads: 3,
+        on_progress: Some(Box::new(|progress| {
+            println!(
+                "{}/{} done, last took {:?}",
+                progress.completed, progress.total, progress.duration
+            )
+        })),
+        collect_timings: true,
+        ..Options::default()
+    };
+    let slept = parallel_map_with(vec![30, 10, 20, 40], options, |millis: u64| {
+        thread::sleep(time::Duration::from_millis(millis));
+        millis
+    });
+    println!("timings: {:?}", slept.durations.unwrap_or_default());
+
+    let lengths = parallel_map_keyed(vec![("ant", "ant"), ("bee", "bumblebee")], 2, str::len);
+    println!("lengths: {:?}", lengths);
+    let numbers = parallel_filter_map(vec!["12", "seven", "3"], 3, |word| word.parse::<u32>().ok());
+    println!("the words that were numbers: {:?}", numbers);
+
+    // Nothing collected: each worker keeps a running total instead.
+    let sum_of_squares = parallel_map_reduce(
+        (1..=1000).collect(),
+        4,
+        |num: u64| num * num,
+        0,
+        |a, b| a + b,
+    );
+    println!("sum of squares up to 1000: {}", sum_of_squares);
+    parallel_for_each(vec!["one", "two", "three"], 2, |word| {
+        println!("visited {}", word)
+    });
+
+    // Stops looking once any worker finds one
+    match parallel_find((0..10_000).collect(), 4, |num: &u64| {
+        num.to_string().contains("77")
+    }) {
+        Some((id, num)) => println!("found {} at index {}", num, id),
+        None => println!("no number with two 7s in a row"),
+    }
+
+    // Results are printed as they come in, in order, while later numbers are still being mapped.
+    for cube in parallel_map_iter(1..=8, 3, |num: u64| {
+        thread::sleep(time::Duration::from_millis(100 * (num % 3)));
+        num * num * num
+    }) {
+        println!("next cube: {}", cube);
+    }
+}
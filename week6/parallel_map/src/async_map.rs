@@ -0,0 +1,161 @@
+use crate::effective_workers;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use std::future::Future;
+
+/// Maps `f` over `input_vec`, with up to `concurrency` (at least 1) of the futures it returns being
+/// polled at once, and returns the results in input order. Nothing is spawned: the futures all run
+/// on whichever task awaits this one, which suits `f` that spends its time waiting on IO rather
+/// than computing.
+///
+/// A future is only started once every earlier one has been or is being polled, so a slow input
+/// holds up at most `concurrency - 1` of the ones after it.
+pub async fn parallel_map_async<T, U, F, Fut>(input_vec: Vec<T>, concurrency: usize, f: F) -> Vec<U>
+where
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = U>,
+{
+    let concurrency = effective_workers(concurrency, input_vec.len());
+    if concurrency == 0 {
+        return Vec::new();
+    }
+    stream::iter(input_vec)
+        .map(f)
+        .buffered(concurrency)
+        .collect()
+        .await
+}
+
+/// Like `parallel_map_async`, but for `f` that may fail: returns every result if none of them is
+/// an error, and otherwise the error for the earliest input that failed. Once it's known, no more
+/// futures are started, and those still running are dropped.
+pub async fn parallel_try_map_async<T, U, E, F, Fut>(
+    input_vec: Vec<T>,
+    concurrency: usize,
+    f: F,
+) -> Result<Vec<U>, E>
+where
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = Result<U, E>>,
+{
+    let concurrency = effective_workers(concurrency, input_vec.len());
+    if concurrency == 0 {
+        return Ok(Vec::new());
+    }
+    stream::iter(input_vec)
+        .map(f)
+        .buffered(concurrency)
+        .try_collect()
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tokio::time;
+
+    /// Counts the futures being polled at once, and the most there ever were.
+    #[derive(Default)]
+    struct Active {
+        now: AtomicUsize,
+        most: AtomicUsize,
+    }
+
+    impl Active {
+        /// Waits for `millis` milliseconds as one of the active futures.
+        async fn sleep(&self, millis: u64) {
+            let now = self.now.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most.fetch_max(now, Ordering::SeqCst);
+            time::sleep(Duration::from_millis(millis)).await;
+            self.now.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keeps_input_order() {
+        // Later inputs finish first.
+        let millis: Vec<u64> = (0..20).rev().collect();
+        let results = parallel_map_async(millis.clone(), 8, |millis| async move {
+            time::sleep(Duration::from_millis(millis)).await;
+            millis * 2
+        })
+        .await;
+        assert_eq!(
+            results,
+            millis.iter().map(|millis| millis * 2).collect::<Vec<_>>()
+        );
+
+        let words = parallel_map_async(
+            vec![1, 22, 333],
+            0,
+            |num: u32| async move { num.to_string() },
+        )
+        .await;
+        assert_eq!(words, vec!["1", "22", "333"]);
+        assert_eq!(
+            parallel_map_async(Vec::new(), 4, |num: u32| async move { num }).await,
+            Vec::<u32>::new()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_limits_concurrency() {
+        for &concurrency in [1, 3, 8].iter() {
+            let active = Active::default();
+            let results = parallel_map_async((0..30).collect(), concurrency, |num: u64| {
+                let active = &active;
+                async move {
+                    active.sleep(num % 4).await;
+                    num
+                }
+            })
+            .await;
+            assert_eq!(results, (0..30).collect::<Vec<u64>>());
+            // Never over the limit, but the limit was reached.
+            assert_eq!(active.most.load(Ordering::SeqCst), concurrency);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_try_map() {
+        let parsed = parallel_try_map_async(vec!["1", "2", "3"], 2, |word: &str| async move {
+            word.parse::<u32>()
+        })
+        .await;
+        assert_eq!(parsed, Ok(vec![1, 2, 3]));
+
+        // Only the earliest error is returned, even if a later one comes in first.
+        let parsed = parallel_try_map_async(vec!["1", "x", "2", "y"], 4, |word: &str| async move {
+            let millis = if word == "x" { 20 } else { 0 };
+            time::sleep(Duration::from_millis(millis)).await;
+            word.parse::<u32>().map_err(|_| word)
+        })
+        .await;
+        assert_eq!(parsed, Err("x"));
+    }
+
+    #[tokio::test]
+    async fn test_try_map_stops_at_first_error() {
+        let started = AtomicUsize::new(0);
+        let finished = AtomicUsize::new(0);
+        let results = parallel_try_map_async((0..100).collect(), 4, |num: u32| {
+            let (started, finished) = (&started, &finished);
+            async move {
+                started.fetch_add(1, Ordering::SeqCst);
+                if num == 10 {
+                    return Err(num);
+                }
+                time::sleep(Duration::from_millis(5)).await;
+                finished.fetch_add(1, Ordering::SeqCst);
+                Ok(num)
+            }
+        })
+        .await;
+        assert_eq!(results, Err(10));
+        // Only the futures already running alongside the failing one were started.
+        let started = started.load(Ordering::SeqCst);
+        assert!(started < 10 + 4, "{} futures started", started);
+        assert!(finished.load(Ordering::SeqCst) <= 10);
+    }
+}
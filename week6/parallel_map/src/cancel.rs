@@ -0,0 +1,209 @@
+use crate::{map_until, spawn_failed, Dispatch};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A flag shared between a `parallel_map_cancellable` call and whoever may want to cut it short.
+/// Clones share the same flag, and once cancelled it stays cancelled.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Tells the workers not to start on any more inputs.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The outcome of `parallel_map_cancellable`: the result for each input, in input order, or None
+/// for the inputs that were skipped because the map was cancelled.
+#[derive(Debug, PartialEq)]
+pub struct CancellableResults<U> {
+    pub results: Vec<Option<U>>,
+    pub cancelled: bool,
+}
+
+/// Maps `f` over `input_vec` on `num_threads` worker threads until `token` is cancelled, whether
+/// by `f` itself or by another thread. Workers check the token before starting on each input, and
+/// `f` is handed it too, so that an input that takes long to map can give up partway. Like
+/// `parallel_map_scoped`, the inputs, the results and `f` may all borrow from the caller.
+///
+/// If `f` panics, the panic is resumed on the calling thread once the workers are done.
+pub fn parallel_map_cancellable<T, U, F>(
+    input_vec: Vec<T>,
+    num_threads: usize,
+    token: &CancellationToken,
+    f: F,
+) -> CancellableResults<U>
+where
+    F: Fn(T, &CancellationToken) -> U + Send + Sync,
+    T: Send,
+    U: Send,
+{
+    let dispatch = Dispatch::new::<T>(input_vec.len(), num_threads);
+    let results = map_until(
+        input_vec,
+        dispatch,
+        &token.0,
+        |_| false,
+        || |_, val| f(val, token),
+        |_, _| {},
+        &|_| {},
+    )
+    .unwrap_or_else(spawn_failed)
+    .into_options();
+    CancellableResults {
+        results,
+        cancelled: token.is_cancelled(),
+    }
+}
+
+/// Looks for an input that `pred` holds for on `num_threads` worker threads, and returns it along
+/// with its index, or None if there is no such input. The search stops as soon as one is found, so
+/// if several inputs match, which of them is returned depends on which the workers got to first.
+pub fn parallel_find<T, P>(input_vec: Vec<T>, num_threads: usize, pred: P) -> Option<(usize, T)>
+where
+    P: Fn(&T) -> bool + Send + Sync,
+    T: Send,
+{
+    let token = CancellationToken::new();
+    let found = parallel_map_cancellable(input_vec, num_threads, &token, |val, token| {
+        if pred(&val) {
+            token.cancel();
+            Some(val)
+        } else {
+            None
+        }
+    });
+    found
+        .results
+        .into_iter()
+        .enumerate()
+        .find_map(|(id, val)| val.flatten().map(|val| (id, val)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_runs_to_completion_unless_cancelled() {
+        let token = CancellationToken::new();
+        let squares =
+            parallel_map_cancellable((0..100).collect(), 4, &token, |num: u32, _| num * num);
+        assert!(!squares.cancelled);
+        let expected: Vec<Option<u32>> = (0..100).map(|num| Some(num * num)).collect();
+        assert_eq!(squares.results, expected);
+    }
+
+    #[test]
+    fn test_cancelled_before_starting() {
+        let token = CancellationToken::new();
+        token.clone().cancel();
+        let calls = AtomicUsize::new(0);
+        let results = parallel_map_cancellable((0..100).collect(), 4, &token, |num: u32, _| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            num
+        });
+        assert!(results.cancelled);
+        assert_eq!(results.results, vec![None; 100]);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_few_inputs_mapped_after_cancelling() {
+        const THREADS: usize = 4;
+        const CANCEL_AFTER: usize = 100;
+        let calls = AtomicUsize::new(0);
+        let token = CancellationToken::new();
+        let results = parallel_map_cancellable(
+            (0..100_000).collect(),
+            THREADS,
+            &token,
+            |num: u64, token| {
+                if calls.fetch_add(1, Ordering::SeqCst) + 1 == CANCEL_AFTER {
+                    token.cancel();
+                }
+                num
+            },
+        );
+        assert!(results.cancelled);
+        // Only the workers already past the check when the token was cancelled got any further.
+        let calls = calls.load(Ordering::SeqCst);
+        assert!(
+            calls < CANCEL_AFTER + THREADS,
+            "{} inputs mapped after cancelling at {}",
+            calls - CANCEL_AFTER,
+            CANCEL_AFTER
+        );
+        assert_eq!(results.results.iter().flatten().count(), calls);
+        for (id, val) in results.results.iter().enumerate() {
+            assert!(val.is_none() || *val == Some(id as u64));
+        }
+    }
+
+    #[test]
+    fn test_long_inputs_can_bail_out() {
+        let token = CancellationToken::new();
+        let start = Instant::now();
+        let results = thread::scope(|scope| {
+            let canceller = token.clone();
+            scope.spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                canceller.cancel();
+            });
+            parallel_map_cancellable(vec![10, 20], 2, &token, |secs: u64, token| {
+                // Stands in for a long computation that checks in every so often.
+                let deadline = Instant::now() + Duration::from_secs(secs);
+                while Instant::now() < deadline {
+                    if token.is_cancelled() {
+                        return None;
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                }
+                Some(secs)
+            })
+        });
+        assert!(results.cancelled);
+        assert_eq!(results.results, vec![Some(None), Some(None)]);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_find() {
+        let words = vec!["apple", "banana", "cherry", "date"];
+        assert_eq!(
+            parallel_find(words.clone(), 3, |word| word.starts_with('c')),
+            Some((2, "cherry"))
+        );
+        assert_eq!(parallel_find(words, 3, |word| word.is_empty()), None);
+        assert_eq!(parallel_find(Vec::<u32>::new(), 3, |_| true), None);
+
+        // Any one of the matches will do.
+        let (id, val) = parallel_find((0..1000).collect(), 4, |num: &u32| num % 100 == 42).unwrap();
+        assert_eq!(id as u32, val);
+        assert_eq!(val % 100, 42);
+    }
+
+    #[test]
+    fn test_find_stops_early() {
+        let checked = AtomicUsize::new(0);
+        let found = parallel_find((0..100_000).collect(), 4, |num: &u64| {
+            checked.fetch_add(1, Ordering::SeqCst);
+            *num % 1000 == 999
+        });
+        assert!(found.is_some());
+        let checked = checked.load(Ordering::SeqCst);
+        assert!(checked < 10_000, "{} inputs checked", checked);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{map_until, spawn_failed, Dispatch};
+use std::sync::atomic::AtomicBool;
+
+/// Maps `f` over `input_vec` on `num_threads` worker threads like `parallel_map_scoped`, handing
+/// `f` some state of the worker's own along with each input. Each worker makes its state with
+/// `init` once, on its own thread, before it takes any inputs, and drops it there once they run
+/// out. This suits state that's expensive to make, like a compiled regex or a connection, which
+/// would otherwise have to be made for every input or shared behind a lock.
+///
+/// If `f` panics, the workers still map every other input, with whatever state it left behind,
+/// and then the panic is resumed on the calling thread (the one for the earliest input, if there
+/// were several).
+pub fn parallel_map_init<T, U, S, I, F>(
+    input_vec: Vec<T>,
+    num_threads: usize,
+    init: I,
+    f: F,
+) -> Vec<U>
+where
+    I: Fn() -> S + Send + Sync,
+    F: Fn(&mut S, T) -> U + Send + Sync,
+    T: Send,
+    U: Send,
+{
+    let dispatch = Dispatch::new::<T>(input_vec.len(), num_threads);
+    let f = &f;
+    map_until(
+        input_vec,
+        dispatch,
+        &AtomicBool::new(false),
+        |_| false,
+        || {
+            let mut state = init();
+            move |_, val| f(&mut state, val)
+        },
+        |_, _| {},
+        &|_| {},
+    )
+    .unwrap_or_else(spawn_failed)
+    .into_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+    use std::panic;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::thread::{self, ThreadId};
+
+    /// A worker's state, which knows which thread made it and how many inputs it has seen.
+    struct Counter<'a> {
+        thread: ThreadId,
+        seen: usize,
+        /// Where to record the thread it was dropped on
+        drops: &'a Mutex<Vec<ThreadId>>,
+    }
+
+    impl Drop for Counter<'_> {
+        fn drop(&mut self) {
+            self.drops.lock().unwrap().push(thread::current().id());
+        }
+    }
+
+    #[test]
+    fn test_each_worker_keeps_its_own_state() {
+        const THREADS: usize = 4;
+        let inits = AtomicUsize::new(0);
+        let drops = Mutex::new(Vec::new());
+        let results = parallel_map_init(
+            (0..1000).collect(),
+            THREADS,
+            || {
+                inits.fetch_add(1, Ordering::SeqCst);
+                Counter {
+                    thread: thread::current().id(),
+                    seen: 0,
+                    drops: &drops,
+                }
+            },
+            |counter, num: u32| {
+                assert_eq!(counter.thread, thread::current().id());
+                counter.seen += 1;
+                (num, counter.thread, counter.seen)
+            },
+        );
+        assert_eq!(inits.load(Ordering::SeqCst), THREADS);
+
+        // Every input mapped in order, and each state's count went up one at a time, which it
+        // wouldn't have if two workers had shared it.
+        let mut seen_by_thread: HashMap<ThreadId, Vec<usize>> = HashMap::new();
+        for (id, (num, thread, seen)) in results.into_iter().enumerate() {
+            assert_eq!(num as usize, id);
+            seen_by_thread.entry(thread).or_default().push(seen);
+        }
+        assert!(seen_by_thread.len() <= THREADS);
+        for seen in seen_by_thread.values_mut() {
+            seen.sort_unstable();
+            assert_eq!(*seen, (1..=seen.len()).collect::<Vec<usize>>());
+        }
+
+        // Each state was dropped by the worker that made it.
+        let drops = drops.into_inner().unwrap();
+        assert_eq!(drops.len(), THREADS);
+        assert!(!drops.contains(&thread::current().id()));
+        assert_eq!(drops.iter().collect::<HashSet<_>>().len(), THREADS);
+    }
+
+    #[test]
+    fn test_init_runs_once_per_worker_that_gets_inputs() {
+        let inits = AtomicUsize::new(0);
+        let lengths = parallel_map_init(
+            vec!["a", "bb", "ccc"],
+            8,
+            || {
+                inits.fetch_add(1, Ordering::SeqCst);
+                String::new()
+            },
+            |buffer, word: &str| {
+                buffer.clear();
+                buffer.push_str(word);
+                buffer.len()
+            },
+        );
+        assert_eq!(lengths, vec![1, 2, 3]);
+        assert_eq!(inits.load(Ordering::SeqCst), 3);
+
+        let inits = AtomicUsize::new(0);
+        let init = || {
+            inits.fetch_add(1, Ordering::SeqCst);
+        };
+        assert_eq!(
+            parallel_map_init(Vec::new(), 4, init, |_, num: u32| num),
+            vec![]
+        );
+        assert_eq!(inits.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_panics_reach_the_caller() {
+        let calls = AtomicUsize::new(0);
+        let result = panic::catch_unwind(|| {
+            parallel_map_init(
+                (0..20).collect(),
+                3,
+                || 0,
+                |_, num: u32| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    assert!(num % 7 != 3, "can't map {}", num);
+                    num
+                },
+            )
+        });
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<String>().unwrap(), "can't map 3");
+        assert_eq!(calls.load(Ordering::SeqCst), 20);
+    }
+}
@@ -0,0 +1,225 @@
+use crate::spawn::spawn_workers;
+use crate::{effective_workers, run_worker, spawn_failed, Chunk, Output};
+use crossbeam_channel as channel;
+use std::collections::HashMap;
+use std::panic;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
+
+/// How many inputs per worker may be taken from the source before their results have been
+/// yielded. This bounds the memory held by a `ParallelMapIter`, however long the source is.
+const IN_FLIGHT_PER_WORKER: usize = 4;
+
+/// An iterator over the results of mapping a function over a source iterator on worker threads,
+/// created by `parallel_map_iter`. Inputs are taken from the source as results are yielded, so only
+/// a bounded number of them are ever in flight.
+pub struct ParallelMapIter<S: Iterator, U> {
+    source: std::iter::Enumerate<S>,
+    input_sender: Option<channel::Sender<Chunk<S::Item>>>,
+    output_receiver: channel::Receiver<Output<U>>,
+    /// Results (or panics) that arrived before their turn, by index
+    pending: HashMap<usize, thread::Result<U>>,
+    /// Index of the next result to yield
+    next_index: usize,
+    /// Number of inputs taken from the source so far
+    dispatched: usize,
+    max_in_flight: usize,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+/// Maps `f` over the items of `iter` on `num_threads` worker threads (at least 1), and returns an
+/// iterator over the results in input order. Each result is yielded as soon as it and every
+/// result before it are ready, so a long or endless source can be mapped without first collecting
+/// it; only the results that finished out of order are buffered.
+///
+/// If `f` panics, the panic is resumed on the thread that asks for that result, once every result
+/// before it has been yielded.
+pub fn parallel_map_iter<I, U, F>(
+    iter: I,
+    num_threads: usize,
+    f: F,
+) -> ParallelMapIter<I::IntoIter, U>
+where
+    I: IntoIterator,
+    I::Item: Send + 'static,
+    F: Fn(I::Item) -> U + Send + Sync + 'static,
+    U: Send + 'static,
+{
+    let source = iter.into_iter();
+    // Fewer workers if the source says it's short, but always one, since it may be wrong.
+    let num_threads = match source.size_hint() {
+        (_, Some(upper)) => effective_workers(num_threads, upper).max(1),
+        (_, None) => num_threads,
+    };
+    let (input_sender, input_receiver) = channel::unbounded();
+    let (output_sender, output_receiver) = channel::unbounded();
+    let f = Arc::new(f);
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let (threads, _) = spawn_workers(num_threads, None, || {
+        let input_receiver = input_receiver.clone();
+        let output_sender = output_sender.clone();
+        let f = f.clone();
+        let stop = stop.clone();
+        move || run_worker(&input_receiver, &output_sender, &stop, |_| false, &*f)
+    })
+    .unwrap_or_else(spawn_failed);
+
+    ParallelMapIter {
+        source: source.enumerate(),
+        input_sender: Some(input_sender),
+        output_receiver,
+        pending: HashMap::new(),
+        next_index: 0,
+        dispatched: 0,
+        max_in_flight: threads.len() * IN_FLIGHT_PER_WORKER,
+        threads,
+    }
+}
+
+impl<S: Iterator, U> ParallelMapIter<S, U> {
+    /// Takes inputs from the source until `max_in_flight` of them are waiting to be yielded or
+    /// the source runs out, which closes the input channel.
+    fn dispatch(&mut self) {
+        while self.dispatched - self.next_index < self.max_in_flight {
+            let sender = match &self.input_sender {
+                Some(sender) => sender,
+                None => return,
+            };
+            match self.source.next() {
+                // One at a time, since the source may be slow to produce the next input
+                Some((id, val)) => {
+                    sender
+                        .send((id, vec![val]))
+                        .expect("Couldn't send an input: the workers are gone!");
+                    self.dispatched += 1;
+                }
+                None => self.input_sender = None,
+            }
+        }
+    }
+}
+
+impl<S: Iterator, U> Iterator for ParallelMapIter<S, U> {
+    type Item = U;
+
+    fn next(&mut self) -> Option<U> {
+        self.dispatch();
+        if self.next_index == self.dispatched {
+            return None;
+        }
+        let result = loop {
+            if let Some(result) = self.pending.remove(&self.next_index) {
+                break result;
+            }
+            let (start, results) = self
+                .output_receiver
+                .recv()
+                .expect("Couldn't receive a result: the workers are gone!");
+            self.pending.extend((start..).zip(results));
+        };
+        self.next_index += 1;
+        match result {
+            Ok(val) => Some(val),
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+}
+
+impl<S: Iterator, U> Drop for ParallelMapIter<S, U> {
+    /// Lets the workers finish what they were given and waits for them.
+    fn drop(&mut self) {
+        self.input_sender = None;
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    /// Sleeps for a time that varies wildly from one number to the next, so that results come in
+    /// far out of order.
+    fn skewed(num: u64) -> u64 {
+        thread::sleep(Duration::from_millis((num * 7919) % 13));
+        num * 2
+    }
+
+    #[test]
+    fn test_yields_in_input_order() {
+        let results: Vec<u64> = parallel_map_iter(0..200, 4, skewed).collect();
+        assert_eq!(results, (0..200).map(|num| num * 2).collect::<Vec<u64>>());
+        assert_eq!(parallel_map_iter(0..0, 4, skewed).count(), 0);
+    }
+
+    #[test]
+    fn test_source_is_read_lazily() {
+        let taken = Rc::new(Cell::new(0));
+        let counter = taken.clone();
+        let source = (0..).inspect(move |_| counter.set(counter.get() + 1));
+        // The first input is much slower than all the others.
+        let mut results = parallel_map_iter(source, 3, |num: u64| {
+            if num == 0 {
+                thread::sleep(Duration::from_millis(200));
+            }
+            num
+        });
+
+        assert_eq!(results.next(), Some(0));
+        let max_in_flight = 3 * IN_FLIGHT_PER_WORKER;
+        assert_eq!(taken.get(), max_in_flight);
+        assert!(results.pending.len() < max_in_flight);
+
+        // An endless source, of which only as much is read as is needed.
+        let taken_so_far = taken.get();
+        let next: Vec<u64> = results.by_ref().take(100).collect();
+        assert_eq!(next, (1..=100).collect::<Vec<u64>>());
+        assert_eq!(taken.get(), taken_so_far + 100);
+        assert!(results.pending.len() < max_in_flight);
+    }
+
+    #[test]
+    fn test_stopping_early() {
+        let mut results = parallel_map_iter(vec!["a", "b", "c"], 2, |s: &str| s.to_uppercase());
+        assert_eq!(results.next().as_deref(), Some("A"));
+        drop(results);
+    }
+
+    #[test]
+    #[should_panic(expected = "no sevens")]
+    fn test_panics_reach_the_caller() {
+        for _ in parallel_map_iter(0..20, 4, |num: u32| {
+            assert_ne!(num, 7, "no sevens");
+        }) {}
+    }
+
+    #[test]
+    fn test_results_before_a_panic_are_yielded() {
+        // The panic comes in long before the results ahead of it.
+        let results = parallel_map_iter(0..10, 4, |num: u32| {
+            if num == 0 {
+                thread::sleep(Duration::from_millis(100));
+            }
+            assert_ne!(num, 5, "no fives");
+            num
+        });
+        let mut yielded = Vec::new();
+        let outcome = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            for num in results {
+                yielded.push(num);
+            }
+        }));
+        let payload = outcome.unwrap_err();
+        assert!(payload
+            .downcast_ref::<String>()
+            .unwrap()
+            .contains("no fives"));
+        assert_eq!(yielded, vec![0, 1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,1266 @@
+//! Maps functions over vectors on worker threads, handing back the results in input order. The
+//! `demo` example shows off each flavor.
+//!
+//! With the `async` feature, `parallel_map_async` does the same for closures that return futures,
+//! for work that mostly waits on IO and would be wasted on a thread of its own.
+
+#[cfg(feature = "async")]
+mod async_map;
+mod cancel;
+mod init;
+mod iter;
+mod options;
+mod pool;
+mod reduce;
+mod slab;
+mod spawn;
+mod timeout;
+
+#[cfg(feature = "async")]
+pub use async_map::{parallel_map_async, parallel_try_map_async};
+pub use cancel::{parallel_find, parallel_map_cancellable, CancellableResults, CancellationToken};
+pub use init::parallel_map_init;
+pub use iter::{parallel_map_iter, ParallelMapIter};
+pub use options::{
+    parallel_map_with, parallel_map_with_stats, DispatchOrder, MapResults, MapStats, Options,
+    Progress, WorkerStats,
+};
+pub use pool::ParallelMapper;
+pub use reduce::{parallel_for_each, parallel_map_reduce};
+pub use spawn::SpawnError;
+pub use timeout::{parallel_map_timeout, TimeoutResult};
+
+use crossbeam_channel as channel;
+use slab::Slab;
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// How many inputs per worker may wait in the input queue, unless asked otherwise. Inputs are fed
+/// to the queue as the workers take them, so this bounds how many are ever moved into it.
+const QUEUE_CAPACITY_PER_THREAD: usize = 2;
+
+/// How many chunks the inputs are split into per worker by default: a few, so that a worker that
+/// gets through its first chunk early can take one that would otherwise hold up the others.
+const CHUNKS_PER_THREAD: usize = 4;
+
+/// Largest default chunk, in bytes of inputs. Past this, sending a chunk costs little next to
+/// mapping it, and large inputs are better off queued a few at a time.
+const MAX_CHUNK_BYTES: usize = 16 * 1024;
+
+/// A chunk of inputs, along with the index of the first one.
+type Chunk<T> = (usize, Vec<T>);
+
+/// The results of mapping a chunk of inputs (or the panics that mapping them ended in), along with
+/// the index of the first one. A chunk cut short by a stop holds only the results from before it.
+type Output<U> = (usize, Vec<thread::Result<U>>);
+
+/// A panic that `f` ended in, along with the index of its input.
+type Panic = (usize, Box<dyn Any + Send>);
+
+/// A worker's report on a chunk of inputs whose results it wrote into a `Slab`: the index of the
+/// first input, how many of them it got through (a stop may cut a chunk short), and the panic of
+/// the first one `f` panicked on, if any. Sent once the worker is done with the chunk's slots.
+struct ChunkDone {
+    start: usize,
+    mapped: usize,
+    panic: Option<Panic>,
+}
+
+/// How many inputs to send to a worker at a time, when there are `len` of them for `num_threads`
+/// workers. Sending each input on its own costs a channel round trip apiece, which for cheap
+/// functions takes far longer than the mapping does.
+fn default_chunk_size<T>(len: usize, num_threads: usize) -> usize {
+    let max_chunk = (MAX_CHUNK_BYTES / mem::size_of::<T>().max(1)).max(1);
+    (len / (num_threads.max(1) * CHUNKS_PER_THREAD)).clamp(1, max_chunk)
+}
+
+/// How many workers a map of `items` inputs gets when `requested` are asked for: as many as asked
+/// for (at least 1), but no more than there are inputs to hand them, and none at all when there
+/// are no inputs. Every flavor of map goes by this, so that none of them spawns workers that would
+/// never get any work.
+pub fn effective_workers(requested: usize, items: usize) -> usize {
+    requested.max(1).min(items)
+}
+
+/// How the inputs of a map are handed out: to how many workers, how many at a time, in which
+/// order, and how many of them may wait for a worker at once. Also how big the workers' stacks
+/// are, if not the default.
+#[derive(Clone, Copy, Debug)]
+struct Dispatch {
+    num_threads: usize,
+    chunk_size: usize,
+    dispatch_order: DispatchOrder,
+    queue_capacity: usize,
+    stack_size: Option<usize>,
+}
+
+impl Dispatch {
+    /// The defaults for `len` inputs of type `T` on `num_threads` workers, or as many of them as
+    /// `effective_workers` allows.
+    fn new<T>(len: usize, num_threads: usize) -> Dispatch {
+        let num_threads = effective_workers(num_threads, len);
+        Dispatch {
+            num_threads,
+            chunk_size: default_chunk_size::<T>(len, num_threads),
+            dispatch_order: DispatchOrder::Forward,
+            queue_capacity: num_threads * QUEUE_CAPACITY_PER_THREAD,
+            stack_size: None,
+        }
+    }
+}
+
+/// Maps the chunks of inputs received on `input_receiver` with `f` until the input channel is
+/// closed, sending the results of each (or the panics `f` ended in) to `output_sender`. Once
+/// `stop` is set, the remaining inputs are skipped; a result for which `stops_on` is true sets it.
+fn run_worker<T, U, F>(
+    input_receiver: &channel::Receiver<Chunk<T>>,
+    output_sender: &channel::Sender<Output<U>>,
+    stop: &AtomicBool,
+    stops_on: fn(&U) -> bool,
+    f: &F,
+) where
+    F: Fn(T) -> U,
+{
+    while let Ok((start, chunk)) = input_receiver.recv() {
+        let mut results = Vec::with_capacity(chunk.len());
+        for val in chunk {
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            let result = panic::catch_unwind(AssertUnwindSafe(|| f(val)));
+            if matches!(&result, Ok(val) if stops_on(val)) {
+                stop.store(true, Ordering::Relaxed);
+            }
+            results.push(result);
+        }
+        if !results.is_empty() {
+            output_sender
+                .send((start, results))
+                .expect("Worker couldn't send results: the collector is gone!");
+        }
+    }
+}
+
+/// Results put back in input order as they arrive, given that there should be at most one for
+/// each index below the number of inputs, along with the panic of the earliest input `f` panicked
+/// on, if any.
+struct Outputs<U> {
+    output_vec: Vec<Option<U>>,
+    first_panic: Option<Panic>,
+}
+
+impl<U> Outputs<U> {
+    fn new(len: usize) -> Outputs<U> {
+        let mut output_vec = Vec::new();
+        output_vec.resize_with(len, || None);
+        Outputs {
+            output_vec,
+            first_panic: None,
+        }
+    }
+
+    fn record(&mut self, (start, results): Output<U>) {
+        for (id, val) in (start..).zip(results) {
+            match val {
+                Ok(val) => self.output_vec[id] = Some(val),
+                Err(payload) => keep_earliest(&mut self.first_panic, (id, payload)),
+            }
+        }
+    }
+
+    /// Returns the results, or the earliest panic if `f` panicked on any input.
+    fn finish(self) -> thread::Result<Vec<Option<U>>> {
+        match self.first_panic {
+            Some((_, payload)) => Err(payload),
+            None => Ok(self.output_vec),
+        }
+    }
+}
+
+/// Keeps whichever of `first_panic` and `panic` is for the earlier input.
+fn keep_earliest(first_panic: &mut Option<Panic>, panic: Panic) {
+    if first_panic
+        .as_ref()
+        .is_none_or(|(first_id, _)| panic.0 < *first_id)
+    {
+        *first_panic = Some(panic);
+    }
+}
+
+/// Maps a chunk of inputs with `f`, which is also given each input's index, writing each result
+/// into its slot in `slab`, and reports how far it got. Once `stop` is set, the remaining inputs
+/// are skipped; a result for which `stops_on` is true sets it.
+///
+/// # Safety
+///
+/// No other thread may access the slots for the chunk's inputs until the report has been handed to
+/// it, which is what makes a slot written here safe to read there.
+unsafe fn map_chunk<T, U, F>(
+    slab: &Slab<U>,
+    (start, chunk): Chunk<T>,
+    stop: &AtomicBool,
+    stops_on: fn(&U) -> bool,
+    mut f: F,
+) -> ChunkDone
+where
+    F: FnMut(usize, T) -> U,
+{
+    let mut done = ChunkDone {
+        start,
+        mapped: 0,
+        panic: None,
+    };
+    for (id, val) in (start..).zip(chunk) {
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        match panic::catch_unwind(AssertUnwindSafe(|| f(id, val))) {
+            Ok(val) => {
+                if stops_on(&val) {
+                    stop.store(true, Ordering::Relaxed);
+                }
+                slab.write(id, val);
+            }
+            // The chunk's inputs come in order, so the first panic is its earliest.
+            Err(payload) => {
+                done.panic.get_or_insert((id, payload));
+            }
+        }
+        done.mapped += 1;
+    }
+    done
+}
+
+/// Maps the chunks of inputs received on `input_receiver` with `f` until the input channel is
+/// closed, writing the results straight into `slab` and reporting each chunk to `done_sender`.
+/// Stops as `map_chunk` does.
+fn run_slab_worker<T, U, F>(
+    input_receiver: &channel::Receiver<Chunk<T>>,
+    slab: &Slab<U>,
+    done_sender: &channel::Sender<ChunkDone>,
+    stop: &AtomicBool,
+    stops_on: fn(&U) -> bool,
+    mut f: F,
+) where
+    F: FnMut(usize, T) -> U,
+{
+    while let Ok(chunk) = input_receiver.recv() {
+        // Safety: each input is sent to a single worker, in a single chunk, and the slots of a
+        // chunk are only read once its report has been received.
+        let done = unsafe { map_chunk(slab, chunk, stop, stops_on, &mut f) };
+        if done.mapped > 0 {
+            done_sender
+                .send(done)
+                .expect("Worker couldn't send results: the collector is gone!");
+        }
+    }
+}
+
+/// Goes through the reports received on `done_receiver` as they arrive, calling `on_result` with
+/// the index and result of each input one says was written into `slab`. Returns the panic of the
+/// earliest input `f` panicked on, if any.
+fn collect_chunks<U, R>(
+    slab: &Slab<U>,
+    done_receiver: channel::Receiver<ChunkDone>,
+    mut on_result: R,
+) -> Option<Panic>
+where
+    R: FnMut(usize, &U),
+{
+    let mut first_panic = None;
+    while let Ok(done) = done_receiver.recv() {
+        for id in done.start..done.start + done.mapped {
+            // Safety: the worker is done with these slots, having reported on them.
+            if let Some(val) = unsafe { slab.get(id) } {
+                on_result(id, val);
+            }
+        }
+        if let Some(panic) = done.panic {
+            keep_earliest(&mut first_panic, panic);
+        }
+    }
+    first_panic
+}
+
+/// Sends the inputs in `input_vec` to `input_sender` `chunk_size` at a time, in `order`, until they
+/// run out or `stop` is set. Either way, each input is moved out of `input_vec` as its chunk is
+/// made, so that the inputs are never held twice.
+fn feed<T>(
+    input_vec: &Mutex<Vec<T>>,
+    input_sender: channel::Sender<Chunk<T>>,
+    chunk_size: usize,
+    order: DispatchOrder,
+    stop: &AtomicBool,
+) {
+    let mut input_vec = mem::take(&mut *input_vec.lock().expect("Inputs poisoned!"));
+    let send = |chunk| {
+        input_sender
+            .send(chunk)
+            .expect("Feeder couldn't send inputs: the workers are gone!")
+    };
+    match order {
+        DispatchOrder::Forward => {
+            let mut inputs = input_vec.into_iter();
+            let mut start = 0;
+            while inputs.len() > 0 && !stop.load(Ordering::Relaxed) {
+                let chunk: Vec<T> = inputs.by_ref().take(chunk_size).collect();
+                let len = chunk.len();
+                send((start, chunk));
+                start += len;
+            }
+        }
+        DispatchOrder::Reverse => {
+            while !input_vec.is_empty() && !stop.load(Ordering::Relaxed) {
+                let start = input_vec.len().saturating_sub(chunk_size);
+                let chunk = input_vec.split_off(start);
+                send((start, chunk));
+            }
+        }
+    }
+}
+
+/// Hands out `input_vec` in chunks to worker threads as laid out by `dispatch`, each of which runs
+/// the function `make_worker` made for it on the input queue, while the calling thread runs
+/// `collect`. No more workers are spawned than there are chunks, and none for no inputs. The
+/// inputs are sent to the workers `chunk_size` (at least 1) at a time, in `dispatch_order`, and at
+/// most `queue_capacity` of them (rounded down to whole chunks, but at least one chunk) wait for a
+/// worker at any time. Once `stop` is set, no more inputs are queued.
+///
+/// If a worker can't be spawned, the map goes on with the ones spawned before it, after passing
+/// the error to `on_spawn_failure`; if none could be, the error is returned instead.
+///
+/// Returns what `collect` returned, along with what each worker returned once the queue closed.
+fn run_workers<T, W, R, M, C, O>(
+    input_vec: Vec<T>,
+    dispatch: Dispatch,
+    stop: &AtomicBool,
+    mut make_worker: M,
+    collect: C,
+    on_spawn_failure: &dyn Fn(&SpawnError),
+) -> Result<(O, Vec<R>), SpawnError>
+where
+    T: Send,
+    M: FnMut() -> W,
+    W: FnOnce(&channel::Receiver<Chunk<T>>) -> R + Send,
+    R: Send,
+    C: FnOnce() -> O,
+{
+    let chunk_size = dispatch.chunk_size.max(1);
+    let order = dispatch.dispatch_order;
+    let num_threads = effective_workers(dispatch.num_threads, input_vec.len().div_ceil(chunk_size));
+    if num_threads == 0 {
+        drop(make_worker);
+        return Ok((collect(), Vec::new()));
+    }
+    let queue_capacity = (dispatch.queue_capacity / chunk_size).max(1);
+    let (input_sender, input_receiver) = channel::bounded(queue_capacity);
+    // Behind a lock only so that the feeder can borrow the inputs, and they're still here if the
+    // feeder can't be spawned.
+    let input_vec = &Mutex::new(input_vec);
+
+    // Scoped threads, so that the workers and the inputs may borrow from the caller
+    thread::scope(|scope| {
+        let mut threads = Vec::new();
+        for id in 0..num_threads {
+            let input_receiver = input_receiver.clone();
+            let worker = make_worker();
+            let spawned = spawn::spawn_worker(id, num_threads, dispatch.stack_size, |builder| {
+                builder.spawn_scoped(scope, move || worker(&input_receiver))
+            });
+            match spawned {
+                Ok(thread) => threads.push(thread),
+                Err(error) if threads.is_empty() => return Err(error),
+                Err(error) => {
+                    on_spawn_failure(&error);
+                    break;
+                }
+            }
+        }
+        // The workers may hold on to senders of their own, which mustn't outlive them.
+        drop(make_worker);
+
+        // Feed the queue from a thread of its own, since it blocks whenever the queue is full
+        // while this thread collects the results.
+        let feeder_sender = input_sender.clone();
+        let feeder = thread::Builder::new()
+            .name("parmap-feeder".to_string())
+            .spawn_scoped(scope, move || {
+                feed(input_vec, feeder_sender, chunk_size, order, stop)
+            });
+        match feeder {
+            Ok(_) => drop(input_sender),
+            // Feed it from this thread then, before collecting anything, which only holds the
+            // results up until the last inputs are queued.
+            Err(_) => feed(input_vec, input_sender, chunk_size, order, stop),
+        }
+
+        let collected = collect();
+
+        if let Ok(feeder) = feeder {
+            feeder.join().expect("Feeder panicked!");
+        }
+        let worker_results = threads
+            .into_iter()
+            .map(|thread| thread.join().expect("Worker panicked!"))
+            .collect();
+        Ok((collected, worker_results))
+    })
+}
+
+/// Maps over `input_vec` on worker threads as laid out by `dispatch` with the function `make_f`
+/// makes for each worker, on the worker's own thread, returning the results in a slab in input
+/// order and calling `on_result` on each, on the calling thread, as it comes in.
+/// The workers write the results straight into the slab, so however big they are, each one is
+/// only moved there once, and there's never more than one of them per input. Once `stop` is set,
+/// workers skip the inputs they haven't started on, which leaves those slots empty; it gets set as
+/// soon as `f` returns a result for which `stops_on` is true, but may also be set from outside.
+///
+/// If `f` panics, the workers still map every other input, and then the panic is resumed on the
+/// calling thread (the one for the earliest input, if there were several). Workers that can't be
+/// spawned are handled as by `run_workers`.
+fn map_until<T, U, M, F, R>(
+    input_vec: Vec<T>,
+    dispatch: Dispatch,
+    stop: &AtomicBool,
+    stops_on: fn(&U) -> bool,
+    make_f: M,
+    on_result: R,
+    on_spawn_failure: &dyn Fn(&SpawnError),
+) -> Result<Slab<U>, SpawnError>
+where
+    M: Fn() -> F + Sync,
+    F: FnMut(usize, T) -> U,
+    T: Send,
+    U: Send,
+    R: FnMut(usize, &U),
+{
+    let slab = Slab::new(input_vec.len());
+    let (done_sender, done_receiver) = channel::unbounded();
+    let (make_f, slab_ref) = (&make_f, &slab);
+    let (first_panic, _) = run_workers(
+        input_vec,
+        dispatch,
+        stop,
+        move || {
+            let done_sender = done_sender.clone();
+            move |input_receiver: &channel::Receiver<Chunk<T>>| {
+                run_slab_worker(
+                    input_receiver,
+                    slab_ref,
+                    &done_sender,
+                    stop,
+                    stops_on,
+                    make_f(),
+                )
+            }
+        },
+        || collect_chunks(slab_ref, done_receiver, on_result),
+        on_spawn_failure,
+    )?;
+    if let Some((_, payload)) = first_panic {
+        panic::resume_unwind(payload);
+    }
+    Ok(slab)
+}
+
+/// Panics with `error`, for maps that have no way to return it.
+fn spawn_failed<V>(error: SpawnError) -> V {
+    panic!("{}", error)
+}
+
+/// Maps `f` over `input_vec` on `num_threads` worker threads, returning the results in input
+/// order. Unlike `parallel_map`, the inputs, the results and `f` may all borrow from the caller,
+/// since the workers are done by the time this returns.
+pub fn parallel_map_scoped<T, U, F>(input_vec: Vec<T>, num_threads: usize, f: F) -> Vec<U>
+where
+    F: Fn(T) -> U + Send + Sync,
+    T: Send,
+    U: Send,
+{
+    let queue_capacity = num_threads.max(1) * QUEUE_CAPACITY_PER_THREAD;
+    parallel_map_with_capacity(input_vec, num_threads, queue_capacity, f)
+}
+
+/// Like `parallel_map_scoped`, but with at most `queue_capacity` inputs waiting for a worker at any
+/// time, rather than the default of a couple per worker. The queue always has room for at least
+/// one chunk of inputs, however small `queue_capacity` is.
+pub fn parallel_map_with_capacity<T, U, F>(
+    input_vec: Vec<T>,
+    num_threads: usize,
+    queue_capacity: usize,
+    f: F,
+) -> Vec<U>
+where
+    F: Fn(T) -> U + Send + Sync,
+    T: Send,
+    U: Send,
+{
+    let dispatch = Dispatch {
+        queue_capacity,
+        ..Dispatch::new::<T>(input_vec.len(), num_threads)
+    };
+    map_until(
+        input_vec,
+        dispatch,
+        &AtomicBool::new(false),
+        |_| false,
+        || |_, val| f(val),
+        |_, _| {},
+        &|_| {},
+    )
+    .unwrap_or_else(spawn_failed)
+    .into_vec()
+}
+
+/// Maps `f` over `input_vec` on `num_threads` worker threads, returning the results in input
+/// order. Asking for 0 threads is treated as asking for 1. The threads only last for this one
+/// call; when mapping over many vectors, a `ParallelMapper` saves spawning them every time.
+///
+/// The inputs are handed to the workers in chunks, sized for the number of inputs and threads.
+///
+/// If `f` panics, the workers still map every other input, and then the panic is resumed on the
+/// calling thread (the one for the earliest input, if there were several).
+pub fn parallel_map<T, U, F>(input_vec: Vec<T>, num_threads: usize, f: F) -> Vec<U>
+where
+    F: Fn(T) -> U + Send + Sync + 'static,
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    match effective_workers(num_threads, input_vec.len()) {
+        0 => Vec::new(),
+        num_threads => ParallelMapper::new(num_threads).map(input_vec, f),
+    }
+}
+
+/// Like `parallel_map`, but returns the error if not a single worker thread could be spawned,
+/// rather than panicking. As with `parallel_map`, if only some of them could be, the map goes on
+/// with those.
+pub fn try_parallel_map<T, U, F>(
+    input_vec: Vec<T>,
+    num_threads: usize,
+    f: F,
+) -> Result<Vec<U>, SpawnError>
+where
+    F: Fn(T) -> U + Send + Sync + 'static,
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    match effective_workers(num_threads, input_vec.len()) {
+        0 => Ok(Vec::new()),
+        num_threads => Ok(ParallelMapper::try_new(num_threads)?.map(input_vec, f)),
+    }
+}
+
+/// Like `parallel_map`, but hands the inputs to the workers `chunk_size` (at least 1) at a time.
+/// A chunk size of 1 sends each input on its own.
+pub fn parallel_map_chunked<T, U, F>(
+    input_vec: Vec<T>,
+    num_threads: usize,
+    chunk_size: usize,
+    f: F,
+) -> Vec<U>
+where
+    F: Fn(T) -> U + Send + Sync + 'static,
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    let chunk_size = chunk_size.max(1);
+    match effective_workers(num_threads, input_vec.len().div_ceil(chunk_size)) {
+        0 => Vec::new(),
+        num_threads => ParallelMapper::new(num_threads).map_chunked(input_vec, chunk_size, f),
+    }
+}
+
+/// Like `parallel_map`, but for a fallible `f`. Returns the results in input order if `f`
+/// succeeded on every input. Otherwise, once `f` has failed, workers stop taking new inputs, and
+/// the error returned is the one for the earliest input among those that were mapped.
+pub fn parallel_try_map<T, U, E, F>(
+    input_vec: Vec<T>,
+    num_threads: usize,
+    f: F,
+) -> Result<Vec<U>, E>
+where
+    F: Fn(T) -> Result<U, E> + Send + Sync + 'static,
+    T: Send + 'static,
+    U: Send + 'static,
+    E: Send + 'static,
+{
+    let dispatch = Dispatch::new::<T>(input_vec.len(), num_threads);
+    let output_vec = map_until(
+        input_vec,
+        dispatch,
+        &AtomicBool::new(false),
+        Result::is_err,
+        || |_, val| f(val),
+        |_, _| {},
+        &|_| {},
+    )
+    .unwrap_or_else(spawn_failed)
+    .into_options();
+    let mut results = Vec::with_capacity(output_vec.len());
+    let mut missing = None;
+    for (id, val) in output_vec.into_iter().enumerate() {
+        match val {
+            Some(Ok(val)) => results.push(val),
+            Some(Err(err)) => return Err(err),
+            // Only skipped because of an error further on
+            None => missing = missing.or(Some(id)),
+        }
+    }
+    if let Some(id) = missing {
+        panic!("No result for input {}!", id);
+    }
+    Ok(results)
+}
+
+/// The outcome of `parallel_try_map_all`: every result of a fallible map, each tagged with the
+/// index of its input and in input order.
+#[derive(Debug, PartialEq)]
+pub struct TryMapResults<U, E> {
+    pub successes: Vec<(usize, U)>,
+    pub failures: Vec<(usize, E)>,
+}
+
+/// Like `parallel_try_map`, but maps every input however many fail, and returns the successes and
+/// the failures separately.
+pub fn parallel_try_map_all<T, U, E, F>(
+    input_vec: Vec<T>,
+    num_threads: usize,
+    f: F,
+) -> TryMapResults<U, E>
+where
+    F: Fn(T) -> Result<U, E> + Send + Sync + 'static,
+    T: Send + 'static,
+    U: Send + 'static,
+    E: Send + 'static,
+{
+    let mut results = TryMapResults {
+        successes: Vec::new(),
+        failures: Vec::new(),
+    };
+    for (id, val) in parallel_map(input_vec, num_threads, f)
+        .into_iter()
+        .enumerate()
+    {
+        match val {
+            Ok(val) => results.successes.push((id, val)),
+            Err(err) => results.failures.push((id, err)),
+        }
+    }
+    results
+}
+
+/// Maps `f` over the values of `pairs` on `num_threads` worker threads, and returns the results
+/// by their keys. If a key appears more than once, the result for its last pair in `pairs` is the
+/// one kept, as if the results had been inserted into the map in input order; `f` is still called
+/// on every value.
+pub fn parallel_map_keyed<K, T, U, F>(pairs: Vec<(K, T)>, num_threads: usize, f: F) -> HashMap<K, U>
+where
+    F: Fn(T) -> U + Send + Sync,
+    K: Hash + Eq + Send,
+    T: Send,
+    U: Send,
+{
+    parallel_map_scoped(pairs, num_threads, |(key, val)| (key, f(val)))
+        .into_iter()
+        .collect()
+}
+
+/// Maps `f` over `input_vec` on `num_threads` worker threads, and returns the results that are
+/// Some, in input order.
+pub fn parallel_filter_map<T, U, F>(input_vec: Vec<T>, num_threads: usize, f: F) -> Vec<U>
+where
+    F: Fn(T) -> Option<U> + Send + Sync,
+    T: Send,
+    U: Send,
+{
+    parallel_map_scoped(input_vec, num_threads, f)
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+    use std::time;
+
+    /// Allocations at least this big are counted by `CountingAllocator`. Smaller ones are left
+    /// out, since the other tests running at the same time make plenty of those.
+    const LARGE_ALLOCATION: usize = 4096;
+
+    /// Bytes currently held in large allocations, and the most held at any time.
+    static LARGE_BYTES: AtomicUsize = AtomicUsize::new(0);
+    static PEAK_LARGE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+    /// Keeps track of how much memory is held in large allocations, so that tests can check how
+    /// much a map needs on top of its inputs.
+    struct CountingAllocator;
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc(layout);
+            if !ptr.is_null() && layout.size() >= LARGE_ALLOCATION {
+                let held = LARGE_BYTES.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+                PEAK_LARGE_BYTES.fetch_max(held, Ordering::SeqCst);
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout);
+            if layout.size() >= LARGE_ALLOCATION {
+                LARGE_BYTES.fetch_sub(layout.size(), Ordering::SeqCst);
+            }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    /// A result type with no sensible default value.
+    #[derive(Debug, PartialEq)]
+    struct Labeled {
+        label: String,
+        value: u32,
+    }
+
+    #[test]
+    fn test_map_into_type_without_default() {
+        let labeled = parallel_map(vec![3, 1, 2], 3, |value| Labeled {
+            label: format!("#{}", value),
+            value,
+        });
+        let labels: Vec<&str> = labeled.iter().map(|l| l.label.as_str()).collect();
+        assert_eq!(labels, vec!["#3", "#1", "#2"]);
+        assert_eq!(labeled[0].value, 3);
+    }
+
+    fn squares(num_threads: usize) -> Vec<u64> {
+        parallel_map(vec![6, 7, 8, 1, 2, 3], num_threads, |num: u64| num * num)
+    }
+
+    #[test]
+    fn test_thread_counts() {
+        let expected = vec![36, 49, 64, 1, 4, 9];
+        assert_eq!(squares(0), expected);
+        assert_eq!(squares(1), expected);
+        assert_eq!(squares(2), expected);
+        assert_eq!(squares(6), expected);
+        assert_eq!(squares(32), expected);
+    }
+
+    #[test]
+    fn test_empty_input() {
+        assert_eq!(parallel_map(Vec::new(), 1, |num: u64| num * num), vec![]);
+        assert_eq!(parallel_map(Vec::new(), 4, |num: u64| num * num), vec![]);
+    }
+
+    #[test]
+    fn test_single_input() {
+        assert_eq!(parallel_map(vec![7], 1, |num: u64| num * num), vec![49]);
+        assert_eq!(parallel_map(vec![7], 4, |num: u64| num * num), vec![49]);
+        assert_eq!(
+            parallel_map_scoped(vec![7], 4, |num: u64| num * num),
+            vec![49]
+        );
+        assert_eq!(
+            parallel_try_map(vec![7], 4, |num: u64| halve(num as u32)),
+            Err("7 is odd".to_string())
+        );
+    }
+
+    #[test]
+    fn test_effective_workers() {
+        // (requested, items, effective)
+        let cases = [
+            (0, 0, 0),
+            (1, 0, 0),
+            (8, 0, 0),
+            (usize::MAX, 0, 0),
+            (0, 1, 1),
+            (0, 100, 1),
+            (1, 1, 1),
+            (1, 100, 1),
+            (4, 3, 3),
+            (4, 4, 4),
+            (4, 5, 4),
+            (10, 3, 3),
+            (usize::MAX, 7, 7),
+            (7, usize::MAX, 7),
+        ];
+        for &(requested, items, effective) in cases.iter() {
+            assert_eq!(
+                effective_workers(requested, items),
+                effective,
+                "{} workers asked for with {} inputs",
+                requested,
+                items
+            );
+        }
+        for requested in 0..12 {
+            for items in 0..12 {
+                let effective = effective_workers(requested, items);
+                assert!(effective <= items);
+                assert!(effective <= requested.max(1));
+                assert_eq!(effective == 0, items == 0);
+                assert!(effective == items || effective == requested.max(1));
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_input_spawns_nothing() {
+        // Any worker spawned would fail.
+        spawn::FAIL_SPAWNS_FROM.with(|limit| limit.set(0));
+        let square = |num: u64| num * num;
+        assert_eq!(parallel_map(Vec::new(), 4, square), vec![]);
+        assert_eq!(parallel_map_scoped(Vec::new(), 4, square), vec![]);
+        assert_eq!(parallel_map_chunked(Vec::new(), 4, 3, square), vec![]);
+        assert_eq!(try_parallel_map(Vec::new(), 4, square).unwrap(), vec![]);
+        assert_eq!(parallel_try_map(Vec::new(), 4, halve), Ok(vec![]));
+        assert_eq!(
+            parallel_map_reduce(Vec::new(), 4, square, 0, |a, b| a + b),
+            0
+        );
+        parallel_for_each(Vec::new(), 4, |num: u64| panic!("mapped {}", num));
+        let timed = parallel_map_timeout(Vec::new(), 4, time::Duration::from_secs(1), square);
+        assert_eq!(timed.results, vec![]);
+    }
+
+    #[test]
+    fn test_no_more_workers_than_chunks() {
+        let workers_for = |input_vec: Vec<u32>, chunk_size: usize| {
+            let options = Options {
+                threads: 10,
+                chunk_size: Some(chunk_size),
+                collect_stats: true,
+                ..Options::default()
+            };
+            let mapped = parallel_map_with(input_vec, options, |num| num);
+            mapped.stats.unwrap().workers.len()
+        };
+        assert_eq!(workers_for(vec![1, 2, 3], 1), 3);
+        assert_eq!(workers_for((0..100).collect(), 50), 2);
+        assert_eq!(workers_for((0..100).collect(), 1), 10);
+        assert_eq!(workers_for(Vec::new(), 1), 0);
+    }
+
+    #[test]
+    fn test_fewer_inputs_than_threads() {
+        let expected = vec![1, 4, 9];
+        assert_eq!(
+            parallel_map(vec![1, 2, 3], 8, |num: u64| num * num),
+            expected
+        );
+        assert_eq!(
+            parallel_map_scoped(vec![1, 2, 3], 8, |num: u64| num * num),
+            expected
+        );
+        assert_eq!(
+            parallel_map_chunked(vec![1, 2, 3], 8, 2, |num: u64| num * num),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_workers_are_named() {
+        let names = parallel_map_scoped((0..100).collect(), 4, |_: u32| {
+            thread::current().name().map(String::from)
+        });
+        for name in names {
+            let name = name.unwrap();
+            let id: usize = name
+                .strip_prefix("parmap-worker-")
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert!(id < 4, "{} isn't one of 4 workers", name);
+        }
+    }
+
+    #[test]
+    fn test_map_makes_do_with_the_workers_it_gets() {
+        spawn::FAIL_SPAWNS_FROM.with(|limit| limit.set(1));
+        let doubled = parallel_map_scoped((0..1000).collect(), 8, |num: u32| {
+            assert_eq!(thread::current().name(), Some("parmap-worker-0"));
+            num * 2
+        });
+        assert_eq!(doubled, (0..1000).map(|num| num * 2).collect::<Vec<u32>>());
+        let sum =
+            reduce::parallel_map_reduce((1..=100).collect(), 4, |num: u32| num, 0, |a, b| a + b);
+        assert_eq!(sum, 5050);
+    }
+
+    #[test]
+    fn test_try_parallel_map() {
+        assert_eq!(
+            try_parallel_map(vec![1, 2, 3], 2, |num: u32| num + 1).unwrap(),
+            vec![2, 3, 4]
+        );
+
+        spawn::FAIL_SPAWNS_FROM.with(|limit| limit.set(0));
+        let error = try_parallel_map(vec![1, 2, 3], 2, |num: u32| num + 1).unwrap_err();
+        assert_eq!((error.worker(), error.requested()), (0, 2));
+        let result = panic::catch_unwind(|| parallel_map_scoped(vec![1, 2, 3], 2, |num: u32| num));
+        let payload = result.unwrap_err();
+        assert_eq!(
+            payload.downcast_ref::<String>().unwrap(),
+            "Couldn't spawn worker 0 of 2: simulated spawn failure"
+        );
+    }
+
+    #[test]
+    fn test_closure_captures_owned_string() {
+        let suffix = String::from("-th");
+        let ordinals = parallel_map(vec![4, 5, 6], 2, move |num: u32| {
+            format!("{}{}", num, suffix)
+        });
+        assert_eq!(ordinals, vec!["4-th", "5-th", "6-th"]);
+    }
+
+    #[test]
+    fn test_closure_captures_shared_table() {
+        let primes: Arc<Vec<u64>> = Arc::new(vec![2, 3, 5, 7, 11, 13, 17, 19]);
+        let table = primes.clone();
+        let nth_primes = parallel_map(vec![7, 0, 3], 3, move |n: usize| table[n]);
+        assert_eq!(nth_primes, vec![19, 2, 7]);
+        // The caller still has its own handle on the table.
+        assert_eq!(primes.len(), 8);
+    }
+
+    #[test]
+    fn test_scoped_map_borrows() {
+        use std::collections::HashMap;
+
+        let text = String::from("one two three four");
+        let mut lengths = HashMap::new();
+        lengths.insert("two", 2);
+        lengths.insert("four", 4);
+        let words: Vec<&str> = text.split(' ').collect();
+        let found = parallel_map_scoped(words, 3, |word| lengths.get(word).copied());
+        assert_eq!(found, vec![None, Some(2), None, Some(4)]);
+
+        // Results may borrow from the inputs too.
+        let firsts = parallel_map_scoped(text.split(' ').collect(), 2, |word: &str| &word[..1]);
+        assert_eq!(firsts, vec!["o", "t", "t", "f"]);
+    }
+
+    #[test]
+    fn test_queue_memory_is_bounded() {
+        // Inputs held inline, so that each one moved into the queue takes up its full size there:
+        // 16MB of them, standing in for something much bigger.
+        const ITEM_SIZE: usize = 64 * 1024;
+        let items: Vec<[u8; ITEM_SIZE]> = (0..256).map(|i| [i as u8; ITEM_SIZE]).collect();
+        let held_before = LARGE_BYTES.load(Ordering::SeqCst);
+        PEAK_LARGE_BYTES.store(held_before, Ordering::SeqCst);
+
+        let firsts = parallel_map_scoped(items, 2, |item: [u8; ITEM_SIZE]| item[ITEM_SIZE - 1]);
+        let extra = PEAK_LARGE_BYTES.load(Ordering::SeqCst) - held_before;
+        assert_eq!(firsts, (0..256).map(|i| i as u8).collect::<Vec<u8>>());
+        // A queue that took all the inputs at once would need another 16MB.
+        assert!(
+            extra < 16 * ITEM_SIZE,
+            "needed {} bytes on top of the inputs",
+            extra
+        );
+    }
+
+    #[test]
+    fn test_queue_capacity() {
+        for &capacity in [0, 1, 3, 100].iter() {
+            let doubled =
+                parallel_map_with_capacity((0..50).collect(), 4, capacity, |n: u32| n * 2);
+            assert_eq!(doubled, (0..50).map(|n| n * 2).collect::<Vec<u32>>());
+        }
+    }
+
+    #[test]
+    fn test_default_chunk_size() {
+        assert_eq!(default_chunk_size::<u64>(1_000_000, 4), 2048);
+        assert_eq!(default_chunk_size::<u64>(1600, 4), 100);
+        assert_eq!(default_chunk_size::<u64>(10, 4), 1);
+        assert_eq!(default_chunk_size::<u64>(0, 4), 1);
+        assert_eq!(default_chunk_size::<u64>(100, 0), 25);
+        assert_eq!(default_chunk_size::<[u8; 64 * 1024]>(1000, 2), 1);
+        assert_eq!(default_chunk_size::<()>(1000, 2), 125);
+    }
+
+    #[test]
+    fn test_chunks_keep_input_order() {
+        for &len in [0, 1, 5, 64, 101].iter() {
+            let expected: Vec<u32> = (0..len).map(|num| num + 1).collect();
+            for &chunk_size in [0, 1, 2, 7, 64, 500].iter() {
+                let dispatch = Dispatch {
+                    num_threads: 3,
+                    chunk_size,
+                    dispatch_order: DispatchOrder::Forward,
+                    queue_capacity: 4,
+                    stack_size: None,
+                };
+                let slab = map_until(
+                    (0..len).collect(),
+                    dispatch,
+                    &AtomicBool::new(false),
+                    |_| false,
+                    || |_, num| num + 1,
+                    |_, _| {},
+                    &|_| {},
+                )
+                .unwrap();
+                assert_eq!(
+                    slab.into_vec(),
+                    expected,
+                    "{} inputs in chunks of {}",
+                    len,
+                    chunk_size
+                );
+            }
+        }
+        let doubled = parallel_map_chunked((0..1000).collect(), 4, 33, |num: u64| num * 2);
+        assert_eq!(doubled, (0..1000).map(|num| num * 2).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn test_panics_reach_the_caller() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        let result = panic::catch_unwind(|| {
+            parallel_map((0..20).collect(), 4, |num: u32| {
+                CALLS.fetch_add(1, Ordering::SeqCst);
+                if num % 7 == 3 {
+                    panic!("can't map {}", num);
+                }
+                num
+            })
+        });
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<String>().unwrap(), "can't map 3");
+        // The workers kept going after the panics.
+        assert_eq!(CALLS.load(Ordering::SeqCst), 20);
+    }
+
+    #[test]
+    fn test_panic_on_one_element() {
+        // Were the panic swallowed, there would be nothing to fill the slot for 5 with.
+        let result = panic::catch_unwind(|| {
+            parallel_map((0..10).collect(), 3, |value: u32| {
+                if value == 5 {
+                    panic!("no label for {}", value);
+                }
+                Labeled {
+                    label: value.to_string(),
+                    value,
+                }
+            })
+        });
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<String>().unwrap(), "no label for 5");
+    }
+
+    fn halve(num: u32) -> Result<u32, String> {
+        if num % 2 == 1 {
+            Err(format!("{} is odd", num))
+        } else {
+            Ok(num / 2)
+        }
+    }
+
+    #[test]
+    fn test_try_map() {
+        assert_eq!(
+            parallel_try_map(vec![8, 2, 6, 0], 3, halve),
+            Ok(vec![4, 1, 3, 0])
+        );
+        assert_eq!(
+            parallel_try_map(vec![8, 2, 5, 6, 0], 1, halve),
+            Err("5 is odd".to_string())
+        );
+        assert_eq!(parallel_try_map(Vec::new(), 3, halve), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_try_map_stops_early() {
+        use std::sync::atomic::AtomicUsize;
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        static FAILED: AtomicBool = AtomicBool::new(false);
+
+        // Whichever input is mapped first fails.
+        let result = parallel_try_map((0..1000).collect(), 4, |num: u32| {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(time::Duration::from_millis(1));
+            if !FAILED.swap(true, Ordering::SeqCst) {
+                return Err(num);
+            }
+            Ok(num)
+        });
+        assert!(result.is_err());
+        let calls = CALLS.load(Ordering::SeqCst);
+        assert!(calls < 100, "{} inputs were mapped after the error", calls);
+    }
+
+    #[test]
+    fn test_try_map_all() {
+        let results = parallel_try_map_all(vec![8, 3, 6, 5, 0], 3, halve);
+        assert_eq!(results.successes, vec![(0, 4), (2, 3), (4, 0)]);
+        assert_eq!(
+            results.failures,
+            vec![(1, "3 is odd".to_string()), (3, "5 is odd".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_keyed() {
+        let pairs = vec![("one", 1), ("two", 2), ("three", 3)];
+        let squares = parallel_map_keyed(pairs, 2, |num: u32| num * num);
+        let expected: HashMap<&str, u32> = vec![("one", 1), ("two", 4), ("three", 9)]
+            .into_iter()
+            .collect();
+        assert_eq!(squares, expected);
+        assert!(parallel_map_keyed(Vec::<(u8, u8)>::new(), 2, |num| num).is_empty());
+    }
+
+    #[test]
+    fn test_keyed_last_duplicate_wins() {
+        let pairs: Vec<(u32, u32)> = (0..1000).map(|num| (num % 10, num)).collect();
+        let results = parallel_map_keyed(pairs, 4, |num| num * 2);
+        assert_eq!(results.len(), 10);
+        for (key, val) in results {
+            assert_eq!(val, (990 + key) * 2);
+        }
+    }
+
+    #[test]
+    fn test_filter_map() {
+        let evens = parallel_filter_map((0..100).collect(), 4, |num: u32| {
+            if num % 2 == 1 {
+                None
+            } else {
+                Some(num / 2)
+            }
+        });
+        assert_eq!(evens, (0..50).collect::<Vec<u32>>());
+
+        let none = parallel_filter_map((0..100).collect(), 4, |_: u32| None::<u32>);
+        assert_eq!(none, vec![]);
+        let all = parallel_filter_map((0..100).collect(), 4, |num: u32| Some(num.to_string()));
+        assert_eq!(
+            all,
+            (0..100).map(|num| num.to_string()).collect::<Vec<String>>()
+        );
+    }
+
+    /// A slab with `vals` written into the slots at `ids`.
+    fn slab_with<U>(len: usize, ids: &[usize], vals: Vec<U>) -> Slab<U> {
+        let slab = Slab::new(len);
+        for (&id, val) in ids.iter().zip(vals) {
+            unsafe { slab.write(id, val) };
+        }
+        slab
+    }
+
+    #[test]
+    fn test_collect_chunks_from_reports() {
+        let slab = slab_with(5, &[0, 1, 2, 4], vec!["a", "b", "c", "e"]);
+        let (sender, receiver) = channel::unbounded();
+        let report = |start, mapped| ChunkDone {
+            start,
+            mapped,
+            panic: None,
+        };
+        sender.send(report(4, 1)).unwrap();
+        sender.send(report(0, 2)).unwrap();
+        // Cut short by a stop, so the last input of this chunk has no result.
+        sender.send(report(2, 1)).unwrap();
+        drop(sender);
+        let mut arrived = Vec::new();
+        assert!(collect_chunks(&slab, receiver, |_, val| arrived.push(*val)).is_none());
+        assert_eq!(arrived, vec!["e", "a", "b", "c"]);
+        assert_eq!(
+            slab.into_options(),
+            vec![Some("a"), Some("b"), Some("c"), None, Some("e")]
+        );
+    }
+
+    #[test]
+    fn test_collect_chunks_keeps_earliest_panic() {
+        let slab = slab_with(5, &[0, 1], vec![0u32, 1]);
+        let (sender, receiver) = channel::unbounded();
+        let report = |start, mapped, panic: Option<(usize, &'static str)>| ChunkDone {
+            start,
+            mapped,
+            panic: panic.map(|(id, payload)| (id, Box::new(payload) as Box<dyn Any + Send>)),
+        };
+        sender.send(report(0, 1, None)).unwrap();
+        sender.send(report(3, 2, Some((3, "three")))).unwrap();
+        sender.send(report(1, 2, Some((2, "two")))).unwrap();
+        drop(sender);
+        let mut arrived = Vec::new();
+        let (id, payload) = collect_chunks(&slab, receiver, |_, val| arrived.push(*val)).unwrap();
+        assert_eq!(id, 2);
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"two"));
+        // Only the inputs that didn't panic have results.
+        assert_eq!(arrived, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_map_chunk_stops() {
+        let slab = Slab::new(6);
+        let stop = AtomicBool::new(false);
+        let done = unsafe {
+            map_chunk(
+                &slab,
+                (2, vec![1, 2, 3, 4]),
+                &stop,
+                |&val| val == 2,
+                // Each input is passed along with its index in the whole input.
+                &|id, val| {
+                    assert_eq!(id as i32, val + 1);
+                    val
+                },
+            )
+        };
+        // Stopped after the result that set the stop, and kept it.
+        assert_eq!(done.start, 2);
+        assert_eq!(done.mapped, 2);
+        assert!(stop.load(Ordering::Relaxed));
+        let done = unsafe { map_chunk(&slab, (0, vec![0, 0]), &stop, |_| false, &|_, val| val) };
+        assert_eq!(done.mapped, 0);
+        assert_eq!(
+            slab.into_options(),
+            vec![None, None, Some(1), Some(2), None, None]
+        );
+    }
+
+    #[test]
+    fn test_map_chunk_keeps_first_panic() {
+        let slab = Slab::new(4);
+        let done = unsafe {
+            map_chunk(
+                &slab,
+                (0, vec![1, 2, 3, 4]),
+                &AtomicBool::new(false),
+                |_| false,
+                &|_, val: u32| {
+                    assert!(val % 2 == 1, "can't map {}", val);
+                    val
+                },
+            )
+        };
+        assert_eq!(done.mapped, 4);
+        let (id, payload) = done.panic.unwrap();
+        assert_eq!(id, 1);
+        assert_eq!(payload.downcast_ref::<String>().unwrap(), "can't map 2");
+        assert_eq!(slab.into_options(), vec![Some(1), None, Some(3), None]);
+    }
+}
@@ -0,0 +1,529 @@
+use crate::{map_until, spawn_failed, Dispatch, SpawnError};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How far along a `parallel_map_with` call is, as of the input that just finished.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Progress {
+    /// Number of inputs mapped so far, counting this one
+    pub completed: usize,
+    pub total: usize,
+    /// How long `f` took on this input
+    pub duration: Duration,
+}
+
+/// How one worker of a `parallel_map_with` call spent its time.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WorkerStats {
+    /// Number of inputs the worker mapped
+    pub items: usize,
+    /// Time spent in `f`
+    pub busy: Duration,
+    /// The rest of the worker's time: mostly waiting for inputs, and handing over results
+    pub idle: Duration,
+}
+
+/// How the workers of a `parallel_map_with` call spent their time, for telling whether a map is
+/// held back by the cost of handing out inputs (lots of idle time all round), by a few inputs
+/// that take much longer than the rest (a few busy workers), or by having too few inputs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MapStats {
+    /// Number of workers asked for
+    pub requested_workers: usize,
+    /// One for each worker there actually was (see `effective_workers`), in no particular order
+    pub workers: Vec<WorkerStats>,
+    /// How long the whole call took, from spawning the workers to having every result
+    pub wall_time: Duration,
+}
+
+impl MapStats {
+    /// How many times faster the map was than mapping every input on a single thread would have
+    /// been, going by the time spent in `f`.
+    pub fn speedup(&self) -> f64 {
+        let busy: Duration = self.workers.iter().map(|worker| worker.busy).sum();
+        if self.wall_time.is_zero() {
+            return 1.0;
+        }
+        busy.as_secs_f64() / self.wall_time.as_secs_f64()
+    }
+}
+
+/// Keeps track of a worker's time, from when it's made on the worker's thread until it's dropped
+/// there, and then adds it to the rest.
+struct WorkerClock<'a> {
+    started: Instant,
+    stats: WorkerStats,
+    all_stats: &'a Mutex<Vec<WorkerStats>>,
+}
+
+impl<'a> WorkerClock<'a> {
+    fn start(all_stats: &'a Mutex<Vec<WorkerStats>>) -> WorkerClock<'a> {
+        WorkerClock {
+            started: Instant::now(),
+            stats: WorkerStats::default(),
+            all_stats,
+        }
+    }
+}
+
+impl Drop for WorkerClock<'_> {
+    fn drop(&mut self) {
+        self.stats.idle = self.started.elapsed().saturating_sub(self.stats.busy);
+        self.all_stats
+            .lock()
+            .expect("Stats poisoned!")
+            .push(self.stats);
+    }
+}
+
+/// Which end of the inputs the workers are handed chunks from first. Results always come back in
+/// input order; this only decides which inputs get mapped sooner, and so the order that `f`'s side
+/// effects happen in, roughly.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DispatchOrder {
+    /// First input first, which keeps workers close together on inputs that refer to nearby data
+    #[default]
+    Forward,
+    /// Last input first, as maps were dispatched before `Forward` was
+    Reverse,
+}
+
+/// What `Options::on_spawn_failure` is called with the error of.
+type SpawnFailureCallback = Box<dyn Fn(&SpawnError) + Send + Sync>;
+
+/// Settings for `parallel_map_with`.
+pub struct Options {
+    /// Number of worker threads (at least 1). Defaults to the number of CPUs available.
+    pub threads: usize,
+    /// How many inputs to hand a worker at a time, or None to pick a size to suit the input.
+    pub chunk_size: Option<usize>,
+    /// Which inputs to hand out first. Defaults to `DispatchOrder::Forward`.
+    pub dispatch_order: DispatchOrder,
+    /// Called on the calling thread each time an input has been mapped. Workers don't wait for it,
+    /// so a slow callback only falls behind.
+    pub on_progress: Option<Box<dyn Fn(Progress) + Send + Sync>>,
+    /// Whether to return how long `f` took on each input along with the results.
+    pub collect_timings: bool,
+    /// Whether to return how each worker spent its time along with the results. Costs nothing
+    /// when not set.
+    pub collect_stats: bool,
+    /// Size of each worker's stack in bytes, or None for the standard library's default. Mostly
+    /// worth setting to save memory when asking for many threads.
+    pub stack_size: Option<usize>,
+    /// Called on the calling thread if fewer workers than asked for could be spawned, with the
+    /// error that stopped the spawning. The map goes on with the workers there are.
+    pub on_spawn_failure: Option<SpawnFailureCallback>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            chunk_size: None,
+            dispatch_order: DispatchOrder::Forward,
+            on_progress: None,
+            collect_timings: false,
+            collect_stats: false,
+            stack_size: None,
+            on_spawn_failure: None,
+        }
+    }
+}
+
+impl fmt::Debug for Options {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Options")
+            .field("threads", &self.threads)
+            .field("chunk_size", &self.chunk_size)
+            .field("dispatch_order", &self.dispatch_order)
+            .field("on_progress", &self.on_progress.as_ref().map(|_| "..."))
+            .field("collect_timings", &self.collect_timings)
+            .field("collect_stats", &self.collect_stats)
+            .field("stack_size", &self.stack_size)
+            .field(
+                "on_spawn_failure",
+                &self.on_spawn_failure.as_ref().map(|_| "..."),
+            )
+            .finish()
+    }
+}
+
+/// The outcome of `parallel_map_with`: the results in input order, how long `f` took on each
+/// input if `Options::collect_timings` was set, and how the workers spent their time if
+/// `Options::collect_stats` was.
+#[derive(Debug, PartialEq)]
+pub struct MapResults<U> {
+    pub results: Vec<U>,
+    pub durations: Option<Vec<Duration>>,
+    pub stats: Option<MapStats>,
+}
+
+/// Maps `f` over `input_vec` like `parallel_map_scoped`, with the settings in `options`. Each
+/// input's time in `f` is measured on the worker, and reported to `Options::on_progress` as its
+/// result is collected.
+///
+/// If `f` panics, the panic is resumed on the calling thread once the workers are done. Progress
+/// is only reported for the inputs `f` returned on. If not a single worker could be spawned, this
+/// panics with the `SpawnError`.
+pub fn parallel_map_with<T, U, F>(input_vec: Vec<T>, options: Options, f: F) -> MapResults<U>
+where
+    F: Fn(T) -> U + Send + Sync,
+    T: Send,
+    U: Send,
+{
+    let total = input_vec.len();
+    let mut dispatch = Dispatch::new::<T>(total, options.threads);
+    if let Some(chunk_size) = options.chunk_size {
+        dispatch.chunk_size = chunk_size;
+    }
+    dispatch.dispatch_order = options.dispatch_order;
+    dispatch.stack_size = options.stack_size;
+    let mut completed = 0;
+    let all_stats = &Mutex::new(Vec::new());
+    // Each input's time in `f`, in nanoseconds, kept off to the side so that the results go
+    // straight into the slab; only allocated when someone is going to look at it
+    let timed = options.collect_timings || options.on_progress.is_some();
+    let nanos: &Vec<AtomicU64> = &if timed {
+        (0..total).map(|_| AtomicU64::new(0)).collect()
+    } else {
+        Vec::new()
+    };
+    let f = &f;
+    let started = Instant::now();
+    let slab = map_until(
+        input_vec,
+        dispatch,
+        &AtomicBool::new(false),
+        |_| false,
+        || {
+            let mut clock = options.collect_stats.then(|| WorkerClock::start(all_stats));
+            move |id, val| {
+                if !timed && clock.is_none() {
+                    return f(val);
+                }
+                let start = Instant::now();
+                let result = f(val);
+                let duration = start.elapsed();
+                if let Some(nanos) = nanos.get(id) {
+                    // Published to the collecting thread along with the result itself
+                    nanos.store(duration.as_nanos() as u64, Ordering::Relaxed);
+                }
+                if let Some(clock) = &mut clock {
+                    clock.stats.items += 1;
+                    clock.stats.busy += duration;
+                }
+                result
+            }
+        },
+        |id, _| {
+            completed += 1;
+            if let Some(on_progress) = &options.on_progress {
+                on_progress(Progress {
+                    completed,
+                    total,
+                    duration: Duration::from_nanos(nanos[id].load(Ordering::Relaxed)),
+                });
+            }
+        },
+        &|error| {
+            if let Some(on_spawn_failure) = &options.on_spawn_failure {
+                on_spawn_failure(error);
+            }
+        },
+    )
+    .unwrap_or_else(spawn_failed);
+    let wall_time = started.elapsed();
+
+    MapResults {
+        results: slab.into_vec(),
+        durations: if options.collect_timings {
+            Some(
+                nanos
+                    .iter()
+                    .map(|nanos| Duration::from_nanos(nanos.load(Ordering::Relaxed)))
+                    .collect(),
+            )
+        } else {
+            None
+        },
+        stats: if options.collect_stats {
+            Some(MapStats {
+                requested_workers: options.threads,
+                workers: all_stats.lock().expect("Stats poisoned!").clone(),
+                wall_time,
+            })
+        } else {
+            None
+        },
+    }
+}
+
+/// Maps `f` over `input_vec` on `num_threads` worker threads like `parallel_map_scoped`, and
+/// returns the results in input order along with how the workers spent their time.
+pub fn parallel_map_with_stats<T, U, F>(
+    input_vec: Vec<T>,
+    num_threads: usize,
+    f: F,
+) -> (Vec<U>, MapStats)
+where
+    F: Fn(T) -> U + Send + Sync,
+    T: Send,
+    U: Send,
+{
+    let options = Options {
+        threads: num_threads,
+        collect_stats: true,
+        ..Options::default()
+    };
+    let mapped = parallel_map_with(input_vec, options, f);
+    let stats = mapped.stats.expect("Stats weren't collected!");
+    (mapped.results, stats)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::spawn::FAIL_SPAWNS_FROM;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_defaults() {
+        let doubled = parallel_map_with((0..100).collect(), Options::default(), |num: u32| num * 2);
+        assert_eq!(
+            doubled.results,
+            (0..100).map(|num| num * 2).collect::<Vec<u32>>()
+        );
+        assert_eq!(doubled.durations, None);
+        assert!(Options::default().threads >= 1);
+    }
+
+    #[test]
+    fn test_progress_reported_for_every_input() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let seen = reports.clone();
+        let options = Options {
+            threads: 4,
+            chunk_size: Some(3),
+            on_progress: Some(Box::new(move |progress| {
+                seen.lock().unwrap().push(progress)
+            })),
+            ..Options::default()
+        };
+        let squares = parallel_map_with((0..50).collect(), options, |num: u64| num * num);
+        assert_eq!(
+            squares.results,
+            (0..50).map(|num| num * num).collect::<Vec<u64>>()
+        );
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 50);
+        let completed: Vec<usize> = reports.iter().map(|progress| progress.completed).collect();
+        assert_eq!(completed, (1..=50).collect::<Vec<usize>>());
+        assert!(reports.iter().all(|progress| progress.total == 50));
+    }
+
+    #[test]
+    fn test_timings() {
+        let options = Options {
+            threads: 2,
+            collect_timings: true,
+            ..Options::default()
+        };
+        let millis = vec![30, 0, 10];
+        let slept = parallel_map_with(millis.clone(), options, |millis: u64| {
+            thread::sleep(Duration::from_millis(millis));
+            millis
+        });
+        assert_eq!(slept.results, millis);
+        let durations = slept.durations.unwrap();
+        assert_eq!(durations.len(), 3);
+        for (duration, millis) in durations.iter().zip(millis) {
+            assert!(*duration >= Duration::from_millis(millis));
+            assert!(*duration < Duration::from_millis(millis + 1000));
+        }
+    }
+
+    #[test]
+    fn test_slow_callback_runs_on_calling_thread() {
+        let caller = thread::current().id();
+        let options = Options {
+            threads: 3,
+            chunk_size: Some(1),
+            on_progress: Some(Box::new(move |_| {
+                assert_eq!(thread::current().id(), caller);
+                thread::sleep(Duration::from_millis(5));
+            })),
+            ..Options::default()
+        };
+        let workers = Mutex::new(Vec::new());
+        let results = parallel_map_with((0..20).collect(), options, |num: u32| {
+            workers.lock().unwrap().push(thread::current().id());
+            num
+        });
+        assert_eq!(results.results, (0..20).collect::<Vec<u32>>());
+        assert!(!workers.into_inner().unwrap().contains(&caller));
+    }
+
+    #[test]
+    fn test_small_stacks() {
+        let options = Options {
+            threads: 256,
+            stack_size: Some(64 * 1024),
+            ..Options::default()
+        };
+        let squares = parallel_map_with((0..1000).collect(), options, |num: u64| num * num);
+        assert_eq!(
+            squares.results,
+            (0..1000).map(|num| num * num).collect::<Vec<u64>>()
+        );
+
+        // Far more than there is memory for, so this only fails if the size is actually used.
+        let options = Options {
+            threads: 2,
+            stack_size: Some(1 << 50),
+            ..Options::default()
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            parallel_map_with(vec![1, 2], options, |num: u8| num)
+        }));
+        let payload = result.unwrap_err();
+        let message = payload.downcast_ref::<String>().unwrap();
+        assert!(
+            message.starts_with("Couldn't spawn worker 0 of 2"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_spawn_failure_is_reported() {
+        FAIL_SPAWNS_FROM.with(|limit| limit.set(3));
+        let failures = Arc::new(Mutex::new(Vec::new()));
+        let seen = failures.clone();
+        let options = Options {
+            threads: 6,
+            on_spawn_failure: Some(Box::new(move |error| {
+                seen.lock()
+                    .unwrap()
+                    .push((error.worker(), error.requested()))
+            })),
+            ..Options::default()
+        };
+        let doubled = parallel_map_with((0..100).collect(), options, |num: u32| num * 2);
+        assert_eq!(
+            doubled.results,
+            (0..100).map(|num| num * 2).collect::<Vec<u32>>()
+        );
+        assert_eq!(*failures.lock().unwrap(), vec![(3, 6)]);
+    }
+
+    #[test]
+    fn test_stats() {
+        const THREADS: usize = 4;
+        let (results, stats) = parallel_map_with_stats((0..40).collect(), THREADS, |num: u64| {
+            thread::sleep(Duration::from_millis(5));
+            num * 2
+        });
+        assert_eq!(results, (0..40).map(|num| num * 2).collect::<Vec<u64>>());
+        assert_eq!(stats.workers.len(), THREADS);
+        assert_eq!(
+            stats
+                .workers
+                .iter()
+                .map(|worker| worker.items)
+                .sum::<usize>(),
+            40
+        );
+        // Each worker lasted about as long as the whole call.
+        for worker in &stats.workers {
+            let lifetime = worker.busy + worker.idle;
+            assert!(worker.busy >= Duration::from_millis(5) * worker.items as u32);
+            assert!(
+                lifetime <= stats.wall_time && lifetime >= stats.wall_time / 2,
+                "worker {:?} in a call that took {:?}",
+                worker,
+                stats.wall_time
+            );
+        }
+        // Sleeping doesn't need a CPU, so the workers all get to sleep at once.
+        assert!(stats.speedup() > 2.0, "speedup of {}", stats.speedup());
+    }
+
+    #[test]
+    fn test_stats_only_when_asked_for() {
+        let options = Options {
+            threads: 2,
+            collect_timings: true,
+            ..Options::default()
+        };
+        let mapped = parallel_map_with(vec![1, 2, 3], options, |num: u32| num);
+        assert!(mapped.durations.is_some());
+        assert_eq!(mapped.stats, None);
+
+        let (results, stats) = parallel_map_with_stats(Vec::new(), 3, |num: u32| num);
+        assert_eq!(results, vec![]);
+        assert!(stats.workers.iter().all(|worker| worker.items == 0));
+    }
+
+    /// Maps `0..len` in chunks of 10 on `threads` workers in `order`, and returns the inputs each
+    /// worker took, in the order it took them.
+    fn taken_by_worker(len: usize, threads: usize, order: DispatchOrder) -> Vec<Vec<usize>> {
+        let taken: Vec<Mutex<Vec<usize>>> = (0..threads).map(|_| Mutex::new(Vec::new())).collect();
+        let options = Options {
+            threads,
+            chunk_size: Some(10),
+            dispatch_order: order,
+            ..Options::default()
+        };
+        let mapped = parallel_map_with((0..len).collect(), options, |id: usize| {
+            let name = thread::current().name().unwrap().to_string();
+            let worker: usize = name["parmap-worker-".len()..].parse().unwrap();
+            taken[worker].lock().unwrap().push(id);
+            id
+        });
+        assert_eq!(mapped.results, (0..len).collect::<Vec<usize>>());
+        taken
+            .into_iter()
+            .map(|taken| taken.into_inner().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_dispatch_order() {
+        assert_eq!(Options::default().dispatch_order, DispatchOrder::Forward);
+
+        // A single worker takes the inputs exactly in dispatch order.
+        let taken = taken_by_worker(100, 1, DispatchOrder::Forward);
+        assert_eq!(taken[0], (0..100).collect::<Vec<usize>>());
+        let taken = taken_by_worker(100, 1, DispatchOrder::Reverse);
+        let expected: Vec<usize> = (0..10)
+            .rev()
+            .flat_map(|chunk| chunk * 10..(chunk + 1) * 10)
+            .collect();
+        assert_eq!(taken[0], expected);
+
+        // Several of them each take their chunks in dispatch order, whatever else they interleave.
+        let taken = taken_by_worker(1000, 4, DispatchOrder::Forward);
+        assert_eq!(taken.iter().map(Vec::len).sum::<usize>(), 1000);
+        for taken in &taken {
+            assert!(
+                taken.windows(2).all(|pair| pair[0] < pair[1]),
+                "{:?}",
+                taken
+            );
+        }
+        let taken = taken_by_worker(1000, 4, DispatchOrder::Reverse);
+        for taken in &taken {
+            let starts: Vec<usize> = taken.chunks(10).map(|chunk| chunk[0]).collect();
+            assert!(
+                starts.windows(2).all(|pair| pair[0] > pair[1]),
+                "{:?}",
+                starts
+            );
+        }
+    }
+}
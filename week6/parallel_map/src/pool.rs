@@ -0,0 +1,273 @@
+use crate::slab::Slab;
+use crate::spawn::spawn_workers;
+use crate::{
+    default_chunk_size, keep_earliest, map_chunk, spawn_failed, SpawnError,
+    QUEUE_CAPACITY_PER_THREAD,
+};
+use crossbeam_channel as channel;
+use std::panic;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// A chunk of inputs of some job, along with the function to map them with and where to send the
+/// results. Jobs differ in their input and result types, so the workers only ever see them as this.
+type Task = Box<dyn FnOnce() + Send>;
+
+/// A pool of worker threads that map functions over vectors, one job at a time per caller. The
+/// threads are spawned once, by `new`, and shut down when the pool is dropped, so that mapping
+/// over many small vectors doesn't spend most of its time starting and stopping threads.
+///
+/// The pool may be shared between threads, each of which gets back only the results of its own
+/// jobs, while the workers take turns on everyone's inputs.
+pub struct ParallelMapper {
+    task_sender: Option<channel::Sender<Task>>,
+    threads: Vec<thread::JoinHandle<()>>,
+    /// Id for the next job, which tags each of its results
+    next_job: AtomicU64,
+    /// How many chunks of a single job may be queued or being mapped at once
+    max_in_flight: usize,
+}
+
+impl ParallelMapper {
+    /// Spawns a pool of `num_threads` worker threads (at least 1). Panics if not a single one
+    /// could be spawned; see `try_new`.
+    pub fn new(num_threads: usize) -> ParallelMapper {
+        ParallelMapper::try_new(num_threads).unwrap_or_else(spawn_failed)
+    }
+
+    /// Spawns a pool of `num_threads` worker threads (at least 1), or as many of them as could be
+    /// spawned, as told by `num_threads`. Returns the error if not a single one could be.
+    pub fn try_new(num_threads: usize) -> Result<ParallelMapper, SpawnError> {
+        let (task_sender, task_receiver) = channel::unbounded::<Task>();
+        let (threads, _) = spawn_workers(num_threads, None, || {
+            let task_receiver = task_receiver.clone();
+            move || {
+                while let Ok(task) = task_receiver.recv() {
+                    task();
+                }
+            }
+        })?;
+        Ok(ParallelMapper {
+            task_sender: Some(task_sender),
+            max_in_flight: threads.len() * QUEUE_CAPACITY_PER_THREAD,
+            threads,
+            next_job: AtomicU64::new(0),
+        })
+    }
+
+    /// Number of worker threads in the pool.
+    pub fn num_threads(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Maps `f` over `input_vec` on the pool's workers, blocking until every input has been
+    /// mapped, and returns the results in input order. Inputs are handed to the workers a few at a
+    /// time as results come back, so that a long job doesn't hold up other callers' jobs for long.
+    ///
+    /// The inputs are handed to the workers in chunks, sized for the number of inputs and threads.
+    ///
+    /// If `f` panics, the workers still map every other input, and then the panic is resumed on the
+    /// calling thread (the one for the earliest input, if there were several). The pool itself
+    /// stays usable.
+    pub fn map<T, U, F>(&self, input_vec: Vec<T>, f: F) -> Vec<U>
+    where
+        F: Fn(T) -> U + Send + Sync + 'static,
+        T: Send + 'static,
+        U: Send + 'static,
+    {
+        let chunk_size = default_chunk_size::<T>(input_vec.len(), self.num_threads());
+        self.map_chunked(input_vec, chunk_size, f)
+    }
+
+    /// Like `map`, but hands the inputs to the workers `chunk_size` (at least 1) at a time.
+    pub fn map_chunked<T, U, F>(&self, input_vec: Vec<T>, chunk_size: usize, f: F) -> Vec<U>
+    where
+        F: Fn(T) -> U + Send + Sync + 'static,
+        T: Send + 'static,
+        U: Send + 'static,
+    {
+        let chunk_size = chunk_size.max(1);
+        let job = self.next_job.fetch_add(1, Ordering::Relaxed);
+        let task_sender = self.task_sender.as_ref().expect("Pool is shutting down!");
+        let len = input_vec.len();
+        let slab = Arc::new(Slab::new(len));
+        let (done_sender, done_receiver) = channel::unbounded();
+        let f = Arc::new(f);
+        let mut first_panic = None;
+        let mut inputs = input_vec.into_iter();
+        let mut next_start = 0;
+        let mut in_flight = 0;
+
+        loop {
+            while in_flight < self.max_in_flight && next_start < len {
+                let start = next_start;
+                let chunk: Vec<T> = inputs.by_ref().take(chunk_size).collect();
+                next_start += chunk.len();
+                let slab = slab.clone();
+                let done_sender = done_sender.clone();
+                let f = f.clone();
+                let task: Task = Box::new(move || {
+                    // Safety: each input is in a single chunk, and the caller doesn't touch the
+                    // slots until it has every chunk's report.
+                    let done = unsafe {
+                        map_chunk(
+                            &slab,
+                            (start, chunk),
+                            &AtomicBool::new(false),
+                            |_| false,
+                            |_, val| f(val),
+                        )
+                    };
+                    // Let go of the slab first, so that the caller has the only handle on it once
+                    // it has every report.
+                    drop(slab);
+                    // The caller only stops listening once it has all of its results.
+                    let _ = done_sender.send((job, done));
+                });
+                task_sender
+                    .send(task)
+                    .expect("Couldn't queue a chunk: the workers are gone!");
+                in_flight += 1;
+            }
+            if in_flight == 0 {
+                break;
+            }
+            let (result_job, done) = done_receiver
+                .recv()
+                .expect("Couldn't receive results: the job's output channel closed!");
+            assert_eq!(
+                result_job, job,
+                "Result for job {} sent to job {}!",
+                result_job, job
+            );
+            if let Some(panic) = done.panic {
+                keep_earliest(&mut first_panic, panic);
+            }
+            in_flight -= 1;
+        }
+
+        if let Some((_, payload)) = first_panic {
+            panic::resume_unwind(payload);
+        }
+        Arc::try_unwrap(slab)
+            .ok()
+            .expect("Workers still hold on to the results!")
+            .into_vec()
+    }
+}
+
+impl Drop for ParallelMapper {
+    /// Lets the workers finish the inputs they were given and waits for them.
+    fn drop(&mut self) {
+        self.task_sender = None;
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::spawn::FAIL_SPAWNS_FROM;
+    use std::collections::HashSet;
+    use std::panic::AssertUnwindSafe;
+    use std::time::Duration;
+
+    #[test]
+    fn test_pool_is_reusable() {
+        let mapper = ParallelMapper::new(3);
+        assert_eq!(mapper.num_threads(), 3);
+        for len in 0..20 {
+            let doubled = mapper.map((0..len).collect(), |num: u32| num * 2);
+            assert_eq!(doubled, (0..len).map(|num| num * 2).collect::<Vec<u32>>());
+        }
+        let words = mapper.map(vec![1, 22, 333], |num: u32| num.to_string());
+        assert_eq!(words, vec!["1", "22", "333"]);
+        assert_eq!(ParallelMapper::new(0).num_threads(), 1);
+    }
+
+    #[test]
+    fn test_pool_makes_do_with_the_workers_it_gets() {
+        FAIL_SPAWNS_FROM.with(|limit| limit.set(2));
+        let mapper = ParallelMapper::try_new(8).unwrap();
+        assert_eq!(mapper.num_threads(), 2);
+        let names = mapper.map((0..100).collect(), |_: u32| {
+            thread::current().name().unwrap().to_string()
+        });
+        assert!(names.iter().all(|name| name.starts_with("parmap-worker-")));
+
+        FAIL_SPAWNS_FROM.with(|limit| limit.set(0));
+        let error = ParallelMapper::try_new(8).err().unwrap();
+        assert_eq!((error.worker(), error.requested()), (0, 8));
+    }
+
+    #[test]
+    fn test_chunks_keep_input_order() {
+        let mapper = ParallelMapper::new(3);
+        for &len in [0, 1, 7, 64, 100].iter() {
+            let expected: Vec<u32> = (0..len).map(|num| num * 3).collect();
+            for &chunk_size in [0, 1, 3, 7, 64, 200].iter() {
+                let tripled =
+                    mapper.map_chunked((0..len).collect(), chunk_size, |num: u32| num * 3);
+                assert_eq!(
+                    tripled, expected,
+                    "{} inputs in chunks of {}",
+                    len, chunk_size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_concurrent_callers_get_their_own_results() {
+        let mapper = ParallelMapper::new(4);
+        thread::scope(|scope| {
+            for caller in 0..6u64 {
+                let mapper = &mapper;
+                scope.spawn(move || {
+                    for round in 0..20 {
+                        let inputs: Vec<u64> = (0..50).collect();
+                        let results = mapper.map(inputs, move |num: u64| {
+                            // Keeps the callers' inputs interleaved on the workers.
+                            if num % 10 == 5 {
+                                thread::sleep(Duration::from_micros(100));
+                            }
+                            (caller, round, num)
+                        });
+                        let expected: Vec<_> = (0..50).map(|num| (caller, round, num)).collect();
+                        assert_eq!(results, expected);
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_pool_survives_panics() {
+        let mapper = ParallelMapper::new(2);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            mapper.map((0..10).collect(), |num: u32| {
+                assert!(num % 4 != 3, "can't map {}", num);
+                num
+            })
+        }));
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<String>().unwrap(), "can't map 3");
+        assert_eq!(mapper.map(vec![1, 2], |num: u32| num + 1), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_threads_are_reused_across_calls() {
+        let mapper = ParallelMapper::new(4);
+        let mut seen = HashSet::new();
+        for _ in 0..100 {
+            let ids = mapper.map((0..8).collect(), |_: u64| thread::current().id());
+            seen.extend(ids);
+        }
+        // Every call was mapped by the same few threads, none of them the caller.
+        assert!(seen.len() <= mapper.num_threads(), "{:?}", seen);
+        assert!(!seen.contains(&thread::current().id()));
+    }
+}
@@ -0,0 +1,241 @@
+use crate::{run_workers, spawn_failed, Chunk, Dispatch};
+use crossbeam_channel as channel;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Calls `f` on each input in `input_vec` on `num_threads` worker threads (at least 1), for its
+/// side effects only. Nothing is sent back from the workers, so unlike mapping to `()`, this needs
+/// no room for the results. The inputs and `f` may borrow from the caller.
+///
+/// If `f` panics, the workers still go through every other input, and then the panic is resumed on
+/// the calling thread (the one for the earliest input, if there were several).
+pub fn parallel_for_each<T, F>(input_vec: Vec<T>, num_threads: usize, f: F)
+where
+    F: Fn(T) + Send + Sync,
+    T: Send,
+{
+    let dispatch = Dispatch::new::<T>(input_vec.len(), num_threads);
+    let f = &f;
+    let (_, first_panics) = run_workers(
+        input_vec,
+        dispatch,
+        &AtomicBool::new(false),
+        || {
+            move |input_receiver: &channel::Receiver<Chunk<T>>| {
+                let mut first_panic: Option<(usize, Box<dyn Any + Send>)> = None;
+                while let Ok((start, chunk)) = input_receiver.recv() {
+                    for (id, val) in (start..).zip(chunk) {
+                        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| f(val))) {
+                            if first_panic
+                                .as_ref()
+                                .is_none_or(|(first_id, _)| id < *first_id)
+                            {
+                                first_panic = Some((id, payload));
+                            }
+                        }
+                    }
+                }
+                first_panic
+            }
+        },
+        || {},
+        &|_| {},
+    )
+    .unwrap_or_else(spawn_failed);
+    if let Some((_, payload)) = first_panics.into_iter().flatten().min_by_key(|(id, _)| *id) {
+        panic::resume_unwind(payload);
+    }
+}
+
+/// Maps `map` over `input_vec` on `num_threads` worker threads (at least 1) and combines the
+/// results with `reduce`, without ever holding more than one partial result per worker. Each
+/// worker folds the results of the inputs it takes into its own accumulator, starting from a clone
+/// of `identity`, and the accumulators are folded together at the end.
+///
+/// Which inputs go to which worker, and in what order chunks of them are taken, is up to
+/// scheduling. So the result is only well defined if `reduce` is associative and commutative, and
+/// `identity` leaves whatever it's combined with unchanged. All that's promised beyond that is that
+/// every input is folded in exactly once, and that a worker folds the inputs of a chunk in order.
+///
+/// If `map` or `reduce` panics, the workers stop taking new inputs, and the panic is resumed on the
+/// calling thread.
+pub fn parallel_map_reduce<T, U, M, R>(
+    input_vec: Vec<T>,
+    num_threads: usize,
+    map: M,
+    identity: U,
+    reduce: R,
+) -> U
+where
+    M: Fn(T) -> U + Send + Sync,
+    R: Fn(U, U) -> U + Send + Sync,
+    T: Send,
+    U: Clone + Send,
+{
+    let dispatch = Dispatch::new::<T>(input_vec.len(), num_threads);
+    let stop = &AtomicBool::new(false);
+    let (map, reduce) = (&map, &reduce);
+    let (_, partials) = run_workers(
+        input_vec,
+        dispatch,
+        stop,
+        || {
+            let mut acc = Ok(identity.clone());
+            move |input_receiver: &channel::Receiver<Chunk<T>>| {
+                // Keeps taking inputs after a panic, only to let the queue close.
+                while let Ok((_, chunk)) = input_receiver.recv() {
+                    if stop.load(Ordering::Relaxed) {
+                        continue;
+                    }
+                    if let Ok(partial) = acc {
+                        acc = panic::catch_unwind(AssertUnwindSafe(|| {
+                            chunk
+                                .into_iter()
+                                .fold(partial, |partial, val| reduce(partial, map(val)))
+                        }));
+                        if acc.is_err() {
+                            stop.store(true, Ordering::Relaxed);
+                        }
+                    }
+                }
+                acc
+            }
+        },
+        || {},
+        &|_| {},
+    )
+    .unwrap_or_else(spawn_failed);
+    partials
+        .into_iter()
+        .fold(identity, |total, partial| match partial {
+            Ok(partial) => reduce(total, partial),
+            Err(payload) => panic::resume_unwind(payload),
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_for_each_visits_every_input_once() {
+        let visits: Vec<AtomicUsize> = (0..1000).map(|_| AtomicUsize::new(0)).collect();
+        parallel_for_each((0..1000).collect(), 4, |id: usize| {
+            visits[id].fetch_add(1, Ordering::SeqCst);
+        });
+        assert!(visits.iter().all(|count| count.load(Ordering::SeqCst) == 1));
+
+        let seen = Mutex::new(Vec::new());
+        parallel_for_each(vec!["a", "b"], 8, |word| seen.lock().unwrap().push(word));
+        let mut seen = seen.into_inner().unwrap();
+        seen.sort_unstable();
+        assert_eq!(seen, vec!["a", "b"]);
+        parallel_for_each(Vec::<u32>::new(), 2, |_| {
+            panic!("no inputs to call this on")
+        });
+    }
+
+    #[test]
+    fn test_for_each_panics_reach_the_caller() {
+        let calls = AtomicUsize::new(0);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            parallel_for_each((0..20).collect(), 4, |num: u32| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                assert!(num % 7 != 3, "can't visit {}", num);
+            })
+        }));
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<String>().unwrap(), "can't visit 3");
+        assert_eq!(calls.load(Ordering::SeqCst), 20);
+    }
+
+    #[test]
+    fn test_sum() {
+        let sum = parallel_map_reduce((1..=100_000).collect(), 4, |num: u64| num, 0, |a, b| a + b);
+        assert_eq!(sum, 100_000 * 100_001 / 2);
+        let squares = parallel_map_reduce(vec![1, 2, 3], 8, |num: u64| num * num, 0, |a, b| a + b);
+        assert_eq!(squares, 14);
+    }
+
+    #[test]
+    fn test_empty_input_gives_identity() {
+        let product = parallel_map_reduce(Vec::new(), 4, |num: u64| num, 1, |a, b| a * b);
+        assert_eq!(product, 1);
+    }
+
+    #[test]
+    fn test_hash_total() {
+        fn hash(word: &String) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            word.hash(&mut hasher);
+            hasher.finish()
+        }
+        let words: Vec<String> = (0..5000).map(|num| format!("file{}.txt", num)).collect();
+        let expected = words.iter().map(hash).fold(0u64, u64::wrapping_add);
+        let total = parallel_map_reduce(words, 3, |word| hash(&word), 0, u64::wrapping_add);
+        assert_eq!(total, expected);
+    }
+
+    /// Merges two sorted vectors, which is associative and commutative.
+    fn merge(a: Vec<u32>, b: Vec<u32>) -> Vec<u32> {
+        let mut merged = Vec::with_capacity(a.len() + b.len());
+        let (mut a, mut b) = (a.into_iter().peekable(), b.into_iter().peekable());
+        while let (Some(x), Some(y)) = (a.peek(), b.peek()) {
+            if x <= y {
+                merged.push(a.next().unwrap());
+            } else {
+                merged.push(b.next().unwrap());
+            }
+        }
+        merged.extend(a);
+        merged.extend(b);
+        merged
+    }
+
+    #[test]
+    fn test_every_input_folded_once() {
+        let inputs: Vec<u32> = (0..2000).map(|num| (num * 7919) % 2000).collect();
+        let sorted = parallel_map_reduce(inputs, 4, |num| vec![num], Vec::new(), merge);
+        assert_eq!(sorted, (0..2000).collect::<Vec<u32>>());
+
+        // Concatenation isn't commutative, so the order is up to scheduling, but every input is
+        // still in there exactly once.
+        let letters: Vec<char> = ('a'..='z').collect();
+        let joined = parallel_map_reduce(letters, 3, String::from, String::new(), |a, b| a + &b);
+        let mut joined: Vec<char> = joined.chars().collect();
+        joined.sort_unstable();
+        assert_eq!(joined, ('a'..='z').collect::<Vec<char>>());
+    }
+
+    #[test]
+    #[should_panic(expected = "can't reduce 13")]
+    fn test_reduce_panics_reach_the_caller() {
+        parallel_map_reduce(
+            (0..1000).collect(),
+            4,
+            |num: u32| num,
+            0,
+            |a, b| {
+                assert!(b != 13, "can't reduce {}", b);
+                a + b
+            },
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "can't map anything")]
+    fn test_every_worker_panicking() {
+        parallel_map_reduce(
+            (0..10_000).collect(),
+            4,
+            |_: u32| -> u32 { panic!("can't map anything") },
+            0,
+            |a, b| a + b,
+        );
+    }
+}
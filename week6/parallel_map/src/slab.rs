@@ -0,0 +1,233 @@
+use std::cell::UnsafeCell;
+use std::mem::{self, MaybeUninit};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Pre-sized storage for the results of a map, which workers write into directly, each result into
+/// the slot for its input's index. Results never pass through a channel on their way out, and once
+/// every slot is written, the slots become the output vector as they are.
+///
+/// Each slot may only be accessed by one thread at a time: first the worker that mapped its input,
+/// then whoever that worker hands it off to, by a message sent after writing it or by being joined.
+pub(crate) struct Slab<U> {
+    slots: Box<[UnsafeCell<MaybeUninit<U>>]>,
+    /// Whether each slot holds a result
+    written: Box<[AtomicBool]>,
+}
+
+// Safety: a slot is only ever accessed by one thread at a time (see `write` and `get`), so sharing
+// a slab only ever hands results from one thread to another, as sending them would.
+unsafe impl<U: Send> Sync for Slab<U> {}
+
+impl<U> Slab<U> {
+    pub(crate) fn new(len: usize) -> Slab<U> {
+        Slab {
+            slots: (0..len)
+                .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+                .collect(),
+            written: (0..len).map(|_| AtomicBool::new(false)).collect(),
+        }
+    }
+
+    /// Puts `val` in slot `id`. Panics if the slot has already been written.
+    ///
+    /// # Safety
+    ///
+    /// No other thread may access slot `id` until it has synchronized with this one.
+    pub(crate) unsafe fn write(&self, id: usize, val: U) {
+        assert!(
+            !self.written[id].load(Ordering::Relaxed),
+            "Result for input {} written twice!",
+            id
+        );
+        (*self.slots[id].get()).write(val);
+        self.written[id].store(true, Ordering::Release);
+    }
+
+    /// Returns the result in slot `id`, if it has been written.
+    ///
+    /// # Safety
+    ///
+    /// This thread must have synchronized with the one that wrote the slot, if any, and no other
+    /// thread may access the slot while the reference is held.
+    pub(crate) unsafe fn get(&self, id: usize) -> Option<&U> {
+        if self.written[id].load(Ordering::Acquire) {
+            Some((*self.slots[id].get()).assume_init_ref())
+        } else {
+            None
+        }
+    }
+
+    /// Returns every result in input order, in the very memory they were written to. Panics,
+    /// naming the index, if any slot was never written.
+    pub(crate) fn into_vec(mut self) -> Vec<U> {
+        if let Some(id) = self
+            .written
+            .iter_mut()
+            .position(|written| !*written.get_mut())
+        {
+            panic!("No result for input {}!", id);
+        }
+        // Taken out, so that dropping the slab leaves the results be
+        let slots = mem::take(&mut self.slots);
+        // Safety: `UnsafeCell` and `MaybeUninit` are both `repr(transparent)`, so the slots are
+        // laid out just like a `[U]`, and every one of them has been written.
+        unsafe { Vec::from(Box::from_raw(Box::into_raw(slots) as *mut [U])) }
+    }
+
+    /// Returns the result for each input in input order, or None for those never written.
+    pub(crate) fn into_options(mut self) -> Vec<Option<U>> {
+        self.slots
+            .iter_mut()
+            .zip(self.written.iter_mut())
+            .map(|(slot, written)| {
+                if mem::replace(written.get_mut(), false) {
+                    // Safety: written, and no longer marked as such, so it's only read once.
+                    Some(unsafe { slot.get_mut().assume_init_read() })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+impl<U> Drop for Slab<U> {
+    fn drop(&mut self) {
+        for (slot, written) in self.slots.iter_mut().zip(self.written.iter_mut()) {
+            if *written.get_mut() {
+                // Safety: written, and about to be freed along with the slab.
+                unsafe { slot.get_mut().assume_init_drop() };
+            }
+        }
+    }
+}
+
+// These exercise the unsafe code above, so CI also runs them under Miri (see
+// .github/workflows/miri.yml); locally, that's `cargo +nightly miri test --lib slab`.
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+    use std::thread;
+
+    /// Counts how many of its kind have been dropped.
+    struct Tracked<'a>(&'a AtomicUsize);
+
+    impl Drop for Tracked<'_> {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_into_vec() {
+        let slab = Slab::new(3);
+        unsafe {
+            slab.write(2, String::from("c"));
+            slab.write(0, String::from("a"));
+            assert_eq!(slab.get(1), None);
+            slab.write(1, String::from("b"));
+            assert_eq!(slab.get(1).map(String::as_str), Some("b"));
+        }
+        assert_eq!(slab.into_vec(), vec!["a", "b", "c"]);
+        assert_eq!(Slab::<u8>::new(0).into_vec(), vec![]);
+    }
+
+    #[test]
+    fn test_into_options() {
+        let slab = Slab::new(4);
+        unsafe {
+            slab.write(1, 10);
+            slab.write(3, 30);
+        }
+        assert_eq!(slab.into_options(), vec![None, Some(10), None, Some(30)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "No result for input 1!")]
+    fn test_missing_result_is_detected() {
+        let slab = Slab::new(3);
+        unsafe {
+            slab.write(0, "a");
+            slab.write(2, "c");
+        }
+        slab.into_vec();
+    }
+
+    #[test]
+    #[should_panic(expected = "Result for input 0 written twice!")]
+    fn test_double_write_is_detected() {
+        let slab = Slab::new(1);
+        unsafe {
+            slab.write(0, 1);
+            slab.write(0, 2);
+        }
+    }
+
+    #[test]
+    fn test_drops_each_result_once() {
+        let dropped = AtomicUsize::new(0);
+
+        // Only the written slots are dropped with the slab.
+        let slab = Slab::new(5);
+        unsafe {
+            slab.write(0, Tracked(&dropped));
+            slab.write(3, Tracked(&dropped));
+        }
+        drop(slab);
+        assert_eq!(dropped.load(Ordering::SeqCst), 2);
+
+        // Handed out results are left for their new owner to drop.
+        let slab = Slab::new(2);
+        unsafe {
+            slab.write(1, Tracked(&dropped));
+        }
+        let options = slab.into_options();
+        assert_eq!(dropped.load(Ordering::SeqCst), 2);
+        drop(options);
+        assert_eq!(dropped.load(Ordering::SeqCst), 3);
+
+        let slab = Slab::new(2);
+        unsafe {
+            slab.write(0, Tracked(&dropped));
+            slab.write(1, Tracked(&dropped));
+        }
+        let results = slab.into_vec();
+        assert_eq!(dropped.load(Ordering::SeqCst), 3);
+        drop(results);
+        assert_eq!(dropped.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn test_zero_sized_results() {
+        let slab = Slab::new(3);
+        for id in 0..3 {
+            unsafe { slab.write(id, ()) };
+        }
+        assert_eq!(slab.into_vec(), vec![(), (), ()]);
+    }
+
+    #[test]
+    fn test_writes_from_many_threads() {
+        const LEN: usize = 1000;
+        let slab = Arc::new(Slab::new(LEN));
+        let threads: Vec<_> = (0..4)
+            .map(|offset| {
+                let slab = slab.clone();
+                // Each thread writes every fourth slot, so no two touch the same one.
+                thread::spawn(move || {
+                    for id in (offset..LEN).step_by(4) {
+                        unsafe { slab.write(id, id.to_string()) };
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let slab = Arc::try_unwrap(slab).ok().unwrap();
+        let expected: Vec<String> = (0..LEN).map(|id| id.to_string()).collect();
+        assert_eq!(slab.into_vec(), expected);
+    }
+}
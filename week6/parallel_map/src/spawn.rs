@@ -0,0 +1,177 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::thread;
+
+#[cfg(test)]
+use std::cell::Cell;
+
+/// The error when a map couldn't spawn one of its worker threads, say because the process has
+/// run into its limit on threads. Maps carry on with the workers spawned before it, if there are
+/// any; this is only returned when there aren't, and otherwise only passed on as a warning.
+#[derive(Debug)]
+pub struct SpawnError {
+    worker: usize,
+    requested: usize,
+    error: io::Error,
+}
+
+impl SpawnError {
+    /// Index of the worker that couldn't be spawned, which is also how many of them were.
+    pub fn worker(&self) -> usize {
+        self.worker
+    }
+
+    /// Number of workers asked for.
+    pub fn requested(&self) -> usize {
+        self.requested
+    }
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Couldn't spawn worker {} of {}: {}",
+            self.worker, self.requested, self.error
+        )
+    }
+}
+
+impl Error for SpawnError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Workers spawned from this thread with this index or above fail to spawn, so that tests can
+    /// see how maps cope.
+    pub(crate) static FAIL_SPAWNS_FROM: Cell<usize> = const { Cell::new(usize::MAX) };
+}
+
+/// A builder for worker `worker`, named after it so that it can be told apart in a debugger or a
+/// profiler, with a stack of `stack_size` bytes if given.
+fn worker_builder(worker: usize, stack_size: Option<usize>) -> thread::Builder {
+    let builder = thread::Builder::new().name(format!("parmap-worker-{}", worker));
+    match stack_size {
+        Some(stack_size) => builder.stack_size(stack_size),
+        None => builder,
+    }
+}
+
+/// Spawns worker `worker` of `requested` with `spawn`, which is handed the builder for it.
+pub(crate) fn spawn_worker<H, S>(
+    worker: usize,
+    requested: usize,
+    stack_size: Option<usize>,
+    spawn: S,
+) -> Result<H, SpawnError>
+where
+    S: FnOnce(thread::Builder) -> io::Result<H>,
+{
+    #[cfg(test)]
+    {
+        if worker >= FAIL_SPAWNS_FROM.with(Cell::get) {
+            return Err(SpawnError {
+                worker,
+                requested,
+                error: io::Error::new(io::ErrorKind::WouldBlock, "simulated spawn failure"),
+            });
+        }
+    }
+    spawn(worker_builder(worker, stack_size)).map_err(|error| SpawnError {
+        worker,
+        requested,
+        error,
+    })
+}
+
+/// Spawns `requested` (at least 1) worker threads running what `make_worker` makes for each, for
+/// as long as spawning them works. Returns the ones that were spawned, along with the error that
+/// stopped the spawning early, if any; or just the error, if not a single one could be spawned.
+pub(crate) fn spawn_workers<M, W>(
+    requested: usize,
+    stack_size: Option<usize>,
+    mut make_worker: M,
+) -> Result<(Vec<thread::JoinHandle<()>>, Option<SpawnError>), SpawnError>
+where
+    M: FnMut() -> W,
+    W: FnOnce() + Send + 'static,
+{
+    let requested = requested.max(1);
+    let mut threads = Vec::new();
+    for worker in 0..requested {
+        let work = make_worker();
+        match spawn_worker(worker, requested, stack_size, |builder| builder.spawn(work)) {
+            Ok(thread) => threads.push(thread),
+            Err(error) if threads.is_empty() => return Err(error),
+            Err(error) => return Ok((threads, Some(error))),
+        }
+    }
+    Ok((threads, None))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_workers_are_named() {
+        let (sender, receiver) = mpsc::channel();
+        let (threads, error) = spawn_workers(3, None, || {
+            let sender = sender.clone();
+            move || {
+                sender
+                    .send(thread::current().name().map(String::from))
+                    .unwrap()
+            }
+        })
+        .unwrap();
+        assert!(error.is_none());
+        threads
+            .into_iter()
+            .for_each(|thread| thread.join().unwrap());
+        drop(sender);
+        let mut names: Vec<_> = receiver.iter().flatten().collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["parmap-worker-0", "parmap-worker-1", "parmap-worker-2"]
+        );
+    }
+
+    #[test]
+    fn test_spawning_stops_at_first_failure() {
+        FAIL_SPAWNS_FROM.with(|limit| limit.set(2));
+        let (threads, error) = spawn_workers(5, None, || || {}).unwrap();
+        assert_eq!(threads.len(), 2);
+        let error = error.unwrap();
+        assert_eq!((error.worker(), error.requested()), (2, 5));
+        assert_eq!(
+            error.to_string(),
+            "Couldn't spawn worker 2 of 5: simulated spawn failure"
+        );
+        threads
+            .into_iter()
+            .for_each(|thread| thread.join().unwrap());
+
+        FAIL_SPAWNS_FROM.with(|limit| limit.set(0));
+        let error = spawn_workers(5, None, || || {}).unwrap_err();
+        assert_eq!(error.worker(), 0);
+        assert!(error.source().is_some());
+    }
+
+    #[test]
+    fn test_stack_size_is_used() {
+        // Far more than there is memory for, so only fails if it's actually asked for.
+        let error = spawn_workers(2, Some(1 << 50), || || {}).unwrap_err();
+        assert_eq!(error.worker(), 0);
+        let (threads, _) = spawn_workers(2, Some(64 * 1024), || || {}).unwrap();
+        threads
+            .into_iter()
+            .for_each(|thread| thread.join().unwrap());
+    }
+}
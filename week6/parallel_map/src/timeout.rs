@@ -0,0 +1,194 @@
+use crate::spawn::spawn_workers;
+use crate::{effective_workers, feed, run_worker, spawn_failed, Dispatch, DispatchOrder, Outputs};
+use crossbeam_channel::{self as channel, RecvTimeoutError};
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The outcome of `parallel_map_timeout`: the result for each input, in input order, or None for
+/// the inputs that didn't get mapped in time.
+#[derive(Debug, PartialEq)]
+pub struct TimeoutResult<U> {
+    pub results: Vec<Option<U>>,
+    /// Number of inputs without a result
+    pub unprocessed: usize,
+}
+
+/// Maps `f` over `input_vec` on `num_threads` worker threads (at least 1) for at most `timeout`,
+/// and returns whichever results came in by then. Once the time is up, workers stop taking new
+/// inputs. The ones they are already mapping are left to finish in the background; their results
+/// are only kept if they arrive while the last results are collected. Inputs are handed out one at
+/// a time, so that each result is sent back as soon as it's ready, through the same bounded queue
+/// as the other maps; the ones that haven't been queued by the deadline never are.
+///
+/// If `f` panicked on any input whose result would have made it in time, the panic is resumed on
+/// the calling thread (the one for the earliest input, if there were several).
+pub fn parallel_map_timeout<T, U, F>(
+    input_vec: Vec<T>,
+    num_threads: usize,
+    timeout: Duration,
+    f: F,
+) -> TimeoutResult<U>
+where
+    F: Fn(T) -> U + Send + Sync + 'static,
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    let deadline = Instant::now() + timeout;
+    let len = input_vec.len();
+    let num_threads = effective_workers(num_threads, len);
+    if num_threads == 0 {
+        return TimeoutResult {
+            results: Vec::new(),
+            unprocessed: 0,
+        };
+    }
+    let dispatch = Dispatch::new::<T>(len, num_threads);
+    let (input_sender, input_receiver) = channel::bounded(dispatch.queue_capacity);
+    let (output_sender, output_receiver) = channel::unbounded();
+    let stop = Arc::new(AtomicBool::new(false));
+    let f = Arc::new(f);
+    let input_vec = Arc::new(Mutex::new(input_vec));
+
+    // Not scoped, since workers that are still busy at the deadline are left behind, and so is
+    // the feeder if it's still waiting for room in the queue.
+    let (threads, _) = spawn_workers(num_threads, None, || {
+        let input_receiver = input_receiver.clone();
+        let output_sender = output_sender.clone();
+        let stop = stop.clone();
+        let f = f.clone();
+        move || run_worker(&input_receiver, &output_sender, &stop, |_| false, &*f)
+    })
+    .unwrap_or_else(spawn_failed);
+    drop(output_sender);
+
+    let feeder = thread::Builder::new()
+        .name("parmap-feeder".to_string())
+        .spawn({
+            let input_vec = input_vec.clone();
+            let input_sender = input_sender.clone();
+            let stop = stop.clone();
+            move || feed(&input_vec, input_sender, 1, DispatchOrder::Forward, &stop)
+        });
+    match feeder {
+        Ok(_) => drop(input_sender),
+        // Feed it from this thread then, which only holds the results up until the last inputs
+        // are queued.
+        Err(_) => feed(&input_vec, input_sender, 1, DispatchOrder::Forward, &stop),
+    }
+
+    let mut outputs = Outputs::new(len);
+    let finished = loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match output_receiver.recv_timeout(remaining) {
+            Ok(output) => outputs.record(output),
+            Err(RecvTimeoutError::Timeout) => break false,
+            Err(RecvTimeoutError::Disconnected) => break true,
+        }
+    };
+    if finished {
+        for thread in threads {
+            thread.join().expect("Worker panicked!");
+        }
+    } else {
+        stop.store(true, Ordering::Relaxed);
+        // Whatever arrived while this thread was waking up still counts.
+        while let Ok(output) = output_receiver.try_recv() {
+            outputs.record(output);
+        }
+    }
+
+    let results = outputs
+        .finish()
+        .unwrap_or_else(|payload| panic::resume_unwind(payload));
+    let unprocessed = results.iter().filter(|val| val.is_none()).count();
+    TimeoutResult {
+        results,
+        unprocessed,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_finishes_in_time() {
+        let start = Instant::now();
+        let doubled = parallel_map_timeout(
+            (0..100).collect(),
+            4,
+            Duration::from_secs(10),
+            |num: u32| num * 2,
+        );
+        assert_eq!(doubled.unprocessed, 0);
+        let expected: Vec<Option<u32>> = (0..100).map(|num| Some(num * 2)).collect();
+        assert_eq!(doubled.results, expected);
+        // Didn't wait for the deadline.
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_partial_results() {
+        let start = Instant::now();
+        // Sleeps for as many milliseconds as it's given.
+        let millis = vec![0, 10, 5000, 20, 5000, 0];
+        let slept = parallel_map_timeout(millis, 2, Duration::from_millis(300), |millis: u64| {
+            thread::sleep(Duration::from_millis(millis));
+            millis
+        });
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(300));
+        assert!(
+            elapsed < Duration::from_secs(2),
+            "returned after {:?}",
+            elapsed
+        );
+        assert_eq!(slept.results[..2], [Some(0), Some(10)]);
+        assert_eq!(slept.results[2], None);
+        assert_eq!(
+            slept.unprocessed,
+            slept.results.iter().filter(|val| val.is_none()).count()
+        );
+        assert!(slept.unprocessed >= 2);
+    }
+
+    #[test]
+    fn test_no_new_inputs_after_deadline() {
+        let started_late = Arc::new(AtomicBool::new(false));
+        let flag = started_late.clone();
+        let deadline = Instant::now() + Duration::from_millis(100);
+        let results = parallel_map_timeout(
+            (0..50).collect(),
+            1,
+            Duration::from_millis(100),
+            move |num: u32| {
+                if Instant::now() > deadline + Duration::from_millis(50) {
+                    flag.store(true, Ordering::SeqCst);
+                }
+                thread::sleep(Duration::from_millis(30));
+                num
+            },
+        );
+        assert!(results.unprocessed > 0);
+        // Give the worker time to notice, and then make sure it has stopped.
+        thread::sleep(Duration::from_millis(200));
+        assert!(!started_late.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_empty_input() {
+        let results =
+            parallel_map_timeout(Vec::new(), 3, Duration::from_millis(10), |num: u32| num);
+        assert_eq!(
+            results,
+            TimeoutResult {
+                results: vec![],
+                unprocessed: 0
+            }
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! Compares collecting results through a channel, the way `parallel_map` used to, with having the
+//! workers write them straight into place, as it does now: 1024 results of 64KB each, 64MB in all,
+//! held inline so that every move copies all of their bytes. Each run's peak memory is measured
+//! by a counting allocator and must come in lower for the direct writes; the times are printed
+//! for comparison (run with `--nocapture` to see them). The allocator counts everything this test
+//! binary allocates, so it holds no other tests.
+
+use crossbeam_channel as channel;
+use parallel_map::parallel_map_scoped;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const ITEM_SIZE: usize = 64 * 1024;
+const LEN: usize = 1024;
+const THREADS: usize = 2;
+/// What `parallel_map_scoped` picks for this many inputs on this many threads
+const CHUNK_SIZE: usize = LEN / (THREADS * 4);
+
+type Item = [u8; ITEM_SIZE];
+
+/// Bytes currently allocated, and the most allocated at any time.
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let held = BYTES.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK_BYTES.fetch_max(held, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        BYTES.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn make_item(id: usize) -> Item {
+    [id as u8; ITEM_SIZE]
+}
+
+/// The old way: workers send each chunk's results over an unbounded channel, and the calling
+/// thread puts them into a vector of options, which is then unwrapped into the output vector.
+fn map_through_channel(input_vec: Vec<usize>, f: fn(usize) -> Item) -> Vec<Item> {
+    let (input_sender, input_receiver) = channel::bounded::<(usize, Vec<usize>)>(1);
+    let (output_sender, output_receiver) = channel::unbounded::<(usize, Vec<Item>)>();
+    let mut output_vec: Vec<Option<Item>> = Vec::new();
+    output_vec.resize_with(input_vec.len(), || None);
+    thread::scope(|scope| {
+        for _ in 0..THREADS {
+            let input_receiver = input_receiver.clone();
+            let output_sender = output_sender.clone();
+            scope.spawn(move || {
+                while let Ok((start, chunk)) = input_receiver.recv() {
+                    let results = chunk.into_iter().map(f).collect();
+                    output_sender.send((start, results)).unwrap();
+                }
+            });
+        }
+        drop(output_sender);
+        scope.spawn(move || {
+            for (i, chunk) in input_vec.chunks(CHUNK_SIZE).enumerate() {
+                input_sender.send((i * CHUNK_SIZE, chunk.to_vec())).unwrap();
+            }
+        });
+        while let Ok((start, results)) = output_receiver.recv() {
+            for (id, val) in (start..).zip(results) {
+                output_vec[id] = Some(val);
+            }
+        }
+    });
+    output_vec.into_iter().map(Option::unwrap).collect()
+}
+
+/// Runs `map` on fresh inputs, and returns how long it took and the most memory it needed on top
+/// of what was allocated before.
+fn measure(map: impl FnOnce(Vec<usize>) -> Vec<Item>) -> (Duration, usize) {
+    let inputs = (0..LEN).collect();
+    let held_before = BYTES.load(Ordering::SeqCst);
+    PEAK_BYTES.store(held_before, Ordering::SeqCst);
+    let start = Instant::now();
+    let items = map(inputs);
+    let elapsed = start.elapsed();
+    let extra = PEAK_BYTES.load(Ordering::SeqCst) - held_before;
+    assert!(items
+        .iter()
+        .enumerate()
+        .all(|(id, item)| item[0] == id as u8));
+    (elapsed, extra)
+}
+
+#[test]
+fn large_results_are_written_in_place() {
+    let (channel_time, channel_bytes) = measure(|inputs| map_through_channel(inputs, make_item));
+    let (slab_time, slab_bytes) = measure(|inputs| parallel_map_scoped(inputs, THREADS, make_item));
+    println!(
+        "{} results of {} bytes: {:?} and {} bytes through a channel, {:?} and {} bytes in place",
+        LEN, ITEM_SIZE, channel_time, channel_bytes, slab_time, slab_bytes
+    );
+    // Just the output vector, and a little for the inputs and the bookkeeping.
+    assert!(
+        slab_bytes < LEN * ITEM_SIZE * 9 / 8,
+        "needed {} bytes for {} bytes of results",
+        slab_bytes,
+        LEN * ITEM_SIZE
+    );
+    assert!(slab_bytes < channel_bytes);
+}
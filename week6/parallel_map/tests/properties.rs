@@ -0,0 +1,37 @@
+//! Checks that every flavor of parallel map agrees with a plain sequential map, whatever the
+//! inputs, thread count and chunk size.
+
+use parallel_map::{parallel_map, parallel_map_chunked, parallel_map_scoped, ParallelMapper};
+use proptest::prelude::*;
+
+fn scramble(num: i64) -> i64 {
+    num.wrapping_mul(6364136223846793005).rotate_left(17) ^ 0x5555
+}
+
+proptest! {
+    #[test]
+    fn parallel_map_matches_map(input_vec: Vec<i64>, num_threads in 1usize..=16) {
+        let expected: Vec<i64> = input_vec.iter().copied().map(scramble).collect();
+        prop_assert_eq!(parallel_map(input_vec.clone(), num_threads, scramble), expected.clone());
+        prop_assert_eq!(parallel_map_scoped(input_vec, num_threads, scramble), expected);
+    }
+
+    #[test]
+    fn chunked_map_matches_map(
+        input_vec: Vec<i64>,
+        num_threads in 1usize..=16,
+        chunk_size in 0usize..=64,
+    ) {
+        let expected: Vec<i64> = input_vec.iter().copied().map(scramble).collect();
+        prop_assert_eq!(parallel_map_chunked(input_vec, num_threads, chunk_size, scramble), expected);
+    }
+
+    #[test]
+    fn pool_matches_map(input_vecs: Vec<Vec<i64>>, num_threads in 1usize..=16) {
+        let mapper = ParallelMapper::new(num_threads);
+        for input_vec in input_vecs {
+            let expected: Vec<i64> = input_vec.iter().copied().map(scramble).collect();
+            prop_assert_eq!(mapper.map(input_vec, scramble), expected);
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Maps a large number of inputs through each flavor of parallel map and checks that every input
+//! was mapped exactly once and that its result landed in the right place, and that every result
+//! is dropped exactly once, however the map ends.
+
+use parallel_map::{
+    parallel_map, parallel_map_cancellable, parallel_map_chunked, parallel_map_iter,
+    CancellationToken, ParallelMapper,
+};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+const LEN: usize = 100_000;
+const THREADS: usize = 8;
+
+/// How many times each input has been mapped.
+fn call_counts() -> Arc<Vec<AtomicU8>> {
+    Arc::new((0..LEN).map(|_| AtomicU8::new(0)).collect())
+}
+
+/// A function that records each call in `counts` and maps an input to something that names it.
+fn counting(counts: &Arc<Vec<AtomicU8>>) -> impl Fn(usize) -> String + Send + Sync + 'static {
+    let counts = counts.clone();
+    move |id| {
+        counts[id].fetch_add(1, Ordering::Relaxed);
+        format!("#{}", id)
+    }
+}
+
+fn check(results: Vec<String>, counts: &[AtomicU8]) {
+    assert_eq!(results.len(), LEN);
+    for (id, result) in results.iter().enumerate() {
+        assert_eq!(*result, format!("#{}", id), "wrong result at index {}", id);
+    }
+    for (id, count) in counts.iter().enumerate() {
+        assert_eq!(
+            count.load(Ordering::Relaxed),
+            1,
+            "input {} mapped the wrong number of times",
+            id
+        );
+    }
+}
+
+#[test]
+fn stress_parallel_map() {
+    let counts = call_counts();
+    check(
+        parallel_map((0..LEN).collect(), THREADS, counting(&counts)),
+        &counts,
+    );
+}
+
+#[test]
+fn stress_one_input_at_a_time() {
+    let counts = call_counts();
+    check(
+        parallel_map_chunked((0..LEN).collect(), THREADS, 1, counting(&counts)),
+        &counts,
+    );
+}
+
+#[test]
+fn stress_pool() {
+    let mapper = ParallelMapper::new(THREADS);
+    for _ in 0..3 {
+        let counts = call_counts();
+        check(mapper.map((0..LEN).collect(), counting(&counts)), &counts);
+    }
+}
+
+#[test]
+fn stress_iter() {
+    let counts = call_counts();
+    check(
+        parallel_map_iter(0..LEN, THREADS, counting(&counts)).collect(),
+        &counts,
+    );
+}
+
+/// A result that records being dropped in `drops`, at its input's index.
+struct Tracked {
+    id: usize,
+    drops: Arc<Vec<AtomicU8>>,
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.drops[self.id].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A function that maps an input to a `Tracked`, recording it in `made`, except for every
+/// `panic_every`th input, which it panics on.
+fn tracking(
+    made: &Arc<Vec<AtomicU8>>,
+    drops: &Arc<Vec<AtomicU8>>,
+    panic_every: usize,
+) -> impl Fn(usize) -> Tracked + Send + Sync + 'static {
+    let (made, drops) = (made.clone(), drops.clone());
+    move |id| {
+        assert!(id % panic_every != panic_every - 1, "can't map {}", id);
+        made[id].fetch_add(1, Ordering::Relaxed);
+        Tracked {
+            id,
+            drops: drops.clone(),
+        }
+    }
+}
+
+fn check_drops(made: &[AtomicU8], drops: &[AtomicU8]) {
+    for (id, (made, drops)) in made.iter().zip(drops).enumerate() {
+        assert_eq!(
+            made.load(Ordering::Relaxed),
+            drops.load(Ordering::Relaxed),
+            "result for input {} made and dropped a different number of times",
+            id
+        );
+    }
+}
+
+#[test]
+fn stress_results_dropped_once() {
+    let (made, drops) = (call_counts(), call_counts());
+    let results = parallel_map(
+        (0..LEN).collect(),
+        THREADS,
+        tracking(&made, &drops, LEN + 1),
+    );
+    assert!(results.iter().enumerate().all(|(id, val)| val.id == id));
+    drop(results);
+    check_drops(&made, &drops);
+    assert!(made.iter().all(|made| made.load(Ordering::Relaxed) == 1));
+}
+
+#[test]
+fn stress_results_dropped_once_after_panics() {
+    let (made, drops) = (call_counts(), call_counts());
+    let result = panic::catch_unwind(|| {
+        parallel_map((0..LEN).collect(), THREADS, tracking(&made, &drops, 997))
+    });
+    assert!(result.is_err());
+    check_drops(&made, &drops);
+
+    let mapper = ParallelMapper::new(THREADS);
+    for _ in 0..3 {
+        let (made, drops) = (call_counts(), call_counts());
+        let f = tracking(&made, &drops, 4999);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| mapper.map((0..LEN).collect(), f)));
+        assert!(result.is_err());
+        check_drops(&made, &drops);
+    }
+}
+
+#[test]
+fn stress_results_dropped_once_after_cancelling() {
+    let (made, drops) = (call_counts(), call_counts());
+    let f = tracking(&made, &drops, LEN + 1);
+    let token = CancellationToken::new();
+    let results = parallel_map_cancellable((0..LEN).collect(), THREADS, &token, |id, token| {
+        if id % 10_000 == 5000 {
+            token.cancel();
+        }
+        f(id)
+    });
+    assert!(results.cancelled);
+    for (id, val) in results.results.iter().enumerate() {
+        assert!(val.as_ref().is_none_or(|val| val.id == id));
+    }
+    drop(results);
+    check_drops(&made, &drops);
+}